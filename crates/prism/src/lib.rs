@@ -0,0 +1,24 @@
+//! Prism as a library.
+//!
+//! The `prism` binary is a thin CLI over [`run`]. Programs that want the proxy without
+//! the config file, workdir or signal handling can build a [`config::Config`] and a
+//! [`router::Router`] themselves and start a listener with [`embed::Proxy`].
+
+mod prism;
+
+pub use prism::{check_config, config, config_schema, embed, router, run};
+
+/// Middleware providers for [`router::Router::from_config`] and host parsers for
+/// [`router::Router::from_static`].
+pub mod middleware {
+    pub use crate::prism::middleware::{
+        BuiltinMiddlewareProvider, ClientMeta, FsWasmMiddlewareProvider, HostFn, HostParser,
+        Middleware, MiddlewareCtx, MiddlewareError, MiddlewareOutput, MiddlewarePhase,
+        MiddlewareProvider, SharedMiddleware, WasmMiddleware, WasmMiddlewareOptions,
+    };
+}
+
+/// Runtime settings for [`embed::Proxy`].
+pub mod proxy {
+    pub use crate::prism::proxy::TcpRuntimeConfig;
+}
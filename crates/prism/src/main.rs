@@ -1,5 +1,3 @@
-mod prism;
-
 use clap::Parser;
 
 #[derive(Debug, Parser)]
@@ -9,7 +7,7 @@ use clap::Parser;
     about = "Prism - lightweight Minecraft reverse proxy"
 )]
 struct Cli {
-    /// Path to Prism config file (.toml/.yaml/.yml). If omitted, uses PRISM_CONFIG; then auto-detects prism.toml > prism.yaml > prism.yml from CWD; then falls back to the OS default path (Linux: /etc/prism/prism.toml; others: user config dir).
+    /// Path to Prism config file (.toml/.yaml/.yml/.json). If omitted, uses PRISM_CONFIG; then auto-detects prism.toml > prism.yaml > prism.yml > prism.json from CWD; then falls back to the OS default path (Linux: /etc/prism/prism.toml; others: user config dir).
     #[arg(long, env = "PRISM_CONFIG")]
     config: Option<std::path::PathBuf>,
 
@@ -20,6 +18,14 @@ struct Cli {
     /// Directory to load middleware .wat files from. Defaults to "<config_dir>/middlewares" (Linux default: /etc/prism/middlewares).
     #[arg(long, env = "PRISM_MIDDLEWARE_DIR")]
     middleware_dir: Option<std::path::PathBuf>,
+
+    /// Validate the config (including every route's middleware chain) and exit without starting listeners. Exits non-zero on errors.
+    #[arg(long)]
+    check_config: bool,
+
+    /// Print the JSON Schema of the config file format (derived from the config loader) and exit.
+    #[arg(long)]
+    print_schema: bool,
 }
 
 #[tokio::main]
@@ -33,5 +39,16 @@ async fn main() -> anyhow::Result<()> {
         .expect("install rustls CryptoProvider");
 
     let cli = Cli::parse();
+    if cli.print_schema {
+        println!("{:#}", prism::config_schema());
+        return Ok(());
+    }
+    if cli.check_config {
+        if let Err(err) = prism::check_config(cli.config, cli.middleware_dir) {
+            eprintln!("config check failed: {err:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     prism::run(cli.config, cli.workdir, cli.middleware_dir).await
 }
@@ -1,10 +1,11 @@
-use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
 use axum::{
     Json, Router,
-    extract::{Path as AxumPath, State},
+    extract::{Path as AxumPath, Request, State},
     http::{HeaderMap, StatusCode, Uri, header},
-    response::IntoResponse,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post, put},
 };
 use rust_embed::Embed;
@@ -12,8 +13,9 @@ use serde::Serialize;
 use tokio::sync::watch;
 use tower_http::cors::CorsLayer;
 
+use crate::prism::net::secret_eq;
 use crate::prism::telemetry;
-use crate::prism::{managed, tunnel};
+use crate::prism::{bufpool, managed, router, tunnel};
 
 #[derive(Embed)]
 #[folder = "frontend-dist/"]
@@ -23,6 +25,10 @@ struct FrontendAssets;
 pub struct AdminAuth {
     pub panel_token: Option<String>,
     pub worker_token: Option<String>,
+    /// `admin_auth_token`: required as a bearer token on every route but `/healthz`.
+    pub admin_token: Option<String>,
+    /// `admin_basic_auth` (`user:password`), accepted in place of `admin_token`.
+    pub basic_auth: Option<String>,
 }
 
 #[derive(Clone)]
@@ -30,7 +36,16 @@ pub struct AdminState {
     pub sessions: telemetry::SharedSessions,
     pub config_path: PathBuf,
     pub reload_tx: watch::Sender<telemetry::ReloadSignal>,
+    /// Outcomes from the reload loop; None when it doesn't run (worker role), in which case
+    /// `POST /reload` only sends the signal.
+    pub reload_done: Option<watch::Receiver<telemetry::ReloadOutcome>>,
     pub tunnel: Option<Arc<tunnel::manager::Manager>>,
+    /// Source of the upstream health gauges on `/metrics`.
+    pub router: Option<Arc<router::Router>>,
+    /// Source of `prism_handshake_buffer_bytes`; None where no listeners run.
+    pub buffer_pool: Option<Arc<bufpool::BufferPool>>,
+    pub readiness: Arc<telemetry::Readiness>,
+    pub reload_metrics: Arc<telemetry::ReloadMetrics>,
     pub auth: AdminAuth,
     pub management: Option<Arc<managed::ManagementPlane>>,
     pub worker: Option<Arc<managed::WorkerAgent>>,
@@ -64,8 +79,15 @@ pub(crate) fn build_router(state: AdminState) -> Router {
     let shared = Arc::new(state);
     Router::new()
         .route("/health", get(health))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
         .route("/conns", get(conns))
+        .route("/sessions/recent", get(recent_sessions))
+        .route("/sessions/{id}/kill", post(kill_session))
+        .route("/rejections", get(rejections))
         .route("/tunnel/services", get(tunnel_services))
+        .route("/tunnel/clients", get(tunnel_clients))
+        .route("/metrics", get(metrics))
         .route("/reload", post(reload))
         .route("/config", get(config))
         .route("/managed/status", get(managed_status))
@@ -79,6 +101,10 @@ pub(crate) fn build_router(state: AdminState) -> Router {
         .route("/managed/worker/status", get(worker_status))
         .route("/managed/worker/config", put(worker_apply_config))
         .fallback(serve_frontend)
+        .layer(middleware::from_fn_with_state(
+            shared.clone(),
+            require_admin_auth,
+        ))
         .with_state(shared)
         .layer(CorsLayer::permissive())
 }
@@ -98,16 +124,16 @@ async fn serve_frontend(uri: Uri) -> impl IntoResponse {
     let path = uri.path().trim_start_matches('/');
 
     // Try the exact path first.
-    if !path.is_empty() {
-        if let Some(file) = FrontendAssets::get(path) {
-            let mime = mime_guess::from_path(path).first_or_octet_stream();
-            return (
-                StatusCode::OK,
-                [(header::CONTENT_TYPE, mime.as_ref().to_string())],
-                file.data.into_owned(),
-            )
-                .into_response();
-        }
+    if !path.is_empty()
+        && let Some(file) = FrontendAssets::get(path)
+    {
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        return (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, mime.as_ref().to_string())],
+            file.data.into_owned(),
+        )
+            .into_response();
     }
 
     // SPA fallback: serve _shell.html for any unmatched route.
@@ -133,11 +159,66 @@ async fn health() -> impl IntoResponse {
     (StatusCode::OK, Json(HealthResponse { ok: true }))
 }
 
+/// Liveness: the process is up and serving the admin API.
+async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+#[derive(Debug, Serialize)]
+struct ReadyResponse {
+    ready: bool,
+    pending: Vec<String>,
+}
+
+/// Readiness: every listener and tunnel endpoint has bound and the tunnel client (if any)
+/// is connected.
+async fn readyz(State(st): State<Arc<AdminState>>) -> impl IntoResponse {
+    let pending = st.readiness.pending();
+    let status = if pending.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(ReadyResponse {
+            ready: pending.is_empty(),
+            pending,
+        }),
+    )
+}
+
 async fn conns(State(st): State<Arc<AdminState>>) -> impl IntoResponse {
     let snap = st.sessions.snapshot();
     (StatusCode::OK, Json(snap))
 }
 
+#[derive(Debug, Serialize)]
+struct KillResponse {
+    killed: String,
+}
+
+async fn kill_session(
+    headers: HeaderMap,
+    AxumPath(id): AxumPath<String>,
+    State(st): State<Arc<AdminState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_mutation_auth(&headers, &st)?;
+    if !st.sessions.kill(&id) {
+        return Err(ApiError::not_found("session not found"));
+    }
+    tracing::info!(sid = %id, "admin: session killed");
+    Ok((StatusCode::OK, Json(KillResponse { killed: id })))
+}
+
+async fn recent_sessions(State(st): State<Arc<AdminState>>) -> impl IntoResponse {
+    (StatusCode::OK, Json(st.sessions.recent_sessions()))
+}
+
+async fn rejections(State(st): State<Arc<AdminState>>) -> impl IntoResponse {
+    (StatusCode::OK, Json(st.sessions.rejections()))
+}
+
 async fn tunnel_services(State(st): State<Arc<AdminState>>) -> impl IntoResponse {
     let snap = if let Some(mgr) = &st.tunnel {
         mgr.snapshot_services().await
@@ -147,9 +228,43 @@ async fn tunnel_services(State(st): State<Arc<AdminState>>) -> impl IntoResponse
     (StatusCode::OK, Json(snap))
 }
 
+async fn tunnel_clients(State(st): State<Arc<AdminState>>) -> impl IntoResponse {
+    let snap = if let Some(mgr) = &st.tunnel {
+        mgr.snapshot_clients().await
+    } else {
+        Vec::new()
+    };
+    (StatusCode::OK, Json(snap))
+}
+
+async fn metrics(State(st): State<Arc<AdminState>>) -> impl IntoResponse {
+    let mut body = st.sessions.render_prometheus();
+    body.push_str(&st.reload_metrics.render_prometheus());
+    if let Some(mgr) = &st.tunnel {
+        body.push_str(&mgr.metrics().render_prometheus());
+    }
+    if let Some(router) = &st.router {
+        body.push_str(&router.render_prometheus());
+    }
+    if let Some(pool) = &st.buffer_pool {
+        body.push_str(&pool.render_prometheus());
+    }
+    body.push_str(&crate::prism::middleware::render_prometheus());
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
+/// How long `POST /reload` waits for the reload loop to report back.
+const RELOAD_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Serialize)]
 struct ReloadResponse {
     seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation: Option<u64>,
 }
 
 async fn reload(
@@ -158,12 +273,42 @@ async fn reload(
 ) -> Result<impl IntoResponse, ApiError> {
     require_mutation_auth(&headers, &st)?;
 
-    let mut next = (*st.reload_tx.borrow()).clone();
-    next.next();
-    let seq = next.seq;
-    let _ = st.reload_tx.send(next);
+    let mut seq = 0;
+    st.reload_tx.send_modify(|sig| {
+        sig.next();
+        seq = sig.seq;
+    });
 
-    Ok((StatusCode::OK, Json(ReloadResponse { seq })))
+    let Some(mut done) = st.reload_done.clone() else {
+        return Ok((
+            StatusCode::OK,
+            Json(ReloadResponse {
+                seq,
+                generation: None,
+            }),
+        ));
+    };
+    // The loop publishes the signal it has consumed, so any outcome at or past `seq`
+    // reflects the config as of this request.
+    let outcome =
+        match tokio::time::timeout(RELOAD_ACK_TIMEOUT, done.wait_for(|o| o.seq >= seq)).await {
+            Ok(Ok(outcome)) => outcome.clone(),
+            Ok(Err(_)) => return Err(ApiError::unavailable("reload loop is not running")),
+            Err(_) => return Err(ApiError::timeout("reload did not finish in time")),
+        };
+    if let Some(err) = outcome.error {
+        return Err(ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: err,
+        });
+    }
+    Ok((
+        StatusCode::OK,
+        Json(ReloadResponse {
+            seq,
+            generation: Some(outcome.generation),
+        }),
+    ))
 }
 
 #[derive(Debug, Serialize)]
@@ -331,6 +476,20 @@ impl ApiError {
         }
     }
 
+    fn unavailable(message: &str) -> Self {
+        Self {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: message.to_string(),
+        }
+    }
+
+    fn timeout(message: &str) -> Self {
+        Self {
+            status: StatusCode::GATEWAY_TIMEOUT,
+            message: message.to_string(),
+        }
+    }
+
     fn bad_request(err: anyhow::Error) -> Self {
         Self {
             status: StatusCode::BAD_REQUEST,
@@ -351,7 +510,69 @@ impl IntoResponse for ApiError {
     }
 }
 
+/// Gate every route behind `admin_auth_token` / `admin_basic_auth` when either is set.
+///
+/// `/healthz` stays open for liveness probes, and `/managed/*` requests carry the panel or
+/// worker token instead, which their handlers check.
+async fn require_admin_auth(
+    State(st): State<Arc<AdminState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let path = req.uri().path();
+    // The whole `/managed/` prefix is exempt, not just the registered routes: each managed
+    // handler checks the panel or worker token itself, and an unmatched `/managed/*` path
+    // only reaches the frontend fallback, which serves the public panel bundle.
+    if !admin_auth_enabled(&st)
+        || path == "/healthz"
+        || path.starts_with("/managed/")
+        || has_admin_credentials(req.headers(), &st)
+    {
+        return next.run(req).await;
+    }
+    let mut res = ApiError::unauthorized("admin credentials required").into_response();
+    if st.auth.basic_auth.is_some() {
+        res.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            header::HeaderValue::from_static("Basic realm=\"prism\""),
+        );
+    }
+    res
+}
+
+fn admin_auth_enabled(st: &AdminState) -> bool {
+    st.auth.admin_token.is_some() || st.auth.basic_auth.is_some()
+}
+
+fn has_admin_credentials(headers: &HeaderMap, st: &AdminState) -> bool {
+    use base64::Engine as _;
+
+    let Some(value) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    if let Some(expected) = &st.auth.admin_token
+        && let Some(token) = value.strip_prefix("Bearer ")
+    {
+        return secret_eq(token.trim().as_bytes(), expected.as_bytes());
+    }
+    if let Some(expected) = &st.auth.basic_auth
+        && let Some(encoded) = value.strip_prefix("Basic ")
+    {
+        return base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .is_ok_and(|decoded| secret_eq(&decoded, expected.as_bytes()));
+    }
+    false
+}
+
 fn require_mutation_auth(headers: &HeaderMap, st: &AdminState) -> Result<(), ApiError> {
+    // Already checked by `require_admin_auth`, and the header can't carry a second token.
+    if admin_auth_enabled(st) && has_admin_credentials(headers, st) {
+        return Ok(());
+    }
     if let Some(token) = st
         .auth
         .panel_token
@@ -392,8 +613,309 @@ fn require_bearer(headers: &HeaderMap, expected: &str) -> Result<(), ApiError> {
     let Some(token) = value.strip_prefix("Bearer ") else {
         return Err(ApiError::unauthorized("expected Bearer token"));
     };
-    if token.trim() != expected {
+    if !secret_eq(token.trim().as_bytes(), expected.as_bytes()) {
         return Err(ApiError::unauthorized("invalid bearer token"));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use super::*;
+    use crate::prism::{
+        bufpool, config, listeners, proxy, router,
+        tunnel::{
+            manager::Manager,
+            protocol::RegisteredService,
+            transport::{BoxedStream, TransportSession},
+        },
+    };
+
+    struct FakeSession;
+
+    #[async_trait::async_trait]
+    impl TransportSession for FakeSession {
+        async fn open_stream(&self) -> anyhow::Result<BoxedStream> {
+            anyhow::bail!("not implemented")
+        }
+
+        async fn accept_stream(&self) -> anyhow::Result<BoxedStream> {
+            anyhow::bail!("not implemented")
+        }
+
+        async fn close(&self) {}
+
+        fn remote_addr(&self) -> Option<SocketAddr> {
+            Some("203.0.113.7:40001".parse().unwrap())
+        }
+
+        fn local_addr(&self) -> Option<SocketAddr> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn tunnel_endpoints_list_registered_clients() {
+        let mgr = Arc::new(Manager::new());
+        mgr.register_client(
+            "home-1".into(),
+            Arc::new(FakeSession),
+            vec![RegisteredService {
+                name: "mc".into(),
+                proto: "tcp".into(),
+                local_addr: "127.0.0.1:25565".into(),
+                route_only: false,
+                remote_addr: ":25565".into(),
+                masquerade_host: String::new(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: String::new(),
+            }],
+        )
+        .await
+        .unwrap();
+
+        let (reload_tx, _) = watch::channel(telemetry::ReloadSignal::new());
+        let app = build_router(AdminState {
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            config_path: PathBuf::from("prism.toml"),
+            reload_tx,
+            reload_done: None,
+            tunnel: Some(mgr),
+            router: None,
+            buffer_pool: None,
+            readiness: Arc::new(telemetry::Readiness::default()),
+            reload_metrics: Arc::new(telemetry::ReloadMetrics::default()),
+            auth: AdminAuth::default(),
+            management: None,
+            worker: None,
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let get = |path: &str| {
+            let url = format!("http://{addr}{path}");
+            async move {
+                reqwest::get(url)
+                    .await
+                    .expect("request")
+                    .json::<serde_json::Value>()
+                    .await
+                    .expect("json")
+            }
+        };
+
+        let clients = get("/tunnel/clients").await;
+        assert_eq!(clients[0]["client_id"], "home-1");
+        assert_eq!(clients[0]["remote"], "203.0.113.7:40001");
+        assert_eq!(clients[0]["draining"], false);
+        assert_eq!(clients[0]["services"][0]["name"], "mc");
+        assert_eq!(clients[0]["services"][0]["proto"], "tcp");
+        assert_eq!(clients[0]["services"][0]["remote_addr"], ":25565");
+        assert_eq!(clients[0]["primary_for"], serde_json::json!(["mc"]));
+
+        let services = get("/tunnel/services").await;
+        assert_eq!(services[0]["client_id"], "home-1");
+        assert_eq!(services[0]["service"]["name"], "mc");
+        assert_eq!(services[0]["primary"], true);
+    }
+
+    #[tokio::test]
+    async fn readyz_waits_for_listeners_to_bind() {
+        let cfg = config::empty_managed_runtime_config();
+        let readiness = Arc::new(telemetry::Readiness::default());
+        let mut set = listeners::ListenerSet::new(listeners::ListenerDeps {
+            router: Arc::new(router::Router::new(Vec::new())),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: Arc::new(Manager::new()),
+            runtime: Arc::new(tokio::sync::RwLock::new(
+                proxy::TcpRuntimeConfig::from_config(&cfg),
+            )),
+            buffer_pool: Arc::new(bufpool::BufferPool::new(0)),
+            readiness: readiness.clone(),
+            in_flight: Arc::default(),
+        });
+
+        let (reload_tx, _) = watch::channel(telemetry::ReloadSignal::new());
+        let app = build_router(AdminState {
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            config_path: PathBuf::from("prism.toml"),
+            reload_tx,
+            reload_done: None,
+            tunnel: None,
+            router: None,
+            buffer_pool: None,
+            readiness,
+            reload_metrics: Arc::new(telemetry::ReloadMetrics::default()),
+            auth: AdminAuth::default(),
+            management: None,
+            worker: None,
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let readyz = || async move {
+            reqwest::get(format!("http://{addr}/readyz"))
+                .await
+                .expect("request")
+        };
+
+        let healthz = reqwest::get(format!("http://{addr}/healthz"))
+            .await
+            .expect("request");
+        assert_eq!(healthz.status(), reqwest::StatusCode::OK);
+
+        // Registered but not yet spawned, so not bound.
+        let serve = set
+            .start(
+                &config::ProxyListenerConfig {
+                    listen_addr: "127.0.0.1:0".into(),
+                    protocol: "tcp".into(),
+                    upstream: String::new(),
+                    source_ip: None,
+                    mode: config::ListenerMode::Proxy,
+                    status_json: String::new(),
+                    health_probes: Vec::new(),
+                },
+                proxy::UdpRuntimeConfig::default(),
+            )
+            .expect("tcp listener");
+        let res = readyz().await;
+        assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = res.json().await.expect("json");
+        assert_eq!(
+            body["pending"],
+            serde_json::json!(["tcp listener 127.0.0.1:0"])
+        );
+
+        tokio::spawn(serve);
+        let mut status = reqwest::StatusCode::SERVICE_UNAVAILABLE;
+        for _ in 0..50 {
+            status = readyz().await.status();
+            if status == reqwest::StatusCode::OK {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(status, reqwest::StatusCode::OK);
+        set.stop_all();
+    }
+
+    #[tokio::test]
+    async fn admin_auth_guards_every_route_but_healthz() {
+        use base64::Engine as _;
+
+        let spawn = |auth: AdminAuth| async move {
+            let (reload_tx, _) = watch::channel(telemetry::ReloadSignal::new());
+            let app = build_router(AdminState {
+                sessions: Arc::new(telemetry::SessionRegistry::new()),
+                config_path: PathBuf::from("prism.toml"),
+                reload_tx,
+                reload_done: None,
+                tunnel: None,
+                router: None,
+                buffer_pool: None,
+                readiness: Arc::new(telemetry::Readiness::default()),
+                reload_metrics: Arc::new(telemetry::ReloadMetrics::default()),
+                auth,
+                management: None,
+                worker: None,
+            });
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            addr
+        };
+        let http = reqwest::Client::new();
+        let get = |addr: SocketAddr, path: &str, authorization: Option<String>| {
+            let mut req = http.get(format!("http://{addr}{path}"));
+            if let Some(value) = authorization {
+                req = req.header(header::AUTHORIZATION, value);
+            }
+            async move { req.send().await.expect("request") }
+        };
+
+        let addr = spawn(AdminAuth {
+            admin_token: Some("s3cret".into()),
+            basic_auth: Some("ops:hunter2".into()),
+            ..Default::default()
+        })
+        .await;
+        let basic = |creds: &str| {
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(creds)
+            )
+        };
+
+        let res = get(addr, "/conns", None).await;
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers()[reqwest::header::WWW_AUTHENTICATE],
+            "Basic realm=\"prism\""
+        );
+        for bad in ["Bearer nope".to_string(), basic("ops:wrong")] {
+            let res = get(addr, "/metrics", Some(bad)).await;
+            assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+        }
+        for good in ["Bearer s3cret".to_string(), basic("ops:hunter2")] {
+            let res = get(addr, "/conns", Some(good)).await;
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+        }
+        let res = get(addr, "/healthz", None).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+        // No credentials configured: everything stays open, as before.
+        let addr = spawn(AdminAuth::default()).await;
+        let res = get(addr, "/conns", None).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn metrics_export_handshake_buffer_and_wasm_pool_gauges() {
+        let pool = Arc::new(bufpool::BufferPool::new(4));
+        let (reload_tx, _) = watch::channel(telemetry::ReloadSignal::new());
+        let app = build_router(AdminState {
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            config_path: PathBuf::from("prism.toml"),
+            reload_tx,
+            reload_done: None,
+            tunnel: None,
+            router: None,
+            buffer_pool: Some(pool.clone()),
+            readiness: Arc::new(telemetry::Readiness::default()),
+            reload_metrics: Arc::new(telemetry::ReloadMetrics::default()),
+            auth: AdminAuth::default(),
+            management: None,
+            worker: None,
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let scrape = || async move {
+            reqwest::get(format!("http://{addr}/metrics"))
+                .await
+                .expect("request")
+                .text()
+                .await
+                .expect("body")
+        };
+
+        assert!(scrape().await.contains("prism_handshake_buffer_bytes 0\n"));
+        let buf = pool.get(1024);
+        let held = buf.capacity();
+        let body = scrape().await;
+        assert!(body.contains(&format!("prism_handshake_buffer_bytes {held}\n")));
+        assert!(body.contains("# TYPE prism_wasm_instances_pooled gauge"));
+        assert!(body.contains("# TYPE prism_wasm_instances_in_use gauge"));
+        drop(buf);
+        assert!(
+            scrape()
+                .await
+                .contains(&format!("prism_handshake_buffer_bytes {held}\n"))
+        );
+    }
+}
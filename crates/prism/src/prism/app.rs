@@ -3,10 +3,9 @@ use std::{net::SocketAddr, path::Path, path::PathBuf, sync::Arc, time::Duration}
 use anyhow::Context;
 use tokio::task::JoinSet;
 
-use crate::prism::middleware::MiddlewareProvider;
 use crate::prism::{
-    admin, config, logging, managed, middleware, net, proxy, router, runtime_paths, telemetry,
-    tunnel,
+    admin, bufpool, config, geoip, health, listeners, logging, managed, middleware, net, proxy,
+    router, runtime_paths, telemetry, tunnel,
 };
 
 pub async fn run(
@@ -24,6 +23,7 @@ pub async fn run(
         .with_context(|| format!("load config: {}", resolved.path.display()))?;
 
     let logrt = logging::init(&bootstrap_cfg.logging)?;
+    let log_level = logrt.level_handle();
     let _logrt_guard = logrt; // keep alive
 
     if created {
@@ -71,14 +71,13 @@ pub async fn run(
 
     if let Some(worker_agent) = &worker_agent
         && worker_agent.connection_mode() == config::ManagedConnectionMode::Active
+        && let Err(err) = worker_agent.sync_once().await
     {
-        if let Err(err) = worker_agent.sync_once().await {
-            tracing::warn!(
-                node_id = %bootstrap_cfg.managed.worker.as_ref().expect("worker config present").node_id,
-                err = %err,
-                "managed: initial worker sync failed; starting from persisted state"
-            );
-        }
+        tracing::warn!(
+            node_id = %bootstrap_cfg.managed.worker.as_ref().expect("worker config present").node_id,
+            err = %err,
+            "managed: initial worker sync failed; starting from persisted state"
+        );
     }
 
     let startup_managed_cfg = if let Some(worker_agent) = &worker_agent {
@@ -135,24 +134,55 @@ pub async fn run(
         "prism: starting"
     );
 
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "fuchsia")))]
+    if !cfg.tcp_user_timeout.is_zero() {
+        tracing::warn!("prism: tcp_user_timeout_ms is not supported on this platform; ignoring");
+    }
+
     // Shared state for admin endpoints.
-    let sessions = Arc::new(telemetry::SessionRegistry::new());
-    let tunnel_manager = Arc::new(tunnel::manager::Manager::new());
+    let sessions = Arc::new(
+        telemetry::SessionRegistry::new()
+            .with_geo(geoip::GeoDb::open_or_warn(&cfg.geoip_db))
+            .with_id_format(cfg.session_id_format, cfg.session_id_client_hash),
+    );
+    let tunnel_manager = Arc::new(
+        tunnel::manager::Manager::new()
+            .with_primary_grace(cfg.tunnel.primary_grace)
+            .with_dial_service_wait(cfg.tunnel.dial_service_wait),
+    );
+    let buffer_pool = Arc::new(bufpool::BufferPool::new(cfg.handshake_buffer_pool));
+    let readiness = Arc::new(telemetry::Readiness::default());
+    // Connections the proxy listeners accepted whose handler is still running; shutdown
+    // drains these.
+    let in_flight = Arc::new(telemetry::ConnectionCounter::default());
+    let reload_metrics = Arc::new(telemetry::ReloadMetrics::default());
+    // Covers the gap until every listener below has registered its own check.
+    let startup = readiness.check("startup");
 
     // Routing stack.
     let routes_with_middlewares = build_routes_with_middlewares(&cfg, &paths.middleware_dir)?;
     let rtr = Arc::new(router::Router::new(routes_with_middlewares));
 
-    let tcp_runtime = Arc::new(tokio::sync::RwLock::new(proxy::TcpRuntimeConfig {
-        max_header_bytes: cfg.max_header_bytes,
-        handshake_timeout: cfg.timeouts.handshake_timeout,
-        idle_timeout: cfg.timeouts.idle_timeout,
-        upstream_dial_timeout: cfg.upstream_dial_timeout,
-        buffer_size: cfg.buffer_size,
-        proxy_protocol_v2: cfg.proxy_protocol_v2,
-    }));
+    let tcp_runtime = Arc::new(tokio::sync::RwLock::new(
+        proxy::TcpRuntimeConfig::from_config(&cfg),
+    ));
+    sessions.set_connection_limit(cfg.max_connections);
+
+    let listeners = Arc::new(tokio::sync::Mutex::new(listeners::ListenerSet::new(
+        listeners::ListenerDeps {
+            router: rtr.clone(),
+            sessions: sessions.clone(),
+            tunnel_manager: tunnel_manager.clone(),
+            runtime: tcp_runtime.clone(),
+            buffer_pool: buffer_pool.clone(),
+            readiness: readiness.clone(),
+            in_flight: in_flight.clone(),
+        },
+    )));
 
     let (reload_tx, reload_rx) = tokio::sync::watch::channel(telemetry::ReloadSignal::new());
+    let (reload_done_tx, reload_done_rx) =
+        tokio::sync::watch::channel(telemetry::ReloadOutcome::default());
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
     let mut tasks = JoinSet::new();
@@ -163,8 +193,13 @@ pub async fn run(
         let static_cfg = cfg.clone();
         let router = rtr.clone();
         let runtime = tcp_runtime.clone();
+        let sessions = sessions.clone();
+        let listeners = listeners.clone();
         let middleware_dir = paths.middleware_dir.clone();
+        let log_level = log_level.clone();
+        let reload_metrics = reload_metrics.clone();
         let mut reload_rx = reload_rx.clone();
+        let reload_done = reload_done_tx;
         let mut shutdown = shutdown_rx.clone();
         let mut enabled = cfg.reload.enabled;
         let mut poll = cfg.reload.poll_interval;
@@ -176,7 +211,12 @@ pub async fn run(
                 middleware_dir,
                 router,
                 runtime,
+                sessions,
+                listeners,
+                log_level,
+                reload_metrics,
                 &mut reload_rx,
+                &reload_done,
                 &mut shutdown,
                 &mut enabled,
                 &mut poll,
@@ -186,6 +226,19 @@ pub async fn run(
         });
     }
 
+    // SIGUSR1 diagnostics dump (Unix only).
+    #[cfg(unix)]
+    {
+        let sessions = sessions.clone();
+        let router = rtr.clone();
+        let buffer_pool = buffer_pool.clone();
+        let shutdown = shutdown_rx.clone();
+        tasks.spawn(async move {
+            dump_on_sigusr1(sessions, router, buffer_pool, shutdown).await;
+            Ok(())
+        });
+    }
+
     // Admin server.
     if admin_enabled {
         let admin_addr = net::normalize_bind_addr(&cfg.admin_addr);
@@ -197,7 +250,13 @@ pub async fn run(
             sessions: sessions.clone(),
             config_path: resolved.path.clone(),
             reload_tx: reload_tx.clone(),
+            // Workers don't run the reload loop, so there is nothing to wait for.
+            reload_done: (cfg.role != config::PrismRole::Worker).then(|| reload_done_rx.clone()),
             tunnel: Some(tunnel_manager.clone()),
+            router: Some(rtr.clone()),
+            buffer_pool: Some(buffer_pool.clone()),
+            readiness: readiness.clone(),
+            reload_metrics: reload_metrics.clone(),
             auth: admin::AdminAuth {
                 panel_token: management_plane
                     .as_ref()
@@ -209,6 +268,8 @@ pub async fn run(
                         .as_ref()
                         .map(|agent| agent.auth_token().to_string())
                 },
+                admin_token: Some(cfg.admin_auth_token.clone()).filter(|t| !t.is_empty()),
+                basic_auth: Some(cfg.admin_basic_auth.clone()).filter(|b| !b.is_empty()),
             },
             management: management_plane.clone(),
             worker: worker_agent.clone(),
@@ -218,59 +279,20 @@ pub async fn run(
         tasks.spawn(async move { admin::serve_with_shutdown(addr, admin_state, shutdown).await });
     }
 
+    // Active upstream health checks (idle unless `health_check` is enabled).
+    if proxy_enabled {
+        let router = rtr.clone();
+        let runtime = tcp_runtime.clone();
+        let shutdown = shutdown_rx.clone();
+        tasks.spawn(async move { health::run(router, runtime, shutdown).await });
+    }
+
     // Proxy listeners.
     if proxy_enabled {
+        let mut set = listeners.lock().await;
         for l in &cfg.listeners {
-            match l.protocol.as_str() {
-                "tcp" => {
-                    let listen_addr = l.listen_addr.clone();
-                    let upstream = l.upstream.clone();
-                    let shutdown = shutdown_rx.clone();
-
-                    let handler = if upstream.trim().is_empty() {
-                        proxy::TcpHandler::routing(proxy::TcpRoutingHandlerOptions {
-                            router: rtr.clone(),
-                            sessions: sessions.clone(),
-                            tunnel_manager: Some(tunnel_manager.clone()),
-                            runtime: tcp_runtime.clone(),
-                        })
-                    } else {
-                        proxy::TcpHandler::forward(proxy::TcpForwardHandlerOptions {
-                            upstream,
-                            sessions: sessions.clone(),
-                            tunnel_manager: Some(tunnel_manager.clone()),
-                            runtime: tcp_runtime.clone(),
-                        })
-                    };
-
-                    tasks.spawn(async move {
-                        proxy::serve_tcp_with_shutdown(&listen_addr, handler, shutdown).await
-                    });
-                }
-                "udp" => {
-                    let listen_addr = l.listen_addr.clone();
-                    let upstream = l.upstream.clone();
-                    let shutdown = shutdown_rx.clone();
-
-                    if upstream.trim().is_empty() {
-                        tracing::warn!(listen_addr = %listen_addr, "udp listener missing upstream; skipping");
-                        continue;
-                    }
-
-                    let opts = proxy::UdpForwardOptions {
-                        upstream,
-                        sessions: sessions.clone(),
-                        tunnel_manager: Some(tunnel_manager.clone()),
-                        idle_timeout: cfg.timeouts.idle_timeout,
-                    };
-
-                    tasks.spawn(async move {
-                        proxy::serve_udp_with_shutdown(&listen_addr, opts, shutdown).await
-                    });
-                }
-                other => {
-                    tracing::warn!(listen_addr = %l.listen_addr, protocol = %other, "unsupported listener protocol");
-                }
+            if let Some(serve) = set.start(l, proxy::UdpRuntimeConfig::from_config(&cfg)) {
+                tasks.spawn(serve);
             }
         }
     }
@@ -281,12 +303,16 @@ pub async fn run(
             let server = tunnel::server::Server::new(tunnel::server::ServerOptions {
                 listen_addr: ep.listen_addr.clone(),
                 transport: ep.transport.clone(),
-                auth_token: cfg.tunnel.auth_token.clone(),
+                auth_tokens: tunnel_auth_tokens(&cfg.tunnel),
                 quic: tunnel::server::QuicServerOptions {
                     cert_file: ep.quic.cert_file.clone(),
                     key_file: ep.quic.key_file.clone(),
+                    client_ca_file: ep.quic.client_ca_file.clone(),
+                    tls: cfg.tls.clone(),
                 },
+                accept_queue_depth: cfg.tunnel.accept_queue_depth,
                 manager: tunnel_manager.clone(),
+                ready: Some(readiness.check(format!("tunnel endpoint {}", ep.listen_addr))),
             })?;
 
             let shutdown = shutdown_rx.clone();
@@ -317,6 +343,9 @@ pub async fn run(
                 route_only: s.route_only,
                 remote_addr: s.remote_addr.clone(),
                 masquerade_host: s.masquerade_host.clone(),
+                rate_limit_bytes_per_sec: s.rate_limit_bytes_per_sec,
+                rate_limit_scope: s.rate_limit_scope,
+                dial_policy: s.dial_policy.clone(),
             })
             .collect::<Vec<_>>();
 
@@ -326,10 +355,17 @@ pub async fn run(
             auth_token: cfg.tunnel.auth_token.clone(),
             services,
             dial_timeout: cc.dial_timeout,
+            heartbeat_interval: cc.heartbeat_interval,
+            drain_timeout: cc.drain_timeout,
             quic: tunnel::client::QuicClientOptions {
                 server_name: cc.quic.server_name.clone(),
                 insecure_skip_verify: cc.quic.insecure_skip_verify,
+                client_cert_file: cc.quic.client_cert_file.clone(),
+                client_key_file: cc.quic.client_key_file.clone(),
+                tls: cfg.tls.clone(),
             },
+            accept_queue_depth: cfg.tunnel.accept_queue_depth,
+            ready: Some(readiness.check("tunnel client")),
         })?;
 
         let client = Arc::new(client);
@@ -343,6 +379,8 @@ pub async fn run(
                 middleware_dir: paths.middleware_dir.clone(),
                 router: rtr.clone(),
                 runtime: tcp_runtime.clone(),
+                sessions: sessions.clone(),
+                reload_metrics: reload_metrics.clone(),
             })
             .await;
 
@@ -360,40 +398,74 @@ pub async fn run(
         }
     }
 
-    // Wait for shutdown signal (Ctrl-C / SIGTERM) or unexpected task termination.
-    tokio::select! {
-        _ = shutdown_signal() => {
-            tracing::info!("shutdown: signal");
-            let _ = shutdown_tx.send(true);
-        }
-        res = tasks.join_next() => {
-            if let Some(res) = res {
-                match res {
-                    Ok(Ok(())) => {}
-                    Ok(Err(err)) => {
-                        let _ = shutdown_tx.send(true);
-                        return Err(err);
-                    }
-                    Err(join_err) => return Err(join_err.into()),
-                }
+    startup.ready();
+
+    // Wait for shutdown signal (Ctrl-C / SIGTERM) or a failed task. Tasks that finish
+    // cleanly, like a listener a hot reload removed, don't stop the process.
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+    loop {
+        tokio::select! {
+            _ = &mut signal => {
+                tracing::info!("shutdown: signal");
+                let _ = shutdown_tx.send(true);
+                break;
             }
+            res = tasks.join_next() => match res {
+                Some(Ok(Ok(()))) => {}
+                Some(Ok(Err(err))) => {
+                    let _ = shutdown_tx.send(true);
+                    return Err(err);
+                }
+                Some(Err(join_err)) => return Err(join_err.into()),
+                None => break,
+            },
         }
     }
 
+    // Listeners have their own stop signals (so reloads can stop them individually).
+    listeners.lock().await.stop_all();
+
     // Drain tasks: exit as soon as they complete; only enforce a timeout if something hangs.
     let drain = async {
         while let Some(_res) = tasks.join_next().await {
             // Best-effort: tasks are expected to observe shutdown; ignore errors during teardown.
         }
+        // Listeners started by reloads. The reload loop has exited, so the set is final;
+        // stop again in case a reload started one after the first `stop_all`.
+        let mut reloaded = {
+            let mut set = listeners.lock().await;
+            set.stop_all();
+            set.take_reloaded()
+        };
+        while reloaded.join_next().await.is_some() {}
     };
 
-    // Hard cap so `docker stop` doesn't stall indefinitely.
-    let drain_timeout = Duration::from_secs(5);
-    if tokio::time::timeout(drain_timeout, drain).await.is_err() {
+    // `shutdown_drain_timeout_ms` (the latest reloaded value) bounds the whole drain so
+    // `docker stop` doesn't stall indefinitely, with room for the tunnel client's own drain.
+    let tunnel_drain = cfg
+        .tunnel
+        .client
+        .as_ref()
+        .filter(|_| tunnel_client_enabled)
+        .map_or(Duration::ZERO, |c| c.drain_timeout);
+    let session_drain = tcp_runtime.read().await.shutdown_drain_timeout;
+    let deadline =
+        tokio::time::Instant::now() + session_drain.max(tunnel_drain + Duration::from_secs(1));
+    if tokio::time::timeout_at(deadline, drain).await.is_err() {
         tasks.abort_all();
         while tasks.join_next().await.is_some() {}
     }
 
+    // The listeners no longer accept; connections already being handled get until the
+    // deadline, and whatever is left is closed when the process exits.
+    if !in_flight.drain(deadline).await {
+        tracing::warn!(
+            connections = in_flight.active(),
+            "shutdown: drain timeout reached; closing remaining sessions"
+        );
+    }
+
     Ok(())
 }
 
@@ -417,6 +489,83 @@ async fn shutdown_signal() {
     }
 }
 
+/// Log a diagnostics dump every time the process receives SIGUSR1, until shutdown.
+#[cfg(unix)]
+async fn dump_on_sigusr1(
+    sessions: Arc<telemetry::SessionRegistry>,
+    router: Arc<router::Router>,
+    buffer_pool: Arc<bufpool::BufferPool>,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut usr1 = match signal(SignalKind::user_defined1()) {
+        Ok(s) => Some(s),
+        Err(err) => {
+            tracing::warn!(err = %err, "prism: failed to install SIGUSR1 handler; diagnostics dump disabled");
+            None
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => return,
+            Some(()) = async { usr1.as_mut()?.recv().await } => {
+                let sessions = sessions.clone();
+                let router = router.clone();
+                let pool = buffer_pool.stats();
+                // Render off the async workers; large session tables shouldn't stall accepts.
+                tokio::spawn(async move {
+                    match tokio::task::spawn_blocking(move || render_diagnostics(&sessions, &router, pool)).await {
+                        Ok(dump) => tracing::info!("prism: diagnostics dump (SIGUSR1)\n{dump}"),
+                        Err(err) => tracing::warn!(err = %err, "prism: diagnostics dump failed"),
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Human-readable snapshot of active sessions, the route table and buffer pool usage.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn render_diagnostics(
+    sessions: &telemetry::SessionRegistry,
+    router: &router::Router,
+    pool: bufpool::BufferPoolStats,
+) -> String {
+    use std::fmt::Write;
+
+    let snapshot = sessions.snapshot();
+    let routes = router.describe();
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "handshake buffers: in_use={} pooled={} pooled_bytes={}",
+        pool.in_use, pool.pooled, pool.pooled_bytes
+    );
+    let _ = writeln!(out, "sessions: {}", snapshot.len());
+    for s in &snapshot {
+        let _ = write!(
+            out,
+            "  {} client={} host={} upstream={} started_at_unix_ms={}",
+            s.id, s.client, s.host, s.upstream, s.started_at_unix_ms
+        );
+        if let Some(country) = &s.geo.country {
+            let _ = write!(out, " country={country}");
+        }
+        if let Some(asn) = s.geo.asn {
+            let _ = write!(out, " asn={asn}");
+        }
+        out.push('\n');
+    }
+    let _ = writeln!(out, "routes: {}", routes.len());
+    for r in &routes {
+        let _ = writeln!(out, "  {r}");
+    }
+    out
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn reload_loop(
     config_path: PathBuf,
@@ -424,12 +573,22 @@ async fn reload_loop(
     middleware_dir: PathBuf,
     router: Arc<router::Router>,
     runtime: Arc<tokio::sync::RwLock<proxy::TcpRuntimeConfig>>,
+    sessions: telemetry::SharedSessions,
+    listeners: Arc<tokio::sync::Mutex<listeners::ListenerSet>>,
+    log_level: logging::LogLevelHandle,
+    reload_metrics: Arc<telemetry::ReloadMetrics>,
     reload_rx: &mut tokio::sync::watch::Receiver<telemetry::ReloadSignal>,
+    reload_done: &tokio::sync::watch::Sender<telemetry::ReloadOutcome>,
     shutdown: &mut tokio::sync::watch::Receiver<bool>,
     enabled: &mut bool,
     poll_interval: &mut Duration,
 ) {
     let mut last_sig = file_sig(&config_path).ok();
+    // Edited middleware files are picked up like config edits: the routes are rebuilt and
+    // only the changed modules get recompiled.
+    let mut last_mw_sig = middleware::middleware_dir_signature(&middleware_dir);
+    // So are edits to included route/listener files, and files newly matching a glob.
+    let mut last_inc_sig = config::include_signature(&config_path);
 
     loop {
         let sleep_dur = if *enabled {
@@ -445,16 +604,25 @@ async fn reload_loop(
                 }
             }
             _ = reload_rx.changed() => {
-                apply_reload(
+                // Taken before the file is read, so the outcome answers every signal up to here.
+                let seq = reload_rx.borrow_and_update().seq;
+                let res = apply_reload(
                     &config_path,
                     &static_cfg,
                     &middleware_dir,
                     &router,
                     &runtime,
+                    &sessions,
+                    &listeners,
+                    &log_level,
+                    &reload_metrics,
                     enabled,
                     poll_interval,
                 ).await;
+                publish_reload_outcome(reload_done, seq, &reload_metrics, res);
                 last_sig = file_sig(&config_path).ok();
+                last_mw_sig = middleware::middleware_dir_signature(&middleware_dir);
+                last_inc_sig = config::include_signature(&config_path);
             }
             _ = tokio::time::sleep(sleep_dur) => {
                 if !*enabled {
@@ -464,38 +632,74 @@ async fn reload_loop(
                     Ok(s) => s,
                     Err(_) => continue,
                 };
-                if last_sig.is_some_and(|prev| prev == sig) {
+                let mw_sig = middleware::middleware_dir_signature(&middleware_dir);
+                let inc_sig = config::include_signature(&config_path);
+                if last_sig.is_some_and(|prev| prev == sig)
+                    && mw_sig == last_mw_sig
+                    && inc_sig == last_inc_sig
+                {
                     continue;
                 }
-                apply_reload(
+                // Taken before the file is read, so the outcome answers every signal up to here.
+                let seq = reload_rx.borrow_and_update().seq;
+                let res = apply_reload(
                     &config_path,
                     &static_cfg,
                     &middleware_dir,
                     &router,
                     &runtime,
+                    &sessions,
+                    &listeners,
+                    &log_level,
+                    &reload_metrics,
                     enabled,
                     poll_interval,
                 ).await;
+                publish_reload_outcome(reload_done, seq, &reload_metrics, res);
                 last_sig = Some(sig);
+                last_mw_sig = middleware::middleware_dir_signature(&middleware_dir);
+                last_inc_sig = config::include_signature(&config_path);
             }
         }
     }
 }
 
+/// Tell `POST /reload` callers waiting on signal `seq` (or earlier) how the reload went.
+/// Poll reloads publish too: they read the same file, so they answer a pending signal.
+fn publish_reload_outcome(
+    reload_done: &tokio::sync::watch::Sender<telemetry::ReloadOutcome>,
+    seq: u64,
+    reload_metrics: &telemetry::ReloadMetrics,
+    res: Result<(), String>,
+) {
+    reload_done.send_replace(telemetry::ReloadOutcome {
+        seq,
+        generation: reload_metrics.generation(),
+        error: res.err(),
+    });
+}
+
+/// Returns the error that kept the config from being applied.
+#[allow(clippy::too_many_arguments)]
 async fn apply_reload(
     config_path: &Path,
     static_cfg: &config::Config,
     middleware_dir: &Path,
     router: &Arc<router::Router>,
     runtime: &Arc<tokio::sync::RwLock<proxy::TcpRuntimeConfig>>,
+    sessions: &telemetry::SessionRegistry,
+    listeners: &tokio::sync::Mutex<listeners::ListenerSet>,
+    log_level: &logging::LogLevelHandle,
+    reload_metrics: &telemetry::ReloadMetrics,
     enabled: &mut bool,
     poll_interval: &mut Duration,
-) {
+) -> Result<(), String> {
     let cfg = match config::load_config(config_path) {
         Ok(c) => c,
         Err(err) => {
             tracing::warn!(path=%config_path.display(), err=%err, "reload: config load failed");
-            return;
+            reload_metrics.record(false);
+            return Err(format!("{err:#}"));
         }
     };
 
@@ -507,20 +711,87 @@ async fn apply_reload(
         );
     }
 
-    let restart_reasons = config::restart_required_reasons(static_cfg, &cfg);
+    // In hot mode the running listeners track the config, so compare against them rather
+    // than the startup listener set.
+    let mut baseline = static_cfg.clone();
+    if reload_listeners(listeners, &cfg).await {
+        baseline.listeners = cfg.listeners.clone();
+    }
+
+    let restart_reasons = config::restart_required_reasons(&baseline, &cfg);
     if !restart_reasons.is_empty() {
         tracing::warn!(reasons = ?restart_reasons, "reload: restart required for static topology changes");
     }
 
-    if let Err(err) = apply_runtime_config_update(&cfg, middleware_dir, router, runtime).await {
+    if let Err(err) = apply_runtime_config_update(
+        &cfg,
+        middleware_dir,
+        router,
+        runtime,
+        sessions,
+        reload_metrics,
+    )
+    .await
+    {
         tracing::warn!(err=%err, "reload: hot-apply failed");
-        return;
+        reload_metrics.record(false);
+        return Err(format!("{err:#}"));
+    }
+
+    match log_level.set_level(&cfg.logging.level, cfg.logging.access_log) {
+        Ok(true) => tracing::info!(
+            level = %cfg.logging.level,
+            access_log = cfg.logging.access_log,
+            "reload: log level updated"
+        ),
+        Ok(false) => {}
+        Err(err) => tracing::warn!(err = %err, "reload: log level update failed"),
     }
 
     *enabled = cfg.reload.enabled;
     *poll_interval = cfg.reload.poll_interval;
 
+    reload_metrics.record(true);
     tracing::info!("reload: applied");
+    Ok(())
+}
+
+/// Bring the running proxy listeners in line with `cfg` when `reload.listener_mode = "hot"`.
+///
+/// Returns true if the listener set was reconciled (hot mode), false if left untouched.
+async fn reload_listeners(
+    listeners: &tokio::sync::Mutex<listeners::ListenerSet>,
+    cfg: &config::Config,
+) -> bool {
+    if cfg.reload.listener_mode != config::ListenerReloadMode::Hot {
+        return false;
+    }
+    let changes = listeners
+        .lock()
+        .await
+        .reconcile(&cfg.listeners, proxy::UdpRuntimeConfig::from_config(cfg))
+        .await;
+    if changes != listeners::ListenerChanges::default() {
+        tracing::info!(
+            added = changes.added,
+            removed = changes.removed,
+            "reload: listeners updated"
+        );
+    }
+    true
+}
+
+/// Tokens the tunnel server accepts: the shared `auth_token` (if set) plus `auth_tokens`.
+fn tunnel_auth_tokens(cfg: &config::TunnelConfig) -> std::collections::HashMap<String, String> {
+    let mut tokens: std::collections::HashMap<_, _> = cfg
+        .auth_tokens
+        .iter()
+        .map(|t| (t.token.clone(), t.label.clone()))
+        .collect();
+    if !cfg.auth_token.is_empty() {
+        tokens.insert(cfg.auth_token.clone(), String::new());
+    }
+    tokens
 }
 
 pub(crate) async fn apply_runtime_config_update(
@@ -528,17 +799,69 @@ pub(crate) async fn apply_runtime_config_update(
     middleware_dir: &Path,
     router: &Arc<router::Router>,
     runtime: &Arc<tokio::sync::RwLock<proxy::TcpRuntimeConfig>>,
+    sessions: &telemetry::SessionRegistry,
+    reload_metrics: &telemetry::ReloadMetrics,
 ) -> anyhow::Result<()> {
     let routes_with_middlewares = build_routes_with_middlewares(cfg, middleware_dir)?;
     router.update(routes_with_middlewares);
-    *runtime.write().await = proxy::TcpRuntimeConfig {
-        max_header_bytes: cfg.max_header_bytes,
-        handshake_timeout: cfg.timeouts.handshake_timeout,
-        idle_timeout: cfg.timeouts.idle_timeout,
-        upstream_dial_timeout: cfg.upstream_dial_timeout,
-        buffer_size: cfg.buffer_size,
-        proxy_protocol_v2: cfg.proxy_protocol_v2,
-    };
+    *runtime.write().await = proxy::TcpRuntimeConfig::from_config(cfg);
+    sessions.set_connection_limit(cfg.max_connections);
+    reload_metrics.bump_generation();
+    Ok(())
+}
+
+/// Validate the config the way startup would (parse it and compile every route's
+/// middleware chain and host patterns) without creating the config file, writing default
+/// middlewares into place or binding any sockets.
+pub fn check_config(
+    config_path: Option<PathBuf>,
+    middleware_dir: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let resolved = config::resolve_config_path(config_path)?;
+    if !resolved.path.exists() {
+        anyhow::bail!("config: {} does not exist", resolved.path.display());
+    }
+    let config_dir = resolved.path.parent().unwrap_or_else(|| Path::new("."));
+    let middleware_dir = runtime_paths::resolve_middleware_dir(config_dir, middleware_dir)?;
+
+    let cfg = config::load_config(&resolved.path)
+        .with_context(|| format!("load config: {}", resolved.path.display()))?;
+
+    // Startup materializes the default middlewares next to the user's; stage both in a
+    // scratch directory so the check sees the same set without touching the real one.
+    let staged = tempfile::Builder::new()
+        .prefix("prism-check-config-")
+        .tempdir()
+        .context("middleware: create scratch dir")?;
+    let res = stage_middlewares(&middleware_dir, staged.path())
+        .and_then(|()| build_routes_with_middlewares(&cfg, staged.path()))
+        .and_then(|routes| router::check_routes(&routes));
+    middleware::forget_cached_modules(staged.path(), &Default::default());
+    drop(staged);
+    res?;
+
+    println!(
+        "config ok: {} ({} listeners, {} routes)",
+        resolved.path.display(),
+        cfg.listeners.len(),
+        cfg.routes.len()
+    );
+    Ok(())
+}
+
+fn stage_middlewares(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dst)
+        .with_context(|| format!("middleware: create dir {}", dst.display()))?;
+    if let Ok(entries) = std::fs::read_dir(src) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() {
+                std::fs::copy(&path, dst.join(entry.file_name()))
+                    .with_context(|| format!("middleware: copy {}", path.display()))?;
+            }
+        }
+    }
+    middleware::materialize_default_middlewares(dst)?;
     Ok(())
 }
 
@@ -546,15 +869,27 @@ pub(crate) fn build_routes_with_middlewares(
     cfg: &config::Config,
     middleware_dir: &Path,
 ) -> anyhow::Result<Vec<(config::RouteConfig, middleware::SharedMiddlewareChain)>> {
-    let provider = middleware::FsWasmMiddlewareProvider::new(middleware_dir.to_path_buf());
-    let mut out = Vec::with_capacity(cfg.routes.len());
-    for (i, r) in cfg.routes.iter().enumerate() {
-        let chain = provider
-            .chain(&r.middlewares)
-            .with_context(|| format!("route[{}] build middleware chain", i))?;
-        out.push((r.clone(), chain));
-    }
-    Ok(out)
+    let options = cfg
+        .middleware_settings
+        .iter()
+        .map(|(name, s)| {
+            (
+                name.clone(),
+                middleware::WasmMiddlewareOptions {
+                    max_output_len: s.max_output_len,
+                    fuel_limit: s.fuel_limit,
+                    max_memory_pages: s.max_memory_pages,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+    let provider = middleware::FsWasmMiddlewareProvider::new(middleware_dir.to_path_buf())
+        .with_options(options)
+        .with_wasm_binaries(cfg.allow_wasm_binary);
+    let routes = router::build_routes(cfg, &provider)?;
+    provider.forget_unused_modules();
+    Ok(routes)
 }
 
 fn file_sig(path: &Path) -> anyhow::Result<(u64, u64)> {
@@ -567,3 +902,455 @@ fn file_sig(path: &Path) -> anyhow::Result<(u64, u64)> {
         .as_millis() as u64;
     Ok((m, len))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn build_routes_rejects_empty_middleware_chain() {
+        let mut cfg = config::empty_managed_runtime_config();
+        cfg.routes.push(config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["127.0.0.1:25566".into()],
+            middlewares: vec![" ".into()],
+            strategy: "sequential".into(),
+            ..Default::default()
+        });
+
+        let err = match build_routes_with_middlewares(&cfg, Path::new("unused")) {
+            Ok(_) => panic!("empty chain must be rejected"),
+            Err(err) => err,
+        };
+        assert!(err.to_string().contains("empty middleware chain"));
+    }
+
+    fn free_tcp_addr() -> String {
+        let ln = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        ln.local_addr().expect("addr").to_string()
+    }
+
+    fn tcp_listener(addr: &str) -> config::ProxyListenerConfig {
+        config::ProxyListenerConfig {
+            listen_addr: addr.to_string(),
+            protocol: "tcp".into(),
+            upstream: String::new(),
+            source_ip: None,
+            mode: config::ListenerMode::Proxy,
+            status_json: String::new(),
+            health_probes: Vec::new(),
+        }
+    }
+
+    /// Whether `addr` accepts connections within a short grace period.
+    async fn accepts(addr: &str) -> bool {
+        for _ in 0..50 {
+            if tokio::net::TcpStream::connect(addr).await.is_ok() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn reload_outcomes_are_counted() {
+        let dir = std::env::temp_dir().join(format!(
+            "prism-app-reload-metrics-{}-{}",
+            std::process::id(),
+            telemetry::now_unix_ms()
+        ));
+        std::fs::create_dir_all(&dir).expect("mkdir");
+        let config_path = dir.join("prism.toml");
+        let middleware_dir = dir.join("middlewares");
+        std::fs::write(&config_path, "admin_addr = \"\"\n").expect("write");
+
+        let cfg = config::load_config(&config_path).expect("load");
+        let router = Arc::new(router::Router::new(Vec::new()));
+        let runtime = Arc::new(tokio::sync::RwLock::new(
+            proxy::TcpRuntimeConfig::from_config(&cfg),
+        ));
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let listeners =
+            tokio::sync::Mutex::new(listeners::ListenerSet::new(listeners::ListenerDeps {
+                router: router.clone(),
+                sessions: sessions.clone(),
+                tunnel_manager: Arc::new(tunnel::manager::Manager::new()),
+                runtime: runtime.clone(),
+                buffer_pool: Arc::new(bufpool::BufferPool::new(0)),
+                readiness: Arc::new(telemetry::Readiness::default()),
+                in_flight: Arc::default(),
+            }));
+        let log_level = logging::LogLevelHandle::detached();
+        let metrics = telemetry::ReloadMetrics::default();
+        let (mut enabled, mut poll) = (true, Duration::from_secs(1));
+        apply_reload(
+            &config_path,
+            &cfg,
+            &middleware_dir,
+            &router,
+            &runtime,
+            &sessions,
+            &listeners,
+            &log_level,
+            &metrics,
+            &mut enabled,
+            &mut poll,
+        )
+        .await
+        .expect("reload");
+        assert_eq!(metrics.counts(), (1, 0));
+        assert_eq!(metrics.generation(), 1);
+
+        std::fs::write(&config_path, "admin_addr = [").expect("write");
+        apply_reload(
+            &config_path,
+            &cfg,
+            &middleware_dir,
+            &router,
+            &runtime,
+            &sessions,
+            &listeners,
+            &log_level,
+            &metrics,
+            &mut enabled,
+            &mut poll,
+        )
+        .await
+        .expect_err("broken config");
+        assert_eq!(metrics.counts(), (1, 1));
+        assert_eq!(metrics.generation(), 1);
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("prism_config_reload_total{result=\"success\"} 1"));
+        assert!(text.contains("prism_config_reload_total{result=\"failure\"} 1"));
+        assert!(text.contains("prism_config_generation 1"));
+        assert!(!text.contains("prism_config_last_reload_timestamp_seconds 0\n"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn admin_reload_reports_the_outcome() {
+        let dir = std::env::temp_dir().join(format!(
+            "prism-app-admin-reload-{}-{}",
+            std::process::id(),
+            telemetry::now_unix_ms()
+        ));
+        std::fs::create_dir_all(&dir).expect("mkdir");
+        let config_path = dir.join("prism.toml");
+        std::fs::write(&config_path, "admin_addr = \"\"\n").expect("write");
+
+        let cfg = config::load_config(&config_path).expect("load");
+        let router = Arc::new(router::Router::new(Vec::new()));
+        let runtime = Arc::new(tokio::sync::RwLock::new(
+            proxy::TcpRuntimeConfig::from_config(&cfg),
+        ));
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let readiness = Arc::new(telemetry::Readiness::default());
+        let listeners = Arc::new(tokio::sync::Mutex::new(listeners::ListenerSet::new(
+            listeners::ListenerDeps {
+                router: router.clone(),
+                sessions: sessions.clone(),
+                tunnel_manager: Arc::new(tunnel::manager::Manager::new()),
+                runtime: runtime.clone(),
+                buffer_pool: Arc::new(bufpool::BufferPool::new(0)),
+                readiness: readiness.clone(),
+                in_flight: Arc::default(),
+            },
+        )));
+        let reload_metrics = Arc::new(telemetry::ReloadMetrics::default());
+        let (reload_tx, mut reload_rx) =
+            tokio::sync::watch::channel(telemetry::ReloadSignal::new());
+        let (reload_done_tx, reload_done_rx) =
+            tokio::sync::watch::channel(telemetry::ReloadOutcome::default());
+        let (shutdown_tx, mut shutdown_rx) = tokio::sync::watch::channel(false);
+
+        let loop_task = tokio::spawn({
+            let config_path = config_path.clone();
+            let middleware_dir = dir.join("middlewares");
+            let sessions = sessions.clone();
+            let reload_metrics = reload_metrics.clone();
+            async move {
+                // Polling off: only the admin signal triggers reloads.
+                let (mut enabled, mut poll) = (false, Duration::from_secs(1));
+                reload_loop(
+                    config_path,
+                    cfg,
+                    middleware_dir,
+                    router,
+                    runtime,
+                    sessions,
+                    listeners,
+                    logging::LogLevelHandle::detached(),
+                    reload_metrics,
+                    &mut reload_rx,
+                    &reload_done_tx,
+                    &mut shutdown_rx,
+                    &mut enabled,
+                    &mut poll,
+                )
+                .await;
+            }
+        });
+
+        let app = admin::build_router(admin::AdminState {
+            sessions,
+            config_path: config_path.clone(),
+            reload_tx,
+            reload_done: Some(reload_done_rx),
+            tunnel: None,
+            router: None,
+            buffer_pool: None,
+            readiness,
+            reload_metrics,
+            auth: admin::AdminAuth::default(),
+            management: None,
+            worker: None,
+        });
+        let ln = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let _ = axum::serve(ln, app).await;
+        });
+        let client = reqwest::Client::new();
+
+        std::fs::write(&config_path, "admin_addr = \"\"\nmax_connections = 10\n").expect("write");
+        let res = client
+            .post(format!("http://{addr}/reload"))
+            .send()
+            .await
+            .expect("reload");
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = res.json().await.expect("json");
+        assert_eq!(body["seq"], 1);
+        assert_eq!(body["generation"], 1);
+
+        std::fs::write(&config_path, "admin_addr = [").expect("write");
+        let res = client
+            .post(format!("http://{addr}/reload"))
+            .send()
+            .await
+            .expect("reload");
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = res.json().await.expect("json");
+        assert!(!body["error"].as_str().unwrap_or_default().is_empty());
+
+        let _ = shutdown_tx.send(true);
+        let _ = loop_task.await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn reload_listener_mode_controls_listener_changes() {
+        let cfg = config::empty_managed_runtime_config();
+        let listeners =
+            tokio::sync::Mutex::new(listeners::ListenerSet::new(listeners::ListenerDeps {
+                router: Arc::new(router::Router::new(Vec::new())),
+                sessions: Arc::new(telemetry::SessionRegistry::new()),
+                tunnel_manager: Arc::new(tunnel::manager::Manager::new()),
+                runtime: Arc::new(tokio::sync::RwLock::new(
+                    proxy::TcpRuntimeConfig::from_config(&cfg),
+                )),
+                buffer_pool: Arc::new(bufpool::BufferPool::new(0)),
+                readiness: Arc::new(telemetry::Readiness::default()),
+                in_flight: Arc::default(),
+            }));
+
+        let old_addr = free_tcp_addr();
+        let serve = listeners
+            .lock()
+            .await
+            .start(&tcp_listener(&old_addr), proxy::UdpRuntimeConfig::default())
+            .expect("tcp listener");
+        tokio::spawn(serve);
+        assert!(accepts(&old_addr).await);
+
+        let new_addr = free_tcp_addr();
+        let mut next = cfg.clone();
+        next.listeners = vec![tcp_listener(&new_addr)];
+
+        // warn (default): nothing changes.
+        assert!(!reload_listeners(&listeners, &next).await);
+        assert!(accepts(&old_addr).await);
+        assert!(tokio::net::TcpStream::connect(&new_addr).await.is_err());
+
+        // hot: the old listener goes away and the new one comes up.
+        next.reload.listener_mode = config::ListenerReloadMode::Hot;
+        assert!(reload_listeners(&listeners, &next).await);
+        assert!(accepts(&new_addr).await);
+        assert!(tokio::net::TcpStream::connect(&old_addr).await.is_err());
+
+        // The reloaded listener is tracked, so shutdown can wait for it.
+        let mut reloaded = {
+            let mut set = listeners.lock().await;
+            set.stop_all();
+            set.take_reloaded()
+        };
+        assert_eq!(reloaded.len(), 1);
+        tokio::time::timeout(Duration::from_secs(5), reloaded.join_next())
+            .await
+            .expect("reloaded listener did not stop")
+            .expect("task")
+            .expect("join");
+    }
+
+    #[tokio::test]
+    async fn tls_listeners_answer_health_probes() {
+        let cfg = config::empty_managed_runtime_config();
+        let mut set = listeners::ListenerSet::new(listeners::ListenerDeps {
+            router: Arc::new(router::Router::new(Vec::new())),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: Arc::new(tunnel::manager::Manager::new()),
+            runtime: Arc::new(tokio::sync::RwLock::new(
+                proxy::TcpRuntimeConfig::from_config(&cfg),
+            )),
+            buffer_pool: Arc::new(bufpool::BufferPool::new(0)),
+            readiness: Arc::new(telemetry::Readiness::default()),
+            in_flight: Arc::default(),
+        });
+
+        let addr = free_tcp_addr();
+        let listener = config::ProxyListenerConfig {
+            protocol: "tls".into(),
+            health_probes: vec![config::HealthProbe {
+                pattern: b"PING\n".to_vec(),
+                reply: b"PONG\n".to_vec(),
+            }],
+            ..tcp_listener(&addr)
+        };
+        let serve = set
+            .start(&listener, proxy::UdpRuntimeConfig::default())
+            .expect("tls listener");
+        tokio::spawn(serve);
+        assert!(accepts(&addr).await);
+
+        let mut c = tokio::net::TcpStream::connect(&addr)
+            .await
+            .expect("connect");
+        c.write_all(b"PING\n").await.expect("write");
+        let mut reply = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), c.read_to_end(&mut reply))
+            .await
+            .expect("reply timeout")
+            .expect("read");
+        assert_eq!(reply, b"PONG\n");
+
+        set.stop_all();
+    }
+
+    #[tokio::test]
+    async fn reconcile_adds_a_listener_and_keeps_the_existing_one() {
+        let cfg = config::empty_managed_runtime_config();
+        let listeners =
+            tokio::sync::Mutex::new(listeners::ListenerSet::new(listeners::ListenerDeps {
+                router: Arc::new(router::Router::new(Vec::new())),
+                sessions: Arc::new(telemetry::SessionRegistry::new()),
+                tunnel_manager: Arc::new(tunnel::manager::Manager::new()),
+                runtime: Arc::new(tokio::sync::RwLock::new(
+                    proxy::TcpRuntimeConfig::from_config(&cfg),
+                )),
+                buffer_pool: Arc::new(bufpool::BufferPool::new(0)),
+                readiness: Arc::new(telemetry::Readiness::default()),
+                in_flight: Arc::default(),
+            }));
+
+        let first = free_tcp_addr();
+        let serve = listeners
+            .lock()
+            .await
+            .start(&tcp_listener(&first), proxy::UdpRuntimeConfig::default())
+            .expect("tcp listener");
+        tokio::spawn(serve);
+        assert!(accepts(&first).await);
+        let held = tokio::net::TcpStream::connect(&first)
+            .await
+            .expect("connect first");
+
+        let second = free_tcp_addr();
+        let wanted = vec![tcp_listener(&first), tcp_listener(&second)];
+        let changes = listeners
+            .lock()
+            .await
+            .reconcile(&wanted, proxy::UdpRuntimeConfig::default())
+            .await;
+        assert_eq!(
+            changes,
+            listeners::ListenerChanges {
+                added: 1,
+                removed: 0
+            }
+        );
+
+        assert!(accepts(&second).await);
+        assert!(accepts(&first).await);
+        // A connection accepted before the reload is untouched.
+        let mut probe = [0u8; 1];
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), held.peek(&mut probe))
+                .await
+                .is_err(),
+            "existing connection was closed"
+        );
+
+        listeners.lock().await.stop_all();
+    }
+
+    #[test]
+    fn render_diagnostics_lists_sessions_and_routes() {
+        let mut cfg = config::empty_managed_runtime_config();
+        cfg.routes.push(config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["127.0.0.1:25566".into()],
+            middlewares: vec!["minecraft_handshake".into()],
+            strategy: "sequential".into(),
+            host_secret: Some("hunter2".into()),
+            ..Default::default()
+        });
+        let chain: middleware::SharedMiddlewareChain =
+            Arc::new(middleware::ChainMiddleware::new(Vec::new()));
+        let rtr = router::Router::new(
+            cfg.routes
+                .iter()
+                .cloned()
+                .map(|r| (r, chain.clone()))
+                .collect(),
+        );
+
+        let sessions = telemetry::SessionRegistry::new();
+        sessions.add(telemetry::SessionInfo {
+            id: "s-1".into(),
+            client: "203.0.113.7:50000".into(),
+            host: "play.example.com".into(),
+            upstream: "127.0.0.1:25566".into(),
+            started_at_unix_ms: 1,
+            geo: Default::default(),
+        });
+
+        let pool = Arc::new(bufpool::BufferPool::new(4));
+        let held = pool.get(4096);
+        drop(pool.get(4096));
+
+        let dump = render_diagnostics(&sessions, &rtr, pool.stats());
+        drop(held);
+        assert!(
+            dump.contains("handshake buffers: in_use=1 pooled=1"),
+            "{dump}"
+        );
+        assert!(dump.contains("sessions: 1"), "{dump}");
+        assert!(
+            dump.contains("s-1 client=203.0.113.7:50000 host=play.example.com"),
+            "{dump}"
+        );
+        assert!(dump.contains("routes: 1"), "{dump}");
+        assert!(
+            dump.contains("route[0] hosts=[\"play.example.com\"]"),
+            "{dump}"
+        );
+        assert!(dump.contains("host_secret=<set>"), "{dump}");
+        assert!(!dump.contains("hunter2"), "{dump}");
+    }
+}
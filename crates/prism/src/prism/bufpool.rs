@@ -0,0 +1,207 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// Buffers that grew past this are freed instead of pooled so one large prelude
+/// doesn't pin memory for the lifetime of the process.
+const MAX_RETAINED_CAPACITY: usize = 64 * 1024;
+
+/// A bounded free list of byte buffers reused across connections on the accept path
+/// (prelude capture and read scratch space).
+#[derive(Debug)]
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+    max_pooled: usize,
+    in_use: AtomicUsize,
+    in_use_bytes: AtomicUsize,
+}
+
+/// Point-in-time pool utilization, for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Buffers currently handed out to connections.
+    pub in_use: usize,
+    /// Capacity of handed-out buffers when they were acquired, in bytes.
+    pub in_use_bytes: usize,
+    /// Idle buffers waiting for reuse.
+    pub pooled: usize,
+    /// Capacity held by idle buffers, in bytes.
+    pub pooled_bytes: usize,
+}
+
+impl BufferPool {
+    /// `max_pooled = 0` disables pooling; every `get` allocates.
+    pub fn new(max_pooled: usize) -> Self {
+        Self {
+            free: Mutex::new(Vec::new()),
+            max_pooled,
+            in_use: AtomicUsize::new(0),
+            in_use_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take an empty buffer with at least `capacity` bytes reserved.
+    pub fn get(self: &Arc<Self>, capacity: usize) -> PooledBuf {
+        let mut buf = if self.max_pooled == 0 {
+            None
+        } else {
+            self.free.lock().unwrap_or_else(|e| e.into_inner()).pop()
+        }
+        .unwrap_or_default();
+        buf.reserve(capacity);
+        let accounted = buf.capacity();
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        self.in_use_bytes.fetch_add(accounted, Ordering::Relaxed);
+        PooledBuf {
+            buf,
+            accounted,
+            pool: self.clone(),
+        }
+    }
+
+    fn put(&self, mut buf: Vec<u8>, accounted: usize) {
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+        self.in_use_bytes.fetch_sub(accounted, Ordering::Relaxed);
+        if self.max_pooled == 0 || buf.capacity() > MAX_RETAINED_CAPACITY {
+            return;
+        }
+        // Never hand one connection's bytes to the next.
+        buf.clear();
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.max_pooled {
+            free.push(buf);
+        }
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        let free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        BufferPoolStats {
+            in_use: self.in_use.load(Ordering::Relaxed),
+            in_use_bytes: self.in_use_bytes.load(Ordering::Relaxed),
+            pooled: free.len(),
+            pooled_bytes: free.iter().map(|b| b.capacity()).sum(),
+        }
+    }
+
+    /// Prometheus text exposition of the memory held by handshake buffers, handed out
+    /// and idle.
+    pub fn render_prometheus(&self) -> String {
+        let stats = self.stats();
+        format!(
+            "# HELP prism_handshake_buffer_bytes Bytes held by handshake buffers, in use and pooled.\n\
+             # TYPE prism_handshake_buffer_bytes gauge\n\
+             prism_handshake_buffer_bytes {}\n",
+            stats.in_use_bytes + stats.pooled_bytes
+        )
+    }
+
+    #[cfg(test)]
+    fn pooled(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+}
+
+/// A buffer borrowed from a `BufferPool`; returned (cleared) on drop.
+#[derive(Debug)]
+pub struct PooledBuf {
+    buf: Vec<u8>,
+    /// Capacity counted against the pool's in-use bytes at acquire time.
+    accounted: usize,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf), self.accounted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused_and_cleared() {
+        let pool = Arc::new(BufferPool::new(2));
+
+        let mut a = pool.get(4096);
+        a.extend_from_slice(b"secret handshake");
+        let ptr = a.as_ptr();
+        drop(a);
+        assert_eq!(pool.pooled(), 1);
+
+        let b = pool.get(4096);
+        assert_eq!(b.as_ptr(), ptr, "allocation should be reused");
+        assert!(b.is_empty());
+        assert!(b.capacity() >= 4096);
+        drop(b);
+
+        // The free list is bounded.
+        let bufs: Vec<_> = (0..4).map(|_| pool.get(16)).collect();
+        drop(bufs);
+        assert_eq!(pool.pooled(), 2);
+
+        // Oversized buffers are not retained.
+        let big = pool.get(MAX_RETAINED_CAPACITY + 1);
+        let _keep: Vec<_> = (0..2).map(|_| pool.get(16)).collect();
+        drop(big);
+        assert_eq!(pool.pooled(), 0);
+    }
+
+    #[test]
+    fn stats_track_acquire_and_release() {
+        let pool = Arc::new(BufferPool::new(4));
+        let a = pool.get(1024);
+        let b = pool.get(2048);
+        let s = pool.stats();
+        assert_eq!((s.in_use, s.pooled, s.pooled_bytes), (2, 0, 0));
+        assert!(s.in_use_bytes >= 1024 + 2048);
+        assert!(pool.render_prometheus().contains(&format!(
+            "prism_handshake_buffer_bytes {}\n",
+            s.in_use_bytes
+        )));
+
+        drop(a);
+        let s = pool.stats();
+        assert_eq!((s.in_use, s.pooled), (1, 1));
+        assert!(s.pooled_bytes >= 1024);
+
+        drop(b);
+        let s = pool.stats();
+        assert_eq!((s.in_use, s.in_use_bytes, s.pooled), (0, 0, 2));
+        assert!(s.pooled_bytes >= 1024 + 2048);
+        assert!(pool.render_prometheus().contains(&format!(
+            "prism_handshake_buffer_bytes {}\n",
+            s.pooled_bytes
+        )));
+
+        // Oversized and unpooled buffers still leave the in-use count.
+        drop(pool.get(MAX_RETAINED_CAPACITY + 1));
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn zero_sized_pool_disables_reuse() {
+        let pool = Arc::new(BufferPool::new(0));
+        drop(pool.get(4096));
+        assert_eq!(pool.pooled(), 0);
+    }
+}
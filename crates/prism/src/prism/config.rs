@@ -1,13 +1,18 @@
 use std::{
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
     time::Duration,
 };
 
 use anyhow::Context;
+#[cfg(not(target_os = "linux"))]
 use directories::ProjectDirs;
+use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::prism::{net::IpNet, router, tls, tunnel};
+
 fn deserialize_ignored_any<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(), D::Error> {
     let _ = serde::de::IgnoredAny::deserialize(deserializer)?;
     Ok(())
@@ -101,7 +106,7 @@ fn normalize_explicit_path(p: &Path) -> anyhow::Result<PathBuf> {
 }
 
 fn discover_config_path(dir: &Path) -> anyhow::Result<PathBuf> {
-    let candidates = ["prism.toml", "prism.yaml", "prism.yml"];
+    let candidates = ["prism.toml", "prism.yaml", "prism.yml", "prism.json"];
     for c in candidates {
         let p = dir.join(c);
         if let Ok(m) = fs::metadata(&p)
@@ -117,7 +122,7 @@ fn default_config_path() -> anyhow::Result<PathBuf> {
     // Linux: system-wide default.
     #[cfg(target_os = "linux")]
     {
-        return Ok(PathBuf::from("/etc/prism/prism.toml"));
+        Ok(PathBuf::from("/etc/prism/prism.toml"))
     }
 
     // Other OSes: per-user config dir.
@@ -169,6 +174,24 @@ pub fn ensure_config_file(path: &Path) -> anyhow::Result<bool> {
     Ok(true)
 }
 
+/// Read a route's `offline_status`: inline JSON, or a file path resolved against the
+/// config file's directory. Either way it must be a JSON object.
+fn load_offline_status(value: &str, config_path: &Path) -> anyhow::Result<String> {
+    let json = if value.starts_with('{') {
+        value.to_string()
+    } else {
+        let path = config_path
+            .parent()
+            .map_or_else(|| Path::new(value).to_path_buf(), |dir| dir.join(value));
+        fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?
+    };
+    let v: serde_json::Value = serde_json::from_str(&json).context("not valid JSON")?;
+    if !v.is_object() {
+        anyhow::bail!("must be a JSON object");
+    }
+    Ok(json.trim().to_string())
+}
+
 fn default_config_template_for_path(path: &Path) -> anyhow::Result<&'static str> {
     let ext = path
         .extension()
@@ -178,14 +201,21 @@ fn default_config_template_for_path(path: &Path) -> anyhow::Result<&'static str>
     match ext.as_str() {
         "toml" => Ok(DEFAULT_CONFIG_TEMPLATE_TOML),
         "yaml" | "yml" => Ok(DEFAULT_CONFIG_TEMPLATE_YAML),
+        "json" => Ok(DEFAULT_CONFIG_TEMPLATE_JSON),
         _ => anyhow::bail!(
-            "config: unsupported config extension {:?} (expected .toml or .yaml/.yml)",
+            "config: unsupported config extension {:?} (expected .toml, .yaml/.yml or .json)",
             path.extension()
         ),
     }
 }
 
 pub fn load_config(path: &Path) -> anyhow::Result<Config> {
+    let mut fc: FileConfig = parse_config_file(path)?;
+    merge_includes(&mut fc, path)?;
+    Config::from_file_config(&mut fc, path)
+}
+
+fn parse_config_file<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
     let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
     let s = String::from_utf8_lossy(&data);
 
@@ -195,15 +225,127 @@ pub fn load_config(path: &Path) -> anyhow::Result<Config> {
         .unwrap_or("")
         .to_ascii_lowercase();
 
-    let mut fc: FileConfig = match ext.as_str() {
+    Ok(match ext.as_str() {
         "toml" => toml::from_str(&s).with_context(|| format!("parse toml {}", path.display()))?,
         "yaml" | "yml" => {
             serde_yaml::from_str(&s).with_context(|| format!("parse yaml {}", path.display()))?
         }
+        "json" => {
+            serde_json::from_str(&s).with_context(|| format!("parse json {}", path.display()))?
+        }
         _ => anyhow::bail!("config: unsupported config extension {}", ext),
+    })
+}
+
+/// Append the `routes` and `listeners` of every file matched by `fc.include` to `fc`.
+///
+/// Patterns are globs relative to the main config's directory; matches are loaded in
+/// sorted order, after the main file's own entries. A pattern without glob characters
+/// must name an existing file. Included files cannot include further files, and a
+/// listener whose address and protocol repeat an earlier one is an error.
+fn merge_includes(fc: &mut FileConfig, path: &Path) -> anyhow::Result<()> {
+    if fc.include.is_empty() {
+        return Ok(());
+    }
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let listener_key = |l: &FileProxyListener| {
+        let proto = l.protocol.trim().to_ascii_lowercase();
+        let proto = if proto.is_empty() {
+            "tcp".into()
+        } else {
+            proto
+        };
+        (l.listen_addr.trim().to_string(), proto)
     };
+    let mut seen: Vec<(String, String)> = fc.listeners.iter().map(listener_key).collect();
 
-    Config::from_file_config(&mut fc, path)
+    for pattern in std::mem::take(&mut fc.include) {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            continue;
+        }
+        let files = include_files(base, pattern)?;
+        if files.is_empty() && !pattern.contains(['*', '?', '[']) {
+            anyhow::bail!(
+                "config: include {:?}: {} not found",
+                pattern,
+                base.join(pattern).display()
+            );
+        }
+
+        for file in files {
+            let inc: FileInclude = parse_config_file(&file)?;
+            for l in &inc.listeners {
+                let key = listener_key(l);
+                if seen.contains(&key) {
+                    anyhow::bail!(
+                        "config: listener {} ({}) in {} duplicates an earlier listener",
+                        key.0,
+                        key.1,
+                        file.display()
+                    );
+                }
+                seen.push(key);
+            }
+            fc.listeners.extend(inc.listeners);
+            fc.routes.extend(inc.routes);
+        }
+    }
+    Ok(())
+}
+
+/// Files matched by one include `pattern`, resolved against `base`, in sorted order.
+fn include_files(base: &Path, pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let full = base.join(pattern);
+    let mut files = glob::glob(&full.to_string_lossy())
+        .with_context(|| format!("config: invalid include pattern {:?}", pattern))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("config: include {:?}", pattern))?;
+    files.sort();
+    Ok(files)
+}
+
+/// Modification time and size of every file the config at `path` currently includes,
+/// sorted by name; the reload loop polls this next to the main file's own signature so
+/// edited, added or removed included files trigger a reload too. Unreadable configs and
+/// patterns yield nothing here and surface as errors from `load_config` instead.
+pub fn include_signature(path: &Path) -> Vec<(PathBuf, u64, u64)> {
+    #[derive(Deserialize)]
+    struct Includes {
+        #[serde(default)]
+        include: Vec<String>,
+    }
+
+    let Ok(Includes { include }) = parse_config_file(path) else {
+        return Vec::new();
+    };
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut out: Vec<_> = include
+        .iter()
+        .map(|pattern| pattern.trim())
+        .filter(|pattern| !pattern.is_empty())
+        .filter_map(|pattern| include_files(base, pattern).ok())
+        .flatten()
+        .filter_map(|file| {
+            let meta = fs::metadata(&file).ok()?;
+            let mtime = meta
+                .modified()
+                .ok()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            Some((file, mtime, meta.len()))
+        })
+        .collect();
+    out.sort();
+    out
+}
+
+/// JSON Schema (draft 2020-12) of the config file format, derived from the types the
+/// loader deserializes into.
+pub fn json_schema() -> serde_json::Value {
+    schemars::schema_for!(FileConfig).to_value()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -346,6 +488,8 @@ pub struct ManagedTimeoutsDocument {
 pub struct ManagedTunnelDocument {
     #[serde(default)]
     pub auth_token: String,
+    #[serde(default)]
+    pub auth_tokens: Vec<String>,
     #[serde(default = "default_true")]
     pub auto_listen_services: bool,
     #[serde(default)]
@@ -375,6 +519,9 @@ pub struct ManagedTunnelClientDocument {
     #[serde(default)]
     pub transport: String,
     pub dial_timeout_ms: Option<i64>,
+    pub heartbeat_interval_ms: Option<i64>,
+    #[serde(default)]
+    pub drain_timeout_ms: Option<i64>,
     pub quic: Option<ManagedQuicClientDocument>,
 }
 
@@ -383,6 +530,7 @@ pub struct ManagedTunnelClientDocument {
 pub struct ManagedQuicServerDocument {
     pub cert_file: Option<String>,
     pub key_file: Option<String>,
+    pub client_ca_file: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -391,6 +539,8 @@ pub struct ManagedQuicClientDocument {
     pub server_name: Option<String>,
     #[serde(default)]
     pub insecure_skip_verify: bool,
+    pub client_cert_file: Option<String>,
+    pub client_key_file: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -406,6 +556,12 @@ pub struct ManagedTunnelServiceDocument {
     pub remote_addr: String,
     #[serde(default)]
     pub masquerade_host: String,
+    #[serde(default)]
+    pub rate_limit_bytes_per_sec: u64,
+    #[serde(default)]
+    pub rate_limit_scope: String,
+    #[serde(default)]
+    pub dial_policy: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -414,34 +570,372 @@ pub struct Config {
     pub managed: ManagedBootstrapConfig,
     pub listeners: Vec<ProxyListenerConfig>,
     pub admin_addr: String,
+    /// Bearer token every admin request must carry (except `/healthz`); empty = none.
+    pub admin_auth_token: String,
+    /// `user:password` accepted as HTTP basic auth on the admin server; empty = none.
+    pub admin_basic_auth: String,
     pub logging: LoggingConfig,
     pub routes: Vec<RouteConfig>,
     pub max_header_bytes: usize,
     pub reload: ReloadConfig,
-    pub proxy_protocol_v2: bool,
+    /// PROXY protocol header written to TCP upstreams unless a route overrides it.
+    pub proxy_protocol: ProxyProtocol,
     pub buffer_size: usize,
     pub upstream_dial_timeout: Duration,
+    /// TCP_USER_TIMEOUT for client and upstream sockets (zero keeps the OS default).
+    pub tcp_user_timeout: Duration,
+    /// Disable Nagle's algorithm on client and upstream sockets.
+    pub tcp_nodelay: bool,
+    /// TCP keepalive idle time for client and upstream sockets (zero leaves keepalive off).
+    pub tcp_keepalive: Duration,
+    /// How long shutdown waits for proxied TCP sessions to finish before closing them.
+    pub shutdown_drain_timeout: Duration,
     pub timeouts: Timeouts,
     pub tunnel: TunnelConfig,
+    /// Per-middleware settings keyed by normalized middleware name.
+    pub middleware_settings: BTreeMap<String, MiddlewareSettings>,
+    /// Load `<name>.wasm` binaries when a middleware has no `<name>.wat`.
+    pub allow_wasm_binary: bool,
+    pub on_no_route: NoRoutePolicy,
+    /// Maximum concurrent TCP sessions per client IP (0 = unlimited).
+    pub max_sessions_per_ip: usize,
+    /// Client networks exempt from per-IP limits.
+    pub trusted_cidrs: Vec<IpNet>,
+    /// New TCP connections allowed per client IP.
+    pub rate_limit: RateLimitConfig,
+    /// Maximum concurrent proxied TCP sessions across all listeners (0 = unlimited).
+    pub max_connections: usize,
+    /// Maximum concurrent client flows per UDP listener (0 = unlimited).
+    pub max_udp_sessions: usize,
+    /// Receive buffer per UDP listener and per UDP flow.
+    pub udp_buffer_size: usize,
+    /// UDP datagrams larger than this are dropped (at most `udp_buffer_size`).
+    pub max_udp_datagram_bytes: usize,
+    /// Maximum concurrent sessions per upstream label (`host:port` or `tunnel:<service>`).
+    pub upstream_max_connections: BTreeMap<String, usize>,
+    /// Consecutive dial failures before an upstream is ejected (0 = never eject).
+    pub upstream_eject_failures: u32,
+    /// How long an ejected upstream is only tried after every healthy candidate.
+    pub upstream_eject_cooldown: Duration,
+    /// Active upstream status probes.
+    pub health_check: HealthCheckConfig,
+    /// Propagate TCP half-closes between client and upstream instead of tearing down
+    /// both sides on the first EOF.
+    pub half_close_propagation: bool,
+    pub upstream_ip_version: UpstreamIpVersion,
+    /// Port appended to port-less upstreams dialed outside any listener (active health
+    /// checks); proxied connections use the accepting listener's port.
+    pub default_minecraft_port: u16,
+    /// Peek at new routing connections to classify them and strip PROXY protocol headers
+    /// sent by `trusted_cidrs` peers.
+    pub sniff_protocols: bool,
+    /// Strip a leading PROXY protocol header from `trusted_cidrs` peers on every TCP
+    /// listener and use its client address.
+    pub accept_proxy_protocol: bool,
+    /// Maximum idle prelude capture buffers kept for reuse (0 disables pooling).
+    pub handshake_buffer_pool: usize,
+    /// Optional MaxMind (GeoLite2) database used to annotate sessions with country/ASN.
+    pub geoip_db: String,
+    pub session_id_format: SessionIdFormat,
+    /// Append a short keyed hash of the client IP to session ids.
+    pub session_id_client_hash: bool,
+    /// Minimum version and cipher suites for every TLS config Prism builds.
+    pub tls: tls::TlsPolicy,
+}
+
+/// Per-client-IP token bucket for new TCP connections, checked at accept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Sustained connections per second (0 = unlimited).
+    pub connections_per_second: u32,
+    /// Connections allowed back to back before the rate applies.
+    pub burst: u32,
+}
+
+/// Periodic Minecraft status pings to every statically configured upstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthCheckConfig {
+    pub enabled: bool,
+    /// Time between probe rounds.
+    pub interval: Duration,
+    /// Deadline for a probe's dial and status response.
+    pub timeout: Duration,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: Duration::from_millis(DEFAULT_HEALTH_CHECK_INTERVAL_MS),
+            timeout: Duration::from_millis(DEFAULT_HEALTH_CHECK_TIMEOUT_MS),
+        }
+    }
+}
+
+/// Address family used when dialing upstream hostnames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpstreamIpVersion {
+    /// Try resolved addresses in resolver order (default).
+    #[default]
+    Auto,
+    V4,
+    V6,
+}
+
+impl UpstreamIpVersion {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "auto" => Ok(Self::Auto),
+            "v4" | "ipv4" => Ok(Self::V4),
+            "v6" | "ipv6" => Ok(Self::V6),
+            other => anyhow::bail!(
+                "config: unsupported upstream_ip_version {:?} (expected auto, v4 or v6)",
+                other
+            ),
+        }
+    }
+
+    pub fn family_label(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::V4 => "IPv4",
+            Self::V6 => "IPv6",
+        }
+    }
+}
+
+/// Shape of generated session ids.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionIdFormat {
+    /// Process-local counter: `s1`, `s2`, ... (default).
+    #[default]
+    Counter,
+    /// Random (v4) UUID.
+    Uuid,
+    /// Unix milliseconds plus counter: `s1718000000000-1`; unique across restarts.
+    Timestamp,
+}
+
+impl SessionIdFormat {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "counter" => Ok(Self::Counter),
+            "uuid" => Ok(Self::Uuid),
+            "timestamp" => Ok(Self::Timestamp),
+            other => anyhow::bail!(
+                "config: unsupported session_id_format {:?} (expected counter, uuid or timestamp)",
+                other
+            ),
+        }
+    }
+}
+
+/// PROXY protocol header written to an upstream ahead of the client's bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    None,
+    V1,
+    V2,
+}
+
+impl ProxyProtocol {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(Self::None),
+            "v1" => Ok(Self::V1),
+            "v2" => Ok(Self::V2),
+            other => anyhow::bail!(
+                "config: unsupported proxy_protocol {:?} (expected none, v1 or v2)",
+                other
+            ),
+        }
+    }
+}
+
+/// How a route tells its backends the real client address inside the Minecraft protocol.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Forwarding {
+    #[default]
+    None,
+    /// BungeeCord legacy forwarding: client IP and UUID appended to the handshake host.
+    Legacy,
+    /// Velocity modern forwarding: signed player info answered to the backend's login query.
+    Modern,
+}
+
+impl Forwarding {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(Self::None),
+            "legacy" | "bungeecord" => Ok(Self::Legacy),
+            "modern" | "velocity" => Ok(Self::Modern),
+            other => anyhow::bail!(
+                "config: unsupported forwarding {:?} (expected none, legacy or modern)",
+                other
+            ),
+        }
+    }
+}
+
+/// What a routing listener does with a connection when no route matches its prelude.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NoRoutePolicy {
+    /// Close the connection (default).
+    #[default]
+    Close,
+    /// Forward the captured prelude as raw TCP to a catch-all upstream.
+    DefaultUpstream(String),
+    /// Send a Minecraft login disconnect with this message (login handshakes only), then close.
+    Kick(String),
+}
+
+impl NoRoutePolicy {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        if value.is_empty() || value.eq_ignore_ascii_case("close") {
+            return Ok(Self::Close);
+        }
+        if let Some((kind, arg)) = value.split_once(':') {
+            let arg = arg.trim();
+            match kind.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+                "default_upstream" => {
+                    if arg.is_empty() {
+                        anyhow::bail!("config: on_no_route default_upstream requires an address");
+                    }
+                    return Ok(Self::DefaultUpstream(arg.to_string()));
+                }
+                "kick" => return Ok(Self::Kick(arg.to_string())),
+                _ => {}
+            }
+        }
+        anyhow::bail!(
+            "config: unsupported on_no_route {:?} (expected close, default_upstream:<addr>, or kick:<message>)",
+            value
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Timeouts {
     pub handshake_timeout: Duration,
     pub idle_timeout: Duration,
+    /// Bound on each single read/write while proxying (zero disables).
+    pub io_op_timeout: Duration,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProxyListenerConfig {
     pub listen_addr: String,
-    pub protocol: String, // tcp | udp
+    pub protocol: String, // tcp | udp | tls | auto
     pub upstream: String,
+    /// Local source IP for direct UDP upstream sockets (None = unspecified address).
+    pub source_ip: Option<std::net::IpAddr>,
+    pub mode: ListenerMode,
+    /// Server list JSON served by `mode = "status-health"` listeners.
+    pub status_json: String,
+    /// Non-Minecraft monitor probes answered before routing (routing listeners only).
+    pub health_probes: Vec<HealthProbe>,
+}
+
+/// Fields replaced in status responses for a route's server list entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusOverride {
+    pub motd: Option<String>,
+    pub max_players: Option<u32>,
+    pub version_name: Option<String>,
+}
+
+/// A load balancer / monitor probe recognized by its leading bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthProbe {
+    pub pattern: Vec<u8>,
+    /// Written back before closing; empty just closes.
+    pub reply: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListenerMode {
+    /// Route or forward traffic to upstreams.
+    #[default]
+    Proxy,
+    /// Answer Minecraft status pings locally so monitors can check Prism itself.
+    StatusHealth,
+    /// Resolve routes and log the decision, then close without dialing upstream.
+    Observe,
+}
+
+impl ListenerMode {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "" | "proxy" => Ok(Self::Proxy),
+            "status-health" => Ok(Self::StatusHealth),
+            "observe" => Ok(Self::Observe),
+            other => anyhow::bail!(
+                "config: unsupported listener mode {:?} (expected proxy, status-health or observe)",
+                other
+            ),
+        }
+    }
 }
 
+/// Default number of idle prelude capture buffers kept for reuse.
+pub const DEFAULT_HANDSHAKE_BUFFER_POOL: usize = 256;
+
+/// Default wait before a route's first upstream dial retry.
+const DEFAULT_DIAL_RETRY_BACKOFF_MS: u64 = 100;
+
+/// Default consecutive dial failures before an upstream is ejected.
+const DEFAULT_UPSTREAM_EJECT_FAILURES: u32 = 3;
+
+/// Default ejection window for an upstream that keeps failing to dial.
+const DEFAULT_UPSTREAM_EJECT_COOLDOWN_MS: u64 = 30_000;
+/// Fits inside the 10s grace period `docker stop` gives before SIGKILL.
+const DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_MS: u64 = 5_000;
+
+/// Default time between active health check rounds.
+const DEFAULT_HEALTH_CHECK_INTERVAL_MS: u64 = 10_000;
+
+/// Default deadline for one active health check probe.
+const DEFAULT_HEALTH_CHECK_TIMEOUT_MS: u64 = 2_000;
+
+/// Default UDP receive buffer (the largest possible datagram payload fits).
+pub const DEFAULT_UDP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Default Minecraft Java port, used when no other source provides an upstream port.
+pub const DEFAULT_MINECRAFT_PORT: u16 = 25565;
+
+/// Default server list JSON for status-health listeners. `version.protocol` is filled in
+/// from the client's handshake when omitted.
+pub const DEFAULT_STATUS_HEALTH_JSON: &str = r#"{"version":{"name":"Prism"},"players":{"max":0,"online":0},"description":{"text":"Prism is healthy"}}"#;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ReloadConfig {
     pub enabled: bool,
     pub poll_interval: Duration,
+    pub listener_mode: ListenerReloadMode,
+}
+
+/// What a reload does when the proxy listener set changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListenerReloadMode {
+    /// Keep the running listeners and log that a restart is required (default).
+    #[default]
+    Warn,
+    /// Stop removed listeners and start added ones in place.
+    Hot,
+}
+
+impl ListenerReloadMode {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "warn" => Ok(Self::Warn),
+            "hot" => Ok(Self::Hot),
+            other => anyhow::bail!(
+                "config: unsupported reload.listener_mode {:?} (expected hot or warn)",
+                other
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -450,20 +944,91 @@ pub struct LoggingConfig {
     pub format: String,
     pub output: String,
     pub add_source: bool,
+    /// Emit one `prism::access` event per finished TCP session, whatever `level` is.
+    pub access_log: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct RouteConfig {
     pub host: Vec<String>,
+    /// Regular expressions matched against the whole (lowercased) host; named groups are
+    /// available to upstream templates as `$name`.
+    pub host_regex: Vec<String>,
     pub upstreams: Vec<String>,
     pub middlewares: Vec<String>,
     pub strategy: String,
+    /// Fraction (0.0..=1.0) of sessions on this route that get a recorded session span.
+    /// None traces every session.
+    pub trace_sample_ratio: Option<f64>,
+    /// Only consider this route for preludes that start like a Minecraft handshake.
+    pub strict_minecraft: bool,
+    /// Required leading host label (`<secret>.play.example.com`), stripped before matching.
+    pub host_secret: Option<String>,
+    /// PROXY protocol header for this route's upstreams; None follows the global setting.
+    pub proxy_protocol: Option<ProxyProtocol>,
+    /// Status JSON answered to server list pings when every upstream fails to dial.
+    pub offline_status: Option<String>,
+    /// In-protocol client address forwarding for login handshakes.
+    pub forwarding: Forwarding,
+    /// Shared secret signing modern forwarding data (required for `Forwarding::Modern`).
+    pub forwarding_secret: Option<String>,
+    /// Server list fields rewritten in status responses (upstream and offline alike).
+    pub status_override: Option<StatusOverride>,
+    /// Maximum concurrent sessions proxied through this route (0 = unlimited).
+    pub max_upstream_connections: usize,
+    /// Extra dial attempts per upstream candidate before failing over.
+    pub dial_retries: u32,
+    /// Wait before the first retry; doubled for each further retry.
+    pub dial_retry_backoff: Duration,
+    /// Overrides `timeouts.handshake_timeout` for sessions on this route.
+    pub handshake_timeout: Option<Duration>,
+    /// Overrides `timeouts.idle_timeout` for sessions on this route (zero = off).
+    pub idle_timeout: Option<Duration>,
+    /// Overrides `upstream_dial_timeout` for this route's upstreams.
+    pub upstream_dial_timeout: Option<Duration>,
+    /// Client networks allowed on this route; empty allows everyone not denied.
+    pub allow_cidrs: Vec<IpNet>,
+    /// Client networks refused on this route; takes precedence over `allow_cidrs`.
+    pub deny_cidrs: Vec<IpNet>,
+    /// Routes with a higher priority are tried first; equal priorities keep config order.
+    pub priority: i32,
+    /// Catch-all route tried only after every specific route fails to match.
+    pub default: bool,
+}
+
+// trace_sample_ratio is validated to 0.0..=1.0 (never NaN), so equality is total.
+impl Eq for RouteConfig {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiddlewareSettings {
+    /// Maximum routing host length (bytes) accepted from this middleware.
+    pub max_output_len: usize,
+    /// Wasm operators a single call may execute before it is aborted.
+    pub fuel_limit: u64,
+    /// Cap on the middleware's linear memory, in 64 KiB pages.
+    pub max_memory_pages: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TunnelAuthToken {
+    pub token: String,
+    /// Prefixes the ids of clients that register with this token (empty = "c").
+    pub label: String,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct TunnelConfig {
+    /// Sent by the tunnel client; also accepted by the tunnel server.
     pub auth_token: String,
+    /// Further tokens the tunnel server accepts, e.g. per client or during rotation.
+    pub auth_tokens: Vec<TunnelAuthToken>,
     pub auto_listen_services: bool,
+    /// Hold-down before a host whose primary just dropped may be promoted again (0 = off).
+    pub primary_grace: Duration,
+    /// How long a dial waits for a momentarily unregistered service to return (0 = off).
+    pub dial_service_wait: Duration,
+    /// Streams buffered per tunnel session before the transport stops accepting more.
+    pub accept_queue_depth: usize,
     pub endpoints: Vec<TunnelEndpointConfig>,
     pub client: Option<TunnelClientConfig>,
     pub services: Vec<TunnelServiceConfig>,
@@ -481,6 +1046,11 @@ pub struct TunnelClientConfig {
     pub server_addr: String,
     pub transport: String,
     pub dial_timeout: Duration,
+    /// How often the client proves liveness to the server (0 = off).
+    pub heartbeat_interval: Duration,
+    /// How long open streams may finish on shutdown once the server stops routing new ones
+    /// here (0 = close at once).
+    pub drain_timeout: Duration,
     pub quic: QuicClientConfig,
 }
 
@@ -488,12 +1058,17 @@ pub struct TunnelClientConfig {
 pub struct QuicServerConfig {
     pub cert_file: String,
     pub key_file: String,
+    /// When set, tunnel clients must present a certificate issued by this CA (mTLS).
+    pub client_ca_file: String,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct QuicClientConfig {
     pub server_name: String,
     pub insecure_skip_verify: bool,
+    /// Certificate and key presented to servers that require mTLS.
+    pub client_cert_file: String,
+    pub client_key_file: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -507,174 +1082,899 @@ pub struct TunnelServiceConfig {
     /// an upstream (tunnel:<service>). Leave empty to preserve the client's protocol host.
     /// This supports $1, $2... substitutions from route wildcard captures.
     pub masquerade_host: String,
+    /// Cap on each direction of the service's traffic (0 = unlimited).
+    pub rate_limit_bytes_per_sec: u64,
+    pub rate_limit_scope: tunnel::protocol::RateLimitScope,
+    /// `primary` dials the service's primary client; `balance` spreads dials across every
+    /// client that provides it.
+    pub dial_policy: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
+#[schemars(
+    title = "Prism configuration",
+    description = "JSON Schema for Prism .toml, .yaml/.yml and .json configuration files."
+)]
+#[schemars(extend(
+    "$id" = "https://raw.githubusercontent.com/Summpot/prism/master/prism.schema.json",
+    "allOf" = [
+        {
+            "if": {"properties": {"role": {"const": "management"}}, "required": ["role"]},
+            "then": {
+                "required": ["admin_addr", "managed"],
+                "properties": {"managed": {"required": ["management"]}}
+            }
+        },
+        {
+            "if": {"properties": {"role": {"const": "worker"}}, "required": ["role"]},
+            "then": {
+                "required": ["admin_addr", "managed"],
+                "properties": {"managed": {"required": ["worker"]}}
+            }
+        }
+    ],
+))]
 struct FileConfig {
+    /// Prism runtime role. standalone preserves the traditional local-file model;
+    /// management enables the managed control plane; worker enables managed worker
+    /// bootstrap plus local agent endpoints.
     #[serde(default)]
+    #[schemars(extend("enum" = ["standalone", "management", "worker"], "default" = "standalone"))]
     role: String,
 
     managed: Option<FileManagedBootstrap>,
 
+    /// Public-facing proxy listeners (multi-port / multi-protocol).
     #[serde(default)]
     listeners: Vec<FileProxyListener>,
 
+    /// Admin HTTP server listen address. Empty disables the admin server.
     #[serde(default)]
+    #[schemars(extend("examples" = [":8080", "127.0.0.1:8080", ""]))]
     admin_addr: String,
 
+    /// Bearer token required on every admin route except /healthz (managed endpoints keep
+    /// their panel/worker tokens). Empty disables admin auth. Changing it requires a
+    /// restart.
+    #[serde(default)]
+    admin_auth_token: String,
+
+    /// "user:password" accepted as HTTP basic auth on the admin server, alongside or
+    /// instead of admin_auth_token. Empty disables it. Changing it requires a restart.
+    #[serde(default)]
+    #[schemars(extend("examples" = ["ops:change-me"]))]
+    admin_basic_auth: String,
+
     /// Accepted and ignored for backward compatibility (metrics support removed).
     #[serde(default, deserialize_with = "deserialize_ignored_any")]
+    #[schemars(skip)]
     #[allow(dead_code)]
     metrics: (),
 
+    /// Schema reference for editors (JSON configs). Ignored by Prism.
+    #[serde(
+        rename = "$schema",
+        default,
+        deserialize_with = "deserialize_ignored_any"
+    )]
+    #[schemars(with = "String")]
+    #[allow(dead_code)]
+    schema: (),
+
     logging: Option<FileLogging>,
 
+    /// Ordered hostname routes. Routes are matched in order; each route targets one or more
+    /// upstreams. TCP hostname extraction and optional prelude rewrites are provided by
+    /// per-route wasm middlewares.
     #[serde(default)]
+    #[schemars(extend("default" = []))]
     routes: Vec<FileRoute>,
 
+    /// Globs (relative to this config's directory) of extra config files whose routes and
+    /// listeners are appended to this config, in sorted file order. A listener repeating an
+    /// earlier address and protocol is an error.
+    #[serde(default)]
+    #[schemars(extend("examples" = [["routes.d/*.toml"]]))]
+    include: Vec<String>,
+
+    /// Maximum number of bytes to peek/read for routing (handshake/SNI/etc). 0 means use
+    /// the default.
     #[serde(default)]
+    #[schemars(extend("examples" = [65536], "minimum" = 0))]
     max_header_bytes: i64,
 
     reload: Option<FileReload>,
 
+    /// Deprecated: true is the same as proxy_protocol = "v2".
     #[serde(default)]
     proxy_protocol_v2: bool,
+    /// HAProxy PROXY protocol header injected on TCP upstream connections (to preserve the
+    /// original client IP/port at the backend). v1 is the text format, v2 the binary one.
+    #[schemars(extend("enum" = ["none", "v1", "v2"], "default" = "none"))]
+    proxy_protocol: Option<String>,
 
+    /// Buffer size (bytes) used for proxying. 0 means use the default.
     #[serde(default)]
+    #[schemars(extend("examples" = [32768], "minimum" = 0))]
     buffer_size: i64,
 
+    /// Dial timeout for upstream connections (milliseconds). 0 means use the default.
     #[serde(default)]
+    #[schemars(extend("examples" = [5000], "minimum" = 0))]
     upstream_dial_timeout_ms: i64,
 
+    /// TCP_USER_TIMEOUT (milliseconds) for client and upstream sockets: how long sent data
+    /// may stay unacknowledged before the connection is dropped. Linux only; 0 keeps the OS
+    /// default.
+    #[serde(default)]
+    #[schemars(extend("examples" = [30000], "minimum" = 0))]
+    tcp_user_timeout_ms: i64,
+
+    /// Set TCP_NODELAY (disable Nagle's algorithm) on client and upstream sockets.
+    #[schemars(extend("default" = true))]
+    tcp_nodelay: Option<bool>,
+
+    /// Enable TCP keepalive on client and upstream sockets with this idle time
+    /// (milliseconds) before the first probe. 0 leaves keepalive off.
+    #[serde(default)]
+    #[schemars(extend("examples" = [60000], "minimum" = 0))]
+    tcp_keepalive_ms: i64,
+
+    /// On shutdown, how long proxied TCP sessions may keep running after the listeners stop
+    /// accepting (milliseconds). Shutdown finishes early once none are left.
+    #[schemars(extend("default" = 5000, "examples" = [30000]))]
+    shutdown_drain_timeout_ms: Option<u64>,
+
     timeouts: Option<FileTimeouts>,
 
     tunnel: Option<FileTunnel>,
+
+    /// Per-middleware settings keyed by middleware name (same normalization as
+    /// routes[].middlewares).
+    #[serde(default)]
+    #[schemars(extend("default" = {}))]
+    middleware_settings: BTreeMap<String, FileMiddlewareSettings>,
+
+    /// Load compiled '<name>.wasm' middlewares when no '<name>.wat' exists. WAT stays the
+    /// default; binaries are rejected unless this is set.
+    #[serde(default)]
+    allow_wasm_binary: bool,
+
+    /// What routing listeners do when no route matches: 'close' (default),
+    /// 'default_upstream:<addr>' (forward the captured prelude as raw TCP to a catch-all
+    /// upstream), or 'kick:<message>' (send a Minecraft login disconnect, then close).
+    #[serde(default)]
+    #[schemars(extend(
+        "default" = "close",
+        "examples" = [
+            "close",
+            "default_upstream:127.0.0.1:25570",
+            "kick:Unknown server address"
+        ],
+        "pattern" = "^(close|default_upstream:.+|kick:.*)$",
+    ))]
+    on_no_route: String,
+
+    /// Maximum concurrent TCP sessions per client IP. New connections over the cap are
+    /// closed. 0 means unlimited.
+    #[serde(default)]
+    #[schemars(extend("minimum" = 0))]
+    max_sessions_per_ip: i64,
+
+    /// Client networks (CIDR or bare IP) exempt from per-IP limits.
+    #[serde(default)]
+    #[schemars(extend("examples" = [["127.0.0.0/8", "10.0.0.0/8"]]))]
+    trusted_cidrs: Vec<String>,
+
+    /// Maximum concurrent sessions per upstream label (host:port with the listener port
+    /// filled in, or tunnel:<service>). A full upstream is skipped in favor of the route's
+    /// next candidate. 0 means unlimited.
+    #[serde(default)]
+    upstream_max_connections: BTreeMap<String, i64>,
+
+    /// Consecutive failed dials before an upstream is ejected (tried only after healthy
+    /// candidates). 0 disables ejection.
+    #[schemars(extend("default" = 3))]
+    upstream_eject_failures: Option<u32>,
+
+    /// How long an ejected upstream stays behind healthy candidates. A successful dial
+    /// reinstates it early.
+    #[schemars(extend("default" = 30000))]
+    upstream_eject_cooldown_ms: Option<u64>,
+
+    /// Active upstream health checks: periodic Minecraft status pings to every configured
+    /// upstream (templates and tunnel services are skipped). Failing upstreams are tried
+    /// after healthy ones.
+    health_check: Option<FileHealthCheck>,
+
+    /// Per-client-IP token bucket for new TCP connections on routing and forwarding
+    /// listeners. Connections over the rate are closed at accept; trusted_cidrs are exempt.
+    rate_limit: Option<FileRateLimit>,
+
+    /// Maximum concurrent proxied TCP sessions across all listeners. Logins over the cap
+    /// are kicked with a 'server is full' message; status pings get the route's
+    /// offline_status. 0 means unlimited.
+    #[serde(default)]
+    #[schemars(extend("minimum" = 0))]
+    max_connections: i64,
+
+    /// Maximum concurrent client flows per UDP listener. While at the cap, packets from new
+    /// source addresses are dropped and existing flows keep working. 0 means unlimited.
+    #[serde(default)]
+    #[schemars(extend("minimum" = 0))]
+    max_udp_sessions: i64,
+
+    /// Receive buffer size in bytes for each UDP listener and each UDP flow's upstream
+    /// socket.
+    #[schemars(extend("default" = 65536, "minimum" = 1))]
+    udp_buffer_size: Option<usize>,
+
+    /// UDP datagrams larger than this are dropped in either direction instead of being
+    /// truncated. Defaults to udp_buffer_size and may not exceed it.
+    #[schemars(extend("minimum" = 1))]
+    max_udp_datagram_bytes: Option<usize>,
+
+    /// Optional MaxMind (GeoLite2 Country/City/ASN) database path. When set, session logs
+    /// and the admin sessions view are annotated with the client's country and ASN. Loaded
+    /// once at startup; a missing or corrupt file only logs a warning.
+    #[serde(default)]
+    #[schemars(extend("examples" = ["/var/lib/GeoIP/GeoLite2-ASN.mmdb"]))]
+    geoip_db: String,
+
+    /// Propagate TCP half-closes: when one side sends EOF, shut down writes towards the
+    /// other and keep copying the remaining direction. Disable for backends that misbehave
+    /// with half-open connections; both sides are then closed on the first EOF.
+    #[schemars(extend("default" = true))]
+    half_close_propagation: Option<bool>,
+
+    /// Maximum idle prelude capture/read buffers kept for reuse across connections. Buffers
+    /// are cleared before reuse. 0 disables pooling.
+    #[schemars(extend("default" = 256, "minimum" = 0))]
+    handshake_buffer_pool: Option<i64>,
+
+    /// Address family for dialing upstream hostnames. 'auto' (default) races resolved
+    /// addresses Happy Eyeballs style, interleaving families; 'v4' / 'v6' only dial
+    /// addresses of that family.
+    #[serde(default)]
+    #[schemars(extend("enum" = ["auto", "v4", "v6"], "default" = "auto"))]
+    upstream_ip_version: String,
+
+    /// Port appended to upstreams configured without one when active health checks probe
+    /// them. Proxied connections use the accepting listener's port.
+    #[schemars(extend("default" = 25565, "examples" = [19132], "minimum" = 1, "maximum" = 65535))]
+    default_minecraft_port: Option<i64>,
+
+    /// Peek at new routing connections to classify them (PROXY v1/v2, TLS, HTTP,
+    /// Minecraft). PROXY headers from trusted_cidrs peers are stripped and their client
+    /// address is used; from other peers the connection is refused.
+    #[serde(default)]
+    sniff_protocols: bool,
+
+    /// Consume a leading PROXY v1/v2 header from trusted_cidrs peers on routing and
+    /// forwarding TCP listeners and use its client address; from other peers the connection
+    /// is refused. Requires trusted_cidrs.
+    #[serde(default)]
+    accept_proxy_protocol: bool,
+
+    /// Shape of session ids: counter (s1, s2, ...), uuid (random v4), or timestamp
+    /// (s<unix_ms>-<counter>). Requires a restart.
+    #[serde(default)]
+    #[schemars(extend("enum" = ["counter", "uuid", "timestamp"], "default" = "counter"))]
+    session_id_format: String,
+
+    /// Append a short hash of the client IP, keyed with a random per-process secret, to
+    /// session ids for correlation with backend logs. Requires a restart.
+    #[serde(default)]
+    session_id_client_hash: bool,
+
+    tls: Option<FileTls>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Managed-mode bootstrap settings. In managed deployments the file stays bootstrap-focused
+/// while desired runtime config is stored centrally on the management node.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct FileManagedBootstrap {
     management: Option<FileManagementBootstrap>,
     worker: Option<FileWorkerBootstrap>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct FileManagementBootstrap {
+    /// JSON state file path for the embedded management control plane. Relative paths
+    /// resolve under Prism workdir.
+    #[schemars(extend("default" = "managed-state.json"))]
     state_file: Option<String>,
+    /// Bearer token required for panel/browser access to /managed/* endpoints.
+    #[schemars(extend("minLength" = 1))]
     panel_token: Option<String>,
+    /// Bearer token required for worker sync or management-to-worker agent access.
+    #[schemars(extend("minLength" = 1))]
     worker_token: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
+#[schemars(extend(
+    "allOf" = [
+        {
+            "if": {"properties": {"connection_mode": {"const": "active"}}},
+            "then": {"required": ["management_url"]}
+        }
+    ],
+))]
 struct FileWorkerBootstrap {
+    /// Stable worker identity used by the management node to track desired/applied
+    /// revisions.
+    #[schemars(extend("minLength" = 1))]
     node_id: Option<String>,
+    /// Management API base URL used by active workers to sync desired config and report
+    /// status.
+    #[schemars(extend("examples" = ["http://127.0.0.1:8080"]))]
     management_url: Option<String>,
+    /// Worker bearer token used for active sync and passive worker-agent endpoints.
+    #[schemars(extend("minLength" = 1))]
     auth_token: Option<String>,
+    /// active = worker dials management; passive = worker exposes local agent endpoints for
+    /// a reachable management node.
+    #[schemars(extend("enum" = ["active", "passive"], "default" = "active"))]
     connection_mode: Option<String>,
+    /// Active worker sync interval in milliseconds.
+    #[schemars(extend("default" = 5000, "minimum" = 0))]
     sync_interval_ms: Option<i64>,
+    /// Optional reachable worker agent URL advertised for passive management access.
+    #[schemars(extend("examples" = ["http://10.0.0.12:8081", ""]))]
     agent_url: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// An included config file: only routes and listeners.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FileInclude {
+    #[serde(default)]
+    routes: Vec<FileRoute>,
+    #[serde(default)]
+    listeners: Vec<FileProxyListener>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(extend(
+    "allOf" = [
+        {
+            "if": {"properties": {"protocol": {"const": "udp"}}, "required": ["protocol"]},
+            "then": {
+                "required": ["upstream"],
+                "properties": {"upstream": {"minLength": 1}}
+            }
+        },
+        {
+            "if": {"properties": {"mode": {"const": "status-health"}}, "required": ["mode"]},
+            "then": {"properties": {"protocol": {"const": "tcp"}}}
+        },
+        {
+            "if": {"properties": {"mode": {"const": "observe"}}, "required": ["mode"]},
+            "then": {
+                "properties": {
+                    "protocol": {"enum": ["tcp", "tls", "auto"]},
+                    "upstream": {"const": ""}
+                }
+            }
+        }
+    ],
+))]
 struct FileProxyListener {
+    /// Listener address.
+    #[schemars(extend("examples" = [":25565"], "minLength" = 1))]
     listen_addr: String,
+    /// Listener protocol. tls routes on the ClientHello SNI and passes the stream through
+    /// untouched. auto sniffs each connection: TLS is routed as on tls, everything else as
+    /// on a tcp routing listener.
     #[serde(default)]
+    #[schemars(extend("enum" = ["tcp", "udp", "tls", "auto"], "default" = "tcp"))]
     protocol: String,
+    /// Forward target. For tcp: empty means hostname-routing mode; non-empty means fixed
+    /// forward mode. For udp: required. For tls and auto: must be empty. A tcp upstream may
+    /// carry a #proxy=none|v1|v2 suffix.
     #[serde(default)]
+    #[schemars(extend("examples" = ["127.0.0.1:19132", "tunnel:home-mc", ""]))]
     upstream: String,
+    /// Local source IP for direct UDP upstream sockets. When empty, Prism binds the
+    /// unspecified address of the upstream's address family.
+    #[schemars(extend("examples" = ["10.0.0.5", "2001:db8::5"]))]
+    source_ip: Option<String>,
+    /// Listener mode. "proxy" (default) proxies traffic; "status-health" (TCP only) answers
+    /// Minecraft status pings locally with status_json, for load balancer health checks;
+    /// "observe" (routing listeners only: tcp without upstream, tls, auto) resolves routes
+    /// and logs the decision, then closes without dialing upstream.
+    #[serde(default)]
+    #[schemars(extend("enum" = ["", "proxy", "status-health", "observe"], "default" = "proxy"))]
+    mode: String,
+    /// Status response JSON object for status-health listeners. When version.protocol is
+    /// omitted, the client's handshake protocol is echoed.
+    status_json: Option<String>,
+    /// Monitor probes answered before routing (routing listeners only: tcp without
+    /// upstream, tls, auto). A connection starting with match gets reply and is closed.
+    #[serde(default)]
+    #[schemars(extend("default" = []))]
+    health_probes: Vec<FileHealthProbe>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FileHealthProbe {
+    /// Bytes a connection must start with to get the reply.
+    #[serde(rename = "match")]
+    #[schemars(extend("minLength" = 1))]
+    pattern: String,
+    /// Bytes written back before closing; empty just closes.
+    #[serde(default)]
+    reply: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct FileLogging {
+    /// Log level.
+    #[schemars(extend("enum" = ["debug", "info", "warn", "error"], "default" = "info"))]
     level: Option<String>,
+    /// Log format.
+    #[schemars(extend("enum" = ["json", "text"], "default" = "json"))]
     format: Option<String>,
+    /// Log output: stderr, stdout, discard, or a file path.
+    #[schemars(extend(
+        "default" = "stderr",
+        "examples" = ["stderr", "stdout", "discard", "./prism.log"],
+    ))]
     output: Option<String>,
+    /// Include source file/line in logs (slightly higher overhead).
     #[serde(default)]
     add_source: bool,
+    /// Emit one INFO event per finished TCP session on the prism::access target (sid,
+    /// client_ip, host, upstream, bytes_in, bytes_out, duration_ms, close_reason),
+    /// regardless of level. Hot-reloaded.
+    #[serde(default)]
+    access_log: bool,
+}
+
+/// Minimum TLS version and cipher suites applied to every TLS config Prism builds (QUIC
+/// tunnel server and client). Requires a restart.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FileTls {
+    /// Lowest TLS version to negotiate.
+    #[serde(default)]
+    #[schemars(extend("enum" = ["1.2", "1.3"], "default" = "1.2"))]
+    min_version: String,
+    /// Allowlist of rustls cipher suite names. Empty keeps the rustls defaults. Must
+    /// include a TLS 1.3 suite when the quic transport is used.
+    #[serde(default)]
+    #[schemars(extend(
+        "examples" = [["TLS13_AES_256_GCM_SHA384", "TLS13_CHACHA20_POLY1305_SHA256"]],
+    ))]
+    cipher_suites: Vec<String>,
+}
+
+/// Per-client-IP token bucket for new TCP connections on routing and forwarding listeners.
+/// Connections over the rate are closed at accept; trusted_cidrs are exempt.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FileRateLimit {
+    /// Sustained new connections per second per client IP. 0 disables rate limiting.
+    #[serde(default)]
+    connections_per_second: u32,
+    /// Connections allowed back to back before the rate applies. Defaults to
+    /// connections_per_second.
+    #[schemars(extend("minimum" = 1))]
+    burst: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Active upstream health checks: periodic Minecraft status pings to every configured
+/// upstream (templates and tunnel services are skipped). Failing upstreams are tried after
+/// healthy ones.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FileHealthCheck {
+    /// Defaults to true when the block is present.
+    #[schemars(extend("default" = true))]
+    enabled: Option<bool>,
+    /// Time between probe rounds.
+    #[schemars(extend("default" = 10000, "minimum" = 1))]
+    interval_ms: Option<u64>,
+    /// Deadline for a probe's dial and status response.
+    #[schemars(extend("default" = 2000, "minimum" = 1))]
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct FileReload {
+    /// Enable automatic reload watching (file-based provider only).
     #[serde(default)]
+    #[schemars(extend("default" = true))]
     enabled: bool,
+    /// How often to poll for file changes (milliseconds).
+    #[schemars(extend("default" = 1000, "minimum" = 0))]
     poll_interval_ms: Option<i64>,
+    /// What a reload does when the listener set changed: warn keeps the running listeners
+    /// and logs that a restart is required; hot stops removed listeners and starts added
+    /// ones in place.
+    #[serde(default)]
+    #[schemars(extend("enum" = ["warn", "hot"], "default" = "warn"))]
+    listener_mode: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct FileTimeouts {
+    /// Timeout for reading the initial handshake/header for routing.
+    #[schemars(extend("default" = 3000, "minimum" = 0))]
     handshake_timeout_ms: Option<i64>,
+    /// Close a proxied TCP session after no bytes moved in either direction for this long
+    /// (also the UDP session idle timeout). 0 means disabled.
+    #[schemars(extend("default" = 0, "minimum" = 0))]
     idle_timeout_ms: Option<i64>,
+    /// Abort a proxied session when a single step stalls this long: waiting for data from
+    /// either side, or writing one chunk. Catches backends that hang mid-transfer sooner
+    /// than idle_timeout_ms. 0 means disabled.
+    #[schemars(extend("default" = 0, "minimum" = 0))]
+    io_op_timeout_ms: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
+#[schemars(extend(
+    "allOf" = [
+        {
+            "anyOf": [
+                {"required": ["host"]},
+                {"required": ["host_regex"]},
+                {"required": ["default"], "properties": {"default": {"const": true}}}
+            ]
+        },
+        {
+            "anyOf": [
+                {"required": ["upstream"]},
+                {"required": ["upstreams"]},
+                {"required": ["backend"]},
+                {"required": ["backends"]}
+            ]
+        },
+        {"anyOf": [{"required": ["middlewares"]}, {"required": ["parsers"]}]}
+    ],
+))]
 struct FileRoute {
+    /// Host pattern(s) to match. Supports '*' and '?' wildcards (case-insensitive).
+    /// Examples: 'play.example.com', '*.example.com', '*'
     host: Option<StringOrVec>,
+    /// Alias of host.
     hosts: Option<StringOrVec>,
+    /// Regular expression(s) matched against the whole lowercased host, in addition to
+    /// host. Groups are available to upstream templates as $1, $2... and named groups as
+    /// $name or ${name}.
+    #[schemars(extend("examples" = ["(?P<region>\\w+)\\.play\\.example\\.com"]))]
+    host_regex: Option<StringOrVec>,
+    /// Upstream target(s) (host:port, srv:<domain> for _minecraft._tcp SRV resolution, or
+    /// tunnel:<service>). $1, $2... are substituted from wildcard capture groups. An
+    /// optional #weight=N suffix sets the weight for strategy "weighted"; #proxy=none|v1|v2
+    /// sets the PROXY protocol header for that upstream alone, overriding the route and
+    /// global proxy_protocol.
     upstream: Option<StringOrVec>,
+    /// Alias of upstream.
     upstreams: Option<StringOrVec>,
+    /// Alias of upstream (gate-lite compatible naming).
     backend: Option<StringOrVec>,
+    /// Alias of upstreams (gate-lite compatible naming).
     backends: Option<StringOrVec>,
 
+    /// Ordered wasm middleware names to apply for this route (name only; do not include
+    /// .wat). Middlewares can parse hostnames and can optionally rewrite the captured
+    /// prelude before proxying upstream.
     middlewares: Option<StringOrVec>,
-    // Back-compat alias (deprecated): `parsers`.
+    /// Deprecated alias of middlewares (kept for backward compatibility).
     parsers: Option<StringOrVec>,
 
+    /// Load balancing strategy when multiple upstreams are configured. "weighted" honors an
+    /// optional #weight=N suffix on upstream entries (default 1). "ip-hash" pins each
+    /// client IP to the same upstream.
+    #[schemars(extend(
+        "enum" = ["sequential", "random", "round-robin", "weighted", "ip-hash"],
+        "default" = "sequential",
+    ))]
     strategy: Option<String>,
-}
 
-#[derive(Debug, Deserialize)]
-struct FileTunnel {
-    auth_token: Option<String>,
-    auto_listen_services: Option<bool>,
-    endpoints: Option<Vec<FileTunnelEndpoint>>,
-    client: Option<FileTunnelClient>,
-    services: Option<Vec<FileTunnelService>>,
-}
+    /// Fraction of sessions on this route that record a session trace span. Omit to trace
+    /// every session.
+    #[schemars(extend("examples" = [1.0, 0.01], "minimum" = 0, "maximum" = 1))]
+    trace_sample_ratio: Option<f64>,
 
-#[derive(Debug, Deserialize)]
-struct FileTunnelEndpoint {
-    listen_addr: String,
+    /// Only consider this route for connections whose first bytes look like a Minecraft
+    /// handshake (VarInt frame length + packet id 0x00). TLS, HTTP and scanner traffic is
+    /// rejected on the first read instead of being buffered up to max_header_bytes.
+    #[serde(default)]
+    strict_minecraft: bool,
+
+    /// Shared secret players prepend as the leading host label (secret.play.example.com).
+    /// Prism strips it before matching; hosts aimed at this route with a missing or wrong
+    /// secret are kicked.
+    #[schemars(extend("examples" = ["k7f2q9"], "pattern" = "^[^.]*$"))]
+    host_secret: Option<String>,
+
+    /// PROXY protocol header written to this route's upstreams. Overrides the global
+    /// proxy_protocol setting.
+    #[schemars(extend("enum" = ["none", "v1", "v2"]))]
+    proxy_protocol: Option<String>,
+
+    /// Status JSON object answered to server list pings when every upstream fails to dial.
+    /// Either inline JSON (starting with '{') or a path relative to the config file.
+    #[schemars(extend(
+        "examples" = ["{\"description\":{\"text\":\"Under maintenance\"}}", "maintenance.json"],
+    ))]
+    offline_status: Option<String>,
+
+    /// In-protocol client address forwarding. legacy (alias bungeecord) appends the client
+    /// IP and UUID to the login handshake host for backends in BungeeCord forwarding mode.
+    /// modern (alias velocity) answers the backend's velocity:player_info login query with
+    /// player info signed by forwarding_secret.
+    #[schemars(extend(
+        "enum" = ["none", "legacy", "bungeecord", "modern", "velocity"],
+        "default" = "none",
+    ))]
+    forwarding: Option<String>,
+
+    /// Shared secret signing modern (Velocity) forwarding data; must match the backend's
+    /// forwarding secret. Required when forwarding is modern.
+    forwarding_secret: Option<String>,
+
+    /// Server list fields replaced in status responses from this route's upstreams and in
+    /// offline_status. Unset fields keep the backend's values.
+    status_override: Option<FileStatusOverride>,
+
+    /// Maximum concurrent sessions proxied through this route. Logins over the cap are
+    /// kicked with a 'server is full' message; status pings get offline_status. 0 means
+    /// unlimited.
+    #[serde(default)]
+    #[schemars(extend("minimum" = 0))]
+    max_upstream_connections: i64,
+
+    /// Extra dial attempts per upstream candidate before failing over to the next one.
+    /// Total retry time stays within handshake_timeout_ms.
+    #[serde(default)]
+    dial_retries: u32,
+
+    /// Wait before the first dial retry; doubled for each further retry.
+    #[schemars(extend("default" = 100))]
+    dial_retry_backoff_ms: Option<u64>,
+
+    /// Overrides timeouts.handshake_timeout_ms for this route. Unrouted handshakes wait up
+    /// to the longest route handshake timeout.
+    #[schemars(extend("minimum" = 1))]
+    handshake_timeout_ms: Option<u64>,
+
+    /// Overrides timeouts.idle_timeout_ms for this route. 0 disables the idle timeout.
+    idle_timeout_ms: Option<u64>,
+
+    /// Overrides upstream_dial_timeout_ms for this route's upstreams.
+    upstream_dial_timeout_ms: Option<u64>,
+
+    /// Client networks (CIDR or bare IP) allowed on this route. Empty allows every client
+    /// not in deny_cidrs.
+    #[serde(default)]
+    #[schemars(extend("examples" = [["10.0.0.0/8", "2001:db8::/32"]]))]
+    allow_cidrs: Vec<String>,
+
+    /// Client networks (CIDR or bare IP) refused on this route; takes precedence over
+    /// allow_cidrs.
+    #[serde(default)]
+    deny_cidrs: Vec<String>,
+
+    /// Routes with a higher priority are matched first; equal priorities keep config order.
+    /// Default routes are still tried last.
+    #[serde(default)]
+    priority: i32,
+
+    /// Catch-all route tried only after every other route fails to match. host may be
+    /// omitted (defaults to "*"). Routes with host "*" are treated the same way.
+    #[serde(default)]
+    default: bool,
+}
+
+/// Server list fields replaced in status responses from this route's upstreams and in
+/// offline_status. Unset fields keep the backend's values.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FileStatusOverride {
+    /// Replaces description.
+    motd: Option<String>,
+    /// Replaces players.max.
+    max_players: Option<u32>,
+    /// Replaces version.name.
+    version_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FileMiddlewareSettings {
+    /// Maximum routing host length (bytes) this middleware may return. Longer hosts are
+    /// treated as a middleware failure.
+    #[schemars(extend("default" = 255, "minimum" = 1))]
+    max_output_len: Option<i64>,
+    /// Wasm operators a single middleware call may execute. A call that runs out of fuel is
+    /// aborted and treated as a middleware failure.
+    #[schemars(extend("default" = 10000000, "minimum" = 1))]
+    fuel_limit: Option<i64>,
+    /// Cap on the middleware's wasm linear memory, in 64 KiB pages. Growth past the cap
+    /// fails, and oversized preludes or declared rewrites are treated as a middleware
+    /// failure.
+    #[schemars(extend("default" = 256, "minimum" = 4, "maximum" = 65536))]
+    max_memory_pages: Option<i64>,
+}
+
+/// Reverse-connection mode (client -> server) for reaching private backends.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct FileTunnel {
+    /// Optional shared secret required for tunnel client registration. The tunnel client
+    /// sends it; the tunnel server accepts it alongside auth_tokens.
+    #[schemars(extend("default" = ""))]
+    auth_token: Option<String>,
+    /// Further tokens the tunnel server accepts, e.g. one per client or old and new during
+    /// rotation. An entry may be a bare token or { token, label }; the label prefixes the
+    /// ids of clients that register with it. Auth is disabled only when both auth_token and
+    /// auth_tokens are empty.
+    #[schemars(extend(
+        "default" = [],
+        "examples" = [["t1", "t2"], [{"token": "t1", "label": "home"}]],
+    ))]
+    auth_tokens: Option<Vec<FileTunnelAuthToken>>,
+    /// When true, Prism auto-opens server-side listeners for services that set remote_addr.
+    #[schemars(extend("default" = true))]
+    auto_listen_services: Option<bool>,
+    /// Hold-down (milliseconds) after a service's primary tunnel client disconnects: other
+    /// sessions from the same remote IP are only promoted if no client from elsewhere can
+    /// serve the service. 0 disables.
+    #[schemars(extend("default" = 0, "examples" = [30000], "minimum" = 0))]
+    primary_grace_ms: Option<i64>,
+    /// How long (milliseconds) a dial to a tunnel service that is momentarily unregistered,
+    /// e.g. while its client reconnects, waits for it to come back before failing. 0
+    /// disables.
+    #[schemars(extend("default" = 0, "examples" = [3000], "minimum" = 0))]
+    dial_service_wait_ms: Option<i64>,
+    /// Streams buffered per tunnel session (server and client) before they are accepted. A
+    /// full queue applies backpressure to the peer instead of dropping streams.
+    #[schemars(extend("default" = 64, "minimum" = 1))]
+    accept_queue_depth: Option<i64>,
+    /// Tunnel server endpoints.
+    endpoints: Option<Vec<FileTunnelEndpoint>>,
+    client: Option<FileTunnelClient>,
+    /// Tunnel client registered services.
+    services: Option<Vec<FileTunnelService>>,
+}
+
+/// Either a bare token or `{ token, label }`.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum FileTunnelAuthToken {
+    Token(String),
+    Labeled {
+        token: String,
+        /// Client id prefix (and log label) for clients using this token.
+        #[schemars(extend("pattern" = "^[^\\s/]*$", "examples" = ["home"]))]
+        label: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct FileTunnelEndpoint {
+    #[schemars(extend("minLength" = 1))]
+    listen_addr: String,
+    #[schemars(extend("enum" = ["tcp", "udp", "quic", "ws", "wss"], "default" = "tcp"))]
     transport: Option<String>,
     quic: Option<FileQuicServer>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct FileTunnelClient {
+    /// Tunnel server address (host:port, or a ws:// or wss:// URL for the ws and wss
+    /// transports).
+    #[schemars(extend("minLength" = 1))]
     server_addr: String,
+    #[schemars(extend("enum" = ["tcp", "udp", "quic", "ws", "wss"], "default" = "tcp"))]
     transport: Option<String>,
+    /// Dial timeout for connecting to the tunnel server.
+    #[schemars(extend("default" = 5000, "minimum" = 0))]
     dial_timeout_ms: Option<i64>,
+    /// How often the client sends heartbeats; the server drops clients that miss 3 in a row
+    /// (0 disables them, and the server then keeps the client until it disconnects).
+    #[schemars(extend("default" = 10000, "minimum" = 0))]
+    heartbeat_interval_ms: Option<i64>,
+    /// On shutdown, the client asks the server to route new streams to other clients, then
+    /// waits up to this long for its open streams to finish before disconnecting (0 closes
+    /// at once).
+    #[schemars(extend("default" = 0, "examples" = [30000], "minimum" = 0))]
+    drain_timeout_ms: Option<i64>,
     quic: Option<FileQuicClient>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct FileQuicServer {
+    /// Path to TLS certificate PEM file. If empty, Prism may generate a self-signed cert.
+    #[schemars(extend("default" = ""))]
     cert_file: Option<String>,
+    /// Path to TLS private key PEM file. If empty, Prism may generate a self-signed key.
+    #[schemars(extend("default" = ""))]
     key_file: Option<String>,
+    /// Path to a CA bundle PEM file. When set, tunnel clients must present a certificate
+    /// issued by one of these CAs (mutual TLS).
+    #[schemars(extend("default" = ""))]
+    client_ca_file: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct FileQuicClient {
+    /// TLS SNI / server name used for QUIC.
+    #[schemars(extend("default" = ""))]
     server_name: Option<String>,
+    /// Skip TLS verification (dev/testing only).
     #[serde(default)]
     insecure_skip_verify: bool,
+    /// Path to the client certificate PEM file presented to servers that require mutual
+    /// TLS. Set together with client_key_file.
+    #[schemars(extend("default" = ""))]
+    client_cert_file: Option<String>,
+    /// Path to the client private key PEM file for mutual TLS. Set together with
+    /// client_cert_file.
+    #[schemars(extend("default" = ""))]
+    client_key_file: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(extend(
+    "allOf" = [
+        {
+            "if": {"properties": {"route_only": {"const": true}}, "required": ["route_only"]},
+            "then": {"not": {"required": ["remote_addr"]}}
+        }
+    ],
+))]
 struct FileTunnelService {
+    #[schemars(extend("minLength" = 1))]
     name: String,
+    #[schemars(extend("enum" = ["tcp", "udp"], "default" = "tcp"))]
     proto: Option<String>,
+    #[schemars(extend("minLength" = 1))]
     local_addr: String,
+    /// If true, only reachable via routes (tunnel:<service>) and never exposed via
+    /// remote_addr.
     #[serde(default)]
     route_only: bool,
+    /// Optional server-side listener request (frp-like). Must not be set when
+    /// route_only=true.
+    #[schemars(extend("examples" = [":25565", ""]))]
     remote_addr: Option<String>,
+    /// Advanced host label used by rewrite middlewares when this service is dialed as an
+    /// upstream (tunnel:<service>). Leave empty for normal Minecraft tunnel routing so
+    /// Prism preserves the client's handshake host. Supports $1, $2... substitutions from
+    /// route wildcard capture groups.
+    #[schemars(extend(
+        "default" = "",
+        "examples" = ["edge.internal", "$1.edge.internal", "edge.internal:25565", ""],
+    ))]
     masquerade_host: Option<String>,
+    /// Caps each direction of this service's traffic at this many bytes per second,
+    /// enforced by the tunnel client. 0 = unlimited.
+    #[schemars(extend("default" = 0, "examples" = [0, 1048576]))]
+    rate_limit_bytes_per_sec: Option<u64>,
+    /// Whether rate_limit_bytes_per_sec applies to each connection separately (connection)
+    /// or is shared by all of the service's connections (service).
+    #[schemars(extend("enum" = ["connection", "service"], "default" = "connection"))]
+    rate_limit_scope: Option<String>,
+    /// When several tunnel clients provide this service: primary sends every dial to the
+    /// routing owner; balance round-robins dials across every client that also set balance.
+    /// The primary client's setting decides whether dials are balanced at all.
+    #[schemars(extend("enum" = ["primary", "balance"], "default" = "primary"))]
+    dial_policy: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Either a single string or a list of strings.
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 enum StringOrVec {
     One(String),
@@ -691,17 +1991,20 @@ impl StringOrVec {
 }
 
 impl Config {
-    fn from_file_config(fc: &mut FileConfig, _config_path: &Path) -> anyhow::Result<Config> {
+    fn from_file_config(fc: &mut FileConfig, config_path: &Path) -> anyhow::Result<Config> {
         let mut cfg = Config {
             role: PrismRole::Standalone,
             managed: ManagedBootstrapConfig::default(),
             listeners: vec![],
             admin_addr: fc.admin_addr.trim().to_string(),
+            admin_auth_token: fc.admin_auth_token.trim().to_string(),
+            admin_basic_auth: parse_admin_basic_auth(&fc.admin_basic_auth)?,
             logging: LoggingConfig {
                 level: "info".into(),
                 format: "json".into(),
                 output: "stderr".into(),
                 add_source: false,
+                access_log: false,
             },
             routes: vec![],
             max_header_bytes: fc.max_header_bytes as usize,
@@ -714,12 +2017,38 @@ impl Config {
                         .unwrap_or(1000)
                         .max(0) as u64,
                 ),
+                listener_mode: ListenerReloadMode::parse(
+                    fc.reload
+                        .as_ref()
+                        .map(|r| r.listener_mode.as_str())
+                        .unwrap_or(""),
+                )?,
+            },
+            proxy_protocol: match fc.proxy_protocol.as_deref() {
+                Some(s) => {
+                    let version = ProxyProtocol::parse(s)?;
+                    if fc.proxy_protocol_v2 && version != ProxyProtocol::V2 {
+                        anyhow::bail!(
+                            "config: proxy_protocol_v2 = true conflicts with proxy_protocol = {:?}",
+                            s
+                        );
+                    }
+                    version
+                }
+                None if fc.proxy_protocol_v2 => ProxyProtocol::V2,
+                None => ProxyProtocol::None,
             },
-            proxy_protocol_v2: fc.proxy_protocol_v2,
             buffer_size: (fc.buffer_size).max(0) as usize,
             upstream_dial_timeout: Duration::from_millis(
                 (fc.upstream_dial_timeout_ms).max(0) as u64
             ),
+            tcp_user_timeout: Duration::from_millis((fc.tcp_user_timeout_ms).max(0) as u64),
+            tcp_nodelay: fc.tcp_nodelay.unwrap_or(true),
+            tcp_keepalive: Duration::from_millis((fc.tcp_keepalive_ms).max(0) as u64),
+            shutdown_drain_timeout: Duration::from_millis(
+                fc.shutdown_drain_timeout_ms
+                    .unwrap_or(DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_MS),
+            ),
             timeouts: Timeouts {
                 handshake_timeout: Duration::from_millis(
                     fc.timeouts
@@ -735,13 +2064,118 @@ impl Config {
                         .unwrap_or(0)
                         .max(0) as u64,
                 ),
+                io_op_timeout: Duration::from_millis(
+                    fc.timeouts
+                        .as_ref()
+                        .and_then(|t| t.io_op_timeout_ms)
+                        .unwrap_or(0)
+                        .max(0) as u64,
+                ),
             },
             tunnel: TunnelConfig::default(),
+            middleware_settings: BTreeMap::new(),
+            allow_wasm_binary: fc.allow_wasm_binary,
+            on_no_route: NoRoutePolicy::parse(&fc.on_no_route)?,
+            max_sessions_per_ip: fc.max_sessions_per_ip.max(0) as usize,
+            trusted_cidrs: fc
+                .trusted_cidrs
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    IpNet::parse(c).with_context(|| format!("config: trusted_cidrs[{}]", i))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            upstream_max_connections: parse_upstream_max_connections(&fc.upstream_max_connections)?,
+            upstream_eject_failures: fc
+                .upstream_eject_failures
+                .unwrap_or(DEFAULT_UPSTREAM_EJECT_FAILURES),
+            upstream_eject_cooldown: Duration::from_millis(
+                fc.upstream_eject_cooldown_ms
+                    .unwrap_or(DEFAULT_UPSTREAM_EJECT_COOLDOWN_MS),
+            ),
+            health_check: match &fc.health_check {
+                Some(h) => HealthCheckConfig {
+                    enabled: h.enabled.unwrap_or(true),
+                    interval: Duration::from_millis(
+                        h.interval_ms.unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL_MS),
+                    ),
+                    timeout: Duration::from_millis(
+                        h.timeout_ms.unwrap_or(DEFAULT_HEALTH_CHECK_TIMEOUT_MS),
+                    ),
+                },
+                None => HealthCheckConfig::default(),
+            },
+            rate_limit: match &fc.rate_limit {
+                Some(r) => RateLimitConfig {
+                    connections_per_second: r.connections_per_second,
+                    burst: r.burst.unwrap_or(r.connections_per_second),
+                },
+                None => RateLimitConfig::default(),
+            },
+            max_connections: fc.max_connections.max(0) as usize,
+            max_udp_sessions: fc.max_udp_sessions.max(0) as usize,
+            udp_buffer_size: fc.udp_buffer_size.unwrap_or(DEFAULT_UDP_BUFFER_SIZE),
+            max_udp_datagram_bytes: fc
+                .max_udp_datagram_bytes
+                .or(fc.udp_buffer_size)
+                .unwrap_or(DEFAULT_UDP_BUFFER_SIZE),
+            half_close_propagation: fc.half_close_propagation.unwrap_or(true),
+            upstream_ip_version: UpstreamIpVersion::parse(&fc.upstream_ip_version)?,
+            default_minecraft_port: match fc.default_minecraft_port {
+                None => DEFAULT_MINECRAFT_PORT,
+                Some(p) if (1..=65535).contains(&p) => p as u16,
+                Some(p) => anyhow::bail!(
+                    "config: default_minecraft_port must be between 1 and 65535 (got {})",
+                    p
+                ),
+            },
+            sniff_protocols: fc.sniff_protocols,
+            accept_proxy_protocol: fc.accept_proxy_protocol,
+            handshake_buffer_pool: fc
+                .handshake_buffer_pool
+                .map(|n| n.max(0) as usize)
+                .unwrap_or(DEFAULT_HANDSHAKE_BUFFER_POOL),
+            geoip_db: fc.geoip_db.trim().to_string(),
+            session_id_format: SessionIdFormat::parse(&fc.session_id_format)?,
+            session_id_client_hash: fc.session_id_client_hash,
+            tls: match &fc.tls {
+                Some(t) => tls::TlsPolicy {
+                    min_version: tls::TlsVersion::parse(&t.min_version)?,
+                    cipher_suites: t
+                        .cipher_suites
+                        .iter()
+                        .map(|s| s.trim().to_ascii_uppercase())
+                        .filter(|s| !s.is_empty())
+                        .collect(),
+                },
+                None => tls::TlsPolicy::default(),
+            },
         };
 
         if cfg.max_header_bytes == 0 {
             cfg.max_header_bytes = 64 * 1024;
         }
+        if cfg.accept_proxy_protocol && cfg.trusted_cidrs.is_empty() {
+            anyhow::bail!(
+                "config: accept_proxy_protocol requires trusted_cidrs (the front proxies)"
+            );
+        }
+        if cfg.health_check.interval.is_zero() || cfg.health_check.timeout.is_zero() {
+            anyhow::bail!("config: health_check.interval_ms and timeout_ms must be at least 1");
+        }
+        if cfg.rate_limit.connections_per_second > 0 && cfg.rate_limit.burst == 0 {
+            anyhow::bail!("config: rate_limit.burst must be at least 1");
+        }
+        if cfg.udp_buffer_size == 0 {
+            anyhow::bail!("config: udp_buffer_size must be at least 1");
+        }
+        if cfg.max_udp_datagram_bytes > cfg.udp_buffer_size {
+            anyhow::bail!(
+                "config: max_udp_datagram_bytes ({}) exceeds udp_buffer_size ({})",
+                cfg.max_udp_datagram_bytes,
+                cfg.udp_buffer_size
+            );
+        }
         if cfg.buffer_size == 0 {
             cfg.buffer_size = 32 * 1024;
         }
@@ -750,16 +2184,81 @@ impl Config {
         }
 
         // --- Listeners ---
-        for l in &fc.listeners {
+        for (i, l) in fc.listeners.iter().enumerate() {
             let proto = if l.protocol.trim().is_empty() {
                 "tcp".to_string()
             } else {
                 l.protocol.trim().to_ascii_lowercase()
             };
+            let source_ip = match l.source_ip.as_deref().map(str::trim) {
+                None | Some("") => None,
+                Some(s) => Some(s.parse().map_err(|_| {
+                    anyhow::anyhow!("config: listeners[{}] invalid source_ip {:?}", i, s)
+                })?),
+            };
+            let mode = ListenerMode::parse(&l.mode)
+                .with_context(|| format!("config: listeners[{}]", i))?;
+            if mode == ListenerMode::StatusHealth && proto != "tcp" {
+                anyhow::bail!(
+                    "config: listeners[{}] mode=status-health requires protocol=tcp",
+                    i
+                );
+            }
+            // tcp without an upstream, tls and auto listeners all route per connection.
+            let routing = proto != "udp" && l.upstream.trim().is_empty();
+            if mode == ListenerMode::Observe && !routing {
+                anyhow::bail!(
+                    "config: listeners[{}] mode=observe requires a routing listener (tcp with an empty upstream, tls or auto)",
+                    i
+                );
+            }
+            if proto == "tcp" && !l.upstream.trim().is_empty() {
+                router::split_upstream_options(l.upstream.trim())
+                    .with_context(|| format!("config: listeners[{}] upstream", i))?;
+            }
+            if (proto == "tls" || proto == "auto") && !l.upstream.trim().is_empty() {
+                anyhow::bail!(
+                    "config: listeners[{}] protocol={} routes per connection and takes no upstream",
+                    i,
+                    proto
+                );
+            }
+            let status_json = match l.status_json.as_deref().map(str::trim) {
+                None | Some("") => DEFAULT_STATUS_HEALTH_JSON.to_string(),
+                Some(s) => {
+                    let v: serde_json::Value = serde_json::from_str(s).with_context(|| {
+                        format!("config: listeners[{}] status_json is not valid JSON", i)
+                    })?;
+                    if !v.is_object() {
+                        anyhow::bail!("config: listeners[{}] status_json must be an object", i);
+                    }
+                    s.to_string()
+                }
+            };
+            if !l.health_probes.is_empty() && (!routing || mode == ListenerMode::StatusHealth) {
+                anyhow::bail!(
+                    "config: listeners[{}] health_probes require a routing listener (tcp with an empty upstream, tls or auto)",
+                    i
+                );
+            }
+            let mut health_probes = Vec::with_capacity(l.health_probes.len());
+            for (j, p) in l.health_probes.iter().enumerate() {
+                if p.pattern.is_empty() {
+                    anyhow::bail!("config: listeners[{}].health_probes[{}] empty match", i, j);
+                }
+                health_probes.push(HealthProbe {
+                    pattern: p.pattern.as_bytes().to_vec(),
+                    reply: p.reply.as_bytes().to_vec(),
+                });
+            }
             cfg.listeners.push(ProxyListenerConfig {
                 listen_addr: l.listen_addr.trim().to_string(),
                 protocol: proto,
                 upstream: l.upstream.trim().to_string(),
+                source_ip,
+                mode,
+                status_json,
+                health_probes,
             });
         }
 
@@ -795,12 +2294,29 @@ impl Config {
                     upstreams.extend(u.into_vec());
                 }
 
-                let hosts: Vec<String> = hosts
+                let mut hosts: Vec<String> = hosts
                     .into_iter()
                     .map(|s| s.trim().to_ascii_lowercase())
                     .filter(|s| !s.is_empty())
                     .collect();
-                if hosts.is_empty() {
+                let host_regex: Vec<String> = r
+                    .host_regex
+                    .clone()
+                    .map(StringOrVec::into_vec)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                for re in &host_regex {
+                    regex::Regex::new(re).with_context(|| {
+                        format!("config: routes[{}] invalid host_regex {:?}", i, re)
+                    })?;
+                }
+                if hosts.is_empty() && host_regex.is_empty() && r.default {
+                    hosts.push("*".to_string());
+                }
+                if hosts.is_empty() && host_regex.is_empty() {
                     anyhow::bail!("config: routes[{}] missing host", i);
                 }
 
@@ -842,15 +2358,163 @@ impl Config {
                     );
                 }
 
+                if let Some(ratio) = r.trace_sample_ratio
+                    && !(0.0..=1.0).contains(&ratio)
+                {
+                    anyhow::bail!(
+                        "config: routes[{}] trace_sample_ratio must be between 0.0 and 1.0",
+                        i
+                    );
+                }
+
+                let host_secret = match r.host_secret.as_deref().map(str::trim) {
+                    None | Some("") => None,
+                    Some(s) => {
+                        if s.contains('.') {
+                            anyhow::bail!(
+                                "config: routes[{}] host_secret must be a single host label (no dots)",
+                                i
+                            );
+                        }
+                        Some(s.to_ascii_lowercase())
+                    }
+                };
+
+                let proxy_protocol = match r.proxy_protocol.as_deref().map(str::trim) {
+                    None | Some("") => None,
+                    Some(s) => Some(ProxyProtocol::parse(s).with_context(|| {
+                        format!("config: routes[{}] invalid proxy_protocol", i)
+                    })?),
+                };
+
+                let offline_status = match r.offline_status.as_deref().map(str::trim) {
+                    None | Some("") => None,
+                    Some(s) => Some(
+                        load_offline_status(s, config_path)
+                            .with_context(|| format!("config: routes[{}] offline_status", i))?,
+                    ),
+                };
+
+                let forwarding = Forwarding::parse(r.forwarding.as_deref().unwrap_or(""))
+                    .with_context(|| format!("config: routes[{}] invalid forwarding", i))?;
+                let forwarding_secret = r
+                    .forwarding_secret
+                    .as_deref()
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string);
+                if forwarding == Forwarding::Modern && forwarding_secret.is_none() {
+                    anyhow::bail!(
+                        "config: routes[{}] forwarding = \"modern\" requires forwarding_secret",
+                        i
+                    );
+                }
+
+                let status_override = r
+                    .status_override
+                    .as_ref()
+                    .map(|o| StatusOverride {
+                        motd: o.motd.clone(),
+                        max_players: o.max_players,
+                        version_name: o.version_name.clone(),
+                    })
+                    .filter(|o| *o != StatusOverride::default());
+
+                // The route is only known once the handshake is parsed, so a handshake
+                // timeout of zero (no limit) would have to lift the limit for every route.
+                if r.handshake_timeout_ms == Some(0) {
+                    anyhow::bail!(
+                        "config: routes[{}] handshake_timeout_ms must be greater than 0",
+                        i
+                    );
+                }
+
+                let parse_cidrs = |field: &str, cidrs: &[String]| {
+                    cidrs
+                        .iter()
+                        .enumerate()
+                        .map(|(j, c)| {
+                            IpNet::parse(c)
+                                .with_context(|| format!("config: routes[{}] {}[{}]", i, field, j))
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()
+                };
+                let allow_cidrs = parse_cidrs("allow_cidrs", &r.allow_cidrs)?;
+                let deny_cidrs = parse_cidrs("deny_cidrs", &r.deny_cidrs)?;
+
                 cfg.routes.push(RouteConfig {
                     host: hosts,
+                    host_regex,
                     upstreams,
                     middlewares,
                     strategy,
+                    trace_sample_ratio: r.trace_sample_ratio,
+                    strict_minecraft: r.strict_minecraft,
+                    host_secret,
+                    proxy_protocol,
+                    offline_status,
+                    forwarding,
+                    forwarding_secret,
+                    status_override,
+                    max_upstream_connections: r.max_upstream_connections.max(0) as usize,
+                    dial_retries: r.dial_retries,
+                    dial_retry_backoff: Duration::from_millis(
+                        r.dial_retry_backoff_ms
+                            .unwrap_or(DEFAULT_DIAL_RETRY_BACKOFF_MS),
+                    ),
+                    handshake_timeout: r.handshake_timeout_ms.map(Duration::from_millis),
+                    idle_timeout: r.idle_timeout_ms.map(Duration::from_millis),
+                    upstream_dial_timeout: r.upstream_dial_timeout_ms.map(Duration::from_millis),
+                    allow_cidrs,
+                    deny_cidrs,
+                    priority: r.priority,
+                    default: r.default,
                 });
             }
         }
 
+        // --- Middleware settings ---
+        for (name, s) in &fc.middleware_settings {
+            let name = normalize_middleware_ref(name)
+                .with_context(|| format!("config: middleware_settings key {:?}", name))?;
+            let max_output_len = s
+                .max_output_len
+                .unwrap_or(crate::prism::middleware::DEFAULT_MAX_OUTPUT_LEN as i64);
+            if max_output_len < 1 {
+                anyhow::bail!(
+                    "config: middleware_settings.{}.max_output_len must be at least 1",
+                    name
+                );
+            }
+            let fuel_limit = s
+                .fuel_limit
+                .unwrap_or(crate::prism::middleware::DEFAULT_FUEL_LIMIT as i64);
+            if fuel_limit < 1 {
+                anyhow::bail!(
+                    "config: middleware_settings.{}.fuel_limit must be at least 1",
+                    name
+                );
+            }
+            let min_pages = crate::prism::middleware::MIN_MEMORY_PAGES as i64;
+            let max_memory_pages = s
+                .max_memory_pages
+                .unwrap_or(crate::prism::middleware::DEFAULT_MAX_MEMORY_PAGES as i64);
+            if !(min_pages..=65536).contains(&max_memory_pages) {
+                anyhow::bail!(
+                    "config: middleware_settings.{}.max_memory_pages must be between {} and 65536",
+                    name,
+                    min_pages
+                );
+            }
+            cfg.middleware_settings.insert(
+                name,
+                MiddlewareSettings {
+                    max_output_len: max_output_len as usize,
+                    fuel_limit: fuel_limit as u64,
+                    max_memory_pages: max_memory_pages as u32,
+                },
+            );
+        }
+
         // --- Logging ---
         if let Some(l) = &fc.logging {
             if let Some(level) = &l.level
@@ -869,12 +2533,51 @@ impl Config {
                 cfg.logging.output = out.trim().to_string();
             }
             cfg.logging.add_source = l.add_source;
+            cfg.logging.access_log = l.access_log;
         }
 
         // --- Tunnel ---
         if let Some(t) = &fc.tunnel {
             cfg.tunnel.auth_token = t.auth_token.clone().unwrap_or_default().trim().to_string();
+            for entry in t.auth_tokens.iter().flatten() {
+                let (token, label) = match entry {
+                    FileTunnelAuthToken::Token(token) => (token.trim(), ""),
+                    FileTunnelAuthToken::Labeled { token, label } => {
+                        (token.trim(), label.as_deref().unwrap_or_default().trim())
+                    }
+                };
+                if token.is_empty() {
+                    anyhow::bail!("config: tunnel.auth_tokens entries must not be empty");
+                }
+                if label.contains(char::is_whitespace) || label.contains('/') {
+                    anyhow::bail!(
+                        "config: tunnel.auth_tokens label {:?} must not contain whitespace or '/'",
+                        label
+                    );
+                }
+                if token == cfg.tunnel.auth_token
+                    || cfg.tunnel.auth_tokens.iter().any(|t| t.token == token)
+                {
+                    anyhow::bail!("config: tunnel.auth_tokens lists the same token twice");
+                }
+                cfg.tunnel.auth_tokens.push(TunnelAuthToken {
+                    token: token.to_string(),
+                    label: label.to_string(),
+                });
+            }
             cfg.tunnel.auto_listen_services = t.auto_listen_services.unwrap_or(true);
+            cfg.tunnel.primary_grace =
+                Duration::from_millis(t.primary_grace_ms.unwrap_or(0).max(0) as u64);
+            cfg.tunnel.dial_service_wait =
+                Duration::from_millis(t.dial_service_wait_ms.unwrap_or(0).max(0) as u64);
+            cfg.tunnel.accept_queue_depth = match t.accept_queue_depth {
+                None => tunnel::transport::DEFAULT_ACCEPT_QUEUE_DEPTH,
+                Some(n) if n > 0 => n as usize,
+                Some(n) => anyhow::bail!(
+                    "config: tunnel.accept_queue_depth must be positive (got {})",
+                    n
+                ),
+            };
 
             if let Some(eps) = &t.endpoints {
                 for ep in eps {
@@ -901,6 +2604,13 @@ impl Config {
                                 .unwrap_or_default()
                                 .trim()
                                 .to_string(),
+                            client_ca_file: ep
+                                .quic
+                                .as_ref()
+                                .and_then(|q| q.client_ca_file.clone())
+                                .unwrap_or_default()
+                                .trim()
+                                .to_string(),
                         },
                     });
                 }
@@ -918,6 +2628,12 @@ impl Config {
                     dial_timeout: Duration::from_millis(
                         c.dial_timeout_ms.unwrap_or(5000).max(0) as u64
                     ),
+                    heartbeat_interval: Duration::from_millis(
+                        c.heartbeat_interval_ms.unwrap_or(10_000).max(0) as u64,
+                    ),
+                    drain_timeout: Duration::from_millis(
+                        c.drain_timeout_ms.unwrap_or(0).max(0) as u64
+                    ),
                     quic: QuicClientConfig {
                         server_name: c
                             .quic
@@ -931,6 +2647,20 @@ impl Config {
                             .as_ref()
                             .map(|q| q.insecure_skip_verify)
                             .unwrap_or(false),
+                        client_cert_file: c
+                            .quic
+                            .as_ref()
+                            .and_then(|q| q.client_cert_file.clone())
+                            .unwrap_or_default()
+                            .trim()
+                            .to_string(),
+                        client_key_file: c
+                            .quic
+                            .as_ref()
+                            .and_then(|q| q.client_key_file.clone())
+                            .unwrap_or_default()
+                            .trim()
+                            .to_string(),
                     },
                 });
             }
@@ -954,14 +2684,48 @@ impl Config {
                             .unwrap_or_default()
                             .trim()
                             .to_string(),
+                        rate_limit_bytes_per_sec: s.rate_limit_bytes_per_sec.unwrap_or(0),
+                        rate_limit_scope: match s
+                            .rate_limit_scope
+                            .as_deref()
+                            .map(|v| v.trim().to_ascii_lowercase())
+                            .as_deref()
+                        {
+                            None | Some("") | Some("connection") => {
+                                tunnel::protocol::RateLimitScope::Connection
+                            }
+                            Some("service") => tunnel::protocol::RateLimitScope::Service,
+                            Some(other) => anyhow::bail!(
+                                "config: tunnel service {:?}: rate_limit_scope must be \"connection\" or \"service\" (got {:?})",
+                                s.name.trim(),
+                                other
+                            ),
+                        },
+                        dial_policy: match s
+                            .dial_policy
+                            .as_deref()
+                            .map(|v| v.trim().to_ascii_lowercase())
+                            .as_deref()
+                        {
+                            None | Some("") | Some("primary") => "primary".into(),
+                            Some("balance") => "balance".into(),
+                            Some(other) => anyhow::bail!(
+                                "config: tunnel service {:?}: dial_policy must be \"primary\" or \"balance\" (got {:?})",
+                                s.name.trim(),
+                                other
+                            ),
+                        },
                     });
                 }
             }
         } else {
             // Default: match Go defaults.
             cfg.tunnel.auto_listen_services = true;
+            cfg.tunnel.accept_queue_depth = tunnel::transport::DEFAULT_ACCEPT_QUEUE_DEPTH;
         }
 
+        validate_tls_policy(&cfg)?;
+
         cfg.role = PrismRole::parse(&fc.role)?;
 
         if let Some(m) = &fc.managed {
@@ -1097,6 +2861,18 @@ fn normalize_middleware_ref(s: &str) -> anyhow::Result<String> {
     Ok(out)
 }
 
+/// `admin_basic_auth` must be `user:password` with a non-empty user; empty disables it.
+fn parse_admin_basic_auth(raw: &str) -> anyhow::Result<String> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Ok(String::new());
+    }
+    match raw.split_once(':') {
+        Some((user, _)) if !user.is_empty() => Ok(raw.to_string()),
+        _ => anyhow::bail!("config: admin_basic_auth must be \"user:password\""),
+    }
+}
+
 pub fn validate_managed_config_document(doc: &ManagedConfigDocument) -> anyhow::Result<Config> {
     let mut fc = FileConfig {
         role: String::new(),
@@ -1108,9 +2884,15 @@ pub fn validate_managed_config_document(doc: &ManagedConfigDocument) -> anyhow::
                 listen_addr: listener.listen_addr.clone(),
                 protocol: listener.protocol.clone(),
                 upstream: listener.upstream.clone(),
+                source_ip: None,
+                mode: String::new(),
+                status_json: None,
+                health_probes: Vec::new(),
             })
             .collect(),
         admin_addr: String::new(),
+        admin_auth_token: String::new(),
+        admin_basic_auth: String::new(),
         logging: None,
         routes: doc
             .routes
@@ -1122,6 +2904,7 @@ pub fn validate_managed_config_document(doc: &ManagedConfigDocument) -> anyhow::
                     Some(StringOrVec::Many(route.hosts.clone()))
                 },
                 hosts: None,
+                host_regex: None,
                 upstream: None,
                 upstreams: if route.upstreams.is_empty() {
                     None
@@ -1141,21 +2924,58 @@ pub fn validate_managed_config_document(doc: &ManagedConfigDocument) -> anyhow::
                 } else {
                     Some(route.strategy.clone())
                 },
+                trace_sample_ratio: None,
+                strict_minecraft: false,
+                host_secret: None,
+                proxy_protocol: None,
+                offline_status: None,
+                forwarding: None,
+                forwarding_secret: None,
+                status_override: None,
+                max_upstream_connections: 0,
+                dial_retries: 0,
+                dial_retry_backoff_ms: None,
+                handshake_timeout_ms: None,
+                idle_timeout_ms: None,
+                upstream_dial_timeout_ms: None,
+                allow_cidrs: Vec::new(),
+                deny_cidrs: Vec::new(),
+                priority: 0,
+                default: false,
             })
             .collect(),
         max_header_bytes: doc.max_header_bytes,
         reload: None,
         proxy_protocol_v2: doc.proxy_protocol_v2,
+        proxy_protocol: None,
         buffer_size: doc.buffer_size,
         upstream_dial_timeout_ms: doc.upstream_dial_timeout_ms,
+        tcp_user_timeout_ms: 0,
+        tcp_nodelay: None,
+        tcp_keepalive_ms: 0,
+        shutdown_drain_timeout_ms: None,
         metrics: (),
+        schema: (),
+        include: Vec::new(),
         timeouts: doc.timeouts.as_ref().map(|timeouts| FileTimeouts {
             handshake_timeout_ms: timeouts.handshake_timeout_ms,
             idle_timeout_ms: timeouts.idle_timeout_ms,
+            io_op_timeout_ms: None,
         }),
         tunnel: doc.tunnel.as_ref().map(|tunnel| FileTunnel {
             auth_token: Some(tunnel.auth_token.clone()),
+            auth_tokens: Some(
+                tunnel
+                    .auth_tokens
+                    .iter()
+                    .cloned()
+                    .map(FileTunnelAuthToken::Token)
+                    .collect(),
+            ),
             auto_listen_services: Some(tunnel.auto_listen_services),
+            primary_grace_ms: None,
+            dial_service_wait_ms: None,
+            accept_queue_depth: None,
             endpoints: Some(
                 tunnel
                     .endpoints
@@ -1170,6 +2990,7 @@ pub fn validate_managed_config_document(doc: &ManagedConfigDocument) -> anyhow::
                         quic: endpoint.quic.as_ref().map(|quic| FileQuicServer {
                             cert_file: quic.cert_file.clone(),
                             key_file: quic.key_file.clone(),
+                            client_ca_file: quic.client_ca_file.clone(),
                         }),
                     })
                     .collect(),
@@ -1182,9 +3003,13 @@ pub fn validate_managed_config_document(doc: &ManagedConfigDocument) -> anyhow::
                     Some(client.transport.clone())
                 },
                 dial_timeout_ms: client.dial_timeout_ms,
+                heartbeat_interval_ms: client.heartbeat_interval_ms,
+                drain_timeout_ms: client.drain_timeout_ms,
                 quic: client.quic.as_ref().map(|quic| FileQuicClient {
                     server_name: quic.server_name.clone(),
                     insecure_skip_verify: quic.insecure_skip_verify,
+                    client_cert_file: quic.client_cert_file.clone(),
+                    client_key_file: quic.client_key_file.clone(),
                 }),
             }),
             services: Some(
@@ -1210,15 +3035,74 @@ pub fn validate_managed_config_document(doc: &ManagedConfigDocument) -> anyhow::
                         } else {
                             Some(service.masquerade_host.clone())
                         },
+                        rate_limit_bytes_per_sec: (service.rate_limit_bytes_per_sec > 0)
+                            .then_some(service.rate_limit_bytes_per_sec),
+                        rate_limit_scope: if service.rate_limit_scope.trim().is_empty() {
+                            None
+                        } else {
+                            Some(service.rate_limit_scope.clone())
+                        },
+                        dial_policy: if service.dial_policy.trim().is_empty() {
+                            None
+                        } else {
+                            Some(service.dial_policy.clone())
+                        },
                     })
                     .collect(),
             ),
         }),
+        middleware_settings: BTreeMap::new(),
+        allow_wasm_binary: false,
+        on_no_route: String::new(),
+        max_sessions_per_ip: 0,
+        trusted_cidrs: Vec::new(),
+        upstream_max_connections: BTreeMap::new(),
+        upstream_eject_failures: None,
+        upstream_eject_cooldown_ms: None,
+        health_check: None,
+        rate_limit: None,
+        max_connections: 0,
+        max_udp_sessions: 0,
+        udp_buffer_size: None,
+        max_udp_datagram_bytes: None,
+        geoip_db: String::new(),
+        half_close_propagation: None,
+        handshake_buffer_pool: None,
+        upstream_ip_version: String::new(),
+        default_minecraft_port: None,
+        sniff_protocols: false,
+        accept_proxy_protocol: false,
+        session_id_format: String::new(),
+        session_id_client_hash: false,
+        tls: None,
     };
 
     Config::from_file_config(&mut fc, Path::new("managed.json"))
 }
 
+fn parse_upstream_max_connections(
+    raw: &BTreeMap<String, i64>,
+) -> anyhow::Result<BTreeMap<String, usize>> {
+    let mut out = BTreeMap::new();
+    for (upstream, &limit) in raw {
+        let key = upstream.trim();
+        if key.is_empty() {
+            anyhow::bail!("config: upstream_max_connections has an empty upstream key");
+        }
+        if limit < 0 {
+            anyhow::bail!(
+                "config: upstream_max_connections[{:?}] must be >= 0",
+                upstream
+            );
+        }
+        // 0 means unlimited, same as leaving the upstream out.
+        if limit > 0 {
+            out.insert(key.to_string(), limit as usize);
+        }
+    }
+    Ok(out)
+}
+
 pub fn overlay_managed_config_document(
     bootstrap: &Config,
     doc: &ManagedConfigDocument,
@@ -1227,11 +3111,40 @@ pub fn overlay_managed_config_document(
     cfg.role = bootstrap.role;
     cfg.managed = bootstrap.managed.clone();
     cfg.admin_addr = bootstrap.admin_addr.clone();
+    cfg.admin_auth_token = bootstrap.admin_auth_token.clone();
+    cfg.admin_basic_auth = bootstrap.admin_basic_auth.clone();
     cfg.logging = bootstrap.logging.clone();
     cfg.reload = bootstrap.reload.clone();
+    cfg.middleware_settings = bootstrap.middleware_settings.clone();
+    cfg.allow_wasm_binary = bootstrap.allow_wasm_binary;
+    cfg.geoip_db = bootstrap.geoip_db.clone();
+    cfg.handshake_buffer_pool = bootstrap.handshake_buffer_pool;
+    cfg.session_id_format = bootstrap.session_id_format;
+    cfg.session_id_client_hash = bootstrap.session_id_client_hash;
+    cfg.tls = bootstrap.tls.clone();
+    cfg.shutdown_drain_timeout = bootstrap.shutdown_drain_timeout;
+    validate_tls_policy(&cfg)?;
     Ok(cfg)
 }
 
+/// QUIC only runs over TLS 1.3, so a policy that filters out every 1.3 suite would
+/// leave QUIC endpoints unable to handshake.
+fn validate_tls_policy(cfg: &Config) -> anyhow::Result<()> {
+    cfg.tls.validate()?;
+    let uses_quic = cfg.tunnel.endpoints.iter().any(|ep| ep.transport == "quic")
+        || cfg
+            .tunnel
+            .client
+            .as_ref()
+            .is_some_and(|c| c.transport == "quic");
+    if uses_quic && !cfg.tls.allows_tls13() {
+        anyhow::bail!(
+            "config: tls.cipher_suites must include a TLS 1.3 suite when the quic tunnel transport is used"
+        );
+    }
+    Ok(())
+}
+
 pub fn worker_bootstrap_runtime_config(bootstrap: &Config) -> Config {
     let mut cfg = bootstrap.clone();
     cfg.listeners.clear();
@@ -1245,6 +3158,14 @@ pub fn empty_managed_runtime_config() -> Config {
         .expect("default managed config document must validate")
 }
 
+/// Built-in defaults with no listeners, routes or tunnels; the starting point for
+/// building a config in code.
+impl Default for Config {
+    fn default() -> Self {
+        empty_managed_runtime_config()
+    }
+}
+
 pub fn restart_required_reasons(current: &Config, next: &Config) -> Vec<String> {
     let mut reasons = Vec::new();
 
@@ -1254,12 +3175,49 @@ pub fn restart_required_reasons(current: &Config, next: &Config) -> Vec<String>
     if current.admin_addr.trim() != next.admin_addr.trim() {
         reasons.push("admin_addr changed".to_string());
     }
+    if current.admin_auth_token != next.admin_auth_token
+        || current.admin_basic_auth != next.admin_basic_auth
+    {
+        reasons.push("admin auth changed".to_string());
+    }
+    if current.logging.format != next.logging.format
+        || current.logging.output != next.logging.output
+        || current.logging.add_source != next.logging.add_source
+    {
+        reasons.push("logging format/output changed".to_string());
+    }
+    if current.geoip_db != next.geoip_db {
+        reasons.push("geoip_db changed".to_string());
+    }
+    if current.handshake_buffer_pool != next.handshake_buffer_pool {
+        reasons.push("handshake_buffer_pool changed".to_string());
+    }
+    if current.session_id_format != next.session_id_format
+        || current.session_id_client_hash != next.session_id_client_hash
+    {
+        reasons.push("session id format changed".to_string());
+    }
+    if current.tls != next.tls {
+        reasons.push("tls policy changed".to_string());
+    }
     if current.tunnel.auth_token != next.tunnel.auth_token {
         reasons.push("tunnel auth_token changed".to_string());
     }
+    if current.tunnel.auth_tokens != next.tunnel.auth_tokens {
+        reasons.push("tunnel auth_tokens changed".to_string());
+    }
     if current.tunnel.auto_listen_services != next.tunnel.auto_listen_services {
         reasons.push("tunnel auto_listen_services changed".to_string());
     }
+    if current.tunnel.primary_grace != next.tunnel.primary_grace {
+        reasons.push("tunnel primary_grace_ms changed".to_string());
+    }
+    if current.tunnel.dial_service_wait != next.tunnel.dial_service_wait {
+        reasons.push("tunnel dial_service_wait_ms changed".to_string());
+    }
+    if current.tunnel.accept_queue_depth != next.tunnel.accept_queue_depth {
+        reasons.push("tunnel accept_queue_depth changed".to_string());
+    }
     if current.tunnel.endpoints != next.tunnel.endpoints {
         reasons.push("tunnel endpoints changed".to_string());
     }
@@ -1352,6 +3310,31 @@ timeouts:
 
 "#;
 
+const DEFAULT_CONFIG_TEMPLATE_JSON: &str = r#"{
+  "$schema": "https://raw.githubusercontent.com/Summpot/prism/master/prism.schema.json",
+  "admin_addr": ":8080",
+  "tunnel": {
+    "auth_token": "",
+    "auto_listen_services": true,
+    "endpoints": [{ "listen_addr": ":7000", "transport": "tcp" }]
+  },
+  "logging": {
+    "level": "info",
+    "format": "json",
+    "output": "stderr",
+    "add_source": false
+  },
+  "reload": {
+    "enabled": true,
+    "poll_interval_ms": 1000
+  },
+  "timeouts": {
+    "handshake_timeout_ms": 3000,
+    "idle_timeout_ms": 0
+  }
+}
+"#;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1387,6 +3370,18 @@ upstreams = ["127.0.0.1:1234"]
         let s = err.to_string().to_ascii_lowercase();
         assert!(s.contains("missing middlewares"));
 
+        let toml = r#"
+[[routes]]
+host = "example.com"
+upstreams = ["127.0.0.1:1234"]
+middlewares = []
+"#;
+
+        std::fs::write(&cfg_path, toml).expect("write");
+        let err = load_config(&cfg_path).unwrap_err();
+        let s = err.to_string().to_ascii_lowercase();
+        assert!(s.contains("missing middlewares"));
+
         let _ = std::fs::remove_dir_all(&dir);
     }
 
@@ -1471,52 +3466,535 @@ middlewares = ["minecraft_handshake"]
     }
 
     #[test]
-    fn legacy_metrics_section_is_ignored() {
-        let dir = temp_dir("metrics_ignored");
-        let cfg_path = dir.join("prism.toml");
+    fn json_config_matches_toml() {
+        let dir = temp_dir("json_config");
+        let toml_path = dir.join("prism.toml");
+        let json_path = dir.join("prism.json");
 
         std::fs::write(
-            &cfg_path,
+            &toml_path,
             r#"
-admin_addr = ":8080"
+admin_addr = ":9090"
+max_connections = 100
+rate_limit = { connections_per_second = 5, burst = 20 }
 
-[metrics]
-enabled = true
-store_path = "local.sqlite"
+[[listeners]]
+listen_addr = ":25565"
+protocol = "tcp"
+
+[timeouts]
+handshake_timeout_ms = 2000
+
+[[routes]]
+host = ["play.example.com", "*.play.example.com"]
+upstreams = ["127.0.0.1:25566", "127.0.0.1:25567"]
+strategy = "round-robin"
+middlewares = ["minecraft_handshake"]
+status_override = { motd = "Play", max_players = 50 }
 "#,
         )
-        .expect("write");
-        let cfg = load_config(&cfg_path).expect("load_config");
-        assert_eq!(cfg.admin_addr, ":8080");
+        .expect("write toml");
+        std::fs::write(
+            &json_path,
+            r#"{
+  "$schema": "https://raw.githubusercontent.com/Summpot/prism/master/prism.schema.json",
+  "admin_addr": ":9090",
+  "max_connections": 100,
+  "rate_limit": { "connections_per_second": 5, "burst": 20 },
+  "listeners": [{ "listen_addr": ":25565", "protocol": "tcp" }],
+  "timeouts": { "handshake_timeout_ms": 2000 },
+  "routes": [
+    {
+      "host": ["play.example.com", "*.play.example.com"],
+      "upstreams": ["127.0.0.1:25566", "127.0.0.1:25567"],
+      "strategy": "round-robin",
+      "middlewares": ["minecraft_handshake"],
+      "status_override": { "motd": "Play", "max_players": 50 }
+    }
+  ]
+}"#,
+        )
+        .expect("write json");
+
+        let from_toml = load_config(&toml_path).expect("load toml");
+        let from_json = load_config(&json_path).expect("load json");
+        assert_eq!(from_json, from_toml);
 
         let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn restart_required_reasons_detect_listener_changes() {
-        let current = validate_managed_config_document(&ManagedConfigDocument {
-            listeners: vec![ManagedProxyListenerDocument {
-                listen_addr: ":25565".to_string(),
-                protocol: "tcp".to_string(),
-                upstream: String::new(),
-            }],
-            routes: vec![ManagedRouteDocument {
-                hosts: vec!["play.example.com".to_string()],
-                upstreams: vec!["127.0.0.1:25566".to_string()],
-                middlewares: vec!["minecraft_handshake".to_string()],
-                strategy: "sequential".to_string(),
-            }],
-            ..Default::default()
-        })
-        .expect("current config");
+    fn included_route_files_are_merged() {
+        let dir = temp_dir("include_merge");
+        let cfg_path = dir.join("prism.toml");
+        std::fs::create_dir_all(dir.join("routes.d")).expect("mkdir");
+        std::fs::write(
+            &cfg_path,
+            r#"
+include = ["routes.d/*.toml"]
 
-        let next = validate_managed_config_document(&ManagedConfigDocument {
-            listeners: vec![ManagedProxyListenerDocument {
-                listen_addr: ":25566".to_string(),
-                protocol: "tcp".to_string(),
-                upstream: String::new(),
-            }],
-            routes: vec![ManagedRouteDocument {
+[[listeners]]
+listen_addr = ":25565"
+
+[[routes]]
+host = "base.example.com"
+upstream = "127.0.0.1:25566"
+middlewares = ["minecraft_handshake"]
+"#,
+        )
+        .expect("write base");
+        std::fs::write(
+            dir.join("routes.d/b.toml"),
+            r#"
+[[routes]]
+host = "b.example.com"
+upstream = "127.0.0.1:25568"
+middlewares = ["minecraft_handshake"]
+"#,
+        )
+        .expect("write b");
+        std::fs::write(
+            dir.join("routes.d/a.toml"),
+            r#"
+[[listeners]]
+listen_addr = ":25575"
+
+[[routes]]
+host = "a.example.com"
+upstream = "127.0.0.1:25567"
+middlewares = ["minecraft_handshake"]
+"#,
+        )
+        .expect("write a");
+
+        let cfg = load_config(&cfg_path).expect("load_config");
+        let hosts: Vec<_> = cfg.routes.iter().map(|r| r.host[0].as_str()).collect();
+        assert_eq!(
+            hosts,
+            vec!["base.example.com", "a.example.com", "b.example.com"]
+        );
+        assert_eq!(cfg.listeners.len(), 2);
+
+        std::fs::write(
+            dir.join("routes.d/c.toml"),
+            "[[listeners]]\nlisten_addr = \":25565\"\nprotocol = \"tcp\"\n",
+        )
+        .expect("write c");
+        let err = load_config(&cfg_path).expect_err("duplicate listener");
+        assert!(
+            format!("{err:#}").contains("duplicates an earlier listener"),
+            "{err:#}"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn default_json_config_is_created_and_loads() {
+        let dir = temp_dir("json_default");
+        let cfg_path = dir.join("prism.json");
+
+        assert!(ensure_config_file(&cfg_path).expect("ensure"));
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert_eq!(cfg.admin_addr, ":8080");
+        assert_eq!(cfg.tunnel.endpoints.len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn legacy_metrics_section_is_ignored() {
+        let dir = temp_dir("metrics_ignored");
+        let cfg_path = dir.join("prism.toml");
+
+        std::fs::write(
+            &cfg_path,
+            r#"
+admin_addr = ":8080"
+
+[metrics]
+enabled = true
+store_path = "local.sqlite"
+"#,
+        )
+        .expect("write");
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert_eq!(cfg.admin_addr, ":8080");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn middleware_settings_max_output_len_is_validated() {
+        let dir = temp_dir("middleware_settings");
+        let cfg_path = dir.join("prism.toml");
+
+        std::fs::write(
+            &cfg_path,
+            r#"
+[middleware_settings.Minecraft-Handshake]
+max_output_len = 512
+fuel_limit = 5000
+max_memory_pages = 64
+
+[middleware_settings.tls_sni]
+"#,
+        )
+        .expect("write");
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert_eq!(
+            cfg.middleware_settings
+                .get("minecraft_handshake")
+                .map(|s| s.max_output_len),
+            Some(512)
+        );
+        assert_eq!(
+            cfg.middleware_settings
+                .get("tls_sni")
+                .map(|s| s.max_output_len),
+            Some(255)
+        );
+        assert_eq!(
+            cfg.middleware_settings
+                .get("minecraft_handshake")
+                .map(|s| s.fuel_limit),
+            Some(5000)
+        );
+        assert_eq!(
+            cfg.middleware_settings.get("tls_sni").map(|s| s.fuel_limit),
+            Some(crate::prism::middleware::DEFAULT_FUEL_LIMIT)
+        );
+
+        assert_eq!(
+            cfg.middleware_settings
+                .get("minecraft_handshake")
+                .map(|s| s.max_memory_pages),
+            Some(64)
+        );
+
+        std::fs::write(
+            &cfg_path,
+            r#"
+[middleware_settings.tls_sni]
+max_memory_pages = 2
+"#,
+        )
+        .expect("write");
+        let err = load_config(&cfg_path).expect_err("tiny memory cap must be rejected");
+        assert!(
+            err.to_string()
+                .contains("max_memory_pages must be between 4")
+        );
+
+        std::fs::write(
+            &cfg_path,
+            r#"
+[middleware_settings.tls_sni]
+fuel_limit = 0
+"#,
+        )
+        .expect("write");
+        let err = load_config(&cfg_path).expect_err("zero fuel must be rejected");
+        assert!(err.to_string().contains("fuel_limit must be at least 1"));
+
+        std::fs::write(
+            &cfg_path,
+            r#"
+[middleware_settings.tls_sni]
+max_output_len = 0
+"#,
+        )
+        .expect("write");
+        let err = load_config(&cfg_path).expect_err("zero cap must be rejected");
+        assert!(
+            err.to_string()
+                .contains("max_output_len must be at least 1")
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn on_no_route_policy_parses() {
+        assert_eq!(NoRoutePolicy::parse("").unwrap(), NoRoutePolicy::Close);
+        assert_eq!(NoRoutePolicy::parse("Close").unwrap(), NoRoutePolicy::Close);
+        assert_eq!(
+            NoRoutePolicy::parse("default_upstream: 127.0.0.1:25570").unwrap(),
+            NoRoutePolicy::DefaultUpstream("127.0.0.1:25570".into())
+        );
+        assert_eq!(
+            NoRoutePolicy::parse("kick:Unknown server: check the address").unwrap(),
+            NoRoutePolicy::Kick("Unknown server: check the address".into())
+        );
+        assert!(NoRoutePolicy::parse("default_upstream:").is_err());
+        assert!(NoRoutePolicy::parse("drop").is_err());
+    }
+
+    #[test]
+    fn tls_policy_must_keep_tls13_for_quic() {
+        let dir = temp_dir("tls_policy");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+[tls]
+min_version = "1.3"
+cipher_suites = ["tls13_aes_256_gcm_sha384"]
+
+[[tunnel.endpoints]]
+listen_addr = ":7000"
+transport = "quic"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("tls 1.3 policy with quic");
+        assert_eq!(cfg.tls.min_version, tls::TlsVersion::Tls13);
+        assert_eq!(cfg.tls.cipher_suites, vec!["TLS13_AES_256_GCM_SHA384"]);
+
+        let toml = r#"
+[tls]
+cipher_suites = ["TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384"]
+
+[[tunnel.endpoints]]
+listen_addr = ":7000"
+transport = "quic"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let err = load_config(&cfg_path).unwrap_err().to_string();
+        assert!(err.contains("TLS 1.3"), "{err}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn route_offline_status_accepts_inline_json_or_a_relative_path() {
+        let dir = temp_dir("offline_status");
+        let cfg_path = dir.join("prism.toml");
+        std::fs::write(
+            dir.join("maintenance.json"),
+            r#"{"description":"maintenance"}"#,
+        )
+        .expect("write");
+
+        let toml = r#"
+[[routes]]
+host = "a.example.com"
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+offline_status = '{"description":"down"}'
+
+[[routes]]
+host = "b.example.com"
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+offline_status = "maintenance.json"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert_eq!(
+            cfg.routes[0].offline_status.as_deref(),
+            Some(r#"{"description":"down"}"#)
+        );
+        assert_eq!(
+            cfg.routes[1].offline_status.as_deref(),
+            Some(r#"{"description":"maintenance"}"#)
+        );
+
+        let toml = r#"
+[[routes]]
+host = "a.example.com"
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+offline_status = "missing.json"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let err = format!("{:#}", load_config(&cfg_path).unwrap_err());
+        assert!(err.contains("offline_status"), "{err}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn route_status_override_parses_and_empty_means_none() {
+        let dir = temp_dir("status_override");
+        let cfg_path = dir.join("prism.toml");
+        let toml = r#"
+[[routes]]
+host = "a.example.com"
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+status_override = { motd = "hi", max_players = 100 }
+
+[[routes]]
+host = "b.example.com"
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+status_override = {}
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert_eq!(
+            cfg.routes[0].status_override,
+            Some(StatusOverride {
+                motd: Some("hi".into()),
+                max_players: Some(100),
+                version_name: None,
+            })
+        );
+        assert_eq!(cfg.routes[1].status_override, None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn generated_schema_matches_the_published_one() {
+        let published: serde_json::Value = serde_json::from_str(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../prism.schema.json"
+        )))
+        .expect("published schema is JSON");
+
+        // Regenerate with `prism --print-schema > prism.schema.json`.
+        assert!(
+            json_schema() == published,
+            "prism.schema.json is stale; regenerate it with `prism --print-schema`"
+        );
+    }
+
+    #[test]
+    fn default_route_may_omit_host() {
+        let dir = temp_dir("default_route");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+[[routes]]
+default = true
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert!(cfg.routes[0].default);
+        assert_eq!(cfg.routes[0].host, vec!["*".to_string()]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn host_regex_routes_need_no_host_and_must_compile() {
+        let dir = temp_dir("host_regex");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+[[routes]]
+host_regex = '(?P<region>\w+)\.play\.example\.com'
+upstream = "$region.backend:25565"
+middlewares = ["minecraft_handshake"]
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert!(cfg.routes[0].host.is_empty());
+        assert_eq!(
+            cfg.routes[0].host_regex,
+            vec![r"(?P<region>\w+)\.play\.example\.com".to_string()]
+        );
+
+        std::fs::write(&cfg_path, toml.replace(r"(?P<region>", "(?P<region")).expect("write");
+        let err = load_config(&cfg_path).expect_err("invalid regex");
+        assert!(format!("{err:#}").contains("invalid host_regex"), "{err:#}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn modern_forwarding_requires_a_secret() {
+        let dir = temp_dir("forwarding_secret");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+[[routes]]
+host = "a.example.com"
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+forwarding = "velocity"
+forwarding_secret = "s3cret"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load_config");
+        assert_eq!(cfg.routes[0].forwarding, Forwarding::Modern);
+        assert_eq!(cfg.routes[0].forwarding_secret.as_deref(), Some("s3cret"));
+
+        let toml = r#"
+[[routes]]
+host = "a.example.com"
+upstreams = ["127.0.0.1:1"]
+middlewares = ["minecraft_handshake"]
+forwarding = "modern"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let err = format!("{:#}", load_config(&cfg_path).unwrap_err());
+        assert!(err.contains("forwarding_secret"), "{err}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn proxy_protocol_v2_flag_maps_to_proxy_protocol() {
+        let dir = temp_dir("proxy_protocol");
+        let cfg_path = dir.join("prism.toml");
+
+        let cases = [
+            ("", ProxyProtocol::None),
+            ("proxy_protocol_v2 = true", ProxyProtocol::V2),
+            ("proxy_protocol = \"v1\"", ProxyProtocol::V1),
+            (
+                "proxy_protocol_v2 = true\nproxy_protocol = \"v2\"",
+                ProxyProtocol::V2,
+            ),
+        ];
+        for (toml, want) in cases {
+            std::fs::write(&cfg_path, toml).expect("write");
+            assert_eq!(
+                load_config(&cfg_path).expect(toml).proxy_protocol,
+                want,
+                "{toml}"
+            );
+        }
+
+        std::fs::write(
+            &cfg_path,
+            "proxy_protocol_v2 = true\nproxy_protocol = \"v1\"",
+        )
+        .expect("write");
+        let err = load_config(&cfg_path).unwrap_err().to_string();
+        assert!(err.contains("conflicts"), "{err}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restart_required_reasons_detect_listener_changes() {
+        let current = validate_managed_config_document(&ManagedConfigDocument {
+            listeners: vec![ManagedProxyListenerDocument {
+                listen_addr: ":25565".to_string(),
+                protocol: "tcp".to_string(),
+                upstream: String::new(),
+            }],
+            routes: vec![ManagedRouteDocument {
+                hosts: vec!["play.example.com".to_string()],
+                upstreams: vec!["127.0.0.1:25566".to_string()],
+                middlewares: vec!["minecraft_handshake".to_string()],
+                strategy: "sequential".to_string(),
+            }],
+            ..Default::default()
+        })
+        .expect("current config");
+
+        let next = validate_managed_config_document(&ManagedConfigDocument {
+            listeners: vec![ManagedProxyListenerDocument {
+                listen_addr: ":25566".to_string(),
+                protocol: "tcp".to_string(),
+                upstream: String::new(),
+            }],
+            routes: vec![ManagedRouteDocument {
                 hosts: vec!["play.example.com".to_string()],
                 upstreams: vec!["127.0.0.1:25566".to_string()],
                 middlewares: vec!["minecraft_handshake".to_string()],
@@ -1553,4 +4031,161 @@ upstreams = ["127.0.0.1:1234"]
 
         let _ = std::fs::remove_dir_all(&dir);
     }
+
+    #[test]
+    fn tunnel_service_rate_limit_scope_and_dial_policy_are_validated() {
+        let dir = temp_dir("rate-limit-scope");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+[[tunnel.services]]
+name = "mc"
+local_addr = "127.0.0.1:25565"
+rate_limit_bytes_per_sec = 1048576
+rate_limit_scope = " Service "
+dial_policy = "Balance"
+
+[[tunnel.services]]
+name = "voice"
+local_addr = "127.0.0.1:9987"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load");
+        assert_eq!(cfg.tunnel.services[0].rate_limit_bytes_per_sec, 1048576);
+        assert_eq!(
+            cfg.tunnel.services[0].rate_limit_scope,
+            tunnel::protocol::RateLimitScope::Service
+        );
+        assert_eq!(cfg.tunnel.services[1].rate_limit_bytes_per_sec, 0);
+        assert_eq!(
+            cfg.tunnel.services[1].rate_limit_scope,
+            tunnel::protocol::RateLimitScope::Connection
+        );
+        assert_eq!(cfg.tunnel.services[0].dial_policy, "balance");
+        assert_eq!(cfg.tunnel.services[1].dial_policy, "primary");
+
+        std::fs::write(&cfg_path, toml.replace("\" Service \"", "\"route\"")).expect("write");
+        let err = load_config(&cfg_path).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("rate_limit_scope"),
+            "unexpected error: {err:#}"
+        );
+
+        std::fs::write(&cfg_path, toml.replace("\"Balance\"", "\"random\"")).expect("write");
+        let err = load_config(&cfg_path).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("dial_policy"),
+            "unexpected error: {err:#}"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn admin_basic_auth_needs_a_user_and_password() {
+        let dir = temp_dir("admin-auth");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+admin_addr = ":8080"
+admin_auth_token = " s3cret "
+admin_basic_auth = "ops:hunter2"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load");
+        assert_eq!(cfg.admin_auth_token, "s3cret");
+        assert_eq!(cfg.admin_basic_auth, "ops:hunter2");
+
+        for bad in ["\"hunter2\"", "\":hunter2\""] {
+            std::fs::write(&cfg_path, toml.replace("\"ops:hunter2\"", bad)).expect("write");
+            let err = load_config(&cfg_path).unwrap_err();
+            assert!(
+                format!("{err:#}").contains("admin_basic_auth"),
+                "unexpected error: {err:#}"
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tls_listeners_route_on_sni_without_an_upstream() {
+        let dir = temp_dir("tls-listener");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+[[listeners]]
+listen_addr = ":443"
+protocol = "TLS"
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load");
+        assert_eq!(cfg.listeners[0].protocol, "tls");
+
+        let with_upstream = format!("{toml}upstream = \"127.0.0.1:8443\"\n");
+        std::fs::write(&cfg_path, with_upstream).expect("write");
+        let err = load_config(&cfg_path).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("protocol=tls"),
+            "unexpected error: {err:#}"
+        );
+
+        // Auto listeners follow the same rule.
+        let auto = toml.replace("\"TLS\"", "\"auto\"");
+        std::fs::write(&cfg_path, &auto).expect("write");
+        assert_eq!(
+            load_config(&cfg_path).expect("load").listeners[0].protocol,
+            "auto"
+        );
+        std::fs::write(&cfg_path, format!("{auto}upstream = \"127.0.0.1:8443\"\n")).expect("write");
+        let err = load_config(&cfg_path).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("protocol=auto"),
+            "unexpected error: {err:#}"
+        );
+
+        // Both are routing listeners, so observe mode and health probes apply.
+        let observed =
+            format!("{toml}mode = \"observe\"\n[[listeners.health_probes]]\nmatch = \"PING\\n\"\n");
+        std::fs::write(&cfg_path, observed).expect("write");
+        let cfg = load_config(&cfg_path).expect("load");
+        assert_eq!(cfg.listeners[0].mode, ListenerMode::Observe);
+        assert_eq!(cfg.listeners[0].health_probes[0].pattern, b"PING\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tunnel_auth_tokens_accept_bare_and_labeled_entries() {
+        let dir = temp_dir("auth-tokens");
+        let cfg_path = dir.join("prism.toml");
+
+        let toml = r#"
+[tunnel]
+auth_token = "shared"
+auth_tokens = ["t1", { token = "t2", label = "home" }]
+"#;
+        std::fs::write(&cfg_path, toml).expect("write");
+        let cfg = load_config(&cfg_path).expect("load");
+        assert_eq!(cfg.tunnel.auth_token, "shared");
+        assert_eq!(
+            cfg.tunnel.auth_tokens,
+            vec![
+                TunnelAuthToken {
+                    token: "t1".into(),
+                    label: String::new(),
+                },
+                TunnelAuthToken {
+                    token: "t2".into(),
+                    label: "home".into(),
+                },
+            ]
+        );
+
+        std::fs::write(&cfg_path, toml.replace("\"t1\"", "\"shared\"")).expect("write");
+        let err = load_config(&cfg_path).unwrap_err();
+        assert!(format!("{err:#}").contains("same token twice"), "{err:#}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,128 @@
+//! Running Prism's TCP proxy inside another program.
+//!
+//! Unlike [`crate::run`], nothing here reads a config file, touches the workdir or
+//! installs signal handlers: the embedder builds the [`config::Config`] and
+//! [`router::Router`] and decides when to stop.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Context;
+use tokio::{net::TcpListener, sync::watch, task::JoinHandle};
+
+use crate::prism::{bufpool, config, net, proxy, router, telemetry};
+
+pub use crate::prism::telemetry::{SessionRegistry, SharedSessions};
+
+/// A routing TCP listener waiting to be started with [`Proxy::serve`].
+pub struct Proxy {
+    router: Arc<router::Router>,
+    runtime: proxy::TcpRuntimeConfig,
+    sessions: SharedSessions,
+    buffer_pool: Arc<bufpool::BufferPool>,
+}
+
+impl Proxy {
+    pub fn new(router: Arc<router::Router>, runtime: proxy::TcpRuntimeConfig) -> Self {
+        Self {
+            router,
+            runtime,
+            sessions: Arc::new(SessionRegistry::new()),
+            buffer_pool: Arc::new(bufpool::BufferPool::new(
+                config::DEFAULT_HANDSHAKE_BUFFER_POOL,
+            )),
+        }
+    }
+
+    /// Router and runtime settings both taken from `cfg`.
+    pub fn from_config(cfg: &config::Config, router: Arc<router::Router>) -> Self {
+        let mut p = Self::new(router, proxy::TcpRuntimeConfig::from_config(cfg));
+        p.buffer_pool = Arc::new(bufpool::BufferPool::new(cfg.handshake_buffer_pool));
+        p
+    }
+
+    /// Record sessions in an existing registry, e.g. one shared by several listeners.
+    /// [`ProxyHandle::shutdown`] still only waits for this proxy's own sessions.
+    pub fn with_sessions(mut self, sessions: SharedSessions) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// Bind `listen_addr` and start accepting in the background. Port 0 picks a free
+    /// port; see [`ProxyHandle::local_addr`].
+    pub async fn serve(self, listen_addr: &str) -> anyhow::Result<ProxyHandle> {
+        let bind_addr = net::normalize_bind_addr(listen_addr);
+        let ln = TcpListener::bind(bind_addr.as_ref())
+            .await
+            .with_context(|| format!("bind tcp {listen_addr}"))?;
+        let local_addr = ln.local_addr().context("tcp local addr")?;
+        tracing::info!(listen_addr = %local_addr, "tcp: listening");
+
+        let drain_timeout = self.runtime.shutdown_drain_timeout;
+        self.sessions
+            .set_connection_limit(self.runtime.max_connections);
+        let handler = proxy::TcpHandler::routing(proxy::TcpRoutingHandlerOptions {
+            router: self.router,
+            sessions: self.sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(self.runtime)),
+            observe: false,
+            protocol: proxy::RoutingProtocol::Middleware,
+            buffer_pool: self.buffer_pool,
+            health_probes: Vec::new(),
+        });
+        let (stop, stop_rx) = watch::channel(false);
+        let in_flight = Arc::new(telemetry::ConnectionCounter::default());
+        let accept = tokio::spawn(proxy::accept_tcp(
+            ln,
+            handler,
+            stop_rx,
+            Some(in_flight.clone()),
+        ));
+        Ok(ProxyHandle {
+            local_addr,
+            sessions: self.sessions,
+            in_flight,
+            drain_timeout,
+            stop,
+            accept,
+        })
+    }
+}
+
+/// A running [`Proxy`]. Dropping the handle stops accepting but leaves in-flight
+/// sessions running; [`ProxyHandle::shutdown`] also waits for them.
+pub struct ProxyHandle {
+    local_addr: SocketAddr,
+    sessions: SharedSessions,
+    /// Connections accepted by this listener whose handler is still running.
+    in_flight: Arc<telemetry::ConnectionCounter>,
+    drain_timeout: std::time::Duration,
+    stop: watch::Sender<bool>,
+    accept: JoinHandle<anyhow::Result<()>>,
+}
+
+impl ProxyHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn sessions(&self) -> &SharedSessions {
+        &self.sessions
+    }
+
+    /// Stop accepting, then wait up to the runtime's `shutdown_drain_timeout` for the
+    /// connections this proxy accepted. Returns false if some were still open at the
+    /// deadline.
+    pub async fn shutdown(mut self) -> anyhow::Result<bool> {
+        let deadline = tokio::time::Instant::now() + self.drain_timeout;
+        let _ = self.stop.send(true);
+        (&mut self.accept).await.context("tcp accept task")??;
+        Ok(self.in_flight.drain(deadline).await)
+    }
+}
+
+impl Drop for ProxyHandle {
+    fn drop(&mut self) {
+        let _ = self.stop.send(true);
+    }
+}
@@ -0,0 +1,113 @@
+use std::{net::IpAddr, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// Country/ASN annotation for a client IP.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GeoInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_org: Option<String>,
+}
+
+/// The subset of GeoLite2 Country/City/ASN records Prism reads. Any database that carries
+/// either part works; missing fields are simply left empty.
+#[derive(Debug, Deserialize)]
+struct Record<'a> {
+    #[serde(borrow)]
+    country: Option<Country<'a>>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Country<'a> {
+    iso_code: Option<&'a str>,
+}
+
+/// A MaxMind (GeoLite2) database loaded into memory once at startup.
+pub struct GeoDb {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl std::fmt::Debug for GeoDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoDb")
+            .field("database_type", &self.reader.metadata.database_type)
+            .finish()
+    }
+}
+
+impl GeoDb {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let reader = maxminddb::Reader::open_readfile(path)
+            .map_err(|e| anyhow::anyhow!("geoip: open {}: {e}", path.display()))?;
+        Ok(Self { reader })
+    }
+
+    /// Open `path`, logging a warning and returning None if it is missing or corrupt.
+    pub fn open_or_warn(path: &str) -> Option<Self> {
+        let path = path.trim();
+        if path.is_empty() {
+            return None;
+        }
+        match Self::open(Path::new(path)) {
+            Ok(db) => {
+                tracing::info!(path = %path, database_type = %db.reader.metadata.database_type, "geoip: database loaded");
+                Some(db)
+            }
+            Err(err) => {
+                tracing::warn!(path = %path, err = %err, "geoip: database unavailable; sessions will not be annotated");
+                None
+            }
+        }
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let Ok(rec) = self.reader.lookup::<Record>(ip.to_canonical()) else {
+            return GeoInfo::default();
+        };
+        GeoInfo {
+            country: rec.country.and_then(|c| c.iso_code).map(|s| s.to_string()),
+            asn: rec.autonomous_system_number,
+            as_org: rec.autonomous_system_organization.map(|s| s.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tiny IPv4 database mapping 203.0.113.0/24 to AU / AS64500 "Prism Test Net".
+    const TEST_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/geoip-test.mmdb");
+
+    #[test]
+    fn lookup_annotates_country_and_asn() {
+        let db = GeoDb::open(Path::new(TEST_DB)).expect("open");
+
+        let info = db.lookup("203.0.113.7".parse().unwrap());
+        assert_eq!(info.country.as_deref(), Some("AU"));
+        assert_eq!(info.asn, Some(64500));
+        assert_eq!(info.as_org.as_deref(), Some("Prism Test Net"));
+
+        let mapped = db.lookup("::ffff:203.0.113.7".parse().unwrap());
+        assert_eq!(mapped, info);
+
+        assert_eq!(
+            db.lookup("198.51.100.1".parse().unwrap()),
+            GeoInfo::default()
+        );
+    }
+
+    #[test]
+    fn open_or_warn_is_fail_soft() {
+        assert!(GeoDb::open_or_warn("").is_none());
+        assert!(GeoDb::open_or_warn("/nonexistent/geoip.mmdb").is_none());
+        assert!(GeoDb::open_or_warn(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).is_none());
+        assert!(GeoDb::open_or_warn(TEST_DB).is_some());
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{RwLock, watch},
+    time,
+};
+
+use crate::prism::{config, minecraft, proxy, router};
+
+/// Protocol version sent in probe handshakes; -1 is the conventional "just pinging" value.
+const PROBE_PROTOCOL_VERSION: i32 = -1;
+
+/// Largest status response a probe will read.
+const MAX_STATUS_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Active health checker: every `health_check.interval`, ping each statically configured
+/// upstream with a Minecraft status request and record the outcome in `router`, whose
+/// candidate ordering keeps failing upstreams behind healthy ones. Settings are re-read
+/// every round, so reloads can turn checks on or off.
+pub async fn run(
+    router: Arc<router::Router>,
+    runtime: Arc<RwLock<proxy::TcpRuntimeConfig>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    loop {
+        let rt = runtime.read().await.clone();
+        if rt.health_check.enabled {
+            check_once(&router, &rt).await;
+        } else {
+            router.forget_probes_except(&[]);
+        }
+
+        tokio::select! {
+            _ = time::sleep(rt.health_check.interval) => {}
+            changed = shutdown.changed() => {
+                if changed.is_err() || *shutdown.borrow() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Probe every target once, concurrently, and record the results.
+async fn check_once(router: &router::Router, rt: &proxy::TcpRuntimeConfig) {
+    let targets = router.health_check_targets();
+    let probes = targets.iter().map(|(upstream, pp)| async move {
+        let pp = pp.unwrap_or(rt.proxy_protocol);
+        let res = time::timeout(rt.health_check.timeout, probe_status(upstream, pp, rt))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("status probe timed out")));
+        if let Err(err) = &res {
+            tracing::debug!(upstream = %upstream, err = %err, "health: status probe failed");
+        }
+        (upstream, res.is_ok())
+    });
+    for (upstream, healthy) in futures_util::future::join_all(probes).await {
+        router.record_probe(upstream, healthy);
+    }
+    let names: Vec<String> = targets.into_iter().map(|(u, _)| u).collect();
+    router.forget_probes_except(&names);
+}
+
+/// Dial `upstream`, send a status handshake and request, and wait for a Status Response
+/// carrying valid JSON. Upstreams that expect a PROXY header get a local (no client) one
+/// first, as they would reject the probe otherwise.
+async fn probe_status(
+    upstream: &str,
+    proxy_protocol: config::ProxyProtocol,
+    rt: &proxy::TcpRuntimeConfig,
+) -> anyhow::Result<()> {
+    let (mut conn, label, _) =
+        proxy::dial_upstream(upstream, Some(rt.default_minecraft_port), rt, None).await?;
+    conn.write_all(proxy::proxy_local_header(proxy_protocol))
+        .await
+        .context("write PROXY header")?;
+
+    let (host, port) = label
+        .rsplit_once(':')
+        .and_then(|(h, p)| {
+            Some((
+                h.trim_start_matches('[').trim_end_matches(']'),
+                p.parse().ok()?,
+            ))
+        })
+        .unwrap_or((label.as_str(), rt.default_minecraft_port));
+    let mut hs = Vec::with_capacity(host.len() + 16);
+    minecraft::write_varint(PROBE_PROTOCOL_VERSION, &mut hs);
+    minecraft::write_string(host, &mut hs);
+    hs.extend_from_slice(&u16::to_be_bytes(port));
+    minecraft::write_varint(minecraft::NEXT_STATE_STATUS, &mut hs);
+    let mut out = minecraft::packet(0x00, &hs);
+    out.extend_from_slice(&minecraft::packet(0x00, &[]));
+    conn.write_all(&out).await.context("write status request")?;
+
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some((_, json)) = minecraft::parse_status_response(&buf) {
+            serde_json::from_str::<serde_json::Value>(&json).context("invalid status JSON")?;
+            return Ok(());
+        }
+        if buf.len() >= MAX_STATUS_RESPONSE_BYTES {
+            anyhow::bail!("status response exceeds {MAX_STATUS_RESPONSE_BYTES} bytes");
+        }
+        let n = conn
+            .read(&mut chunk)
+            .await
+            .context("read status response")?;
+        if n == 0 {
+            anyhow::bail!("connection closed before a status response");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prism::middleware;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    struct NoopChain;
+
+    impl middleware::MiddlewareChain for NoopChain {
+        fn name(&self) -> &str {
+            "noop"
+        }
+
+        fn parse(
+            &self,
+            _prelude: &[u8],
+            _client: middleware::ClientMeta,
+        ) -> Result<(String, Option<Vec<u8>>), middleware::MiddlewareError> {
+            Err(middleware::MiddlewareError::NoMatch)
+        }
+
+        fn rewrite(
+            &self,
+            _prelude: &[u8],
+            _selected_upstream: &str,
+            _client: middleware::ClientMeta,
+        ) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    /// A backend that answers status requests until `silent` is set, then accepts
+    /// connections but never replies.
+    async fn status_backend(silent: watch::Receiver<bool>) -> String {
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut c, _)) = ln.accept().await else {
+                    return;
+                };
+                let silent = *silent.borrow();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 512];
+                    let _ = c.read(&mut buf).await;
+                    if silent {
+                        time::sleep(Duration::from_secs(5)).await;
+                        return;
+                    }
+                    let json =
+                        r#"{"version":{"name":"test","protocol":763},"description":{"text":"ok"}}"#;
+                    let _ = c.write_all(&minecraft::status_response_packet(json)).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn unresponsive_backend_is_marked_unhealthy() {
+        let (silence_a, silent_a) = watch::channel(false);
+        let (_silence_b, silent_b) = watch::channel(false);
+        let a = status_backend(silent_a).await;
+        let b = status_backend(silent_b).await;
+
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![a.clone(), b.clone()],
+            strategy: "sequential".into(),
+            middlewares: vec!["noop".into()],
+            ..Default::default()
+        };
+        let chain = Arc::new(NoopChain) as middleware::SharedMiddlewareChain;
+        let router = router::Router::new(vec![(route, chain)]);
+        let mut rt = proxy::TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.health_check.timeout = Duration::from_millis(300);
+        let order = || router.resolve("play.example.com").expect("match").upstreams;
+
+        check_once(&router, &rt).await;
+        assert_eq!(order(), vec![a.clone(), b.clone()]);
+
+        silence_a.send(true).expect("silence");
+        check_once(&router, &rt).await;
+        assert_eq!(order(), vec![b.clone(), a.clone()]);
+
+        silence_a.send(false).expect("unsilence");
+        check_once(&router, &rt).await;
+        assert_eq!(order(), vec![a, b]);
+    }
+
+    #[tokio::test]
+    async fn port_less_upstreams_are_probed_on_the_default_port() {
+        let (_silence, silent) = watch::channel(false);
+        let addr = status_backend(silent).await;
+        let (host, port) = addr.rsplit_once(':').expect("port");
+        let mut rt = proxy::TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.default_minecraft_port = port.parse().expect("port");
+
+        probe_status(host, config::ProxyProtocol::None, &rt)
+            .await
+            .expect("probe");
+    }
+
+    /// A status backend that reports the first bytes of each probe on `seen`.
+    async fn recording_backend(seen: tokio::sync::mpsc::UnboundedSender<Vec<u8>>) -> String {
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            while let Ok((mut c, _)) = ln.accept().await {
+                let mut buf = [0u8; 512];
+                let n = c.read(&mut buf).await.unwrap_or(0);
+                let _ = seen.send(buf[..n].to_vec());
+                let json =
+                    r#"{"version":{"name":"test","protocol":763},"description":{"text":"ok"}}"#;
+                let _ = c.write_all(&minecraft::status_response_packet(json)).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn probes_send_the_configured_proxy_header() {
+        let (seen_a, mut got_a) = tokio::sync::mpsc::unbounded_channel();
+        let (seen_b, mut got_b) = tokio::sync::mpsc::unbounded_channel();
+        let a = recording_backend(seen_a).await;
+        let b = recording_backend(seen_b).await;
+
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![a, format!("{b}#proxy=v2")],
+            proxy_protocol: Some(config::ProxyProtocol::V1),
+            middlewares: vec!["noop".into()],
+            ..Default::default()
+        };
+        let chain = Arc::new(NoopChain) as middleware::SharedMiddlewareChain;
+        let router = router::Router::new(vec![(route, chain)]);
+        let rt = proxy::TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+
+        check_once(&router, &rt).await;
+        let first_bytes = |got: Option<Vec<u8>>| got.expect("probe");
+        assert!(first_bytes(got_a.recv().await).starts_with(b"PROXY UNKNOWN\r\n"));
+        assert!(
+            first_bytes(got_b.recv().await).starts_with(b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00")
+        );
+    }
+}
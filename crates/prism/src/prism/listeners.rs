@@ -0,0 +1,236 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::future::BoxFuture;
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinSet,
+};
+
+use crate::prism::{bufpool, config, proxy, router, telemetry, tunnel};
+
+/// Shared state every proxy listener's handler is built from.
+#[derive(Clone)]
+pub struct ListenerDeps {
+    pub router: Arc<router::Router>,
+    pub sessions: telemetry::SharedSessions,
+    pub tunnel_manager: Arc<tunnel::manager::Manager>,
+    pub runtime: Arc<tokio::sync::RwLock<proxy::TcpRuntimeConfig>>,
+    pub buffer_pool: Arc<bufpool::BufferPool>,
+    pub readiness: Arc<telemetry::Readiness>,
+    /// Every TCP connection a listener accepted holds a slot until its handler returns.
+    pub in_flight: Arc<telemetry::ConnectionCounter>,
+}
+
+struct RunningListener {
+    cfg: config::ProxyListenerConfig,
+    stop: watch::Sender<bool>,
+    done: oneshot::Receiver<()>,
+    /// Held here too so a listener that failed to bind keeps `/readyz` failing until a
+    /// reload removes it.
+    _ready: telemetry::ReadyCheck,
+}
+
+/// The running proxy listeners, each with its own stop signal so a reload can add or
+/// remove listeners without touching the others.
+pub struct ListenerSet {
+    deps: ListenerDeps,
+    running: Vec<RunningListener>,
+    /// Serve tasks of listeners added by `reconcile`; startup listeners run in the
+    /// caller's task set instead.
+    reloaded: JoinSet<()>,
+}
+
+/// Listeners added and removed by `ListenerSet::reconcile`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ListenerChanges {
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl ListenerSet {
+    pub fn new(deps: ListenerDeps) -> Self {
+        Self {
+            deps,
+            running: Vec::new(),
+            reloaded: JoinSet::new(),
+        }
+    }
+
+    /// Register `l` and return its serve future; the caller decides where to spawn it.
+    ///
+    /// Returns None for listeners that cannot run (unsupported protocol, UDP without upstream).
+    pub fn start(
+        &mut self,
+        l: &config::ProxyListenerConfig,
+        udp: proxy::UdpRuntimeConfig,
+    ) -> Option<BoxFuture<'static, anyhow::Result<()>>> {
+        let listen_addr = l.listen_addr.clone();
+        let upstream = l.upstream.clone();
+        let (stop, stop_rx) = watch::channel(false);
+        let (done_tx, done) = oneshot::channel();
+        let ready = self
+            .deps
+            .readiness
+            .check(format!("{} listener {listen_addr}", l.protocol));
+        let serve_ready = Some(ready.clone());
+        let in_flight = Some(self.deps.in_flight.clone());
+
+        let serve: BoxFuture<'static, anyhow::Result<()>> = match l.protocol.as_str() {
+            "tcp" => {
+                let handler = if l.mode == config::ListenerMode::StatusHealth {
+                    proxy::TcpHandler::status_health(proxy::StatusHealthHandlerOptions {
+                        status_json: l.status_json.clone(),
+                        runtime: self.deps.runtime.clone(),
+                    })
+                } else if upstream.trim().is_empty() {
+                    proxy::TcpHandler::routing(proxy::TcpRoutingHandlerOptions {
+                        router: self.deps.router.clone(),
+                        sessions: self.deps.sessions.clone(),
+                        tunnel_manager: Some(self.deps.tunnel_manager.clone()),
+                        runtime: self.deps.runtime.clone(),
+                        observe: l.mode == config::ListenerMode::Observe,
+                        protocol: proxy::RoutingProtocol::Middleware,
+                        buffer_pool: self.deps.buffer_pool.clone(),
+                        health_probes: l.health_probes.clone(),
+                    })
+                } else {
+                    // Validated at config load; only `#proxy=` means anything here.
+                    let (upstream, upstream_opts) = router::split_upstream_options(&upstream)
+                        .unwrap_or((upstream, router::UpstreamOptions::default()));
+                    proxy::TcpHandler::forward(proxy::TcpForwardHandlerOptions {
+                        upstream,
+                        sessions: self.deps.sessions.clone(),
+                        tunnel_manager: Some(self.deps.tunnel_manager.clone()),
+                        runtime: self.deps.runtime.clone(),
+                        proxy_protocol: upstream_opts.proxy_protocol,
+                    })
+                };
+                Box::pin(async move {
+                    proxy::serve_tcp_with_shutdown(
+                        &listen_addr,
+                        handler,
+                        stop_rx,
+                        serve_ready,
+                        in_flight,
+                    )
+                    .await
+                })
+            }
+            "tls" | "auto" => {
+                let protocol = if l.protocol == "tls" {
+                    proxy::RoutingProtocol::Tls
+                } else {
+                    proxy::RoutingProtocol::Auto
+                };
+                let handler = proxy::TcpHandler::routing(proxy::TcpRoutingHandlerOptions {
+                    router: self.deps.router.clone(),
+                    sessions: self.deps.sessions.clone(),
+                    tunnel_manager: Some(self.deps.tunnel_manager.clone()),
+                    runtime: self.deps.runtime.clone(),
+                    observe: l.mode == config::ListenerMode::Observe,
+                    protocol,
+                    buffer_pool: self.deps.buffer_pool.clone(),
+                    health_probes: l.health_probes.clone(),
+                });
+                Box::pin(async move {
+                    proxy::serve_tcp_with_shutdown(
+                        &listen_addr,
+                        handler,
+                        stop_rx,
+                        serve_ready,
+                        in_flight,
+                    )
+                    .await
+                })
+            }
+            "udp" => {
+                if upstream.trim().is_empty() {
+                    tracing::warn!(listen_addr = %listen_addr, "udp listener missing upstream; skipping");
+                    return None;
+                }
+                let opts = proxy::UdpForwardOptions {
+                    upstream,
+                    sessions: self.deps.sessions.clone(),
+                    tunnel_manager: Some(self.deps.tunnel_manager.clone()),
+                    idle_timeout: udp.idle_timeout,
+                    source_ip: l.source_ip,
+                    max_sessions: udp.max_sessions,
+                    limits: udp.limits,
+                };
+                Box::pin(async move {
+                    proxy::serve_udp_with_shutdown(&listen_addr, opts, stop_rx, serve_ready).await
+                })
+            }
+            other => {
+                tracing::warn!(listen_addr = %l.listen_addr, protocol = %other, "unsupported listener protocol");
+                return None;
+            }
+        };
+
+        self.running.push(RunningListener {
+            cfg: l.clone(),
+            stop,
+            done,
+            _ready: ready,
+        });
+        Some(Box::pin(async move {
+            let res = serve.await;
+            drop(done_tx);
+            res
+        }))
+    }
+
+    /// Stop listeners missing from `wanted` and start the new ones, spawning them into the
+    /// set's own tasks (see [`ListenerSet::take_reloaded`]). Removed listeners are given a moment to release their sockets first so a
+    /// changed listener can rebind the same address.
+    pub async fn reconcile(
+        &mut self,
+        wanted: &[config::ProxyListenerConfig],
+        udp: proxy::UdpRuntimeConfig,
+    ) -> ListenerChanges {
+        let mut changes = ListenerChanges::default();
+        while self.reloaded.try_join_next().is_some() {}
+
+        let (keep, gone): (Vec<_>, Vec<_>) = std::mem::take(&mut self.running)
+            .into_iter()
+            .partition(|r| wanted.contains(&r.cfg));
+        self.running = keep;
+        for r in gone {
+            tracing::info!(listen_addr = %r.cfg.listen_addr, protocol = %r.cfg.protocol, "reload: stopping listener");
+            let _ = r.stop.send(true);
+            let _ = tokio::time::timeout(Duration::from_secs(5), r.done).await;
+            changes.removed += 1;
+        }
+
+        for l in wanted {
+            if self.running.iter().any(|r| &r.cfg == l) {
+                continue;
+            }
+            let Some(serve) = self.start(l, udp) else {
+                continue;
+            };
+            tracing::info!(listen_addr = %l.listen_addr, protocol = %l.protocol, "reload: starting listener");
+            let listen_addr = l.listen_addr.clone();
+            self.reloaded.spawn(async move {
+                if let Err(err) = serve.await {
+                    tracing::warn!(listen_addr = %listen_addr, err = %err, "reload: listener failed");
+                }
+            });
+            changes.added += 1;
+        }
+
+        changes
+    }
+
+    pub fn stop_all(&self) {
+        for r in &self.running {
+            let _ = r.stop.send(true);
+        }
+    }
+
+    /// Hand over the serve tasks of listeners added by reloads, so shutdown can wait for
+    /// them after `stop_all` (dropping the set aborts them).
+    pub fn take_reloaded(&mut self) -> JoinSet<()> {
+        std::mem::take(&mut self.reloaded)
+    }
+}
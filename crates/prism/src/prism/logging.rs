@@ -2,32 +2,108 @@ use std::{io, path::Path};
 
 use anyhow::Context;
 use tracing_appender::non_blocking::WorkerGuard;
-use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
 
-use crate::prism::config;
+use crate::prism::{config, telemetry};
 
 #[derive(Debug)]
 pub struct LoggingRuntime {
     _guard: WorkerGuard,
+    level: LogLevelHandle,
+}
+
+impl LoggingRuntime {
+    pub fn level_handle(&self) -> LogLevelHandle {
+        self.level.clone()
+    }
+}
+
+/// Handle for changing the active log level after `init`.
+///
+/// Only `logging.level` and `logging.access_log` are reloadable; format/output changes
+/// still require a restart.
+#[derive(Debug, Clone)]
+pub struct LogLevelHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    /// RUST_LOG was set at startup and takes precedence over `logging.level`.
+    env_override: bool,
+}
+
+impl LogLevelHandle {
+    /// Apply `level` and the access log switch to the running subscriber.
+    ///
+    /// Returns Ok(true) if the filter changed.
+    pub fn set_level(&self, level: &str, access_log: bool) -> anyhow::Result<bool> {
+        if self.env_override {
+            return Ok(false);
+        }
+
+        let filter = EnvFilter::try_new(filter_directive(level, access_log))
+            .context("logging: build filter")?;
+        // Compare rendered filters: EnvFilter orders directives its own way.
+        let wanted = filter.to_string();
+        let unchanged = self
+            .filter
+            .with_current(|f| f.to_string() == wanted)
+            .context("logging: read current filter")?;
+        if unchanged {
+            return Ok(false);
+        }
+
+        self.filter
+            .reload(filter)
+            .context("logging: reload filter")?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+impl LogLevelHandle {
+    /// A handle not wired to any subscriber; `set_level` is a no-op.
+    pub fn detached() -> Self {
+        let (_layer, filter) = reload::Layer::new(EnvFilter::new("info"));
+        Self {
+            filter,
+            env_override: true,
+        }
+    }
+}
+
+/// `level` for everything, plus the access log target switched on (at info, so it shows
+/// even under `level = "warn"`) or off.
+fn filter_directive(level: &str, access_log: bool) -> String {
+    let access = if access_log { "info" } else { "off" };
+    format!(
+        "{},{}={access}",
+        level_directive(level),
+        telemetry::ACCESS_LOG_TARGET
+    )
+}
+
+fn level_directive(level: &str) -> &'static str {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "debug" => "debug",
+        "info" => "info",
+        "warn" => "warn",
+        "error" => "error",
+        _ => "info",
+    }
 }
 
 pub fn init(logging: &config::LoggingConfig) -> anyhow::Result<LoggingRuntime> {
-    let level = logging.level.trim().to_ascii_lowercase();
     let fmt = logging.format.trim().to_ascii_lowercase();
     let out = logging.output.trim();
 
-    let filter = EnvFilter::try_from_default_env()
-        .or_else(|_| {
-            let directive = match level.as_str() {
-                "debug" => "debug",
-                "info" => "info",
-                "warn" => "warn",
-                "error" => "error",
-                _ => "info",
-            };
-            EnvFilter::try_new(directive)
-        })
-        .context("logging: init filter")?;
+    let env_filter = EnvFilter::try_from_default_env().ok();
+    let env_override = env_filter.is_some();
+    let filter = match env_filter {
+        Some(f) => f,
+        None => EnvFilter::try_new(filter_directive(&logging.level, logging.access_log))
+            .context("logging: init filter")?,
+    };
+    let (filter, filter_handle) = reload::Layer::new(filter);
 
     let (writer, guard) = make_writer(out)?;
 
@@ -49,7 +125,13 @@ pub fn init(logging: &config::LoggingConfig) -> anyhow::Result<LoggingRuntime> {
         .with(base_fmt)
         .init();
 
-    Ok(LoggingRuntime { _guard: guard })
+    Ok(LoggingRuntime {
+        _guard: guard,
+        level: LogLevelHandle {
+            filter: filter_handle,
+            env_override,
+        },
+    })
 }
 
 fn make_writer(
@@ -76,3 +158,51 @@ fn make_writer(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_handle_updates_filter() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let subscriber = tracing_subscriber::registry().with(filter);
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let level = LogLevelHandle {
+            filter: handle,
+            env_override: false,
+        };
+
+        assert!(!tracing::enabled!(tracing::Level::DEBUG));
+        assert!(level.set_level("debug", false).expect("set debug"));
+        tracing::callsite::rebuild_interest_cache();
+        assert!(tracing::enabled!(tracing::Level::DEBUG));
+
+        // Same level again is a no-op.
+        assert!(!level.set_level("DEBUG", false).expect("set debug again"));
+
+        assert!(level.set_level("warn", false).expect("set warn"));
+        tracing::callsite::rebuild_interest_cache();
+        assert!(!tracing::enabled!(tracing::Level::INFO));
+        assert!(tracing::enabled!(tracing::Level::WARN));
+        assert!(!tracing::enabled!(target: "prism::access", tracing::Level::INFO));
+
+        // The access log stays visible under a quieter level.
+        assert!(level.set_level("warn", true).expect("enable access log"));
+        tracing::callsite::rebuild_interest_cache();
+        assert!(!tracing::enabled!(tracing::Level::INFO));
+        assert!(tracing::enabled!(target: "prism::access", tracing::Level::INFO));
+        assert!(!level.set_level("warn", true).expect("same again"));
+    }
+
+    #[test]
+    fn level_handle_respects_env_override() {
+        let (_filter, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let level = LogLevelHandle {
+            filter: handle,
+            env_override: true,
+        };
+        assert!(!level.set_level("debug", false).expect("set debug"));
+    }
+}
@@ -98,6 +98,8 @@ pub struct RuntimeApplyHandles {
     pub middleware_dir: PathBuf,
     pub router: Arc<router::Router>,
     pub runtime: Arc<RwLock<proxy::TcpRuntimeConfig>>,
+    pub sessions: telemetry::SharedSessions,
+    pub reload_metrics: Arc<telemetry::ReloadMetrics>,
 }
 
 pub struct ManagementPlane {
@@ -442,6 +444,8 @@ impl WorkerAgent {
                 &runtime.middleware_dir,
                 &runtime.router,
                 &runtime.runtime,
+                &runtime.sessions,
+                &runtime.reload_metrics,
             )
             .await
             {
@@ -741,10 +745,16 @@ mod tests {
             sessions: Arc::new(telemetry::SessionRegistry::new()),
             config_path: PathBuf::from("managed.json"),
             reload_tx,
+            reload_done: None,
             tunnel: None,
+            router: None,
+            buffer_pool: None,
+            readiness: Arc::new(telemetry::Readiness::default()),
+            reload_metrics: Arc::new(telemetry::ReloadMetrics::default()),
             auth: admin::AdminAuth {
                 panel_token: Some(plane.panel_token().to_string()),
                 worker_token: Some(plane.worker_token().to_string()),
+                ..Default::default()
             },
             management: Some(plane),
             worker: None,
@@ -779,14 +789,11 @@ mod tests {
         RuntimeApplyHandles {
             middleware_dir: middleware_dir.to_path_buf(),
             router: Arc::new(router::Router::new(Vec::new())),
-            runtime: Arc::new(RwLock::new(proxy::TcpRuntimeConfig {
-                max_header_bytes: 64 * 1024,
-                handshake_timeout: Duration::from_millis(3000),
-                idle_timeout: Duration::from_millis(0),
-                upstream_dial_timeout: Duration::from_millis(5000),
-                buffer_size: 32 * 1024,
-                proxy_protocol_v2: false,
-            })),
+            runtime: Arc::new(RwLock::new(proxy::TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            reload_metrics: Arc::new(telemetry::ReloadMetrics::default()),
         }
     }
 
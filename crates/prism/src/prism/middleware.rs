@@ -1,15 +1,122 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::Write,
+    net::IpAddr,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    ptr::NonNull,
+    sync::{
+        Arc, Mutex, OnceLock, Weak,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
 };
 
 use anyhow::Context;
 use thiserror::Error;
-use wasmer::{Engine, Instance, Memory, Module, Pages, Store, TypedFunction, imports};
+use wasmer::{
+    Engine, Function, FunctionEnv, FunctionEnvMut, Instance, Memory, MemoryError, MemoryStyle,
+    MemoryType, Module, Pages, Store, TableStyle, TableType, TypedFunction, imports,
+    sys::{
+        BaseTunables, CompilerConfig, NativeEngineExt, Singlepass, Target, Tunables,
+        vm::{VMMemory, VMMemoryDefinition, VMTable, VMTableDefinition},
+        wasmparser::Operator,
+    },
+};
+use wasmer_middlewares::{
+    Metering,
+    metering::{MeteringPoints, get_remaining_points, set_remaining_points},
+};
+
+use crate::prism::{minecraft, tls};
 
 type WasmInstanceParts = (Store, Instance, Memory, TypedFunction<(i32, i32), i64>);
+type InstancePool = (Weak<()>, IdleInstances);
+
+thread_local! {
+    /// Idle instances per middleware (by `WasmMiddleware::pool_id`). Wasm stores can't move
+    /// between threads, so each worker thread keeps its own; entries of dropped
+    /// middlewares are pruned on the next check-in.
+    static INSTANCE_POOLS: std::cell::RefCell<HashMap<u64, InstancePool>> =
+        std::cell::RefCell::new(HashMap::new());
+}
+
+static NEXT_POOL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Idle pooled instances across all worker threads.
+static WASM_INSTANCES_POOLED: AtomicUsize = AtomicUsize::new(0);
+/// Instances currently running a middleware call.
+static WASM_INSTANCES_IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+/// One middleware's idle instances on one thread, counted in `WASM_INSTANCES_POOLED`
+/// until popped or dropped (pruned pools and exiting threads included).
+#[derive(Default)]
+struct IdleInstances(Vec<WasmInstanceParts>);
+
+impl IdleInstances {
+    fn pop(&mut self) -> Option<WasmInstanceParts> {
+        let parts = self.0.pop()?;
+        WASM_INSTANCES_POOLED.fetch_sub(1, Ordering::Relaxed);
+        Some(parts)
+    }
+
+    fn push(&mut self, parts: WasmInstanceParts) {
+        self.0.push(parts);
+        WASM_INSTANCES_POOLED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl std::ops::Deref for IdleInstances {
+    type Target = [WasmInstanceParts];
+
+    fn deref(&self) -> &[WasmInstanceParts] {
+        &self.0
+    }
+}
+
+impl Drop for IdleInstances {
+    fn drop(&mut self) {
+        WASM_INSTANCES_POOLED.fetch_sub(self.0.len(), Ordering::Relaxed);
+    }
+}
+
+/// Holds a slot in `WASM_INSTANCES_IN_USE` for the duration of a middleware call.
+struct InstanceInUse;
+
+impl InstanceInUse {
+    fn enter() -> Self {
+        WASM_INSTANCES_IN_USE.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for InstanceInUse {
+    fn drop(&mut self) {
+        WASM_INSTANCES_IN_USE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Prometheus text exposition of wasm instance pool utilization across worker threads.
+pub fn render_prometheus() -> String {
+    format!(
+        "# HELP prism_wasm_instances_pooled Idle wasm middleware instances kept for reuse.\n\
+         # TYPE prism_wasm_instances_pooled gauge\n\
+         prism_wasm_instances_pooled {}\n\
+         # HELP prism_wasm_instances_in_use Wasm middleware instances currently running a call.\n\
+         # TYPE prism_wasm_instances_in_use gauge\n\
+         prism_wasm_instances_in_use {}\n",
+        WASM_INSTANCES_POOLED.load(Ordering::Relaxed),
+        WASM_INSTANCES_IN_USE.load(Ordering::Relaxed),
+    )
+}
+
+/// Compiled middlewares by source file and load options, with a digest of the source they
+/// were built from. Shared by every provider so a reload only recompiles changed files.
+type ModuleCache = HashMap<ModuleKey, (u64, SharedMiddleware)>;
+type ModuleKey = (PathBuf, WasmMiddlewareOptions);
+
+fn module_cache() -> &'static Mutex<ModuleCache> {
+    static CACHE: OnceLock<Mutex<ModuleCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
 #[derive(Debug, Error)]
 pub enum MiddlewareError {
@@ -27,6 +134,24 @@ pub enum MiddlewarePhase {
     Parse = 0,
     /// Rewrite the captured prelude before proxying upstream.
     Rewrite = 1,
+    /// Rewrite the upstream's first reply before it reaches the client (status pings only).
+    Response = 2,
+}
+
+/// Details of the client connection being routed, exposed to middlewares through the v2
+/// ctx struct.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientMeta {
+    /// Client address (from a trusted PROXY header when one was accepted).
+    pub ip: Option<IpAddr>,
+    /// Local port of the listener that accepted the connection.
+    pub listen_port: Option<u16>,
+}
+
+impl ClientMeta {
+    fn is_empty(&self) -> bool {
+        self.ip.is_none() && self.listen_port.is_none()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +163,7 @@ pub struct MiddlewareCtx {
     /// For tunnel upstreams (`tunnel:<service>`), Prism skips rewrite unless a configured
     /// masquerade host (see `tunnel.services[].masquerade_host`) provides a real protocol host.
     pub selected_upstream: Option<String>,
+    pub client: ClientMeta,
 }
 
 impl MiddlewareCtx {
@@ -45,6 +171,7 @@ impl MiddlewareCtx {
         Self {
             phase: MiddlewarePhase::Parse,
             selected_upstream: None,
+            client: ClientMeta::default(),
         }
     }
 
@@ -52,8 +179,22 @@ impl MiddlewareCtx {
         Self {
             phase: MiddlewarePhase::Rewrite,
             selected_upstream: Some(selected_upstream.trim().to_string()),
+            client: ClientMeta::default(),
+        }
+    }
+
+    pub fn response() -> Self {
+        Self {
+            phase: MiddlewarePhase::Response,
+            selected_upstream: None,
+            client: ClientMeta::default(),
         }
     }
+
+    pub fn with_client(mut self, client: ClientMeta) -> Self {
+        self.client = client;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -71,6 +212,12 @@ pub trait Middleware: Send + Sync {
         prelude: &[u8],
         ctx: &MiddlewareCtx,
     ) -> Result<MiddlewareOutput, MiddlewareError>;
+
+    /// Whether this middleware wants the response phase. Status replies are only buffered
+    /// and handed to middlewares when one of the route's does.
+    fn handles_response(&self) -> bool {
+        false
+    }
 }
 
 pub type SharedMiddleware = Arc<dyn Middleware>;
@@ -96,12 +243,33 @@ pub trait MiddlewareChain: Send + Sync {
     /// Apply middleware chain in parse mode.
     ///
     /// On success returns (host, maybe_rewritten_prelude).
-    fn parse(&self, prelude: &[u8]) -> Result<(String, Option<Vec<u8>>), MiddlewareError>;
+    fn parse(
+        &self,
+        prelude: &[u8],
+        client: ClientMeta,
+    ) -> Result<(String, Option<Vec<u8>>), MiddlewareError>;
 
     /// Apply middleware chain in rewrite mode.
     ///
     /// Returns Some(new_prelude) if any middleware rewrote the buffer.
-    fn rewrite(&self, prelude: &[u8], selected_upstream: &str) -> Option<Vec<u8>>;
+    fn rewrite(
+        &self,
+        prelude: &[u8],
+        selected_upstream: &str,
+        client: ClientMeta,
+    ) -> Option<Vec<u8>>;
+
+    /// Apply middleware chain in response mode to the upstream's first reply.
+    ///
+    /// Returns Some(new_bytes) if any middleware rewrote the buffer.
+    fn response(&self, _bytes: &[u8], _client: ClientMeta) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Whether any middleware in the chain wants the response phase.
+    fn handles_response(&self) -> bool {
+        false
+    }
 }
 
 pub struct ChainMiddleware {
@@ -123,8 +291,12 @@ impl MiddlewareChain for ChainMiddleware {
         "chain"
     }
 
-    fn parse(&self, prelude: &[u8]) -> Result<(String, Option<Vec<u8>>), MiddlewareError> {
-        let ctx = MiddlewareCtx::parse();
+    fn parse(
+        &self,
+        prelude: &[u8],
+        client: ClientMeta,
+    ) -> Result<(String, Option<Vec<u8>>), MiddlewareError> {
+        let ctx = MiddlewareCtx::parse().with_client(client);
 
         let mut need_more = false;
         let mut current: Vec<u8> = prelude.to_vec();
@@ -164,8 +336,13 @@ impl MiddlewareChain for ChainMiddleware {
         }
     }
 
-    fn rewrite(&self, prelude: &[u8], selected_upstream: &str) -> Option<Vec<u8>> {
-        let ctx = MiddlewareCtx::rewrite(selected_upstream);
+    fn rewrite(
+        &self,
+        prelude: &[u8],
+        selected_upstream: &str,
+        client: ClientMeta,
+    ) -> Option<Vec<u8>> {
+        let ctx = MiddlewareCtx::rewrite(selected_upstream).with_client(client);
 
         let mut current: Vec<u8> = prelude.to_vec();
         let mut changed = false;
@@ -186,24 +363,85 @@ impl MiddlewareChain for ChainMiddleware {
 
         if changed { Some(current) } else { None }
     }
+
+    fn response(&self, bytes: &[u8], client: ClientMeta) -> Option<Vec<u8>> {
+        let ctx = MiddlewareCtx::response().with_client(client);
+
+        let mut current: Vec<u8> = bytes.to_vec();
+        let mut changed = false;
+
+        for m in self.middlewares.iter().filter(|m| m.handles_response()) {
+            // Like rewrites, failures leave the bytes as they were.
+            if let Ok(MiddlewareOutput {
+                rewrite: Some(rw), ..
+            }) = m.apply(&current, &ctx)
+            {
+                current = rw;
+                changed = true;
+            }
+        }
+
+        if changed { Some(current) } else { None }
+    }
+
+    fn handles_response(&self) -> bool {
+        self.middlewares.iter().any(|m| m.handles_response())
+    }
 }
 
+/// Loads middlewares from `<dir>/<name>.wat` (or `.wasm` when allowed).
+///
+/// Every `get` checks the file's contents against the compiled module cache, so edited
+/// files are recompiled on the next route build. If an edited file fails to compile, the
+/// last good module keeps serving and a warning is logged; a file that can no longer be
+/// read is dropped from the cache and fails like any missing middleware.
 pub struct FsWasmMiddlewareProvider {
     dir: PathBuf,
-    cache: Mutex<HashMap<String, SharedMiddleware>>,
+    options: HashMap<String, WasmMiddlewareOptions>,
+    allow_wasm_binary: bool,
+    /// Cache keys this provider has served, for `forget_unused_modules`.
+    used: Mutex<HashSet<ModuleKey>>,
 }
 
 impl FsWasmMiddlewareProvider {
     pub fn new(dir: PathBuf) -> Self {
         Self {
             dir,
-            cache: Mutex::new(HashMap::new()),
+            options: HashMap::new(),
+            allow_wasm_binary: false,
+            used: Mutex::new(HashSet::new()),
         }
     }
 
+    /// Drop cached modules from this provider's directory that it hasn't served. Call
+    /// it once the routes built from this provider are in use, so modules of removed
+    /// middlewares don't outlive the reload that removed them.
+    pub fn forget_unused_modules(&self) {
+        let used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        forget_cached_modules(&self.dir, &used);
+    }
+
+    /// Per-middleware load options keyed by middleware name.
+    ///
+    /// Middlewares without an entry use `WasmMiddlewareOptions::default()`.
+    pub fn with_options(mut self, options: HashMap<String, WasmMiddlewareOptions>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Fall back to `<name>.wasm` binaries for middlewares without a `<name>.wat`.
+    pub fn with_wasm_binaries(mut self, allow: bool) -> Self {
+        self.allow_wasm_binary = allow;
+        self
+    }
+
     fn wat_path_for(&self, name: &str) -> PathBuf {
         self.dir.join(format!("{name}.wat"))
     }
+
+    fn wasm_path_for(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.wasm"))
+    }
 }
 
 impl MiddlewareProvider for FsWasmMiddlewareProvider {
@@ -213,23 +451,215 @@ impl MiddlewareProvider for FsWasmMiddlewareProvider {
             anyhow::bail!("middleware: empty name");
         }
 
-        if let Ok(guard) = self.cache.lock()
-            && let Some(m) = guard.get(name)
+        let wat_path = self.wat_path_for(name);
+        let wasm_path = self.wasm_path_for(name);
+        let binary = !wat_path.exists() && wasm_path.exists();
+        if binary && !self.allow_wasm_binary {
+            anyhow::bail!(
+                "middleware: loading raw .wasm is disabled; provide a .wat file instead or set allow_wasm_binary = true ({})",
+                wasm_path.display()
+            );
+        }
+        let path = if binary { wasm_path } else { wat_path };
+        let opts = self.options.get(name).copied().unwrap_or_default();
+        let key = (path.clone(), opts);
+
+        let digest = std::fs::read(&path).ok().map(|src| content_digest(&src));
+        let cached = match module_cache().lock() {
+            // Deleted or unreadable: nothing to fall back to.
+            Ok(mut cache) if digest.is_none() => {
+                cache.remove(&key);
+                None
+            }
+            Ok(cache) => cache.get(&key).cloned(),
+            Err(_) => None,
+        };
+        if let (Some(digest), Some((built_from, mw))) = (digest, &cached)
+            && digest == *built_from
         {
-            return Ok(m.clone());
+            return Ok(mw.clone());
         }
 
-        let wat_path = self.wat_path_for(name);
-        let mw = Arc::new(WasmMiddleware::from_wat_path(name, &wat_path)?) as SharedMiddleware;
+        let loaded = if binary {
+            WasmMiddleware::from_wasm_path(name, &path, opts)
+        } else {
+            WasmMiddleware::from_wat_path(name, &path, opts)
+        };
+        if loaded.is_ok() || cached.is_some() {
+            self.used
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(key.clone());
+        }
+        match (loaded, cached) {
+            (Ok(mw), _) => {
+                let mw = Arc::new(mw) as SharedMiddleware;
+                if let Some(digest) = digest
+                    && let Ok(mut cache) = module_cache().lock()
+                {
+                    cache.insert(key, (digest, mw.clone()));
+                }
+                Ok(mw)
+            }
+            (Err(err), Some((_, last_good))) => {
+                tracing::warn!(
+                    middleware = %name,
+                    path = %path.display(),
+                    err = %format!("{err:#}"),
+                    "middleware: reload failed; keeping the last good module"
+                );
+                Ok(last_good)
+            }
+            (Err(err), None) => Err(err),
+        }
+    }
+}
+
+/// Drop cached modules loaded from `dir` except the `keep` ones.
+pub fn forget_cached_modules(dir: &Path, keep: &HashSet<(PathBuf, WasmMiddlewareOptions)>) {
+    if let Ok(mut cache) = module_cache().lock() {
+        cache.retain(|key, _| !key.0.starts_with(dir) || keep.contains(key));
+    }
+}
+
+/// Serves the middlewares bundled into the binary (`minecraft_handshake`, `tls_sni`)
+/// straight from memory, for embedders that don't want a middleware directory.
+#[derive(Default)]
+pub struct BuiltinMiddlewareProvider {
+    options: HashMap<String, WasmMiddlewareOptions>,
+}
+
+impl BuiltinMiddlewareProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        if let Ok(mut guard) = self.cache.lock() {
-            guard.insert(name.to_string(), mw.clone());
+    /// Per-middleware load options keyed by middleware name.
+    pub fn with_options(mut self, options: HashMap<String, WasmMiddlewareOptions>) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl MiddlewareProvider for BuiltinMiddlewareProvider {
+    fn get(&self, name: &str) -> anyhow::Result<SharedMiddleware> {
+        let name = name.trim();
+        let Some((_, wat)) = DEFAULT_MIDDLEWARES.iter().find(|(n, _)| *n == name) else {
+            anyhow::bail!("middleware: no built-in middleware named {name:?}");
+        };
+        let opts = self.options.get(name).copied().unwrap_or_default();
+        Ok(Arc::new(WasmMiddleware::from_wat(name, wat, opts)?))
+    }
+}
+
+/// Host extractor for `HostParser::Custom`, called with the prelude captured so far.
+pub type HostFn = Arc<dyn Fn(&[u8]) -> Result<String, MiddlewareError> + Send + Sync>;
+
+/// Extracts the routing host natively, without a wasm module. Used by routers built in
+/// code (`Router::from_static`); it only acts in parse mode and never rewrites.
+#[derive(Clone)]
+pub enum HostParser {
+    /// Server address of a Minecraft handshake, cut at the first NUL (Forge markers).
+    Minecraft,
+    /// SNI host name of a TLS ClientHello.
+    Sni,
+    /// Caller-supplied extractor.
+    Custom(HostFn),
+}
+
+impl HostParser {
+    fn parse_minecraft(prelude: &[u8]) -> Result<String, MiddlewareError> {
+        if !minecraft::looks_like_handshake(prelude) {
+            return Err(MiddlewareError::NoMatch);
+        }
+        if let Some(hs) = minecraft::parse_handshake(prelude) {
+            let host = hs.server_address.split('\0').next().unwrap_or_default();
+            return Ok(host.to_string());
         }
+        // A complete frame that still doesn't parse is not a handshake.
+        match minecraft::read_varint(prelude, 0) {
+            Some((len, n)) if prelude.len() >= n + len as usize => Err(MiddlewareError::NoMatch),
+            _ => Err(MiddlewareError::NeedMoreData),
+        }
+    }
 
-        Ok(mw)
+    fn parse_sni(prelude: &[u8]) -> Result<String, MiddlewareError> {
+        match tls::parse_client_hello(prelude) {
+            Ok(tls::ClientHello::Incomplete) => Err(MiddlewareError::NeedMoreData),
+            Ok(tls::ClientHello::Complete {
+                server_name: Some(host),
+            }) => Ok(host),
+            Ok(tls::ClientHello::Complete { server_name: None }) | Err(_) => {
+                Err(MiddlewareError::NoMatch)
+            }
+        }
     }
 }
 
+impl Middleware for HostParser {
+    fn name(&self) -> &str {
+        match self {
+            HostParser::Minecraft => "minecraft_handshake",
+            HostParser::Sni => "tls_sni",
+            HostParser::Custom(_) => "custom",
+        }
+    }
+
+    fn apply(
+        &self,
+        prelude: &[u8],
+        ctx: &MiddlewareCtx,
+    ) -> Result<MiddlewareOutput, MiddlewareError> {
+        if ctx.phase != MiddlewarePhase::Parse {
+            return Ok(MiddlewareOutput::default());
+        }
+        let host = match self {
+            HostParser::Minecraft => Self::parse_minecraft(prelude)?,
+            HostParser::Sni => Self::parse_sni(prelude)?,
+            HostParser::Custom(f) => f(prelude)?,
+        };
+        Ok(MiddlewareOutput {
+            host: Some(host),
+            rewrite: None,
+        })
+    }
+}
+
+fn content_digest(bytes: &[u8]) -> u64 {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let mut h = DefaultHasher::new();
+    bytes.hash(&mut h);
+    h.finish()
+}
+
+/// Modification time and size of every `.wat`/`.wasm` file in `dir`, sorted by name;
+/// the reload loop polls this to notice edited middlewares.
+pub fn middleware_dir_signature(dir: &Path) -> Vec<(PathBuf, u64, u64)> {
+    let mut out: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+            if ext != "wat" && ext != "wasm" {
+                return None;
+            }
+            let meta = entry.metadata().ok()?;
+            let mtime = meta
+                .modified()
+                .ok()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            Some((path, mtime, meta.len()))
+        })
+        .collect();
+    out.sort();
+    out
+}
+
 const DEFAULT_MIDDLEWARES: &[(&str, &str)] = &[
     (
         "minecraft_handshake",
@@ -292,24 +722,77 @@ pub fn materialize_default_middlewares(dir: &Path) -> anyhow::Result<Vec<PathBuf
     Ok(created)
 }
 
+/// Default cap on the routing host a middleware may return (the DNS name length limit).
+pub const DEFAULT_MAX_OUTPUT_LEN: usize = 255;
+
+/// Default number of idle wasm instances kept per middleware and worker thread.
+pub const DEFAULT_INSTANCE_POOL_SIZE: usize = 16;
+
+/// Default number of wasm operators a single middleware call may execute.
+pub const DEFAULT_FUEL_LIMIT: u64 = 10_000_000;
+
+/// Default cap on a middleware instance's linear memory, in 64 KiB pages (16 MiB).
+pub const DEFAULT_MAX_MEMORY_PAGES: u32 = 256;
+
+/// Pages the host always provides, so fixed-offset middlewares can place their output.
+pub const MIN_MEMORY_PAGES: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WasmMiddlewareOptions {
+    /// Maximum length in bytes of the host returned in parse mode.
+    pub max_output_len: usize,
+    /// Idle instances kept per worker thread for reuse (0 = instantiate on every call).
+    pub instance_pool: usize,
+    /// Wasm operators a single call may execute before it is aborted (0 = unmetered).
+    pub fuel_limit: u64,
+    /// Cap on linear memory in 64 KiB pages, for both host and in-module growth.
+    pub max_memory_pages: u32,
+}
+
+impl Default for WasmMiddlewareOptions {
+    fn default() -> Self {
+        Self {
+            max_output_len: DEFAULT_MAX_OUTPUT_LEN,
+            instance_pool: DEFAULT_INSTANCE_POOL_SIZE,
+            fuel_limit: DEFAULT_FUEL_LIMIT,
+            max_memory_pages: DEFAULT_MAX_MEMORY_PAGES,
+        }
+    }
+}
+
 pub struct WasmMiddleware {
     name: String,
     path_hint: String,
     fn_name: String,
+    max_output_len: usize,
+    fuel_limit: u64,
+    max_memory_pages: u32,
     engine: Engine,
     module: Module,
+    /// Key of this middleware's idle instances in `INSTANCE_POOLS`.
+    pool_id: u64,
+    /// Dropped with the middleware, marking its pooled instances for pruning.
+    alive: Arc<()>,
+    /// Pool capacity per thread; 0 when pooling is off or the module has mutable globals,
+    /// which the host can't reset between calls.
+    pool_size: usize,
+    /// Linear memory right after instantiation (data segments applied).
+    initial_memory: OnceLock<Vec<u8>>,
+    /// The module exports `RESPONSE_PHASE_EXPORT`.
+    response_phase: bool,
 }
 
-impl WasmMiddleware {
-    pub fn from_wat_path(name: &str, path: &Path) -> anyhow::Result<Self> {
-        let name = name.trim();
-        if name.is_empty() {
-            anyhow::bail!("middleware: empty wasm middleware name");
-        }
-        if path.as_os_str().is_empty() {
-            anyhow::bail!("middleware: empty wasm middleware path");
-        }
+/// Export a module declares (any kind, usually an immutable global) to take part in the
+/// response phase.
+pub const RESPONSE_PHASE_EXPORT: &str = "prism_mw_response";
 
+impl WasmMiddleware {
+    pub fn from_wat_path(
+        name: &str,
+        path: &Path,
+        opts: WasmMiddlewareOptions,
+    ) -> anyhow::Result<Self> {
+        let name = check_load_args(name, path, &opts)?;
         if path
             .extension()
             .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("wasm"))
@@ -330,23 +813,147 @@ impl WasmMiddleware {
             );
         }
 
+        let wasm = wasmer::wat2wasm(&wat_bytes).context("middleware: compile wat module")?;
+        Self::from_wasm_bytes(name, path, &wasm, opts)
+    }
+
+    /// Compile WAT source held in memory; errors name it as `<name>.wat`.
+    pub fn from_wat(name: &str, wat: &str, opts: WasmMiddlewareOptions) -> anyhow::Result<Self> {
+        let hint = PathBuf::from(format!("{}.wat", name.trim()));
+        let name = check_load_args(name, &hint, &opts)?;
+        let wasm = wasmer::wat2wasm(wat.as_bytes()).context("middleware: compile wat module")?;
+        Self::from_wasm_bytes(name, &hint, &wasm, opts)
+    }
+
+    /// Load a compiled `.wasm` module (e.g. built with a Rust or TinyGo SDK). Callers gate
+    /// this behind `allow_wasm_binary`; `from_wat_path` remains the default.
+    pub fn from_wasm_path(
+        name: &str,
+        path: &Path,
+        opts: WasmMiddlewareOptions,
+    ) -> anyhow::Result<Self> {
+        let name = check_load_args(name, path, &opts)?;
+        let wasm = std::fs::read(path)
+            .with_context(|| format!("middleware: read wasm {}", path.display()))?;
+        if !wasm.starts_with(b"\0asm") {
+            anyhow::bail!(
+                "middleware: expected a wasm binary but got something else (path={})",
+                path.display()
+            );
+        }
+        Self::from_wasm_bytes(name, path, &wasm, opts)
+    }
+
+    fn from_wasm_bytes(
+        name: &str,
+        path: &Path,
+        wasm: &[u8],
+        opts: WasmMiddlewareOptions,
+    ) -> anyhow::Result<Self> {
         let fn_name = "prism_mw_run".to_string();
-        let engine = Engine::default();
+        let mut engine = if opts.fuel_limit > 0 {
+            // Charges one unit per executed operator. Costs are settled before every branch,
+            // call and block boundary, so a runaway loop traps instead of hanging the
+            // routing path. A `Metering` instance meters exactly one module.
+            let mut compiler = Singlepass::new();
+            compiler.push_middleware(Arc::new(Metering::new(opts.fuel_limit, |_: &Operator| 1)));
+            Engine::from(compiler)
+        } else {
+            Engine::default()
+        };
+        engine.set_tunables(MemoryCap {
+            max_pages: Pages(opts.max_memory_pages),
+            base: BaseTunables::for_target(&Target::default()),
+        });
         let store = Store::new(engine.clone());
-        let module = Module::new(&store, wat_bytes).context("middleware: compile wat module")?;
+        let module = Module::new(&store, wasm).context("middleware: compile wasm module")?;
+        let response_phase = module.exports().any(|e| e.name() == RESPONSE_PHASE_EXPORT);
+        let pool_size = if has_mutable_globals(wasm) {
+            0
+        } else {
+            opts.instance_pool
+        };
 
         Ok(Self {
             name: name.to_string(),
             path_hint: path.display().to_string(),
             fn_name,
+            max_output_len: opts.max_output_len,
+            fuel_limit: opts.fuel_limit,
+            max_memory_pages: opts.max_memory_pages,
             engine,
             module,
+            pool_id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            alive: Arc::new(()),
+            pool_size,
+            initial_memory: OnceLock::new(),
+            response_phase,
         })
     }
 
+    /// An idle pooled instance, or a fresh one.
+    fn checkout(&self) -> anyhow::Result<WasmInstanceParts> {
+        if self.pool_size > 0
+            && let Some(parts) = INSTANCE_POOLS.with_borrow_mut(|pools| {
+                pools
+                    .get_mut(&self.pool_id)
+                    .and_then(|(_, idle)| idle.pop())
+            })
+        {
+            return Ok(parts);
+        }
+        let parts = self.instantiate()?;
+        if self.pool_size > 0 && self.initial_memory.get().is_none() {
+            let image = parts.2.view(&parts.0).copy_to_vec()?;
+            let _ = self.initial_memory.set(image);
+        }
+        Ok(parts)
+    }
+
+    /// Return an instance to the pool after restoring its memory to the initial image, so
+    /// the next call sees exactly what a fresh instance would. Instances whose memory grew
+    /// (a large prelude, or `memory.grow` in the module) are dropped instead: memory can't
+    /// shrink, and pooling them would pin the grown size for the life of the worker.
+    fn checkin(&self, parts: WasmInstanceParts) {
+        if self.pool_size == 0 {
+            return;
+        }
+        let Some(image) = self.initial_memory.get() else {
+            return;
+        };
+        let (store, _, memory, _) = &parts;
+        let view = memory.view(store);
+        if view.data_size() != image.len() as u64 || view.write(0, image).is_err() {
+            return;
+        }
+
+        INSTANCE_POOLS.with_borrow_mut(|pools| {
+            pools.retain(|_, (alive, _)| alive.strong_count() > 0);
+            let (_, idle) = pools
+                .entry(self.pool_id)
+                .or_insert_with(|| (Arc::downgrade(&self.alive), IdleInstances::default()));
+            if idle.len() < self.pool_size {
+                idle.push(parts);
+            }
+        });
+    }
+
     fn instantiate(&self) -> anyhow::Result<WasmInstanceParts> {
         let mut store = Store::new(self.engine.clone());
-        let import_object = imports! {};
+        let env = FunctionEnv::new(
+            &mut store,
+            HostEnv {
+                name: self.name.clone(),
+                memory: None,
+            },
+        );
+        // Modules only get the imports they declare, so import-free ones are unaffected.
+        let import_object = imports! {
+            "prism" => {
+                "log" => Function::new_typed_with_env(&mut store, &env, host_log),
+                "now_unix_ms" => Function::new_typed(&mut store, host_now_unix_ms),
+            },
+        };
 
         let instance = Instance::new(&mut store, &self.module, &import_object)
             .context("middleware: instantiate wasm")?;
@@ -362,6 +969,18 @@ impl WasmMiddleware {
             .map_err(|e| anyhow::anyhow!("middleware: wasm missing exported memory 'memory': {e}"))?
             .clone();
 
+        env.as_mut(&mut store).memory = Some(memory.clone());
+
+        // Every call grows memory to the minimum anyway; doing it up front keeps that
+        // growth out of pooled instances' initial image.
+        let size = memory.view(&store).data_size();
+        let min = MIN_MEMORY_PAGES as u64 * 65536;
+        if size < min {
+            memory
+                .grow(&mut store, Pages((min - size).div_ceil(65536) as u32))
+                .context("middleware: grow wasm memory to the minimum")?;
+        }
+
         Ok((store, instance, memory, run))
     }
 
@@ -370,23 +989,51 @@ impl WasmMiddleware {
         prelude: &[u8],
         ctx: &MiddlewareCtx,
     ) -> Result<MiddlewareOutput, MiddlewareError> {
-        let (mut store, _instance, memory, run) = self
-            .instantiate()
+        let _in_use = InstanceInUse::enter();
+        let mut parts = self
+            .checkout()
             .map_err(|e| MiddlewareError::Fatal(e.to_string()))?;
+        let res = self.call_instance(&mut parts, prelude, ctx);
+        // A trapped or misbehaving instance may be left in any state; don't reuse it.
+        if !matches!(res, Err(MiddlewareError::Fatal(_))) {
+            self.checkin(parts);
+        }
+        res
+    }
+
+    fn call_instance(
+        &self,
+        (store, instance, memory, run): &mut WasmInstanceParts,
+        prelude: &[u8],
+        ctx: &MiddlewareCtx,
+    ) -> Result<MiddlewareOutput, MiddlewareError> {
+        let mut store = store;
 
         // Layout: [prelude @0] [ctx struct] [ctx strings]
         // ABI structs are little-endian.
         // Ctx struct (v1):
         //   u32 version (=1)
-        //   u32 phase   (=0 parse, 1 rewrite)
+        //   u32 phase   (=0 parse, 1 rewrite, 2 response)
         //   u32 upstream_ptr
         //   u32 upstream_len
-        const CTX_STRUCT_LEN: u32 = 16;
+        // v2 (written when client details are known) appends:
+        //   u32 client_ip_ptr (textual IP, e.g. "203.0.113.7"; 0 if unknown)
+        //   u32 client_ip_len
+        //   u32 listen_port   (0 if unknown)
+        // and sets version = 2, so v1 modules keep reading the same offsets.
+        const CTX_V1_LEN: u32 = 16;
+        const CTX_V2_LEN: u32 = 28;
+
+        let (version, ctx_len) = if ctx.client.is_empty() {
+            (1u32, CTX_V1_LEN)
+        } else {
+            (2u32, CTX_V2_LEN)
+        };
 
         let mut cursor: u32 = ((prelude.len() as u32) + 7) & !7; // align8
         let ctx_ptr = cursor;
         cursor = cursor
-            .checked_add(CTX_STRUCT_LEN)
+            .checked_add(ctx_len)
             .ok_or_else(|| MiddlewareError::Fatal("ctx overflow".into()))?;
 
         let upstream = ctx
@@ -405,8 +1052,26 @@ impl WasmMiddleware {
             0
         };
 
+        let client_ip = ctx.client.ip.map(|ip| ip.to_string()).unwrap_or_default();
+        let client_ip_ptr = if !client_ip.is_empty() {
+            let p = cursor;
+            cursor = cursor
+                .checked_add(client_ip.len() as u32)
+                .ok_or_else(|| MiddlewareError::Fatal("ctx client ip overflow".into()))?;
+            p
+        } else {
+            0
+        };
+
         // Ensure memory can fit prelude+ctx at their offsets.
         let need = cursor as u64;
+        let cap = self.max_memory_pages as u64 * 65536;
+        if need > cap {
+            return Err(MiddlewareError::Fatal(format!(
+                "prelude and ctx need {need} bytes, above max_memory_pages={} (path={})",
+                self.max_memory_pages, self.path_hint
+            )));
+        }
         let mut mem_size = memory.view(&store).data_size();
         if need > mem_size {
             let delta = need - mem_size;
@@ -419,8 +1084,8 @@ impl WasmMiddleware {
 
         // Always ensure a few pages so fixed-offset middlewares can place output safely.
         // (Rewrite outputs may be larger than the ctx struct region; wasm itself can't grow memory.)
-        if mem_size < 4 * 65536 {
-            let pages = (4 * 65536 - mem_size).div_ceil(65536);
+        if mem_size < MIN_MEMORY_PAGES as u64 * 65536 {
+            let pages = (MIN_MEMORY_PAGES as u64 * 65536 - mem_size).div_ceil(65536);
             memory
                 .grow(&mut store, Pages(pages as u32))
                 .map_err(|e| MiddlewareError::Fatal(format!("wasm memory grow failed: {e}")))?;
@@ -441,21 +1106,52 @@ impl WasmMiddleware {
                 })?;
         }
 
+        if !client_ip.is_empty() {
+            memory
+                .view(&store)
+                .write(client_ip_ptr as u64, client_ip.as_bytes())
+                .map_err(|e| {
+                    MiddlewareError::Fatal(format!("wasm memory write client ip failed: {e}"))
+                })?;
+        }
+
         // Write ctx struct.
-        let mut ctx_buf = [0u8; CTX_STRUCT_LEN as usize];
-        ctx_buf[0..4].copy_from_slice(&1u32.to_le_bytes());
+        let mut ctx_buf = [0u8; CTX_V2_LEN as usize];
+        ctx_buf[0..4].copy_from_slice(&version.to_le_bytes());
         ctx_buf[4..8].copy_from_slice(&(ctx.phase as u32).to_le_bytes());
         ctx_buf[8..12].copy_from_slice(&upstream_ptr.to_le_bytes());
         ctx_buf[12..16].copy_from_slice(&(upstream.len() as u32).to_le_bytes());
+        ctx_buf[16..20].copy_from_slice(&client_ip_ptr.to_le_bytes());
+        ctx_buf[20..24].copy_from_slice(&(client_ip.len() as u32).to_le_bytes());
+        ctx_buf[24..28]
+            .copy_from_slice(&u32::from(ctx.client.listen_port.unwrap_or(0)).to_le_bytes());
 
         memory
             .view(&store)
-            .write(ctx_ptr as u64, &ctx_buf)
+            .write(ctx_ptr as u64, &ctx_buf[..ctx_len as usize])
             .map_err(|e| MiddlewareError::Fatal(format!("wasm memory write ctx failed: {e}")))?;
 
+        if self.fuel_limit > 0 {
+            set_remaining_points(&mut *store, instance, self.fuel_limit);
+        }
+
         let out = run
             .call(&mut store, prelude.len() as i32, ctx_ptr as i32)
-            .map_err(|e| MiddlewareError::Fatal(format!("wasm middleware call failed: {e}")))?;
+            .map_err(|e| {
+                let exhausted = self.fuel_limit > 0
+                    && matches!(
+                        get_remaining_points(&mut *store, instance),
+                        MeteringPoints::Exhausted
+                    );
+                if exhausted {
+                    MiddlewareError::Fatal(format!(
+                        "wasm middleware ran out of fuel (limit={}, path={})",
+                        self.fuel_limit, self.path_hint
+                    ))
+                } else {
+                    MiddlewareError::Fatal(format!("wasm middleware call failed: {e}"))
+                }
+            })?;
 
         if out == 0 {
             return Err(MiddlewareError::NeedMoreData);
@@ -499,6 +1195,12 @@ impl WasmMiddleware {
         let mut out = MiddlewareOutput::default();
 
         if host_len > 0 {
+            if host_len as usize > self.max_output_len {
+                return Err(MiddlewareError::Fatal(format!(
+                    "wasm middleware host too long (len={host_len}, max={}, path={})",
+                    self.max_output_len, self.path_hint
+                )));
+            }
             let host_end = (host_ptr as u64)
                 .checked_add(host_len as u64)
                 .ok_or_else(|| MiddlewareError::Fatal("host range overflow".into()))?;
@@ -515,6 +1217,12 @@ impl WasmMiddleware {
         }
 
         if rw_len > 0 {
+            if rw_len as u64 > cap {
+                return Err(MiddlewareError::Fatal(format!(
+                    "wasm rewrite too long (len={rw_len}, max_memory_pages={}, path={})",
+                    self.max_memory_pages, self.path_hint
+                )));
+            }
             let rw_end = (rw_ptr as u64)
                 .checked_add(rw_len as u64)
                 .ok_or_else(|| MiddlewareError::Fatal("rewrite range overflow".into()))?;
@@ -535,6 +1243,158 @@ impl WasmMiddleware {
     }
 }
 
+/// Validates the arguments shared by the `WasmMiddleware` loaders; returns the trimmed name.
+fn check_load_args<'a>(
+    name: &'a str,
+    path: &Path,
+    opts: &WasmMiddlewareOptions,
+) -> anyhow::Result<&'a str> {
+    let name = name.trim();
+    if name.is_empty() {
+        anyhow::bail!("middleware: empty wasm middleware name");
+    }
+    if path.as_os_str().is_empty() {
+        anyhow::bail!("middleware: empty wasm middleware path");
+    }
+    if opts.max_output_len == 0 {
+        anyhow::bail!("middleware: {name}: max_output_len must be at least 1");
+    }
+    if opts.max_memory_pages < MIN_MEMORY_PAGES {
+        anyhow::bail!("middleware: {name}: max_memory_pages must be at least {MIN_MEMORY_PAGES}");
+    }
+    Ok(name)
+}
+
+/// Tunables that cap every linear memory at `max_pages`: a module declaring more as its
+/// minimum fails to instantiate, and growth past the cap fails (`memory.grow` returns -1
+/// inside wasm, and a host-side grow becomes a `Fatal` error).
+struct MemoryCap {
+    max_pages: Pages,
+    base: BaseTunables,
+}
+
+impl MemoryCap {
+    fn capped(&self, ty: &MemoryType) -> Result<MemoryType, MemoryError> {
+        if ty.minimum > self.max_pages {
+            return Err(MemoryError::MinimumMemoryTooLarge {
+                min_requested: ty.minimum,
+                max_allowed: self.max_pages,
+            });
+        }
+        let mut ty = *ty;
+        ty.maximum = Some(ty.maximum.map_or(self.max_pages, |m| m.min(self.max_pages)));
+        Ok(ty)
+    }
+}
+
+impl Tunables for MemoryCap {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        let capped = self.capped(memory).unwrap_or(*memory);
+        self.base.memory_style(&capped)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        self.base.create_host_memory(&self.capped(ty)?, style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        let ty = self.capped(ty)?;
+        // SAFETY: forwarded unchanged from the caller, who upholds the same contract.
+        unsafe {
+            self.base
+                .create_vm_memory(&ty, style, vm_definition_location)
+        }
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        // SAFETY: forwarded unchanged from the caller, who upholds the same contract.
+        unsafe { self.base.create_vm_table(ty, style, vm_definition_location) }
+    }
+}
+
+/// Longest message `prism.log` forwards; the rest is cut off.
+const MAX_LOG_MESSAGE_LEN: u32 = 4096;
+
+/// State behind the `prism` host imports of one instance.
+struct HostEnv {
+    name: String,
+    /// The instance's exported memory, set right after instantiation.
+    memory: Option<Memory>,
+}
+
+/// `prism.log(level, ptr, len)`: logs the UTF-8 message at `ptr..ptr+len` under the
+/// `prism::middleware::wasm` target. Levels: 0 trace, 1 debug, 2 info, 3 warn, 4 error.
+fn host_log(mut env: FunctionEnvMut<HostEnv>, level: i32, ptr: i32, len: i32) {
+    let (host, store) = env.data_and_store_mut();
+    let Some(memory) = host.memory.as_ref() else {
+        return;
+    };
+    let mut buf = vec![0u8; (len as u32).min(MAX_LOG_MESSAGE_LEN) as usize];
+    if memory
+        .view(&store)
+        .read(ptr as u32 as u64, &mut buf)
+        .is_err()
+    {
+        return;
+    }
+    let msg = String::from_utf8_lossy(&buf);
+    let mw = host.name.as_str();
+    match level {
+        ..=0 => tracing::trace!(target: "prism::middleware::wasm", middleware = mw, "{msg}"),
+        1 => tracing::debug!(target: "prism::middleware::wasm", middleware = mw, "{msg}"),
+        2 => tracing::info!(target: "prism::middleware::wasm", middleware = mw, "{msg}"),
+        3 => tracing::warn!(target: "prism::middleware::wasm", middleware = mw, "{msg}"),
+        _ => tracing::error!(target: "prism::middleware::wasm", middleware = mw, "{msg}"),
+    }
+}
+
+/// `prism.now_unix_ms() -> i64`: wall-clock time in milliseconds since the Unix epoch.
+fn host_now_unix_ms() -> i64 {
+    crate::prism::telemetry::now_unix_ms() as i64
+}
+
+/// Whether the module defines mutable globals (state that survives between calls and
+/// can't be reset from the host). Unparseable modules count as having them.
+fn has_mutable_globals(wasm: &[u8]) -> bool {
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload {
+            Ok(wasmparser::Payload::GlobalSection(globals)) => {
+                for g in globals {
+                    match g {
+                        Ok(g) if !g.ty.mutable => {}
+                        _ => return true,
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => return true,
+        }
+    }
+    false
+}
+
 impl Middleware for WasmMiddleware {
     fn name(&self) -> &str {
         &self.name
@@ -547,12 +1407,16 @@ impl Middleware for WasmMiddleware {
     ) -> Result<MiddlewareOutput, MiddlewareError> {
         self.apply_impl(prelude, ctx)
     }
+
+    fn handles_response(&self) -> bool {
+        self.response_phase
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    use std::{fs, time::Duration};
 
     fn temp_test_dir(name: &str) -> PathBuf {
         let mut p = std::env::temp_dir();
@@ -614,7 +1478,8 @@ mod tests {
         let wat_path = dir.join("t.wat");
         fs::write(&wat_path, TEST_WAT).expect("write");
 
-        let m = WasmMiddleware::from_wat_path("t", &wat_path).expect("load");
+        let m = WasmMiddleware::from_wat_path("t", &wat_path, WasmMiddlewareOptions::default())
+            .expect("load");
         let out = m.apply(b"zzz", &MiddlewareCtx::parse()).expect("apply");
         assert_eq!(out.host.as_deref(), Some("x"));
         assert_eq!(out.rewrite.as_deref(), Some(b"abc".as_slice()));
@@ -622,6 +1487,504 @@ mod tests {
         let _ = fs::remove_dir_all(&dir);
     }
 
+    // Echo middleware: returns the whole prelude (at offset 0) as the routing host.
+    const ECHO_HOST_WAT: &str = r#"(module
+  (memory (export "memory") 2)
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (i32.store (i32.const 65536) (i32.const 0))
+    (i32.store (i32.const 65540) (local.get $n))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (i32.const 0))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#;
+
+    #[test]
+    fn wasm_middleware_max_output_len_is_configurable() {
+        let dir = temp_test_dir("max_output_len");
+        let wat_path = dir.join("echo.wat");
+        fs::write(&wat_path, ECHO_HOST_WAT).expect("write");
+
+        let long_host = format!("{}.example.com", "a".repeat(300));
+
+        let default =
+            WasmMiddleware::from_wat_path("echo", &wat_path, WasmMiddlewareOptions::default())
+                .expect("load");
+        assert!(matches!(
+            default.apply(long_host.as_bytes(), &MiddlewareCtx::parse()),
+            Err(MiddlewareError::Fatal(_))
+        ));
+
+        let raised = WasmMiddleware::from_wat_path(
+            "echo",
+            &wat_path,
+            WasmMiddlewareOptions {
+                max_output_len: 1024,
+                ..Default::default()
+            },
+        )
+        .expect("load raised");
+        let out = raised
+            .apply(long_host.as_bytes(), &MiddlewareCtx::parse())
+            .expect("apply raised");
+        assert_eq!(out.host.as_deref(), Some(long_host.as_str()));
+
+        let lowered = WasmMiddleware::from_wat_path(
+            "echo",
+            &wat_path,
+            WasmMiddlewareOptions {
+                max_output_len: 8,
+                ..Default::default()
+            },
+        )
+        .expect("load lowered");
+        assert!(matches!(
+            lowered.apply(b"play.example.com", &MiddlewareCtx::parse()),
+            Err(MiddlewareError::Fatal(_))
+        ));
+
+        assert!(
+            WasmMiddleware::from_wat_path(
+                "echo",
+                &wat_path,
+                WasmMiddlewareOptions {
+                    max_output_len: 0,
+                    ..Default::default()
+                },
+            )
+            .is_err()
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // Routes by client IP: returns it as the host when the ctx is v2 and the connection came
+    // in on port 25565, and no-match otherwise.
+    const CLIENT_IP_WAT: &str = r#"(module
+  (memory (export "memory") 2)
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (if (i32.lt_u (i32.load (local.get $ctx)) (i32.const 2))
+      (then (return (i64.const 1))))
+    (if (i32.ne (i32.load (i32.add (local.get $ctx) (i32.const 24))) (i32.const 25565))
+      (then (return (i64.const 1))))
+    (i32.store (i32.const 65536) (i32.load (i32.add (local.get $ctx) (i32.const 16))))
+    (i32.store (i32.const 65540) (i32.load (i32.add (local.get $ctx) (i32.const 20))))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (i32.const 0))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#;
+
+    #[test]
+    fn middleware_ctx_v2_carries_client_metadata() {
+        let dir = temp_test_dir("client_meta");
+        let wat_path = dir.join("client_ip.wat");
+        fs::write(&wat_path, CLIENT_IP_WAT).expect("write");
+        let m = WasmMiddleware::from_wat_path("client_ip", &wat_path, Default::default())
+            .expect("load");
+
+        let client = ClientMeta {
+            ip: Some("203.0.113.7".parse().unwrap()),
+            listen_port: Some(25565),
+        };
+        let out = m
+            .apply(b"\x10\x00", &MiddlewareCtx::parse().with_client(client))
+            .expect("apply");
+        assert_eq!(out.host.as_deref(), Some("203.0.113.7"));
+
+        let other_port = ClientMeta {
+            listen_port: Some(25566),
+            ..client
+        };
+        assert!(matches!(
+            m.apply(b"\x10\x00", &MiddlewareCtx::parse().with_client(other_port)),
+            Err(MiddlewareError::NoMatch)
+        ));
+        // Without client details the ctx stays v1.
+        assert!(matches!(
+            m.apply(b"\x10\x00", &MiddlewareCtx::parse()),
+            Err(MiddlewareError::NoMatch)
+        ));
+
+        let chain = ChainMiddleware::new(vec![Arc::new(m)]);
+        assert_eq!(
+            chain.parse(b"", client).expect("chain parse").0,
+            "203.0.113.7"
+        );
+
+        // v1 modules read the same offsets as before.
+        let mc = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../middlewares/minecraft_handshake.wat");
+        let mc = WasmMiddleware::from_wat_path("mc", &mc, Default::default()).expect("load mc");
+        let prelude = mc_handshake_prelude("play.example.com", 25565);
+        let out = mc
+            .apply(&prelude, &MiddlewareCtx::parse().with_client(client))
+            .expect("apply mc");
+        assert_eq!(out.host.as_deref(), Some("play.example.com"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn host_imports_log_and_tell_time() {
+        let dir = temp_test_dir("host_imports");
+        let wat_path = dir.join("chatty.wat");
+        fs::write(
+            &wat_path,
+            r#"(module
+  (import "prism" "log" (func $log (param i32 i32 i32)))
+  (import "prism" "now_unix_ms" (func $now (result i64)))
+  (memory (export "memory") 2)
+  (data (i32.const 1024) "parsing prelude")
+  (data (i32.const 2048) "play.example.com")
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (call $log (i32.const 2) (i32.const 1024) (i32.const 15))
+    ;; Out-of-range and oversized messages are ignored or truncated, never a trap.
+    (call $log (i32.const 9) (i32.const 0x7fffff00) (i32.const 64))
+    (call $log (i32.const -1) (i32.const 1024) (i32.const -1))
+    (if (i64.lt_s (call $now) (i64.const 1600000000000))
+      (then (return (i64.const -1))))
+    (i32.store (i32.const 65536) (i32.const 2048))
+    (i32.store (i32.const 65540) (i32.const 16))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (i32.const 0))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#,
+        )
+        .expect("write");
+
+        let m =
+            WasmMiddleware::from_wat_path("chatty", &wat_path, Default::default()).expect("load");
+        for _ in 0..2 {
+            let out = m
+                .apply(b"\x10\x00", &MiddlewareCtx::parse())
+                .expect("apply");
+            assert_eq!(out.host.as_deref(), Some("play.example.com"));
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn wasm_binaries_load_only_when_allowed() {
+        let dir = temp_test_dir("wasm_binary");
+        let wasm = wasmer::wat2wasm(ECHO_HOST_WAT.as_bytes()).expect("wat2wasm");
+        fs::write(dir.join("echo.wasm"), &wasm).expect("write");
+
+        let err = FsWasmMiddlewareProvider::new(dir.clone())
+            .get("echo")
+            .err()
+            .expect("binary must be rejected by default");
+        assert!(
+            err.to_string().contains("loading raw .wasm is disabled"),
+            "{err:#}"
+        );
+
+        let m = FsWasmMiddlewareProvider::new(dir.clone())
+            .with_wasm_binaries(true)
+            .get("echo")
+            .expect("load binary");
+        let out = m
+            .apply(b"play.example.com", &MiddlewareCtx::parse())
+            .expect("apply");
+        assert_eq!(out.host.as_deref(), Some("play.example.com"));
+
+        // A WAT file of the same name still wins (here a broken one).
+        fs::write(dir.join("echo.wat"), "(module").expect("write");
+        let err = FsWasmMiddlewareProvider::new(dir.clone())
+            .with_wasm_binaries(true)
+            .get("echo")
+            .err()
+            .expect("the .wat must be preferred");
+        assert!(err.to_string().contains("compile wat module"), "{err:#}");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn fixed_host_wat(host: &str) -> String {
+        format!(
+            r#"(module
+  (memory (export "memory") 2)
+  (data (i32.const 1024) "{host}")
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (i32.store (i32.const 65536) (i32.const 1024))
+    (i32.store (i32.const 65540) (i32.const {len}))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (i32.const 0))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#,
+            len = host.len()
+        )
+    }
+
+    #[test]
+    fn provider_recompiles_edited_middlewares() {
+        let dir = temp_test_dir("hot_reload");
+        let wat_path = dir.join("fixed.wat");
+        let host = |p: &FsWasmMiddlewareProvider| {
+            p.get("fixed")
+                .expect("get")
+                .apply(b"\x10\x00", &MiddlewareCtx::parse())
+                .expect("apply")
+                .host
+        };
+
+        fs::write(&wat_path, fixed_host_wat("a.example.com")).expect("write");
+        let provider = FsWasmMiddlewareProvider::new(dir.clone());
+        let first = provider.get("fixed").expect("get");
+        assert_eq!(host(&provider), Some("a.example.com".to_string()));
+        // Unchanged files are served from the cache, across provider instances too.
+        assert!(Arc::ptr_eq(
+            &first,
+            &FsWasmMiddlewareProvider::new(dir.clone())
+                .get("fixed")
+                .expect("get")
+        ));
+
+        fs::write(&wat_path, fixed_host_wat("b.example.com")).expect("write");
+        assert_eq!(host(&provider), Some("b.example.com".to_string()));
+
+        // A broken edit keeps the last good module.
+        fs::write(&wat_path, "(module").expect("write");
+        assert_eq!(host(&provider), Some("b.example.com".to_string()));
+
+        fs::write(&wat_path, fixed_host_wat("c.example.com")).expect("write");
+        assert_eq!(host(&provider), Some("c.example.com".to_string()));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_declared_middlewares_run_in_the_response_phase() {
+        // Replaces any reply with "!" in every phase.
+        let wat = |marker: &str| {
+            format!(
+                r#"(module
+  (memory (export "memory") 2)
+  {marker}
+  (data (i32.const 1024) "!")
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (i32.store (i32.const 65536) (i32.const 0))
+    (i32.store (i32.const 65540) (i32.const 0))
+    (i32.store (i32.const 65544) (i32.const 1024))
+    (i32.store (i32.const 65548) (i32.const 1))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#
+            )
+        };
+        let load = |name: &str, marker: &str| -> SharedMiddleware {
+            Arc::new(
+                WasmMiddleware::from_wat(name, &wat(marker), Default::default()).expect("load"),
+            )
+        };
+        let undeclared = load("plain", "");
+        let declared = load(
+            "resp",
+            r#"(global (export "prism_mw_response") i32 (i32.const 1))"#,
+        );
+        assert!(!undeclared.handles_response());
+        assert!(declared.handles_response());
+
+        let chain = ChainMiddleware::new(vec![undeclared.clone()]);
+        assert!(!chain.handles_response());
+        assert_eq!(chain.response(b"reply", ClientMeta::default()), None);
+
+        let chain = ChainMiddleware::new(vec![undeclared, declared]);
+        assert!(chain.handles_response());
+        assert_eq!(
+            chain.response(b"reply", ClientMeta::default()).as_deref(),
+            Some(&b"!"[..])
+        );
+    }
+
+    fn cached_under(dir: &Path) -> usize {
+        module_cache()
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|k| k.0.starts_with(dir))
+            .count()
+    }
+
+    #[test]
+    fn deleted_middlewares_leave_the_module_cache() {
+        let dir = temp_test_dir("cache_evict");
+        fs::write(dir.join("a.wat"), fixed_host_wat("a.example.com")).expect("write");
+        fs::write(dir.join("b.wat"), fixed_host_wat("b.example.com")).expect("write");
+        let provider = FsWasmMiddlewareProvider::new(dir.clone());
+        provider.get("a").expect("get a");
+        provider.get("b").expect("get b");
+        assert_eq!(cached_under(&dir), 2);
+
+        // A deleted file fails instead of serving its last good module.
+        fs::remove_file(dir.join("a.wat")).expect("remove");
+        assert!(FsWasmMiddlewareProvider::new(dir.clone()).get("a").is_err());
+        assert_eq!(cached_under(&dir), 1);
+
+        // A reload whose routes no longer use `b` drops it too.
+        fs::write(dir.join("a.wat"), fixed_host_wat("a.example.com")).expect("write");
+        let reloaded = FsWasmMiddlewareProvider::new(dir.clone());
+        reloaded.get("a").expect("get a");
+        reloaded.forget_unused_modules();
+        assert_eq!(cached_under(&dir), 1);
+
+        forget_cached_modules(&dir, &HashSet::new());
+        assert_eq!(cached_under(&dir), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn wasm_memory_is_capped() {
+        let dir = temp_test_dir("memory_cap");
+        let load = |file: &str, wat: &str, max_memory_pages| {
+            let path = dir.join(file);
+            fs::write(&path, wat).expect("write");
+            WasmMiddleware::from_wat_path(
+                "cap",
+                &path,
+                WasmMiddlewareOptions {
+                    max_memory_pages,
+                    ..Default::default()
+                },
+            )
+        };
+        let fatal = |res: Result<MiddlewareOutput, MiddlewareError>, needle: &str| match res {
+            Err(MiddlewareError::Fatal(msg)) => assert!(msg.contains(needle), "{msg}"),
+            other => panic!("expected a fatal error mentioning {needle:?}, got {other:?}"),
+        };
+
+        // A declared rewrite far beyond the cap is refused before anything is allocated.
+        let huge_rewrite = load(
+            "huge_rewrite.wat",
+            r#"(module
+  (memory (export "memory") 4)
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (i32.store (i32.const 65536) (i32.const 0))
+    (i32.store (i32.const 65540) (i32.const 0))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (i32.const 0x7fffffff))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#,
+            8,
+        )
+        .expect("load");
+        fatal(
+            huge_rewrite.apply(b"\x10\x00", &MiddlewareCtx::rewrite("backend:25565")),
+            "rewrite too long",
+        );
+
+        // In-module growth stops at the cap: memory.grow returns -1 (reported as fatal).
+        let grower = load(
+            "grower.wat",
+            r#"(module
+  (memory (export "memory") 1)
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (if (i32.ne (memory.grow (i32.const 1000)) (i32.const -1))
+      (then (return (i64.const 1))))
+    (i64.const -1)
+  )
+)"#,
+            8,
+        )
+        .expect("load");
+        fatal(
+            grower.apply(b"\x10\x00", &MiddlewareCtx::parse()),
+            "fatal error",
+        );
+
+        // A prelude that can't fit under the cap is refused instead of growing memory.
+        let echo = load("echo.wat", ECHO_HOST_WAT, 4).expect("load");
+        fatal(
+            echo.apply(&vec![b'a'; 5 * 65536], &MiddlewareCtx::parse()),
+            "above max_memory_pages",
+        );
+
+        // Modules that start above the cap don't instantiate.
+        let big = load(
+            "big.wat",
+            r#"(module
+  (memory (export "memory") 16)
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64) (i64.const 1))
+)"#,
+            8,
+        )
+        .expect("load");
+        fatal(
+            big.apply(b"\x10\x00", &MiddlewareCtx::parse()),
+            "instantiate",
+        );
+
+        assert!(load("echo.wat", ECHO_HOST_WAT, 2).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn runaway_middleware_is_stopped_by_the_fuel_limit() {
+        let dir = temp_test_dir("fuel_limit");
+        let wat_path = dir.join("spin.wat");
+        fs::write(
+            &wat_path,
+            r#"(module
+  (memory (export "memory") 1)
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (loop $spin (br $spin))
+    (i64.const 1)
+  )
+)"#,
+        )
+        .expect("write");
+
+        let m = WasmMiddleware::from_wat_path(
+            "spin",
+            &wat_path,
+            WasmMiddlewareOptions {
+                fuel_limit: 1_000_000,
+                ..Default::default()
+            },
+        )
+        .expect("load");
+        let started = std::time::Instant::now();
+        for _ in 0..2 {
+            match m.apply(b"anything", &MiddlewareCtx::parse()) {
+                Err(MiddlewareError::Fatal(msg)) => {
+                    assert!(msg.contains("ran out of fuel"), "{msg}")
+                }
+                other => panic!("expected fuel exhaustion, got {other:?}"),
+            }
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Metering doesn't change what a well-behaved middleware returns.
+        let mc = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../middlewares/minecraft_handshake.wat");
+        let metered = WasmMiddleware::from_wat_path("mc", &mc, WasmMiddlewareOptions::default())
+            .expect("load metered");
+        let unmetered = WasmMiddleware::from_wat_path(
+            "mc",
+            &mc,
+            WasmMiddlewareOptions {
+                fuel_limit: 0,
+                ..Default::default()
+            },
+        )
+        .expect("load unmetered");
+        let prelude = mc_handshake_prelude("play.example.com", 25565);
+        let host = |m: &WasmMiddleware| {
+            m.apply(&prelude, &MiddlewareCtx::parse())
+                .ok()
+                .and_then(|o| o.host)
+        };
+        assert_eq!(host(&metered), Some("play.example.com".to_string()));
+        assert_eq!(host(&metered), host(&unmetered));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn repo_sample_middlewares_compile() {
         let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -636,11 +1999,133 @@ mod tests {
                 "expected repo sample middleware at {}, but it does not exist",
                 wat_path.display()
             );
-            WasmMiddleware::from_wat_path(name, &wat_path)
+            WasmMiddleware::from_wat_path(name, &wat_path, WasmMiddlewareOptions::default())
                 .unwrap_or_else(|e| panic!("failed to compile {name}.wat: {e:#}"));
         }
     }
 
+    // Returns a one-letter host that advances on every call in the same instance, from a
+    // counter kept in linear memory (at 70000) or in a mutable global.
+    const COUNTER_IN_MEMORY_WAT: &str = r#"(module
+  (memory (export "memory") 2)
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (i32.store8 (i32.const 70000) (i32.add (i32.load8_u (i32.const 70000)) (i32.const 1)))
+    (i32.store8 (i32.const 100) (i32.add (i32.const 0x60) (i32.load8_u (i32.const 70000))))
+    (i32.store (i32.const 65536) (i32.const 100))
+    (i32.store (i32.const 65540) (i32.const 1))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (i32.const 0))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#;
+
+    const COUNTER_IN_GLOBAL_WAT: &str = r#"(module
+  (memory (export "memory") 2)
+  (global $calls (mut i32) (i32.const 0))
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+    (i32.store8 (i32.const 100) (i32.add (i32.const 0x60) (global.get $calls)))
+    (i32.store (i32.const 65536) (i32.const 100))
+    (i32.store (i32.const 65540) (i32.const 1))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (i32.const 0))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#;
+
+    #[test]
+    fn pooled_instances_behave_like_fresh_ones() {
+        let dir = temp_test_dir("instance_pool");
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../..");
+        let memory_path = dir.join("memory.wat");
+        let global_path = dir.join("global.wat");
+        fs::write(&memory_path, COUNTER_IN_MEMORY_WAT).expect("write");
+        fs::write(&global_path, COUNTER_IN_GLOBAL_WAT).expect("write");
+
+        let load = |path: &Path, instance_pool| {
+            let opts = WasmMiddlewareOptions {
+                instance_pool,
+                ..Default::default()
+            };
+            WasmMiddleware::from_wat_path("t", path, opts).expect("load")
+        };
+        let hosts = |m: &WasmMiddleware, preludes: &[Vec<u8>]| -> Vec<Option<String>> {
+            preludes
+                .iter()
+                .map(|p| {
+                    m.apply(p, &MiddlewareCtx::parse())
+                        .ok()
+                        .and_then(|o| o.host)
+                })
+                .collect()
+        };
+
+        let preludes = vec![
+            mc_handshake_prelude(&format!("{}.example.com", "long".repeat(40)), 25565),
+            mc_handshake_prelude("a.example.com", 25565),
+            b"\x10\x00".to_vec(),
+            mc_handshake_prelude("play.example.com", 25566),
+        ];
+        let mc = root.join("middlewares/minecraft_handshake.wat");
+        let (pooled, fresh) = (load(&mc, 4), load(&mc, 0));
+        assert_eq!(pooled.pool_size, 4);
+        let expected = hosts(&fresh, &preludes);
+        assert_eq!(hosts(&pooled, &preludes), expected);
+        assert_eq!(hosts(&pooled, &preludes), expected);
+
+        // State left in linear memory is wiped before an instance is reused.
+        let counter = load(&memory_path, 4);
+        assert_eq!(hosts(&counter, &preludes), vec![Some("a".to_string()); 4]);
+        INSTANCE_POOLS.with_borrow(|pools| {
+            assert_eq!(
+                pools.get(&counter.pool_id).map(|(_, idle)| idle.len()),
+                Some(1)
+            );
+        });
+
+        // A prelude past the initial memory grows the instance, which is then dropped
+        // rather than pooled at its grown size.
+        let big = vec![b'x'; MIN_MEMORY_PAGES as usize * 65536 + 1];
+        let before = INSTANCE_POOLS
+            .with_borrow(|pools| pools.get(&pooled.pool_id).map_or(0, |(_, idle)| idle.len()));
+        let _ = pooled.apply(&big, &MiddlewareCtx::parse());
+        INSTANCE_POOLS.with_borrow(|pools| {
+            let idle = &pools.get(&pooled.pool_id).expect("pool").1;
+            assert_eq!(idle.len(), before - 1);
+            assert!(idle.iter().all(|(store, _, memory, _)| {
+                memory.view(store).data_size() == MIN_MEMORY_PAGES as u64 * 65536
+            }));
+        });
+
+        // Mutable globals can't be reset from the host, so such modules aren't pooled.
+        let global = load(&global_path, 4);
+        assert_eq!(global.pool_size, 0);
+        assert_eq!(hosts(&global, &preludes), vec![Some("a".to_string()); 4]);
+
+        // Dropped middlewares' idle instances are released on the next check-in.
+        let id = counter.pool_id;
+        drop(counter);
+        hosts(&pooled, &preludes[..1]);
+        INSTANCE_POOLS.with_borrow(|pools| assert!(!pools.contains_key(&id)));
+
+        // The gauges are process-wide and other tests share them, so compare against
+        // this thread's own share: its idle instances and one in-flight call.
+        let local =
+            || INSTANCE_POOLS.with_borrow(|pools| pools.values().map(|(_, idle)| idle.len()).sum());
+        let pooled_now = || WASM_INSTANCES_POOLED.load(Ordering::Relaxed);
+        assert!(local() > 0);
+        assert!(pooled_now() >= local());
+        {
+            let _call = InstanceInUse::enter();
+            assert!(WASM_INSTANCES_IN_USE.load(Ordering::Relaxed) >= 1);
+        }
+        let out = render_prometheus();
+        assert!(out.contains("# TYPE prism_wasm_instances_pooled gauge"));
+        assert!(out.contains("# TYPE prism_wasm_instances_in_use gauge"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn materialize_default_middlewares_is_idempotent_and_non_destructive() {
         let dir = temp_test_dir("materialize_defaults");
@@ -864,6 +2349,7 @@ mod tests {
         let mc = WasmMiddleware::from_wat_path(
             "minecraft_handshake",
             &dir.join("minecraft_handshake.wat"),
+            WasmMiddlewareOptions::default(),
         )
         .expect("compile minecraft_handshake");
 
@@ -895,6 +2381,7 @@ mod tests {
         let mc = WasmMiddleware::from_wat_path(
             "minecraft_handshake",
             &dir.join("minecraft_handshake.wat"),
+            WasmMiddlewareOptions::default(),
         )
         .expect("compile minecraft_handshake");
 
@@ -930,6 +2417,7 @@ mod tests {
         let mc = WasmMiddleware::from_wat_path(
             "minecraft_handshake",
             &dir.join("minecraft_handshake.wat"),
+            WasmMiddlewareOptions::default(),
         )
         .expect("compile minecraft_handshake");
 
@@ -966,8 +2454,12 @@ mod tests {
             .join("..");
         let dir = root.join("middlewares");
 
-        let tls = WasmMiddleware::from_wat_path("tls_sni", &dir.join("tls_sni.wat"))
-            .expect("compile tls_sni");
+        let tls = WasmMiddleware::from_wat_path(
+            "tls_sni",
+            &dir.join("tls_sni.wat"),
+            WasmMiddlewareOptions::default(),
+        )
+        .expect("compile tls_sni");
 
         let prelude = tls_client_hello_prelude("orig.example.com");
 
@@ -0,0 +1,498 @@
+//! Minimal Minecraft Java Edition protocol helpers.
+//!
+//! Host extraction and prelude rewrites live in the WAT middlewares; these helpers only
+//! cover the few packets Prism writes itself (e.g. disconnect messages).
+
+/// Handshake `next_state` for status requests.
+pub const NEXT_STATE_STATUS: i32 = 1;
+/// Handshake `next_state` for login.
+pub const NEXT_STATE_LOGIN: i32 = 2;
+/// First protocol (1.20.2) whose Login Start always carries the player UUID.
+const PROTOCOL_LOGIN_START_UUID: i32 = 764;
+/// Login plugin channel Velocity-mode backends query for forwarded player info.
+pub const VELOCITY_PLAYER_INFO_CHANNEL: &str = "velocity:player_info";
+/// Velocity forwarding version Prism speaks (`MODERN_DEFAULT`: no player key data).
+const VELOCITY_FORWARDING_VERSION: i32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub protocol_version: i32,
+    pub server_address: String,
+    pub server_port: u16,
+    pub next_state: i32,
+    /// Total bytes of the framed handshake packet (length prefix included).
+    pub packet_len: usize,
+}
+
+/// Read a VarInt at `pos`.
+///
+/// Returns (value, bytes_read), or None if the buffer ends early or the VarInt is too long.
+pub fn read_varint(buf: &[u8], pos: usize) -> Option<(i32, usize)> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let b = *buf.get(pos + i)?;
+        value |= ((b & 0x7f) as u32) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((value as i32, i + 1));
+        }
+    }
+    None
+}
+
+pub fn write_varint(value: i32, out: &mut Vec<u8>) {
+    let mut v = value as u32;
+    loop {
+        if v & !0x7f == 0 {
+            out.push(v as u8);
+            return;
+        }
+        out.push(((v & 0x7f) | 0x80) as u8);
+        v >>= 7;
+    }
+}
+
+pub fn write_string(s: &str, out: &mut Vec<u8>) {
+    write_varint(s.len() as i32, out);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Frame a packet: VarInt(len(id + payload)) + VarInt(id) + payload.
+pub fn packet(id: i32, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(payload.len() + 5);
+    write_varint(id, &mut body);
+    body.extend_from_slice(payload);
+
+    let mut out = Vec::with_capacity(body.len() + 5);
+    write_varint(body.len() as i32, &mut out);
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Split one framed packet off the start of `buf`.
+///
+/// Returns (frame_len, packet_id, payload), or None if the frame is incomplete or malformed.
+pub fn split_packet(buf: &[u8]) -> Option<(usize, i32, &[u8])> {
+    let (len, n) = read_varint(buf, 0)?;
+    if len <= 0 {
+        return None;
+    }
+    let end = n.checked_add(len as usize)?;
+    let body = buf.get(n..end)?;
+    let (id, k) = read_varint(body, 0)?;
+    Some((end, id, &body[k..]))
+}
+
+/// Largest handshake frame we consider plausible (Forge/BungeeCord hosts carry extra data).
+const MAX_HANDSHAKE_FRAME: i32 = 32 * 1024;
+
+/// Cheap check that `buf` could be the start of a handshake: a sane VarInt frame length
+/// followed by packet id 0x00. Incomplete prefixes are given the benefit of the doubt.
+///
+/// This rejects TLS (`0x16 0x03`), HTTP and most scanner noise on the first bytes.
+pub fn looks_like_handshake(buf: &[u8]) -> bool {
+    let (len, n) = match read_varint(buf, 0) {
+        Some(v) => v,
+        // Fewer than 5 bytes that are all continuation bytes may still become a VarInt.
+        None => return buf.len() < 5,
+    };
+    if !(1..=MAX_HANDSHAKE_FRAME).contains(&len) {
+        return false;
+    }
+    match buf.get(n) {
+        Some(&id) => id == 0x00,
+        None => true,
+    }
+}
+
+/// Classify a prelude starting with 0xFE as a pre-1.7 server list ping (`FE`, `FE 01`,
+/// or `FE 01 FA ...` from 1.6). Returns None while the bytes so far are ambiguous.
+///
+/// A modern handshake can also start with `FE 01` (a 254-byte frame), but its packet id
+/// byte is 0x00, never 0xFA.
+pub fn legacy_ping(buf: &[u8]) -> Option<bool> {
+    match buf {
+        [] | [0xFE] | [0xFE, 0x01] => None,
+        [0xFE, 0x01, 0xFA, ..] => Some(true),
+        _ => Some(false),
+    }
+}
+
+/// Parse a complete handshake packet (id 0x00) at the start of `buf`.
+pub fn parse_handshake(buf: &[u8]) -> Option<Handshake> {
+    let (len, n) = read_varint(buf, 0)?;
+    if len <= 0 {
+        return None;
+    }
+    let end = n.checked_add(len as usize)?;
+    let body = buf.get(n..end)?;
+
+    let (id, mut i) = read_varint(body, 0)?;
+    if id != 0 {
+        return None;
+    }
+    let (protocol_version, k) = read_varint(body, i)?;
+    i += k;
+    let (addr_len, k) = read_varint(body, i)?;
+    i += k;
+    if addr_len < 0 {
+        return None;
+    }
+    let addr = body.get(i..i + addr_len as usize)?;
+    i += addr_len as usize;
+    let port = body.get(i..i + 2)?;
+    i += 2;
+    let (next_state, _) = read_varint(body, i)?;
+
+    Some(Handshake {
+        protocol_version,
+        server_address: String::from_utf8_lossy(addr).into_owned(),
+        server_port: u16::from_be_bytes([port[0], port[1]]),
+        next_state,
+        packet_len: end,
+    })
+}
+
+/// Frame `hs` as a handshake packet with `server_address` in place of its own.
+pub fn handshake_packet(hs: &Handshake, server_address: &str) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(server_address.len() + 16);
+    write_varint(hs.protocol_version, &mut payload);
+    write_string(server_address, &mut payload);
+    payload.extend_from_slice(&hs.server_port.to_be_bytes());
+    write_varint(hs.next_state, &mut payload);
+    packet(0x00, &payload)
+}
+
+/// Parse the Login Start packet at the start of `buf`, sent right after a login
+/// handshake with `protocol_version`. Returns (player name, UUID if the client sent one).
+pub fn parse_login_start(buf: &[u8], protocol_version: i32) -> Option<(String, Option<[u8; 16]>)> {
+    let (_, id, payload) = split_packet(buf)?;
+    if id != 0x00 {
+        return None;
+    }
+    let (name_len, n) = read_varint(payload, 0)?;
+    if !(1..=16).contains(&name_len) {
+        return None;
+    }
+    let name = payload.get(n..n + name_len as usize)?;
+    let name = String::from_utf8(name.to_vec()).ok()?;
+    let uuid = if protocol_version >= PROTOCOL_LOGIN_START_UUID {
+        let rest = payload.get(n + name_len as usize..)?;
+        Some(rest.get(..16)?.try_into().ok()?)
+    } else {
+        None
+    };
+    Some((name, uuid))
+}
+
+/// The UUID an offline-mode server assigns `name` (v3 of `OfflinePlayer:<name>`).
+pub fn offline_uuid(name: &str) -> [u8; 16] {
+    use md5::{Digest, Md5};
+
+    let mut uuid: [u8; 16] = Md5::digest(format!("OfflinePlayer:{name}")).into();
+    uuid[6] = (uuid[6] & 0x0f) | 0x30;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
+
+/// BungeeCord legacy IP forwarding: the handshake host becomes
+/// `<host>\0<client ip>\0<undashed uuid>`, which backends in bungeecord mode unpack.
+///
+/// The client's address is cut at its first NUL and whatever followed (e.g. Forge's
+/// `\0FML\0` marker) is kept after the forwarded fields, so a client can't supply its
+/// own ip/uuid.
+pub fn bungee_forwarded_handshake(hs: &Handshake, client_ip: &str, uuid: &[u8; 16]) -> Vec<u8> {
+    let uuid: String = uuid.iter().map(|b| format!("{b:02x}")).collect();
+    let (host, extra) = match hs.server_address.find('\0') {
+        Some(i) => hs.server_address.split_at(i),
+        None => (hs.server_address.as_str(), ""),
+    };
+    handshake_packet(hs, &format!("{host}\0{client_ip}\0{uuid}{extra}"))
+}
+
+/// Parse a Login Plugin Request payload (login state, packet id 0x04) into
+/// (message id, channel).
+pub fn parse_login_plugin_request(payload: &[u8]) -> Option<(i32, String)> {
+    let (message_id, n) = read_varint(payload, 0)?;
+    let (len, k) = read_varint(payload, n)?;
+    if len < 0 {
+        return None;
+    }
+    let channel = payload.get(n + k..n + k + len as usize)?;
+    Some((message_id, String::from_utf8(channel.to_vec()).ok()?))
+}
+
+/// Login Plugin Response packet (id 0x02) answering `message_id` successfully with `data`.
+pub fn login_plugin_response(message_id: i32, data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(data.len() + 6);
+    write_varint(message_id, &mut payload);
+    payload.push(1);
+    payload.extend_from_slice(data);
+    packet(0x02, &payload)
+}
+
+/// Velocity modern forwarding data: HMAC-SHA256(secret, payload) followed by the payload
+/// (version, client address, UUID, username, no profile properties).
+pub fn velocity_forwarding_data(
+    secret: &[u8],
+    client_ip: &str,
+    uuid: &[u8; 16],
+    name: &str,
+) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+
+    let mut payload = Vec::with_capacity(64 + client_ip.len() + name.len());
+    write_varint(VELOCITY_FORWARDING_VERSION, &mut payload);
+    write_string(client_ip, &mut payload);
+    payload.extend_from_slice(uuid);
+    write_string(name, &mut payload);
+    write_varint(0, &mut payload);
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret).expect("hmac accepts any key");
+    mac.update(&payload);
+    let mut out = mac.finalize().into_bytes().to_vec();
+    out.extend_from_slice(&payload);
+    out
+}
+
+/// Status Response packet (id 0x00) carrying the server list JSON.
+pub fn status_response_packet(json: &str) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(json.len() + 5);
+    write_string(json, &mut payload);
+    packet(0x00, &payload)
+}
+
+/// Parse the Status Response packet at the start of `buf` into (frame_len, JSON).
+pub fn parse_status_response(buf: &[u8]) -> Option<(usize, String)> {
+    let (frame_len, id, payload) = split_packet(buf)?;
+    if id != 0x00 {
+        return None;
+    }
+    let (len, n) = read_varint(payload, 0)?;
+    if len < 0 {
+        return None;
+    }
+    let json = payload.get(n..n + len as usize)?;
+    Some((frame_len, String::from_utf8(json.to_vec()).ok()?))
+}
+
+/// Pong packet (id 0x01) echoing the client's ping payload.
+pub fn pong_packet(payload: i64) -> Vec<u8> {
+    packet(0x01, &payload.to_be_bytes())
+}
+
+/// Reply to a legacy server list ping (1.4–1.6 format): 0xFF, the UTF-16 length, then
+/// `§1\0<protocol>\0<version>\0<motd>\0<online>\0<max>` in UTF-16BE.
+///
+/// Protocol 127 matches no legacy client, so the entry shows as incompatible.
+pub fn legacy_status_packet(version: &str, motd: &str, online: u32, max: u32) -> Vec<u8> {
+    let text = format!("\u{a7}1\0127\0{version}\0{motd}\0{online}\0{max}");
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let mut out = Vec::with_capacity(3 + units.len() * 2);
+    out.push(0xFF);
+    out.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for u in units {
+        out.extend_from_slice(&u.to_be_bytes());
+    }
+    out
+}
+
+/// Login-state Disconnect packet (id 0x00) carrying a plain text chat component.
+pub fn login_disconnect_packet(message: &str) -> Vec<u8> {
+    let reason = serde_json::json!({ "text": message }).to_string();
+    let mut payload = Vec::with_capacity(reason.len() + 5);
+    write_string(&reason, &mut payload);
+    packet(0x00, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(host: &str, port: u16, next_state: i32) -> Vec<u8> {
+        let mut payload = Vec::new();
+        write_varint(763, &mut payload);
+        write_string(host, &mut payload);
+        payload.extend_from_slice(&port.to_be_bytes());
+        write_varint(next_state, &mut payload);
+        packet(0x00, &payload)
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        for v in [0, 1, 127, 128, 255, 25565, 2097151, i32::MAX, -1] {
+            let mut buf = Vec::new();
+            write_varint(v, &mut buf);
+            assert_eq!(read_varint(&buf, 0), Some((v, buf.len())));
+        }
+        assert_eq!(read_varint(&[0x80], 0), None);
+    }
+
+    #[test]
+    fn parse_handshake_reads_fields_and_length() {
+        let mut buf = handshake("play.example.com", 25565, NEXT_STATE_LOGIN);
+        let framed = buf.len();
+        buf.extend_from_slice(&[0x01, 0x00]);
+
+        let hs = parse_handshake(&buf).expect("handshake");
+        assert_eq!(hs.protocol_version, 763);
+        assert_eq!(hs.server_address, "play.example.com");
+        assert_eq!(hs.server_port, 25565);
+        assert_eq!(hs.next_state, NEXT_STATE_LOGIN);
+        assert_eq!(hs.packet_len, framed);
+
+        assert_eq!(parse_handshake(&buf[..framed - 1]), None);
+    }
+
+    #[test]
+    fn bungee_forwarding_round_trips_through_parse_handshake() {
+        let hs = parse_handshake(&handshake("mc.example.com", 25565, NEXT_STATE_LOGIN)).unwrap();
+        let uuid = offline_uuid("Notch");
+        // Well-known offline UUID for "Notch".
+        assert_eq!(
+            uuid.iter().map(|b| format!("{b:02x}")).collect::<String>(),
+            "b50ad385829d3141a2167e7d7539ba7f"
+        );
+
+        let out = bungee_forwarded_handshake(&hs, "203.0.113.7", &uuid);
+        let got = parse_handshake(&out).expect("forwarded handshake");
+        assert_eq!(
+            got.server_address,
+            "mc.example.com\x00203.0.113.7\x00b50ad385829d3141a2167e7d7539ba7f"
+        );
+        assert_eq!(got.protocol_version, hs.protocol_version);
+        assert_eq!(got.server_port, 25565);
+        assert_eq!(got.next_state, NEXT_STATE_LOGIN);
+        assert_eq!(got.packet_len, out.len());
+    }
+
+    #[test]
+    fn velocity_forwarding_data_matches_known_vector() {
+        let uuid = [
+            0x06, 0x9a, 0x79, 0xf4, 0x44, 0xe9, 0x47, 0x26, 0xa5, 0xbe, 0xfc, 0xa9, 0x0e, 0x38,
+            0xaa, 0xf5,
+        ];
+        let data = velocity_forwarding_data(b"secret", "203.0.113.7", &uuid, "Notch");
+
+        let mut payload = vec![0x01, 0x0b];
+        payload.extend_from_slice(b"203.0.113.7");
+        payload.extend_from_slice(&uuid);
+        payload.push(0x05);
+        payload.extend_from_slice(b"Notch");
+        payload.push(0x00);
+        assert_eq!(&data[32..], payload.as_slice());
+
+        // HMAC-SHA256 with key "secret" over the payload above.
+        let sig: String = data[..32].iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(
+            sig,
+            "af783956cc6bc1396acc249e0059c7911abfa438e175d72e9bb946e3b1f9c76f"
+        );
+    }
+
+    #[test]
+    fn login_plugin_messages_round_trip() {
+        let mut payload = Vec::new();
+        write_varint(3, &mut payload);
+        write_string(VELOCITY_PLAYER_INFO_CHANNEL, &mut payload);
+        payload.push(4);
+        assert_eq!(
+            parse_login_plugin_request(&payload),
+            Some((3, VELOCITY_PLAYER_INFO_CHANNEL.to_string()))
+        );
+
+        let resp = login_plugin_response(3, b"abc");
+        let (len, id, body) = split_packet(&resp).expect("packet");
+        assert_eq!((len, id), (resp.len(), 0x02));
+        assert_eq!(body, &[0x03, 0x01, b'a', b'b', b'c']);
+    }
+
+    #[test]
+    fn bungee_forwarding_replaces_client_supplied_fields() {
+        let uuid = offline_uuid("Notch");
+        let hex: String = uuid.iter().map(|b| format!("{b:02x}")).collect();
+
+        // A spoofed ip/uuid after the host is pushed behind the real ones.
+        let spoofed = "mc.example.com\x001.2.3.4\x00ffffffffffffffffffffffffffffffff";
+        let hs = parse_handshake(&handshake(spoofed, 25565, NEXT_STATE_LOGIN)).unwrap();
+        let got = parse_handshake(&bungee_forwarded_handshake(&hs, "203.0.113.7", &uuid)).unwrap();
+        let fields: Vec<&str> = got.server_address.split('\0').collect();
+        assert_eq!(fields[..3], ["mc.example.com", "203.0.113.7", hex.as_str()]);
+
+        // Forge's marker survives, after the forwarded fields.
+        let hs = parse_handshake(&handshake(
+            "mc.example.com\x00FML\x00",
+            25565,
+            NEXT_STATE_LOGIN,
+        ))
+        .unwrap();
+        let got = parse_handshake(&bungee_forwarded_handshake(&hs, "203.0.113.7", &uuid)).unwrap();
+        assert_eq!(
+            got.server_address,
+            format!("mc.example.com\x00203.0.113.7\x00{hex}\x00FML\x00")
+        );
+    }
+
+    #[test]
+    fn login_start_uuid_depends_on_protocol() {
+        let mut payload = Vec::new();
+        write_string("Steve", &mut payload);
+        payload.extend_from_slice(&[7u8; 16]);
+        let login = packet(0x00, &payload);
+        assert_eq!(
+            parse_login_start(&login, 765),
+            Some(("Steve".to_string(), Some([7u8; 16])))
+        );
+        assert_eq!(
+            parse_login_start(&login, 340),
+            Some(("Steve".to_string(), None))
+        );
+        assert_eq!(parse_login_start(&login[..4], 765), None);
+    }
+
+    #[test]
+    fn legacy_ping_is_told_apart_from_modern_handshakes() {
+        assert_eq!(legacy_ping(&[0xFE]), None);
+        assert_eq!(legacy_ping(&[0xFE, 0x01]), None);
+        assert_eq!(legacy_ping(&[0xFE, 0x01, 0xFA, 0x00, 0x0B]), Some(true));
+
+        // A 254-byte modern handshake frame also starts with FE 01.
+        let long_host = "a".repeat(246);
+        let hs = handshake(&long_host, 25565, NEXT_STATE_LOGIN);
+        assert_eq!(&hs[..3], &[0xFE, 0x01, 0x00]);
+        assert_eq!(legacy_ping(&hs), Some(false));
+        assert_eq!(
+            legacy_ping(&handshake("mc.example.com", 25565, 1)),
+            Some(false)
+        );
+
+        let reply = legacy_status_packet("Prism", "hi", 0, 20);
+        assert_eq!(&reply[..3], &[0xFF, 0x00, 0x14]);
+        assert_eq!(&reply[3..7], &[0x00, 0xA7, 0x00, b'1']);
+    }
+
+    #[test]
+    fn looks_like_handshake_rejects_non_minecraft_prefixes() {
+        let hs = handshake("play.example.com", 25565, NEXT_STATE_LOGIN);
+        for end in 0..=hs.len() {
+            assert!(looks_like_handshake(&hs[..end]), "prefix len {end}");
+        }
+
+        assert!(!looks_like_handshake(&[0x16, 0x03, 0x01, 0x02, 0x00]));
+        assert!(!looks_like_handshake(b"GET / HTTP/1.1\r\n"));
+        assert!(!looks_like_handshake(&[0x00]));
+        assert!(!looks_like_handshake(&[0xff, 0xff, 0xff, 0xff, 0x0f]));
+    }
+
+    #[test]
+    fn login_disconnect_packet_frames_json_reason() {
+        let pkt = login_disconnect_packet("bye \"now\"");
+        let (len, n) = read_varint(&pkt, 0).expect("len");
+        assert_eq!(len as usize + n, pkt.len());
+        assert_eq!(pkt[n], 0x00);
+        let (slen, k) = read_varint(&pkt, n + 1).expect("str len");
+        let json = &pkt[n + 1 + k..];
+        assert_eq!(json.len(), slen as usize);
+        let v: serde_json::Value = serde_json::from_slice(json).expect("json");
+        assert_eq!(v["text"], "bye \"now\"");
+    }
+}
@@ -1,15 +1,24 @@
-pub mod admin;
-pub mod app;
+pub(crate) mod admin;
+pub(crate) mod app;
+pub(crate) mod bufpool;
 pub mod config;
-pub mod logging;
-pub mod managed;
-pub mod middleware;
-pub mod net;
-pub mod proxy;
+pub mod embed;
+pub(crate) mod geoip;
+pub(crate) mod health;
+pub(crate) mod listeners;
+pub(crate) mod logging;
+pub(crate) mod managed;
+pub(crate) mod middleware;
+pub(crate) mod minecraft;
+pub(crate) mod net;
+pub(crate) mod proxy;
 pub mod router;
-pub mod runtime_paths;
-pub mod telemetry;
-pub mod tunnel;
+pub(crate) mod runtime_paths;
+pub(crate) mod sniff;
+pub(crate) mod srv;
+pub(crate) mod telemetry;
+pub(crate) mod tls;
+pub(crate) mod tunnel;
 
 pub async fn run(
     config_path: Option<std::path::PathBuf>,
@@ -18,3 +27,14 @@ pub async fn run(
 ) -> anyhow::Result<()> {
     app::run(config_path, workdir, middleware_dir).await
 }
+
+pub fn check_config(
+    config_path: Option<std::path::PathBuf>,
+    middleware_dir: Option<std::path::PathBuf>,
+) -> anyhow::Result<()> {
+    app::check_config(config_path, middleware_dir)
+}
+
+pub fn config_schema() -> serde_json::Value {
+    config::json_schema()
+}
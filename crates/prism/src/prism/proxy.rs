@@ -1,4 +1,4 @@
-use std::{sync::Arc, time::Duration};
+use std::{borrow::Cow, collections::BTreeMap, net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use tokio::{
@@ -8,13 +8,17 @@ use tokio::{
 };
 
 use dashmap::DashMap;
+use tracing::Instrument;
 
-use crate::prism::{middleware, net, router, telemetry, tunnel};
+use crate::prism::{
+    bufpool, config, middleware, minecraft, net, router, sniff, srv, telemetry, tunnel,
+};
 
 #[derive(Clone)]
 pub enum TcpHandler {
     Routing(Arc<TcpRoutingHandlerOptions>),
     Forward(Arc<TcpForwardHandlerOptions>),
+    StatusHealth(Arc<StatusHealthHandlerOptions>),
 }
 
 impl TcpHandler {
@@ -26,10 +30,42 @@ impl TcpHandler {
         Self::Forward(Arc::new(opts))
     }
 
+    pub fn status_health(opts: StatusHealthHandlerOptions) -> Self {
+        Self::StatusHealth(Arc::new(opts))
+    }
+
+    /// Apply the per-IP connection rate limit to a freshly accepted peer; false means
+    /// the connection should be closed right away.
+    async fn admit(&self, peer: SocketAddr) -> bool {
+        let (sessions, runtime) = match self {
+            TcpHandler::Routing(opts) => (&opts.sessions, &opts.runtime),
+            TcpHandler::Forward(opts) => (&opts.sessions, &opts.runtime),
+            TcpHandler::StatusHealth(_) => return true,
+        };
+        let (limit, trusted) = {
+            let rt = runtime.read().await;
+            (
+                rt.rate_limit,
+                rt.trusted_cidrs.iter().any(|n| n.contains(peer.ip())),
+            )
+        };
+        if limit.connections_per_second == 0 || trusted {
+            return true;
+        }
+        if sessions.try_admit_connection(peer.ip(), limit.connections_per_second, limit.burst) {
+            return true;
+        }
+        tracing::debug!(client = %peer, "tcp: connection rate limit exceeded");
+        sessions.record_rejection(&peer.to_string(), telemetry::RejectReason::RateLimited, "");
+        sessions.session_metrics().record_ratelimited();
+        false
+    }
+
     async fn handle(&self, conn: TcpStream) {
         match self {
             TcpHandler::Routing(opts) => handle_routing(conn, opts.clone()).await,
             TcpHandler::Forward(opts) => handle_forward(conn, opts.clone()).await,
+            TcpHandler::StatusHealth(opts) => handle_status_health(conn, opts.clone()).await,
         }
     }
 }
@@ -41,6 +77,32 @@ pub struct TcpRoutingHandlerOptions {
     pub tunnel_manager: Option<Arc<tunnel::manager::Manager>>,
 
     pub runtime: Arc<tokio::sync::RwLock<TcpRuntimeConfig>>,
+
+    /// Log routing decisions and close instead of dialing upstream (`mode = "observe"`).
+    pub observe: bool,
+
+    /// Where the routing host comes from.
+    pub protocol: RoutingProtocol,
+
+    /// Reused prelude capture / read buffers.
+    pub buffer_pool: Arc<bufpool::BufferPool>,
+
+    /// Monitor probes answered before routing.
+    pub health_probes: Vec<config::HealthProbe>,
+}
+
+/// How a routing listener finds the host in a connection's first bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoutingProtocol {
+    /// Run the matched routes' middleware chains (`protocol = "tcp"`).
+    #[default]
+    Middleware,
+    /// Route on the TLS ClientHello's SNI and pass the stream through untouched
+    /// (`protocol = "tls"`).
+    Tls,
+    /// Sniff each connection: TLS ClientHellos take the `Tls` path, everything else
+    /// (Minecraft handshakes, legacy pings) the `Middleware` one (`protocol = "auto"`).
+    Auto,
 }
 
 pub struct TcpForwardHandlerOptions {
@@ -50,6 +112,17 @@ pub struct TcpForwardHandlerOptions {
     pub tunnel_manager: Option<Arc<tunnel::manager::Manager>>,
 
     pub runtime: Arc<tokio::sync::RwLock<TcpRuntimeConfig>>,
+
+    /// The upstream's `#proxy=` flag; None follows the global `proxy_protocol`.
+    pub proxy_protocol: Option<config::ProxyProtocol>,
+}
+
+/// Options for `mode = "status-health"` listeners, which answer Minecraft status pings
+/// locally instead of proxying.
+pub struct StatusHealthHandlerOptions {
+    pub status_json: String,
+
+    pub runtime: Arc<tokio::sync::RwLock<TcpRuntimeConfig>>,
 }
 
 #[derive(Debug, Clone)]
@@ -57,9 +130,93 @@ pub struct TcpRuntimeConfig {
     pub max_header_bytes: usize,
     pub handshake_timeout: Duration,
     pub idle_timeout: Duration,
+    pub io_op_timeout: Duration,
     pub upstream_dial_timeout: Duration,
+    pub tcp_user_timeout: Duration,
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Duration,
     pub buffer_size: usize,
-    pub proxy_protocol_v2: bool,
+    pub proxy_protocol: config::ProxyProtocol,
+    pub on_no_route: config::NoRoutePolicy,
+    pub max_sessions_per_ip: usize,
+    pub trusted_cidrs: Vec<net::IpNet>,
+    pub rate_limit: config::RateLimitConfig,
+    pub max_connections: usize,
+    pub upstream_max_connections: BTreeMap<String, usize>,
+    pub upstream_eject_failures: u32,
+    pub upstream_eject_cooldown: Duration,
+    pub health_check: config::HealthCheckConfig,
+    pub half_close_propagation: bool,
+    pub upstream_ip_version: config::UpstreamIpVersion,
+    pub default_minecraft_port: u16,
+    pub sniff_protocols: bool,
+    pub accept_proxy_protocol: bool,
+    pub shutdown_drain_timeout: Duration,
+}
+
+impl TcpRuntimeConfig {
+    fn socket_options(&self) -> TcpSocketOptions {
+        TcpSocketOptions {
+            user_timeout: self.tcp_user_timeout,
+            nodelay: self.tcp_nodelay,
+            keepalive: self.tcp_keepalive,
+        }
+    }
+
+    pub fn from_config(cfg: &config::Config) -> Self {
+        Self {
+            max_header_bytes: cfg.max_header_bytes,
+            handshake_timeout: cfg.timeouts.handshake_timeout,
+            idle_timeout: cfg.timeouts.idle_timeout,
+            io_op_timeout: cfg.timeouts.io_op_timeout,
+            upstream_dial_timeout: cfg.upstream_dial_timeout,
+            tcp_user_timeout: cfg.tcp_user_timeout,
+            tcp_nodelay: cfg.tcp_nodelay,
+            tcp_keepalive: cfg.tcp_keepalive,
+            buffer_size: cfg.buffer_size,
+            proxy_protocol: cfg.proxy_protocol,
+            on_no_route: cfg.on_no_route.clone(),
+            max_sessions_per_ip: cfg.max_sessions_per_ip,
+            trusted_cidrs: cfg.trusted_cidrs.clone(),
+            rate_limit: cfg.rate_limit,
+            max_connections: cfg.max_connections,
+            upstream_max_connections: cfg.upstream_max_connections.clone(),
+            upstream_eject_failures: cfg.upstream_eject_failures,
+            upstream_eject_cooldown: cfg.upstream_eject_cooldown,
+            health_check: cfg.health_check,
+            half_close_propagation: cfg.half_close_propagation,
+            upstream_ip_version: cfg.upstream_ip_version,
+            default_minecraft_port: cfg.default_minecraft_port,
+            sniff_protocols: cfg.sniff_protocols,
+            accept_proxy_protocol: cfg.accept_proxy_protocol,
+            shutdown_drain_timeout: cfg.shutdown_drain_timeout,
+        }
+    }
+}
+
+/// Reserve a per-IP session slot for `conn`.
+///
+/// Ok(None) means no limit applies (unlimited, trusted, or unknown peer); Err means the
+/// client IP is at `max_sessions_per_ip`.
+fn acquire_client_slot(
+    peer: Option<SocketAddr>,
+    rt: &TcpRuntimeConfig,
+    sessions: &telemetry::SharedSessions,
+) -> Result<Option<telemetry::IpSessionGuard>, std::net::IpAddr> {
+    if rt.max_sessions_per_ip == 0 {
+        return Ok(None);
+    }
+    let Some(peer) = peer else {
+        return Ok(None);
+    };
+    let ip = peer.ip();
+    if rt.trusted_cidrs.iter().any(|n| n.contains(ip)) {
+        return Ok(None);
+    }
+    sessions
+        .try_acquire_ip(ip, rt.max_sessions_per_ip)
+        .map(Some)
+        .ok_or(ip)
 }
 
 #[allow(dead_code)]
@@ -68,13 +225,15 @@ pub async fn serve_tcp(listen_addr: &str, handler: TcpHandler) -> anyhow::Result
     let (tx, rx) = tokio::sync::watch::channel(false);
     // Keep sender alive for the lifetime of the listener.
     let _tx = tx;
-    serve_tcp_with_shutdown(listen_addr, handler, rx).await
+    serve_tcp_with_shutdown(listen_addr, handler, rx, None, None).await
 }
 
 pub async fn serve_tcp_with_shutdown(
     listen_addr: &str,
     handler: TcpHandler,
-    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    shutdown: tokio::sync::watch::Receiver<bool>,
+    ready: Option<telemetry::ReadyCheck>,
+    in_flight: Option<Arc<telemetry::ConnectionCounter>>,
 ) -> anyhow::Result<()> {
     let bind_addr = net::normalize_bind_addr(listen_addr);
     let ln = TcpListener::bind(bind_addr.as_ref())
@@ -82,7 +241,21 @@ pub async fn serve_tcp_with_shutdown(
         .with_context(|| format!("bind tcp {listen_addr}"))?;
 
     tracing::info!(listen_addr = %listen_addr, "tcp: listening");
+    if let Some(ready) = &ready {
+        ready.ready();
+    }
+
+    accept_tcp(ln, handler, shutdown, in_flight).await
+}
 
+/// Accept until `shutdown` flips. With `in_flight`, every accepted connection holds a
+/// slot of that counter until its handler returns.
+pub(crate) async fn accept_tcp(
+    ln: TcpListener,
+    handler: TcpHandler,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    in_flight: Option<Arc<telemetry::ConnectionCounter>>,
+) -> anyhow::Result<()> {
     loop {
         tokio::select! {
             _ = shutdown.changed() => {
@@ -92,9 +265,15 @@ pub async fn serve_tcp_with_shutdown(
             }
             res = ln.accept() => {
                 let (conn, peer) = res?;
+                if !handler.admit(peer).await {
+                    drop(conn);
+                    continue;
+                }
                 let h = handler.clone();
+                let permit = in_flight.as_ref().and_then(|c| c.try_acquire(0));
 
                 tokio::spawn(async move {
+                    let _permit = permit;
                     if tracing::enabled!(tracing::Level::DEBUG) {
                         tracing::debug!(client = %peer, "tcp: accepted");
                     }
@@ -107,11 +286,67 @@ pub async fn serve_tcp_with_shutdown(
     Ok(())
 }
 
+/// Settings shared by every UDP forwarding listener.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UdpRuntimeConfig {
+    pub idle_timeout: Duration,
+    /// Maximum concurrent client flows per listener (0 = unlimited).
+    pub max_sessions: usize,
+    pub limits: UdpDatagramLimits,
+}
+
+impl UdpRuntimeConfig {
+    pub fn from_config(cfg: &config::Config) -> Self {
+        Self {
+            idle_timeout: cfg.timeouts.idle_timeout,
+            max_sessions: cfg.max_udp_sessions,
+            limits: UdpDatagramLimits {
+                buffer_size: cfg.udp_buffer_size,
+                max_datagram_bytes: cfg.max_udp_datagram_bytes,
+            },
+        }
+    }
+}
+
+/// Receive buffer size and largest datagram forwarded, in both directions.
+#[derive(Debug, Clone, Copy)]
+pub struct UdpDatagramLimits {
+    pub buffer_size: usize,
+    /// Larger datagrams are dropped; never more than `buffer_size`.
+    pub max_datagram_bytes: usize,
+}
+
+impl Default for UdpDatagramLimits {
+    fn default() -> Self {
+        Self {
+            buffer_size: config::DEFAULT_UDP_BUFFER_SIZE,
+            max_datagram_bytes: config::DEFAULT_UDP_BUFFER_SIZE,
+        }
+    }
+}
+
+impl UdpDatagramLimits {
+    /// One byte larger than `buffer_size`, so a datagram that doesn't fit shows up as
+    /// oversize instead of being silently truncated.
+    fn recv_buffer(&self) -> Vec<u8> {
+        vec![0u8; self.buffer_size + 1]
+    }
+
+    fn allows(&self, n: usize) -> bool {
+        n <= self.max_datagram_bytes.min(self.buffer_size)
+    }
+}
+
 pub struct UdpForwardOptions {
     pub upstream: String,
     pub sessions: telemetry::SharedSessions,
     pub tunnel_manager: Option<Arc<tunnel::manager::Manager>>,
     pub idle_timeout: Duration,
+    /// Local source IP for direct upstream sockets.
+    pub source_ip: Option<std::net::IpAddr>,
+    /// Packets from new sources are dropped while this many flows are open (0 = unlimited).
+    pub max_sessions: usize,
+    pub limits: UdpDatagramLimits,
 }
 
 #[allow(dead_code)]
@@ -120,13 +355,14 @@ pub async fn serve_udp(listen_addr: &str, opts: UdpForwardOptions) -> anyhow::Re
     let (tx, rx) = tokio::sync::watch::channel(false);
     // Keep sender alive for the lifetime of the listener.
     let _tx = tx;
-    serve_udp_with_shutdown(listen_addr, opts, rx).await
+    serve_udp_with_shutdown(listen_addr, opts, rx, None).await
 }
 
 pub async fn serve_udp_with_shutdown(
     listen_addr: &str,
     opts: UdpForwardOptions,
-    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    shutdown: tokio::sync::watch::Receiver<bool>,
+    ready: Option<telemetry::ReadyCheck>,
 ) -> anyhow::Result<()> {
     let bind_addr = net::normalize_bind_addr(listen_addr);
     let sock = UdpSocket::bind(bind_addr.as_ref())
@@ -134,7 +370,18 @@ pub async fn serve_udp_with_shutdown(
         .with_context(|| format!("bind udp {listen_addr}"))?;
 
     tracing::info!(listen_addr = %listen_addr, "udp: listening");
+    if let Some(ready) = &ready {
+        ready.ready();
+    }
+
+    serve_udp_socket(sock, opts, shutdown).await
+}
 
+async fn serve_udp_socket(
+    sock: UdpSocket,
+    opts: UdpForwardOptions,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
     let sock = Arc::new(sock);
     let sessions: Arc<DashMap<std::net::SocketAddr, Arc<UdpSession>>> = Arc::new(DashMap::new());
 
@@ -146,7 +393,7 @@ pub async fn serve_udp_with_shutdown(
         });
     }
 
-    let mut buf = vec![0u8; 64 * 1024];
+    let mut buf = opts.limits.recv_buffer();
     loop {
         tokio::select! {
             _ = shutdown.changed() => {
@@ -159,21 +406,23 @@ pub async fn serve_udp_with_shutdown(
                 if n == 0 {
                     continue;
                 }
+                if !opts.limits.allows(n) {
+                    tracing::debug!(client = %src, len = n, limit = opts.limits.max_datagram_bytes, "udp: dropping oversize datagram");
+                    opts.sessions.session_metrics().record_udp_oversize();
+                    continue;
+                }
 
                 let payload = buf[..n].to_vec();
 
-                let mut sess = sessions
-                    .get(&src)
-                    .map(|s| s.value().clone())
+                let existing = sessions.get(&src).map(|s| s.value().clone());
+                if existing.is_none() && opts.max_sessions > 0 && sessions.len() >= opts.max_sessions {
+                    tracing::debug!(client = %src, limit = opts.max_sessions, "udp: session limit reached; dropping packet");
+                    opts.sessions.session_metrics().record_udp_session_dropped();
+                    continue;
+                }
+                let mut sess = existing
                     .unwrap_or_else(|| {
-                        let s = Arc::new(UdpSession::new(
-                            telemetry::new_session_id(),
-                            src,
-                            opts.upstream.clone(),
-                            sock.clone(),
-                            opts.sessions.clone(),
-                            opts.tunnel_manager.clone(),
-                        ));
+                        let s = Arc::new(UdpSession::new(src, sock.clone(), &opts));
                         sessions.insert(src, s.clone());
                         s
                     });
@@ -183,14 +432,7 @@ pub async fn serve_udp_with_shutdown(
                 if sess.tx.try_send(payload).is_err() {
                     // Session is likely closed or congested; recreate once.
                     let _ = sessions.remove(&src);
-                    sess = Arc::new(UdpSession::new(
-                        telemetry::new_session_id(),
-                        src,
-                        opts.upstream.clone(),
-                        sock.clone(),
-                        opts.sessions.clone(),
-                        opts.tunnel_manager.clone(),
-                    ));
+                    sess = Arc::new(UdpSession::new(src, sock.clone(), &opts));
                     sessions.insert(src, sess.clone());
                     // Best-effort re-send.
                     let _ = sess.tx.try_send(buf[..n].to_vec());
@@ -209,33 +451,31 @@ struct UdpSession {
     sock: Arc<UdpSocket>,
     sessions: telemetry::SharedSessions,
     tunnel_manager: Option<Arc<tunnel::manager::Manager>>,
+    source_ip: Option<std::net::IpAddr>,
+    limits: UdpDatagramLimits,
     last_seen_unix_ms: std::sync::atomic::AtomicU64,
     tx: tokio::sync::mpsc::Sender<Vec<u8>>,
 }
 
 impl UdpSession {
-    fn new(
-        sid: String,
-        src: std::net::SocketAddr,
-        upstream: String,
-        sock: Arc<UdpSocket>,
-        sessions: telemetry::SharedSessions,
-        tunnel_manager: Option<Arc<tunnel::manager::Manager>>,
-    ) -> Self {
+    fn new(src: std::net::SocketAddr, sock: Arc<UdpSocket>, opts: &UdpForwardOptions) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel::<Vec<u8>>(128);
 
         let s = Self {
-            sid: sid.clone(),
+            sid: opts.sessions.new_session_id(Some(src.ip())),
             src,
-            upstream: upstream.clone(),
+            upstream: opts.upstream.clone(),
             sock,
-            sessions,
-            tunnel_manager,
+            sessions: opts.sessions.clone(),
+            tunnel_manager: opts.tunnel_manager.clone(),
+            source_ip: opts.source_ip,
+            limits: opts.limits,
             last_seen_unix_ms: std::sync::atomic::AtomicU64::new(telemetry::now_unix_ms()),
             tx,
         };
 
         s.spawn(rx);
+        s.sessions.session_metrics().udp_session_opened();
         s
     }
 
@@ -258,17 +498,33 @@ impl UdpSession {
         let sock = self.sock.clone();
         let sessions = self.sessions.clone();
         let tunnel_manager = self.tunnel_manager.clone();
+        let source_ip = self.source_ip;
+        let limits = self.limits;
 
-        sessions.add(telemetry::SessionInfo {
+        let kill = sessions.add(telemetry::SessionInfo {
             id: sid.clone(),
             client: src.to_string(),
             host: "".into(),
             upstream: upstream.clone(),
             started_at_unix_ms: telemetry::now_unix_ms(),
+            geo: sessions.geo_lookup(&src.to_string()),
         });
 
         tokio::spawn(async move {
-            let res = udp_session_loop(sock, src, upstream, tunnel_manager, rx).await;
+            let res = until_killed(
+                &kill,
+                udp_session_loop(
+                    sock,
+                    src,
+                    upstream,
+                    tunnel_manager,
+                    source_ip,
+                    limits,
+                    sessions.clone(),
+                    rx,
+                ),
+            )
+            .await;
             sessions.remove(&sid);
             if let Err(err) = res
                 && tracing::enabled!(tracing::Level::DEBUG)
@@ -279,11 +535,22 @@ impl UdpSession {
     }
 }
 
+/// Flows live exactly as long as their listener's session map holds them.
+impl Drop for UdpSession {
+    fn drop(&mut self) {
+        self.sessions.session_metrics().udp_session_closed();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn udp_session_loop(
     sock: Arc<UdpSocket>,
     src: std::net::SocketAddr,
     upstream: String,
     tunnel_manager: Option<Arc<tunnel::manager::Manager>>,
+    source_ip: Option<std::net::IpAddr>,
+    limits: UdpDatagramLimits,
+    sessions: telemetry::SharedSessions,
     mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
 ) -> anyhow::Result<()> {
     if upstream.trim().is_empty() {
@@ -301,15 +568,24 @@ async fn udp_session_loop(
             .map_err(|e| anyhow::anyhow!("tunnel udp dial failed: {e}"))?;
         let mut up = tunnel::datagram::DatagramConn::new(st);
 
-        let mut buf = vec![0u8; 64 * 1024];
+        let mut buf = limits.recv_buffer();
         loop {
             tokio::select! {
                 Some(payload) = rx.recv() => {
                     up.write_datagram(&payload).await.map_err(|e| anyhow::anyhow!("tunnel udp write failed: {e}"))?;
                 }
                 res = up.read_datagram(&mut buf) => {
-                    let n = res.map_err(|e| anyhow::anyhow!("tunnel udp read failed: {e}"))?;
-                    let _ = sock.send_to(&buf[..n], src).await;
+                    let n = match res {
+                        Ok(n) => n,
+                        // Already drained from the stream; only this datagram is lost.
+                        Err(tunnel::protocol::ProtocolError::ShortBuffer(n)) => n,
+                        Err(e) => anyhow::bail!("tunnel udp read failed: {e}"),
+                    };
+                    if limits.allows(n) {
+                        let _ = sock.send_to(&buf[..n], src).await;
+                    } else {
+                        sessions.session_metrics().record_udp_oversize();
+                    }
                 }
                 else => {
                     break;
@@ -321,11 +597,14 @@ async fn udp_session_loop(
     }
 
     // Direct UDP forwarding.
-    let up = UdpSocket::bind("0.0.0.0:0").await?;
-    up.connect(upstream.trim()).await?;
+    let target = resolve_udp_upstream(upstream.trim(), source_ip).await?;
+    let up = UdpSocket::bind(udp_bind_addr(target, source_ip))
+        .await
+        .with_context(|| format!("udp bind for upstream {target}"))?;
+    up.connect(target).await?;
     let up = Arc::new(up);
 
-    let mut buf = vec![0u8; 64 * 1024];
+    let mut buf = limits.recv_buffer();
     loop {
         tokio::select! {
             Some(payload) = rx.recv() => {
@@ -333,7 +612,11 @@ async fn udp_session_loop(
             }
             res = up.recv(&mut buf) => {
                 let n = res?;
-                let _ = sock.send_to(&buf[..n], src).await;
+                if limits.allows(n) {
+                    let _ = sock.send_to(&buf[..n], src).await;
+                } else {
+                    sessions.session_metrics().record_udp_oversize();
+                }
             }
             else => {
                 break;
@@ -344,6 +627,39 @@ async fn udp_session_loop(
     Ok(())
 }
 
+/// Resolve a direct UDP upstream, preferring addresses in the source IP's family.
+async fn resolve_udp_upstream(
+    upstream: &str,
+    source_ip: Option<std::net::IpAddr>,
+) -> anyhow::Result<std::net::SocketAddr> {
+    let addrs: Vec<std::net::SocketAddr> = tokio::net::lookup_host(upstream)
+        .await
+        .with_context(|| format!("resolve udp upstream {upstream}"))?
+        .collect();
+    let pick = match source_ip {
+        Some(ip) => addrs.iter().find(|a| a.is_ipv6() == ip.is_ipv6()),
+        None => addrs.first(),
+    };
+    pick.copied()
+        .with_context(|| format!("udp upstream {upstream} has no usable address"))
+}
+
+/// Local bind address for a direct UDP upstream socket: the configured source IP, or the
+/// unspecified address of the upstream's family (so IPv6-only upstreams work).
+fn udp_bind_addr(
+    upstream: std::net::SocketAddr,
+    source_ip: Option<std::net::IpAddr>,
+) -> std::net::SocketAddr {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    let ip = source_ip.unwrap_or(if upstream.is_ipv6() {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    });
+    SocketAddr::new(ip, 0)
+}
+
 async fn udp_sweep_loop(
     sessions: Arc<DashMap<std::net::SocketAddr, Arc<UdpSession>>>,
     idle_timeout: Duration,
@@ -385,8 +701,7 @@ async fn udp_sweep_loop(
 }
 
 async fn handle_forward(mut conn: TcpStream, opts: Arc<TcpForwardHandlerOptions>) {
-    let sid = telemetry::new_session_id();
-    let client = conn.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let mut peer = conn.peer_addr().ok();
 
     let upstream = opts.upstream.trim().to_string();
     if upstream.is_empty() {
@@ -395,11 +710,44 @@ async fn handle_forward(mut conn: TcpStream, opts: Arc<TcpForwardHandlerOptions>
     }
 
     let rt = { opts.runtime.read().await.clone() };
+    apply_socket_options(&conn, rt.socket_options());
+
+    let mut source: Option<SocketAddr> = None;
+    if rt.accept_proxy_protocol {
+        let Ok(addr) = accept_forward_prelude(&mut conn, peer, &rt, &opts.sessions).await else {
+            return;
+        };
+        source = addr;
+        peer = source.or(peer);
+    }
+
+    let sid = opts.sessions.new_session_id(peer.map(|a| a.ip()));
+    let client = peer.map(|a| a.to_string()).unwrap_or_default();
+
+    let _ip_slot = match acquire_client_slot(peer, &rt, &opts.sessions) {
+        Ok(slot) => slot,
+        Err(ip) => {
+            tracing::warn!(sid = %sid, client = %client, ip = %ip, limit = rt.max_sessions_per_ip, "proxy: per-IP session limit reached");
+            opts.sessions
+                .record_rejection(&client, telemetry::RejectReason::IpLimit, "");
+            let _ = conn.shutdown().await;
+            return;
+        }
+    };
 
-    let (up, upstream_used, _tunnel_masquerade_host) = match dial_upstream(
+    let Some(_conn_permit) = opts.sessions.try_acquire_connection(rt.max_connections) else {
+        tracing::warn!(sid = %sid, client = %client, limit = rt.max_connections, "proxy: max_connections reached");
+        opts.sessions
+            .record_rejection(&client, telemetry::RejectReason::ConnectionLimit, "");
+        let _ = conn.shutdown().await;
+        return;
+    };
+
+    let (up, upstream_used, _tunnel_masquerade_host) = match timed_dial_upstream(
+        &opts.sessions,
         &upstream,
         None,
-        rt.upstream_dial_timeout,
+        &rt,
         opts.tunnel_manager.as_ref(),
     )
     .await
@@ -407,43 +755,155 @@ async fn handle_forward(mut conn: TcpStream, opts: Arc<TcpForwardHandlerOptions>
         Ok(v) => v,
         Err(err) => {
             tracing::warn!(sid = %sid, client = %client, upstream = %upstream, err = %err, "proxy: forward dial failed");
+            opts.sessions.record_rejection(
+                &client,
+                telemetry::RejectReason::UpstreamUnavailable,
+                "",
+            );
             let _ = conn.shutdown().await;
             return;
         }
     };
 
-    opts.sessions.add(telemetry::SessionInfo {
+    let kill = opts.sessions.add(telemetry::SessionInfo {
         id: sid.clone(),
         client: client.clone(),
         host: "".into(),
         upstream: upstream_used.clone(),
         started_at_unix_ms: telemetry::now_unix_ms(),
+        geo: opts.sessions.geo_lookup(&client),
     });
 
     let mut up = up;
-    if rt.proxy_protocol_v2
-        && let Err(err) = write_proxy_proto_v2(&mut *up, &conn).await
-    {
-        tracing::warn!(sid = %sid, client = %client, upstream = %upstream_used, err = %err, "proxy: proxy_protocol_v2 write failed");
+    let proxy_protocol = opts.proxy_protocol.unwrap_or(rt.proxy_protocol);
+    if let Err(err) = write_proxy_proto(proxy_protocol, &mut *up, &conn, source).await {
+        tracing::warn!(sid = %sid, client = %client, upstream = %upstream_used, version = ?proxy_protocol, err = %err, "proxy: proxy_protocol write failed");
         let _ = conn.shutdown().await;
         opts.sessions.remove(&sid);
         return;
     }
 
-    let res = proxy_bidirectional(&mut conn, up, rt.buffer_size, rt.idle_timeout).await;
+    let activity = Activity::new();
+    let res = until_killed(&kill, proxy_bidirectional(&mut conn, up, &rt, &activity)).await;
 
-    opts.sessions.remove(&sid);
+    opts.sessions
+        .finish(&sid, activity.bytes(), close_reason(&res));
 
     if let Err(err) = res {
         tracing::debug!(sid = %sid, err = %err, "proxy: forward ended with error");
     }
 }
 
+/// Reply to a configured monitor probe and close. Returns false when the connection is not
+/// a probe (or stalls on a partial match) and should be routed normally.
+async fn answer_health_probe(
+    conn: &mut TcpStream,
+    peer: Option<SocketAddr>,
+    opts: &TcpRoutingHandlerOptions,
+    rt: &TcpRuntimeConfig,
+) -> bool {
+    let matched = if rt.handshake_timeout.is_zero() {
+        sniff::peek_health_probe(conn, &opts.health_probes).await
+    } else {
+        match time::timeout(
+            rt.handshake_timeout,
+            sniff::peek_health_probe(conn, &opts.health_probes),
+        )
+        .await
+        {
+            Ok(r) => r,
+            Err(_) => Ok(None),
+        }
+    };
+    let Ok(Some(probe)) = matched else {
+        return false;
+    };
+    tracing::debug!(client = ?peer, "proxy: answering health probe");
+    // Drain the probe so closing doesn't reset the connection before the reply lands.
+    let mut probe_bytes = vec![0u8; probe.pattern.len()];
+    if conn.read_exact(&mut probe_bytes).await.is_ok() && !probe.reply.is_empty() {
+        let _ = conn.write_all(&probe.reply).await;
+    }
+    let _ = conn.shutdown().await;
+    true
+}
+
 async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>) {
-    let sid = telemetry::new_session_id();
-    let client = conn.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let mut peer = conn.peer_addr().ok();
 
     let rt = { opts.runtime.read().await.clone() };
+    apply_socket_options(&conn, rt.socket_options());
+
+    if !opts.health_probes.is_empty() && answer_health_probe(&mut conn, peer, &opts, &rt).await {
+        return;
+    }
+
+    // Client address from a trusted load balancer's PROXY header; it stands in for the
+    // socket peer in logs, limits and the PROXY header we send upstream.
+    let mut source: Option<SocketAddr> = None;
+    if rt.sniff_protocols || rt.accept_proxy_protocol {
+        let Ok(addr) = accept_prelude(&mut conn, peer, &rt, &opts.sessions).await else {
+            return;
+        };
+        source = addr;
+        peer = source.or(peer);
+    }
+    let client_meta = middleware::ClientMeta {
+        ip: peer.map(|a| a.ip()),
+        listen_port: conn.local_addr().ok().map(|a| a.port()),
+    };
+
+    let sid = opts.sessions.new_session_id(peer.map(|a| a.ip()));
+    let client = peer.map(|a| a.to_string()).unwrap_or_default();
+    let geo = opts.sessions.geo_lookup(&client);
+
+    let _ip_slot = match acquire_client_slot(peer, &rt, &opts.sessions) {
+        Ok(slot) => slot,
+        Err(ip) => {
+            tracing::warn!(sid = %sid, client = %client, ip = %ip, limit = rt.max_sessions_per_ip, "proxy: per-IP session limit reached");
+            opts.sessions
+                .record_rejection(&client, telemetry::RejectReason::IpLimit, "");
+            let _ = conn.shutdown().await;
+            return;
+        }
+    };
+
+    // Until a route matches, wait as long as the most patient route would; the matched
+    // route's own limit is checked once it is known.
+    let handshake_started = time::Instant::now();
+    let handshake_ceiling = opts.router.handshake_timeout_ceiling(rt.handshake_timeout);
+
+    // Auto listeners decide TLS vs. the middleware path from the first bytes. Pre-1.7
+    // clients ping with 0xFE instead of a framed handshake; no middleware parses it.
+    let sniffed = async {
+        let tls = match opts.protocol {
+            RoutingProtocol::Middleware => false,
+            RoutingProtocol::Tls => true,
+            RoutingProtocol::Auto => sniff::peek_protocol(&conn).await? == sniff::Protocol::Tls,
+        };
+        let legacy = !tls && sniff::peek_legacy_ping(&conn).await?;
+        Ok::<_, std::io::Error>((tls, legacy))
+    };
+    let sniffed = if handshake_ceiling.is_zero() {
+        Ok(sniffed.await)
+    } else {
+        time::timeout(handshake_ceiling, sniffed).await
+    };
+    let tls = match sniffed {
+        Ok(Ok((_, true))) => {
+            handle_legacy_ping(conn, &rt, &opts, &sid, &client, source).await;
+            return;
+        }
+        Ok(Ok((tls, false))) => tls,
+        Ok(Err(_)) => opts.protocol == RoutingProtocol::Tls,
+        Err(_) => {
+            tracing::debug!(sid=%sid, client=%client, "proxy: handshake timeout");
+            opts.sessions
+                .record_rejection(&client, telemetry::RejectReason::HandshakeTimeout, "");
+            let _ = conn.shutdown().await;
+            return;
+        }
+    };
 
     let max_header = if rt.max_header_bytes == 0 {
         64 * 1024
@@ -451,9 +911,10 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
         rt.max_header_bytes
     };
 
-    // Capture prelude.
-    let mut captured: Vec<u8> = Vec::with_capacity(4096.min(max_header));
-    let mut tmp = vec![0u8; 4096];
+    // Capture prelude. Both buffers come back (cleared) to the pool when dropped.
+    let mut captured = opts.buffer_pool.get(4096.min(max_header));
+    let mut tmp = opts.buffer_pool.get(4096);
+    tmp.resize(4096, 0);
 
     let res = {
         let read_fut = async {
@@ -462,7 +923,7 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
                     break Ok::<Option<router::Resolution>, middleware::MiddlewareError>(None);
                 }
                 let n = conn
-                    .read(&mut tmp)
+                    .read(&mut tmp[..])
                     .await
                     .map_err(|e| middleware::MiddlewareError::Fatal(format!("read failed: {e}")))?;
                 if n == 0 {
@@ -472,7 +933,12 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
                 let need = (max_header - captured.len()).min(n);
                 captured.extend_from_slice(&tmp[..need]);
 
-                match opts.router.resolve_prelude(&captured) {
+                let resolved = if tls {
+                    opts.router.resolve_sni(&captured, client_meta)
+                } else {
+                    opts.router.resolve_prelude(&captured, client_meta)
+                };
+                match resolved {
                     Ok(Some(r)) => break Ok(Some(r)),
                     Ok(None) => {
                         tracing::debug!(
@@ -497,16 +963,27 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
             }
         };
 
-        if rt.handshake_timeout > Duration::from_millis(0) {
-            match time::timeout(rt.handshake_timeout, read_fut).await {
+        if handshake_ceiling > Duration::from_millis(0) {
+            let remaining = handshake_ceiling.saturating_sub(handshake_started.elapsed());
+            match time::timeout(remaining, read_fut).await {
                 Ok(Ok(r)) => r,
                 Ok(Err(e)) => {
                     tracing::warn!(sid=%sid, client=%client, err=%e, "proxy: routing header parse failed");
+                    opts.sessions.record_rejection(
+                        &client,
+                        telemetry::RejectReason::HandshakeInvalid,
+                        "",
+                    );
                     let _ = conn.shutdown().await;
                     return;
                 }
                 Err(_) => {
                     tracing::debug!(sid=%sid, client=%client, "proxy: handshake timeout");
+                    opts.sessions.record_rejection(
+                        &client,
+                        telemetry::RejectReason::HandshakeTimeout,
+                        "",
+                    );
                     let _ = conn.shutdown().await;
                     return;
                 }
@@ -516,6 +993,11 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
                 Ok(r) => r,
                 Err(e) => {
                     tracing::warn!(sid=%sid, client=%client, err=%e, "proxy: routing header parse failed");
+                    opts.sessions.record_rejection(
+                        &client,
+                        telemetry::RejectReason::HandshakeInvalid,
+                        "",
+                    );
                     let _ = conn.shutdown().await;
                     return;
                 }
@@ -523,14 +1005,31 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
         }
     };
 
+    drop(tmp);
+    opts.sessions
+        .latency()
+        .handshake
+        .observe(handshake_started.elapsed());
+
     let Some(res) = res else {
+        if !tls && opts.router.rejects_non_minecraft(&captured) {
+            opts.sessions
+                .session_metrics()
+                .record_non_minecraft_rejected();
+        }
+        if opts.observe {
+            tracing::info!(sid = %sid, client = %client, prelude_len = captured.len(), "observe: no route matched");
+            let _ = conn.shutdown().await;
+            return;
+        }
         tracing::warn!(
             sid = %sid,
             client = %client,
             prelude_len = captured.len(),
+            policy = ?rt.on_no_route,
             "proxy: no route matched prelude (check host patterns, wildcard captures, and middleware host extraction; enable RUST_LOG=prism=debug for details)"
         );
-        let _ = conn.shutdown().await;
+        handle_no_route(conn, &captured, &rt, &opts, &sid, &client, source).await;
         return;
     };
 
@@ -541,15 +1040,173 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
         middleware,
         prelude_override,
         captures,
+        named_captures,
+        trace_sampled,
+        host_secret_rejected,
+        client_denied,
+        proxy_protocol,
+        upstream_proxy_protocols,
+        offline_status,
+        forwarding,
+        forwarding_secret,
+        status_override,
+        connections: route_connections,
+        max_upstream_connections,
+        dial_retries,
+        dial_retry_backoff,
+        handshake_timeout,
+        idle_timeout,
+        upstream_dial_timeout,
     } = res;
 
+    let rt = TcpRuntimeConfig {
+        handshake_timeout: handshake_timeout.unwrap_or(rt.handshake_timeout),
+        idle_timeout: idle_timeout.unwrap_or(rt.idle_timeout),
+        upstream_dial_timeout: upstream_dial_timeout.unwrap_or(rt.upstream_dial_timeout),
+        ..rt
+    };
+
     let host = router::normalize_routing_host(&resolved_host);
     if host.is_empty() {
         tracing::warn!(sid = %sid, client = %client, "proxy: empty host after route resolution");
+        opts.sessions
+            .record_rejection(&client, telemetry::RejectReason::HandshakeInvalid, "");
+        let _ = conn.shutdown().await;
+        return;
+    }
+
+    if !rt.handshake_timeout.is_zero() && handshake_started.elapsed() > rt.handshake_timeout {
+        tracing::debug!(sid = %sid, client = %client, host = %host, "proxy: handshake timeout");
+        opts.sessions
+            .record_rejection(&client, telemetry::RejectReason::HandshakeTimeout, &host);
         let _ = conn.shutdown().await;
         return;
     }
 
+    if opts.observe {
+        tracing::info!(
+            sid = %sid,
+            client = %client,
+            host = %host,
+            matched_host = %matched_host,
+            captures = ?captures,
+            upstreams = ?upstreams,
+            host_secret_rejected,
+            client_denied,
+            "observe: route resolved"
+        );
+        let _ = conn.shutdown().await;
+        return;
+    }
+
+    if host_secret_rejected {
+        tracing::warn!(sid = %sid, client = %client, matched_host = %matched_host, "proxy: host_secret mismatch");
+        opts.sessions
+            .record_rejection(&client, telemetry::RejectReason::HostSecret, &host);
+        kick_login(&mut conn, &captured, HOST_SECRET_KICK_MESSAGE).await;
+        let _ = conn.shutdown().await;
+        return;
+    }
+
+    if client_denied {
+        tracing::warn!(sid = %sid, client = %client, host = %host, matched_host = %matched_host, "proxy: client IP not allowed on route");
+        opts.sessions
+            .record_rejection(&client, telemetry::RejectReason::RouteDenied, &host);
+        opts.sessions
+            .session_metrics()
+            .record_route_denied(&matched_host);
+        let _ = conn.shutdown().await;
+        return;
+    }
+
+    // Capacity caps apply to proxied sessions only; pings can still get the offline status.
+    let permits = opts
+        .sessions
+        .try_acquire_connection(rt.max_connections)
+        .and_then(|global| {
+            let route = route_connections.try_acquire(max_upstream_connections)?;
+            Some((global, route))
+        });
+    let Some(_conn_permits) = permits else {
+        tracing::warn!(
+            sid = %sid,
+            client = %client,
+            host = %host,
+            max_connections = rt.max_connections,
+            max_upstream_connections,
+            "proxy: connection limit reached"
+        );
+        opts.sessions
+            .record_rejection(&client, telemetry::RejectReason::ConnectionLimit, &host);
+        if let Some(status_json) = &offline_status {
+            serve_offline_status(
+                &mut conn,
+                &captured,
+                status_json,
+                status_override.as_deref(),
+                &rt,
+                max_header,
+                &sid,
+            )
+            .await;
+        }
+        kick_login(&mut conn, &captured, CONNECTION_LIMIT_KICK_MESSAGE).await;
+        let _ = conn.shutdown().await;
+        return;
+    };
+
+    // Forwarding needs the player's name and UUID from Login Start, which may not have been
+    // captured yet; any bytes read for it are appended to the prelude.
+    let resolved_len = captured.len();
+    let mut forwarded_player: Option<(String, [u8; 16])> = None;
+    if forwarding != config::Forwarding::None
+        && let Some(hs) = minecraft::parse_handshake(&captured)
+        && hs.next_state == minecraft::NEXT_STATE_LOGIN
+    {
+        let read_login = async {
+            let mut chunk = [0u8; 512];
+            loop {
+                let rest = &captured[hs.packet_len..];
+                if minecraft::split_packet(rest).is_some() {
+                    // The UUID in Login Start is whatever the client claims; forward the
+                    // one an offline-mode server would derive from the name instead.
+                    return minecraft::parse_login_start(rest, hs.protocol_version).map(
+                        |(name, _)| {
+                            let uuid = minecraft::offline_uuid(&name);
+                            (name, uuid)
+                        },
+                    );
+                }
+                if captured.len() >= max_header {
+                    return None;
+                }
+                match conn.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return None,
+                    Ok(n) => captured.extend_from_slice(&chunk[..n]),
+                }
+            }
+        };
+        forwarded_player = if rt.handshake_timeout.is_zero() {
+            read_login.await
+        } else {
+            // Login Start is part of the handshake, so it only gets what's left of the budget.
+            time::timeout_at(handshake_started + rt.handshake_timeout, read_login)
+                .await
+                .ok()
+                .flatten()
+        };
+        if forwarded_player.is_none() {
+            tracing::warn!(sid = %sid, client = %client, host = %host, forwarding = ?forwarding, "proxy: no Login Start for forwarding");
+            opts.sessions.record_rejection(
+                &client,
+                telemetry::RejectReason::HandshakeInvalid,
+                &host,
+            );
+            let _ = conn.shutdown().await;
+            return;
+        }
+    }
+
     let default_port = conn.local_addr().ok().map(|a| a.port());
 
     // Dial upstream candidates with failover.
@@ -557,6 +1214,8 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
     let mut upstream_used = String::new();
     let mut up_conn: Option<tunnel::transport::BoxedStream> = None;
     let mut tunnel_masquerade_host: Option<String> = None;
+    let mut _upstream_slot: Option<telemetry::UpstreamSlotGuard> = None;
+    let mut upstream_proxy_protocol: Option<config::ProxyProtocol> = None;
 
     tracing::debug!(
         sid = %sid,
@@ -568,20 +1227,79 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
         "proxy: dialing route upstreams"
     );
 
+    // Retries wait no longer than the client would wait for a handshake to route.
+    let retry_deadline =
+        (!rt.handshake_timeout.is_zero()).then(|| time::Instant::now() + rt.handshake_timeout);
+
     for cand in &upstreams {
         let addr = cand.trim().to_string();
-        match dial_upstream(
+
+        // Reserve a slot before dialing; a full upstream fails over like a dead one.
+        let label = upstream_label(&addr, default_port);
+        let slot = match rt.upstream_max_connections.get(&label) {
+            Some(&limit) => match opts.sessions.try_acquire_upstream(&label, limit) {
+                Some(slot) => Some(slot),
+                None => {
+                    tracing::warn!(
+                        sid = %sid,
+                        client = %client,
+                        host = %host,
+                        candidate = %label,
+                        limit,
+                        "proxy: upstream at connection limit; trying next candidate"
+                    );
+                    last_err = Some(anyhow::anyhow!(
+                        "upstream {label} at connection limit ({limit})"
+                    ));
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let mut attempt = 0u32;
+        let dialed = loop {
+            match timed_dial_upstream(
+                &opts.sessions,
+                &addr,
+                default_port,
+                &rt,
+                opts.tunnel_manager.as_ref(),
+            )
+            .await
+            {
+                Err(err) if attempt < dial_retries => {
+                    let wait = dial_retry_backoff.saturating_mul(1 << attempt.min(16));
+                    if retry_deadline.is_some_and(|d| time::Instant::now() + wait >= d) {
+                        break Err(err);
+                    }
+                    tracing::debug!(
+                        sid = %sid,
+                        candidate = %addr,
+                        attempt = attempt + 1,
+                        wait_ms = wait.as_millis() as u64,
+                        err = %err,
+                        "proxy: upstream dial failed; retrying"
+                    );
+                    attempt += 1;
+                    time::sleep(wait).await;
+                }
+                res => break res,
+            }
+        };
+        opts.router.record_dial(
             &addr,
-            default_port,
-            rt.upstream_dial_timeout,
-            opts.tunnel_manager.as_ref(),
-        )
-        .await
-        {
+            dialed.is_ok(),
+            rt.upstream_eject_failures,
+            rt.upstream_eject_cooldown,
+        );
+        match dialed {
             Ok((c, label, masq)) => {
                 tracing::info!(
                     sid = %sid,
                     client = %client,
+                    country = geo.country.as_deref(),
+                    asn = geo.asn,
                     host = %host,
                     matched_host = %matched_host,
                     upstream = %label,
@@ -591,6 +1309,11 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
                 upstream_used = label;
                 up_conn = Some(c);
                 tunnel_masquerade_host = masq;
+                _upstream_slot = slot;
+                upstream_proxy_protocol = upstream_proxy_protocols
+                    .iter()
+                    .find(|(u, _)| *u == addr)
+                    .map(|(_, pp)| *pp);
                 break;
             }
             Err(err) => {
@@ -618,24 +1341,48 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
             err = %last_err.map(|e| e.to_string()).unwrap_or_default(),
             "proxy: all upstream candidates failed (for tunnel:$1 wildcards, service name must equal capture and be registered)"
         );
+        opts.sessions.record_rejection(
+            &client,
+            telemetry::RejectReason::UpstreamUnavailable,
+            &host,
+        );
+        if let Some(status_json) = &offline_status {
+            serve_offline_status(
+                &mut conn,
+                &captured,
+                status_json,
+                status_override.as_deref(),
+                &rt,
+                max_header,
+                &sid,
+            )
+            .await;
+        }
         let _ = conn.shutdown().await;
         return;
     };
 
-    opts.sessions.add(telemetry::SessionInfo {
+    let kill = opts.sessions.add(telemetry::SessionInfo {
         id: sid.clone(),
         client: client.clone(),
         host: host.clone(),
         upstream: upstream_used.clone(),
         started_at_unix_ms: telemetry::now_unix_ms(),
+        geo: geo.clone(),
     });
 
     // Apply any middleware prelude overrides from parse phase, then allow a rewrite pass based on
     // the selected upstream.
-    let mut prelude = prelude_override.unwrap_or(captured);
+    let mut prelude: Cow<[u8]> = match prelude_override {
+        Some(mut p) => {
+            p.extend_from_slice(&captured[resolved_len..]);
+            Cow::Owned(p)
+        }
+        None => Cow::Borrowed(&captured),
+    };
 
     let selected_for_rewrite = if let Some(tpl) = tunnel_masquerade_host.as_ref() {
-        let v = router::substitute_params(tpl, &captures);
+        let v = router::substitute_params(tpl, &captures, &named_captures);
         let v = v.trim().to_ascii_lowercase();
         if v.is_empty() {
             upstream_used.clone()
@@ -646,13 +1393,25 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
         upstream_used.clone()
     };
 
-    let rewrite_eligible = should_rewrite_prelude(&selected_for_rewrite);
+    // TLS passthrough forwards the ClientHello exactly as the client sent it.
+    let rewrite_eligible = !tls && should_rewrite_prelude(&selected_for_rewrite);
     let mut rewrite_applied = false;
-    if rewrite_eligible {
-        if let Some(rw) = middleware.rewrite(&prelude, &selected_for_rewrite) {
-            prelude = rw;
-            rewrite_applied = true;
-        }
+    if rewrite_eligible
+        && let Some(rw) = middleware.rewrite(&prelude, &selected_for_rewrite, client_meta)
+    {
+        prelude = Cow::Owned(rw);
+        rewrite_applied = true;
+    }
+
+    // Rebuilt from the final prelude so middleware host rewrites carry through.
+    let client_ip = peer.map(|a| a.ip().to_string()).unwrap_or_default();
+    if forwarding == config::Forwarding::Legacy
+        && let Some((_, uuid)) = &forwarded_player
+        && let Some(hs) = minecraft::parse_handshake(&prelude)
+    {
+        let mut out = minecraft::bungee_forwarded_handshake(&hs, &client_ip, uuid);
+        out.extend_from_slice(&prelude[hs.packet_len..]);
+        prelude = Cow::Owned(out);
     }
 
     if tracing::enabled!(tracing::Level::DEBUG) {
@@ -673,11 +1432,13 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
         );
     }
 
-    // Forward captured prelude upstream.
-    if rt.proxy_protocol_v2
-        && let Err(err) = write_proxy_proto_v2(&mut *up, &conn).await
-    {
-        tracing::warn!(sid=%sid, err=%err, "proxy: proxy_protocol_v2 write failed");
+    // Forward captured prelude upstream. The upstream's `#proxy=` flag beats the route's
+    // override, which beats the global setting.
+    let proxy_protocol = upstream_proxy_protocol
+        .or(proxy_protocol)
+        .unwrap_or(rt.proxy_protocol);
+    if let Err(err) = write_proxy_proto(proxy_protocol, &mut *up, &conn, source).await {
+        tracing::warn!(sid=%sid, version=?proxy_protocol, err=%err, "proxy: proxy_protocol write failed");
         let _ = conn.shutdown().await;
         opts.sessions.remove(&sid);
         return;
@@ -689,30 +1450,716 @@ async fn handle_routing(mut conn: TcpStream, opts: Arc<TcpRoutingHandlerOptions>
         opts.sessions.remove(&sid);
         return;
     }
+    let is_status = minecraft::parse_handshake(&prelude)
+        .is_some_and(|hs| hs.next_state == minecraft::NEXT_STATE_STATUS);
+    // Hand the capture buffer back before the (long-lived) session starts.
+    drop(prelude);
+    drop(captured);
+
+    // Without an override or a response-phase middleware the reply needs no buffering;
+    // it goes through the plain splice below.
+    if is_status && (status_override.is_some() || middleware.handles_response()) {
+        let fut = relay_status_response(
+            &mut conn,
+            &mut *up,
+            status_override.as_deref(),
+            &middleware,
+            client_meta,
+            max_header,
+        );
+        let res = if rt.handshake_timeout.is_zero() {
+            fut.await
+        } else {
+            time::timeout(rt.handshake_timeout, fut)
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("status response: timeout")))
+        };
+        if let Err(err) = res {
+            tracing::debug!(sid = %sid, client = %client, upstream = %upstream_used, err = %err, "proxy: status response relay failed");
+            let _ = conn.shutdown().await;
+            opts.sessions.remove(&sid);
+            return;
+        }
+    }
+
+    if forwarding == config::Forwarding::Modern
+        && let Some((name, _)) = &forwarded_player
+    {
+        let secret = forwarding_secret.as_deref().unwrap_or_default();
+        let fut = answer_velocity_query(
+            &mut conn,
+            &mut *up,
+            secret.as_bytes(),
+            &client_ip,
+            name,
+            max_header,
+        );
+        let res = if rt.handshake_timeout.is_zero() {
+            fut.await
+        } else {
+            time::timeout(rt.handshake_timeout, fut)
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("modern forwarding: timeout")))
+        };
+        if let Err(err) = res {
+            tracing::warn!(sid = %sid, client = %client, host = %host, upstream = %upstream_used, err = %err, "proxy: modern forwarding failed");
+            let _ = conn.shutdown().await;
+            opts.sessions.remove(&sid);
+            return;
+        }
+    }
 
-    let res = proxy_bidirectional(&mut conn, up, rt.buffer_size, rt.idle_timeout).await;
+    let session_span = if trace_sampled {
+        tracing::info_span!("session", sid = %sid, client = %client, country = geo.country.as_deref(), asn = geo.asn, host = %host, upstream = %upstream_used)
+    } else {
+        tracing::Span::none()
+    };
+    let activity = Activity::new();
+    let res = until_killed(&kill, proxy_bidirectional(&mut conn, up, &rt, &activity))
+        .instrument(session_span)
+        .await;
 
-    opts.sessions.remove(&sid);
+    opts.sessions
+        .finish(&sid, activity.bytes(), close_reason(&res));
 
     if let Err(err) = res {
         tracing::debug!(sid=%sid, err=%err, "proxy: session ended with error");
     }
 }
 
-async fn dial_tcp_stream(
-    addr: &str,
-    timeout: Duration,
-) -> anyhow::Result<tunnel::transport::BoxedStream> {
-    let c = if timeout > Duration::from_millis(0) {
-        time::timeout(timeout, TcpStream::connect(addr))
-            .await
+/// Velocity modern forwarding: wait for the backend's first login packet and, if it is the
+/// `velocity:player_info` plugin request, answer it with signed player info. Anything else
+/// the backend sent is passed through to the client unchanged.
+///
+/// The signed UUID is always the offline one derived from `name`; a UUID the client sent
+/// in Login Start is never vouched for.
+async fn answer_velocity_query<C, U>(
+    client: &mut C,
+    upstream: &mut U,
+    secret: &[u8],
+    client_ip: &str,
+    name: &str,
+    max_bytes: usize,
+) -> anyhow::Result<()>
+where
+    C: tokio::io::AsyncWrite + Unpin + ?Sized,
+    U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = Vec::with_capacity(256);
+    let mut chunk = [0u8; 512];
+    let (frame_len, id, payload) = loop {
+        if let Some(p) = minecraft::split_packet(&buf) {
+            break p;
+        }
+        if buf.len() >= max_bytes {
+            anyhow::bail!("backend login packet exceeds {max_bytes} bytes");
+        }
+        let n = upstream.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("backend closed before its first login packet");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    match minecraft::parse_login_plugin_request(payload) {
+        Some((message_id, channel))
+            if id == 0x04 && channel == minecraft::VELOCITY_PLAYER_INFO_CHANNEL =>
+        {
+            let uuid = minecraft::offline_uuid(name);
+            let data = minecraft::velocity_forwarding_data(secret, client_ip, &uuid, name);
+            upstream
+                .write_all(&minecraft::login_plugin_response(message_id, &data))
+                .await?;
+            client.write_all(&buf[frame_len..]).await?;
+        }
+        _ => client.write_all(&buf).await?,
+    }
+    Ok(())
+}
+
+async fn handle_status_health(mut conn: TcpStream, opts: Arc<StatusHealthHandlerOptions>) {
+    let client = conn.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let rt = { opts.runtime.read().await.clone() };
+
+    let fut = serve_status_health(&mut conn, &[], &opts.status_json, rt.max_header_bytes);
+    let res = if rt.handshake_timeout > Duration::from_millis(0) {
+        time::timeout(rt.handshake_timeout, fut)
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("status-health: timeout")))
+    } else {
+        fut.await
+    };
+    if let Err(err) = res {
+        tracing::debug!(client = %client, err = %err, "status-health: request failed");
+    }
+    let _ = conn.shutdown().await;
+}
+
+/// Answer one Minecraft status exchange (handshake, status request, optional ping).
+/// `prelude` holds bytes of the exchange already read from `conn`.
+async fn serve_status_health(
+    conn: &mut TcpStream,
+    prelude: &[u8],
+    status_json: &str,
+    max_bytes: usize,
+) -> anyhow::Result<()> {
+    let max_bytes = if max_bytes == 0 { 64 * 1024 } else { max_bytes };
+    let mut buf: Vec<u8> = Vec::with_capacity(512.max(prelude.len()));
+    buf.extend_from_slice(prelude);
+    let mut tmp = [0u8; 1024];
+
+    let hs = loop {
+        if let Some(hs) = minecraft::parse_handshake(&buf) {
+            break hs;
+        }
+        if buf.len() >= max_bytes {
+            anyhow::bail!("status-health: handshake too large");
+        }
+        let n = conn.read(&mut tmp).await?;
+        if n == 0 {
+            anyhow::bail!("status-health: eof before handshake");
+        }
+        buf.extend_from_slice(&tmp[..n]);
+    };
+    if hs.next_state != minecraft::NEXT_STATE_STATUS {
+        anyhow::bail!("status-health: not a status handshake");
+    }
+    buf.drain(..hs.packet_len);
+
+    loop {
+        if let Some((len, id, payload)) = minecraft::split_packet(&buf) {
+            match id {
+                0x00 => {
+                    let json = status_json_for_client(status_json, hs.protocol_version);
+                    conn.write_all(&minecraft::status_response_packet(&json))
+                        .await?;
+                }
+                0x01 => {
+                    let ping = payload
+                        .get(..8)
+                        .context("status-health: short ping payload")?;
+                    let ping = i64::from_be_bytes(ping.try_into().expect("8 bytes"));
+                    conn.write_all(&minecraft::pong_packet(ping)).await?;
+                    return Ok(());
+                }
+                other => anyhow::bail!("status-health: unexpected packet id {other:#x}"),
+            }
+            buf.drain(..len);
+            continue;
+        }
+        if buf.len() >= max_bytes {
+            anyhow::bail!("status-health: packet too large");
+        }
+        let n = conn.read(&mut tmp).await?;
+        if n == 0 {
+            // Monitors may hang up after the status response without pinging.
+            return Ok(());
+        }
+        buf.extend_from_slice(&tmp[..n]);
+    }
+}
+
+/// Fill `version.protocol` from the client's handshake when the configured JSON omits it,
+/// so clients of any version render the entry as compatible.
+fn status_json_for_client(status_json: &str, protocol_version: i32) -> String {
+    let Ok(mut v) = serde_json::from_str::<serde_json::Value>(status_json) else {
+        return status_json.to_string();
+    };
+    if let Some(obj) = v.as_object_mut() {
+        let version = obj
+            .entry("version")
+            .or_insert_with(|| serde_json::json!({ "name": "Prism" }));
+        if let Some(version) = version.as_object_mut() {
+            version
+                .entry("protocol")
+                .or_insert_with(|| serde_json::json!(protocol_version));
+        }
+    }
+    v.to_string()
+}
+
+/// Answer a status handshake in `captured` with a route's `offline_status`; other
+/// handshakes are left alone.
+async fn serve_offline_status(
+    conn: &mut TcpStream,
+    captured: &[u8],
+    status_json: &str,
+    status_override: Option<&config::StatusOverride>,
+    rt: &TcpRuntimeConfig,
+    max_bytes: usize,
+    sid: &str,
+) {
+    if minecraft::parse_handshake(captured)
+        .is_none_or(|hs| hs.next_state != minecraft::NEXT_STATE_STATUS)
+    {
+        return;
+    }
+    let status_json = match status_override {
+        Some(ov) => Cow::Owned(override_status_json(status_json, ov)),
+        None => Cow::Borrowed(status_json),
+    };
+    let fut = serve_status_health(conn, captured, &status_json, max_bytes);
+    let res = if rt.handshake_timeout.is_zero() {
+        fut.await
+    } else {
+        time::timeout(rt.handshake_timeout, fut)
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("offline status: timeout")))
+    };
+    if let Err(err) = res {
+        tracing::debug!(sid = %sid, err = %err, "proxy: offline status failed");
+    }
+}
+
+/// Apply a route's `status_override` to a status JSON object. Invalid JSON is passed
+/// through unchanged.
+fn override_status_json(status_json: &str, ov: &config::StatusOverride) -> String {
+    let Ok(mut v) = serde_json::from_str::<serde_json::Value>(status_json) else {
+        return status_json.to_string();
+    };
+    if let Some(obj) = v.as_object_mut() {
+        if let Some(motd) = &ov.motd {
+            obj.insert("description".into(), serde_json::json!({ "text": motd }));
+        }
+        if let Some(max) = ov.max_players {
+            let players = obj
+                .entry("players")
+                .or_insert_with(|| serde_json::json!({ "online": 0 }));
+            if let Some(players) = players.as_object_mut() {
+                players.insert("max".into(), serde_json::json!(max));
+            }
+        }
+        if let Some(name) = &ov.version_name {
+            let version = obj
+                .entry("version")
+                .or_insert_with(|| serde_json::json!({}));
+            if let Some(version) = version.as_object_mut() {
+                version.insert("name".into(), serde_json::json!(name));
+            }
+        }
+    }
+    v.to_string()
+}
+
+/// Rewrite a Status Response frame at the start of `buf` with `ov`; None if `buf` does not
+/// start with a complete one.
+fn override_status_response(buf: &[u8], ov: &config::StatusOverride) -> Option<Vec<u8>> {
+    let (frame_len, json) = minecraft::parse_status_response(buf)?;
+    let mut out = minecraft::status_response_packet(&override_status_json(&json, ov));
+    out.extend_from_slice(&buf[frame_len..]);
+    Some(out)
+}
+
+/// Read the upstream's first reply to a status ping and pass it to the client with `ov`
+/// and then the route's response-phase middlewares applied. Client bytes (a late status
+/// request, the ping) keep flowing upstream meanwhile. A reply that isn't a Status
+/// Response is passed through without the override.
+async fn relay_status_response<C, U>(
+    client: &mut C,
+    upstream: &mut U,
+    ov: Option<&config::StatusOverride>,
+    middleware: &middleware::SharedMiddlewareChain,
+    client_meta: middleware::ClientMeta,
+    max_bytes: usize,
+) -> anyhow::Result<()>
+where
+    C: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + ?Sized,
+    U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    let mut from_client = [0u8; 1024];
+    let mut client_open = true;
+    while minecraft::split_packet(&buf).is_none() {
+        if buf.len() >= max_bytes {
+            if ov.is_some() {
+                anyhow::bail!("status response exceeds {max_bytes} bytes");
+            }
+            // Too large to hand to middlewares; relay it as is.
+            client.write_all(&buf).await?;
+            return Ok(());
+        }
+        tokio::select! {
+            n = upstream.read(&mut chunk) => {
+                let n = n?;
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            n = client.read(&mut from_client), if client_open => match n? {
+                0 => client_open = false,
+                n => upstream.write_all(&from_client[..n]).await?,
+            },
+        }
+    }
+    let mut out = ov
+        .and_then(|ov| override_status_response(&buf, ov))
+        .unwrap_or(buf);
+    if let Some(rw) = middleware.response(&out, client_meta) {
+        out = rw;
+    }
+    client.write_all(&out).await?;
+    Ok(())
+}
+
+/// Server list text for legacy pings, which Prism can't route by host.
+const LEGACY_PING_MOTD: &str = "This server requires a newer Minecraft version";
+
+/// Answer a pre-1.7 server list ping: hand it to the `default_upstream` no-route policy
+/// when configured, else reply with a legacy status.
+async fn handle_legacy_ping(
+    mut conn: TcpStream,
+    rt: &TcpRuntimeConfig,
+    opts: &TcpRoutingHandlerOptions,
+    sid: &str,
+    client: &str,
+    source: Option<SocketAddr>,
+) {
+    tracing::debug!(sid = %sid, client = %client, "proxy: legacy server list ping");
+    if let config::NoRoutePolicy::DefaultUpstream(_) = rt.on_no_route {
+        let mut prelude = [0u8; 3];
+        let n = conn.peek(&mut prelude).await.unwrap_or(0);
+        if conn.read_exact(&mut prelude[..n]).await.is_ok() {
+            handle_no_route(conn, &prelude[..n], rt, opts, sid, client, source).await;
+        }
+        return;
+    }
+    let reply = minecraft::legacy_status_packet("Prism", LEGACY_PING_MOTD, 0, 0);
+    if conn.write_all(&reply).await.is_ok() {
+        let _ = conn.shutdown().await;
+        // Drain the rest of the ping until the client hangs up, so closing with unread
+        // bytes doesn't reset the connection before the reply is read.
+        let _ = time::timeout(Duration::from_secs(1), async {
+            let mut sink = [0u8; 256];
+            while matches!(conn.read(&mut sink).await, Ok(n) if n > 0) {}
+        })
+        .await;
+    }
+}
+
+/// Disconnect reason shown to players who omit or mistype a route's `host_secret`.
+const HOST_SECRET_KICK_MESSAGE: &str = "You are not allowed to join this server";
+
+/// Disconnect message for logins refused by `max_connections`/`max_upstream_connections`.
+const CONNECTION_LIMIT_KICK_MESSAGE: &str = "Server is full, please try again later";
+
+/// Send a login disconnect with `message` if `captured` is a login handshake.
+///
+/// Only login handshakes can display a disconnect reason; other preludes get nothing.
+async fn kick_login(conn: &mut TcpStream, captured: &[u8], message: &str) {
+    if minecraft::parse_handshake(captured)
+        .is_some_and(|hs| hs.next_state == minecraft::NEXT_STATE_LOGIN)
+    {
+        let _ = conn
+            .write_all(&minecraft::login_disconnect_packet(message))
+            .await;
+    }
+}
+
+async fn handle_no_route(
+    mut conn: TcpStream,
+    captured: &[u8],
+    rt: &TcpRuntimeConfig,
+    opts: &TcpRoutingHandlerOptions,
+    sid: &str,
+    client: &str,
+    source: Option<SocketAddr>,
+) {
+    let reject = |reason| opts.sessions.record_rejection(client, reason, "");
+    match &rt.on_no_route {
+        config::NoRoutePolicy::Close => reject(telemetry::RejectReason::NoRoute),
+        config::NoRoutePolicy::Kick(message) => {
+            reject(telemetry::RejectReason::NoRoute);
+            kick_login(&mut conn, captured, message).await
+        }
+        config::NoRoutePolicy::DefaultUpstream(upstream) if !captured.is_empty() => {
+            let Some(_conn_permit) = opts.sessions.try_acquire_connection(rt.max_connections)
+            else {
+                tracing::warn!(sid = %sid, client = %client, limit = rt.max_connections, "proxy: max_connections reached");
+                reject(telemetry::RejectReason::ConnectionLimit);
+                let _ = conn.shutdown().await;
+                return;
+            };
+            let default_port = conn.local_addr().ok().map(|a| a.port());
+            let (mut up, upstream_used, _) = match timed_dial_upstream(
+                &opts.sessions,
+                upstream,
+                default_port,
+                rt,
+                opts.tunnel_manager.as_ref(),
+            )
+            .await
+            {
+                Ok(v) => v,
+                Err(err) => {
+                    tracing::warn!(sid = %sid, client = %client, upstream = %upstream, err = %err, "proxy: default upstream dial failed");
+                    reject(telemetry::RejectReason::UpstreamUnavailable);
+                    let _ = conn.shutdown().await;
+                    return;
+                }
+            };
+
+            let kill = opts.sessions.add(telemetry::SessionInfo {
+                id: sid.to_string(),
+                client: client.to_string(),
+                host: "".into(),
+                upstream: upstream_used.clone(),
+                started_at_unix_ms: telemetry::now_unix_ms(),
+                geo: opts.sessions.geo_lookup(client),
+            });
+
+            if let Err(err) = write_proxy_proto(rt.proxy_protocol, &mut *up, &conn, source).await {
+                tracing::warn!(sid=%sid, version=?rt.proxy_protocol, err=%err, "proxy: proxy_protocol write failed");
+                let _ = conn.shutdown().await;
+                opts.sessions.remove(sid);
+                return;
+            }
+
+            if let Err(err) = (*up).write_all(captured).await {
+                tracing::debug!(sid=%sid, err=%err, "proxy: failed writing prelude to default upstream");
+                let _ = conn.shutdown().await;
+                opts.sessions.remove(sid);
+                return;
+            }
+
+            let activity = Activity::new();
+            let res = until_killed(&kill, proxy_bidirectional(&mut conn, up, rt, &activity)).await;
+            opts.sessions
+                .finish(sid, activity.bytes(), close_reason(&res));
+            if let Err(err) = res {
+                tracing::debug!(sid=%sid, err=%err, "proxy: default upstream session ended with error");
+            }
+            return;
+        }
+        config::NoRoutePolicy::DefaultUpstream(_) => reject(telemetry::RejectReason::NoRoute),
+    }
+
+    let _ = conn.shutdown().await;
+}
+
+/// Run `sniff_prelude` under the handshake timeout. Returns the client address from a
+/// trusted PROXY header, or Err once the connection has been rejected and closed.
+async fn accept_prelude(
+    conn: &mut TcpStream,
+    peer: Option<SocketAddr>,
+    rt: &TcpRuntimeConfig,
+    sessions: &telemetry::SessionRegistry,
+) -> Result<Option<SocketAddr>, ()> {
+    let sniffed = if rt.handshake_timeout.is_zero() {
+        Ok(sniff_prelude(conn, peer, rt).await)
+    } else {
+        time::timeout(rt.handshake_timeout, sniff_prelude(conn, peer, rt)).await
+    };
+    let client = peer.map(|a| a.to_string()).unwrap_or_default();
+    match sniffed {
+        Ok(Ok(addr)) => return Ok(addr),
+        Ok(Err(err)) => {
+            tracing::warn!(client = %client, err = %err, "proxy: protocol sniffing rejected connection");
+            sessions.record_rejection(&client, telemetry::RejectReason::HandshakeInvalid, "");
+        }
+        Err(_) => {
+            tracing::debug!(client = %client, "proxy: handshake timeout while sniffing protocol");
+            sessions.record_rejection(&client, telemetry::RejectReason::HandshakeTimeout, "");
+        }
+    }
+    let _ = conn.shutdown().await;
+    Err(())
+}
+
+/// How long a fixed forwarding listener waits for a client's first bytes before assuming
+/// it sent no PROXY header. Front proxies write the header as soon as they connect.
+const FORWARD_PROXY_HEADER_WAIT: Duration = Duration::from_millis(500);
+
+/// [`accept_prelude`] for fixed forwarding listeners, whose clients may speak only after
+/// the upstream does (SSH, SMTP, ...). A client that sends nothing within
+/// [`FORWARD_PROXY_HEADER_WAIT`] has no header and is forwarded as is.
+async fn accept_forward_prelude(
+    conn: &mut TcpStream,
+    peer: Option<SocketAddr>,
+    rt: &TcpRuntimeConfig,
+    sessions: &telemetry::SessionRegistry,
+) -> Result<Option<SocketAddr>, ()> {
+    match time::timeout(FORWARD_PROXY_HEADER_WAIT, sniff::peek_proxy_header(conn)).await {
+        Err(_) | Ok(Ok(None)) => Ok(None),
+        Ok(_) => accept_prelude(conn, peer, rt, sessions).await,
+    }
+}
+
+/// Classify a routing connection by peeking at its first bytes. A PROXY protocol header
+/// is consumed when `peer` is in `trusted_cidrs` (and rejected otherwise); its client
+/// address is returned. Every other protocol is left for the capture loop.
+async fn sniff_prelude(
+    conn: &mut TcpStream,
+    peer: Option<SocketAddr>,
+    rt: &TcpRuntimeConfig,
+) -> anyhow::Result<Option<SocketAddr>> {
+    // Without full sniffing only a leading PROXY header is looked for.
+    let protocol = if rt.sniff_protocols {
+        sniff::peek_protocol(conn).await?
+    } else {
+        sniff::peek_proxy_header(conn)
+            .await?
+            .unwrap_or(sniff::Protocol::Unknown)
+    };
+    tracing::debug!(peer = ?peer, protocol = ?protocol, "proxy: sniffed connection protocol");
+    match protocol {
+        sniff::Protocol::ProxyV1 | sniff::Protocol::ProxyV2 => {
+            let trusted = peer.is_some_and(|p| rt.trusted_cidrs.iter().any(|n| n.contains(p.ip())));
+            if !trusted {
+                anyhow::bail!("PROXY protocol header from a peer outside trusted_cidrs");
+            }
+            sniff::read_proxy_header(conn, protocol).await
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Socket options applied to accepted clients and dialed upstreams.
+#[derive(Debug, Clone, Copy, Default)]
+struct TcpSocketOptions {
+    user_timeout: Duration,
+    nodelay: bool,
+    /// Zero leaves keepalive off.
+    keepalive: Duration,
+}
+
+fn apply_socket_options(conn: &TcpStream, opts: TcpSocketOptions) {
+    apply_tcp_user_timeout(conn, opts.user_timeout);
+    if opts.nodelay
+        && let Err(err) = conn.set_nodelay(true)
+    {
+        tracing::debug!(err = %err, "proxy: failed to set TCP_NODELAY");
+    }
+    if !opts.keepalive.is_zero() {
+        let keepalive = socket2::TcpKeepalive::new().with_time(opts.keepalive);
+        if let Err(err) = socket2::SockRef::from(conn).set_tcp_keepalive(&keepalive) {
+            tracing::debug!(err = %err, "proxy: failed to set TCP keepalive");
+        }
+    }
+}
+
+/// Set TCP_USER_TIMEOUT on `conn` so silently dead peers are dropped sooner than keepalive
+/// alone would. No-op for a zero timeout and on platforms without the option.
+fn apply_tcp_user_timeout(conn: &TcpStream, timeout: Duration) {
+    if timeout.is_zero() {
+        return;
+    }
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "fuchsia"))]
+    if let Err(err) = socket2::SockRef::from(conn).set_tcp_user_timeout(Some(timeout)) {
+        tracing::debug!(err = %err, "proxy: failed to set TCP_USER_TIMEOUT");
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "fuchsia")))]
+    let _ = conn;
+}
+
+async fn dial_tcp_stream(
+    addr: &str,
+    timeout: Duration,
+    ip_version: config::UpstreamIpVersion,
+    sockopts: TcpSocketOptions,
+) -> anyhow::Result<tunnel::transport::BoxedStream> {
+    let connect = async {
+        let resolved: Vec<std::net::SocketAddr> = tokio::net::lookup_host(addr)
+            .await
+            .with_context(|| format!("resolve {addr}"))?
+            .collect();
+        let addrs = filter_ip_version(resolved, ip_version);
+        if addrs.is_empty() {
+            if ip_version == config::UpstreamIpVersion::Auto {
+                anyhow::bail!("{addr} did not resolve to any address");
+            }
+            anyhow::bail!("{addr} has no {} address", ip_version.family_label());
+        }
+        Ok(connect_happy_eyeballs(addrs, CONNECTION_ATTEMPT_DELAY).await?)
+    };
+    let c = if timeout > Duration::from_millis(0) {
+        time::timeout(timeout, connect)
+            .await
             .with_context(|| format!("dial timeout {addr}"))??
     } else {
-        TcpStream::connect(addr).await?
+        connect.await?
     };
+    apply_socket_options(&c, sockopts);
     Ok(Box::new(c))
 }
 
+/// RFC 8305 "Connection Attempt Delay": how long an attempt gets before the next address
+/// is tried in parallel.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Happy Eyeballs (RFC 8305): try `addrs` with address families interleaved, starting a new
+/// attempt every `delay` (or as soon as one fails), and keep the first that connects.
+async fn connect_happy_eyeballs(
+    addrs: Vec<std::net::SocketAddr>,
+    delay: Duration,
+) -> std::io::Result<TcpStream> {
+    let mut pending = interleave_families(addrs).into_iter();
+    let mut attempts = tokio::task::JoinSet::new();
+    let mut last_err = None;
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(a) => {
+                    attempts.spawn(TcpStream::connect(a));
+                }
+                None => {
+                    return Err(last_err.unwrap_or_else(|| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, "no addresses")
+                    }));
+                }
+            }
+        }
+        tokio::select! {
+            Some(res) = attempts.join_next() => match res {
+                // Dropping the set aborts the slower attempts.
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(err)) => {
+                    last_err = Some(err);
+                    if let Some(a) = pending.next() {
+                        attempts.spawn(TcpStream::connect(a));
+                    }
+                }
+                Err(err) => last_err = Some(std::io::Error::other(err)),
+            },
+            _ = time::sleep(delay), if pending.len() > 0 => {
+                if let Some(a) = pending.next() {
+                    attempts.spawn(TcpStream::connect(a));
+                }
+            }
+        }
+    }
+}
+
+/// Alternate address families, starting with the resolver's first choice.
+fn interleave_families(addrs: Vec<std::net::SocketAddr>) -> Vec<std::net::SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (std::collections::VecDeque<_>, std::collections::VecDeque<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first_v6);
+    let mut out = Vec::with_capacity(preferred.len() + other.len());
+    loop {
+        match (preferred.pop_front(), other.pop_front()) {
+            (None, None) => return out,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Keep only resolved addresses of the configured family (all of them for `auto`).
+fn filter_ip_version(
+    addrs: Vec<std::net::SocketAddr>,
+    ip_version: config::UpstreamIpVersion,
+) -> Vec<std::net::SocketAddr> {
+    match ip_version {
+        config::UpstreamIpVersion::Auto => addrs,
+        config::UpstreamIpVersion::V4 => addrs.into_iter().filter(|a| a.is_ipv4()).collect(),
+        config::UpstreamIpVersion::V6 => addrs.into_iter().filter(|a| a.is_ipv6()).collect(),
+    }
+}
+
 fn should_rewrite_prelude(selected_upstream: &str) -> bool {
     let selected_upstream = selected_upstream.trim();
     !selected_upstream.is_empty()
@@ -721,13 +2168,27 @@ fn should_rewrite_prelude(selected_upstream: &str) -> bool {
             .is_some_and(|prefix| prefix.eq_ignore_ascii_case("tunnel:"))
 }
 
-async fn dial_upstream(
+/// `dial_upstream`, recording how long it took in the dial latency histogram.
+async fn timed_dial_upstream(
+    sessions: &telemetry::SessionRegistry,
     upstream: &str,
     default_port: Option<u16>,
-    timeout: Duration,
+    rt: &TcpRuntimeConfig,
+    tunnel_manager: Option<&Arc<tunnel::manager::Manager>>,
+) -> anyhow::Result<(tunnel::transport::BoxedStream, String, Option<String>)> {
+    let started = time::Instant::now();
+    let res = dial_upstream(upstream, default_port, rt, tunnel_manager).await;
+    sessions.latency().upstream_dial.observe(started.elapsed());
+    res
+}
+
+pub(crate) async fn dial_upstream(
+    upstream: &str,
+    default_port: Option<u16>,
+    rt: &TcpRuntimeConfig,
     tunnel_manager: Option<&Arc<tunnel::manager::Manager>>,
 ) -> anyhow::Result<(tunnel::transport::BoxedStream, String, Option<String>)> {
-    let mut addr = upstream.trim().to_string();
+    let addr = upstream.trim();
     if addr.is_empty() {
         anyhow::bail!("empty upstream");
     }
@@ -750,66 +2211,391 @@ async fn dial_upstream(
         return Ok((st, format!("tunnel:{service}"), masq));
     }
 
-    if let Some(p) = default_port
-        && upstream_needs_port(&addr)
-    {
-        addr = format!("{addr}:{p}");
+    if let Some(rest) = addr.strip_prefix(srv::SRV_PREFIX) {
+        let domain = rest.trim();
+        if domain.is_empty() {
+            anyhow::bail!("srv upstream missing domain");
+        }
+        let deadline = time::Instant::now() + rt.upstream_dial_timeout;
+        let port = default_port.unwrap_or(rt.default_minecraft_port);
+        let targets = srv::SrvResolver::system().targets(domain, port).await;
+        let (stream, target) = dial_srv_targets(addr, targets, deadline, rt).await?;
+        return Ok((stream, target, None));
+    }
+
+    let addr = upstream_label(addr, default_port);
+    let stream = dial_tcp_stream(
+        &addr,
+        rt.upstream_dial_timeout,
+        rt.upstream_ip_version,
+        rt.socket_options(),
+    )
+    .await?;
+    Ok((stream, addr, None))
+}
+
+/// Dial SRV `targets` in order until one connects. `upstream_dial_timeout` bounds the
+/// whole walk (ending at `deadline`), not each target.
+async fn dial_srv_targets(
+    addr: &str,
+    targets: Vec<String>,
+    deadline: time::Instant,
+    rt: &TcpRuntimeConfig,
+) -> anyhow::Result<(tunnel::transport::BoxedStream, String)> {
+    let mut last_err = None;
+    for target in targets {
+        // A zero dial timeout means unbounded, for the walk as for a single dial.
+        let timeout = if rt.upstream_dial_timeout.is_zero() {
+            Duration::ZERO
+        } else {
+            let left = deadline.saturating_duration_since(time::Instant::now());
+            if left.is_zero() {
+                return Err(last_err.unwrap_or_else(|| anyhow::anyhow!("dial timeout {addr}")));
+            }
+            left
+        };
+        match dial_tcp_stream(
+            &target,
+            timeout,
+            rt.upstream_ip_version,
+            rt.socket_options(),
+        )
+        .await
+        {
+            Ok(stream) => return Ok((stream, target)),
+            Err(err) => {
+                tracing::debug!(upstream = %addr, target = %target, err = %err, "proxy: srv target dial failed");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("{addr} has no targets")))
+}
+
+/// Ways a session is cut short by Prism itself rather than by either peer.
+#[derive(Debug, thiserror::Error)]
+enum SessionEnd {
+    #[error("killed via admin API")]
+    Killed,
+    #[error("idle timeout")]
+    IdleTimeout,
+}
+
+/// Why a TCP session's copy loop returned, for `SessionRegistry::finish`.
+fn close_reason<T>(res: &anyhow::Result<T>) -> telemetry::CloseReason {
+    match res {
+        Ok(_) => telemetry::CloseReason::Closed,
+        Err(err) => match err.downcast_ref::<SessionEnd>() {
+            Some(SessionEnd::Killed) => telemetry::CloseReason::Killed,
+            Some(SessionEnd::IdleTimeout) => telemetry::CloseReason::IdleTimeout,
+            None => telemetry::CloseReason::Error,
+        },
     }
+}
 
-    Ok((dial_tcp_stream(&addr, timeout).await?, addr, None))
+/// Run a session until it ends on its own or is killed via `POST /sessions/{id}/kill`.
+async fn until_killed<T>(
+    kill: &tokio::sync::Notify,
+    session: impl std::future::Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    tokio::select! {
+        res = session => res,
+        _ = kill.notified() => Err(SessionEnd::Killed.into()),
+    }
 }
 
+/// Copy a session in both directions until it ends; `activity` keeps the bytes moved so
+/// far, so the caller can read them however the copy ended.
 async fn proxy_bidirectional(
     client: &mut TcpStream,
     mut upstream: tunnel::transport::BoxedStream,
-    buffer_size: usize,
-    idle_timeout: Duration,
-) -> anyhow::Result<(u64, u64)> {
-    // Apply optional idle timeout by bounding the whole copy operation.
+    rt: &TcpRuntimeConfig,
+    activity: &Activity,
+) -> anyhow::Result<()> {
     let copy_fut = async {
-        let (a, b) = tokio::io::copy_bidirectional(client, &mut *upstream).await?;
-        Ok::<(u64, u64), std::io::Error>((a, b))
+        if rt.half_close_propagation && rt.io_op_timeout.is_zero() {
+            // Forwards each side's EOF as a write shutdown on the other and keeps copying
+            // the remaining direction until it closes too.
+            copy_both_ways(client, &mut *upstream, copy_buffer_size(rt), activity).await
+        } else {
+            copy_with_deadlines(client, &mut *upstream, rt, activity).await
+        }
     };
 
-    let (ingress, egress) = if idle_timeout > Duration::from_millis(0) {
-        time::timeout(idle_timeout, copy_fut)
-            .await
-            .context("idle timeout")??
+    // The idle timeout only fires after no bytes moved in either direction for that long.
+    if rt.idle_timeout > Duration::from_millis(0) {
+        tokio::select! {
+            res = copy_fut => res?,
+            _ = activity.idle_for(rt.idle_timeout) => return Err(SessionEnd::IdleTimeout.into()),
+        }
     } else {
         copy_fut.await?
-    };
-
-    // `copy_bidirectional` doesn't allow tuning buffer sizes; keep the field for future improvements.
-    let _ = buffer_size;
+    }
 
     // Best-effort shutdown.
     let _ = (*upstream).shutdown().await;
-    Ok((ingress, egress))
+    if !rt.half_close_propagation {
+        let _ = client.shutdown().await;
+    }
+    Ok(())
 }
 
-async fn write_proxy_proto_v2(
-    upstream: &mut (dyn tokio::io::AsyncWrite + Send + Unpin),
-    client: &TcpStream,
-) -> anyhow::Result<()> {
-    use std::net::{IpAddr, SocketAddr};
+/// Bytes copied so far and the time of the last one, shared by both halves of a session.
+struct Activity {
+    start: time::Instant,
+    last_ms: std::sync::atomic::AtomicU64,
+    /// Client to upstream.
+    ingress: std::sync::atomic::AtomicU64,
+    /// Upstream to client.
+    egress: std::sync::atomic::AtomicU64,
+}
 
-    let src: SocketAddr = client.peer_addr().context("proxy: peer_addr")?;
-    let dst: SocketAddr = client.local_addr().context("proxy: local_addr")?;
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: time::Instant::now(),
+            last_ms: std::sync::atomic::AtomicU64::new(0),
+            ingress: std::sync::atomic::AtomicU64::new(0),
+            egress: std::sync::atomic::AtomicU64::new(0),
+        }
+    }
 
-    // Signature: "\r\n\r\n\0\r\nQUIT\n"
-    const SIG: [u8; 12] = [13, 10, 13, 10, 0, 13, 10, 81, 85, 73, 84, 10];
+    /// Record `n` bytes copied; `from_client` picks the direction.
+    fn moved(&self, from_client: bool, n: usize) {
+        let ms = self.start.elapsed().as_millis() as u64;
+        self.last_ms.store(ms, std::sync::atomic::Ordering::Relaxed);
+        let counter = if from_client {
+            &self.ingress
+        } else {
+            &self.egress
+        };
+        counter.fetch_add(n as u64, std::sync::atomic::Ordering::Relaxed);
+    }
 
-    let mut out = Vec::with_capacity(16 + 36);
-    out.extend_from_slice(&SIG);
+    /// (ingress, egress) bytes copied so far.
+    fn bytes(&self) -> (u64, u64) {
+        (
+            self.ingress.load(std::sync::atomic::Ordering::Relaxed),
+            self.egress.load(std::sync::atomic::Ordering::Relaxed),
+        )
+    }
 
-    // ver=2 (0x2) | cmd=PROXY (0x1)
-    out.push(0x21);
+    /// Resolves once nothing has been copied for `limit`.
+    async fn idle_for(&self, limit: Duration) {
+        loop {
+            let last = self.start
+                + Duration::from_millis(self.last_ms.load(std::sync::atomic::Ordering::Relaxed));
+            let deadline = last + limit;
+            if time::Instant::now() >= deadline {
+                return;
+            }
+            time::sleep_until(deadline).await;
+        }
+    }
+}
 
-    match (src.ip(), dst.ip()) {
-        (IpAddr::V4(sip), IpAddr::V4(dip)) => {
-            // fam=INET(0x1) | proto=STREAM(0x1)
-            out.push(0x11);
-            out.extend_from_slice(&(12u16).to_be_bytes());
+fn copy_buffer_size(rt: &TcpRuntimeConfig) -> usize {
+    if rt.buffer_size == 0 {
+        32 * 1024
+    } else {
+        rt.buffer_size
+    }
+}
+
+/// Copy both directions concurrently through `buffer_size` buffers, propagating each
+/// side's EOF as a write shutdown on the other. Bytes are counted into `activity`.
+async fn copy_both_ways(
+    client: &mut TcpStream,
+    upstream: &mut (dyn tunnel::transport::AsyncStream + Unpin + Send),
+    buffer_size: usize,
+    activity: &Activity,
+) -> std::io::Result<()> {
+    async fn copy_half(
+        r: &mut (impl tokio::io::AsyncRead + Unpin),
+        w: &mut (impl tokio::io::AsyncWrite + Unpin),
+        buffer_size: usize,
+        activity: &Activity,
+        from_client: bool,
+    ) -> std::io::Result<()> {
+        let mut buf = vec![0u8; buffer_size];
+        loop {
+            let n = r.read(&mut buf).await?;
+            if n == 0 {
+                w.shutdown().await?;
+                return Ok(());
+            }
+            w.write_all(&buf[..n]).await?;
+            activity.moved(from_client, n);
+        }
+    }
+
+    let (mut cr, mut cw) = client.split();
+    let (mut ur, mut uw) = tokio::io::split(upstream);
+    tokio::try_join!(
+        copy_half(&mut cr, &mut uw, buffer_size, activity, true),
+        copy_half(&mut ur, &mut cw, buffer_size, activity, false),
+    )?;
+    Ok(())
+}
+
+/// Copy in both directions with the knobs `copy_both_ways` lacks:
+///
+/// - without `half_close_propagation`, stop at the first EOF from either side (for backends
+///   that misbehave with half-open connections; the caller closes both sides afterwards);
+/// - with `io_op_timeout`, abort when waiting for data or flushing a chunk takes longer
+///   than that, so a peer that hangs mid-transfer is noticed before the idle timeout.
+async fn copy_with_deadlines(
+    client: &mut TcpStream,
+    upstream: &mut (dyn tunnel::transport::AsyncStream + Unpin + Send),
+    rt: &TcpRuntimeConfig,
+    activity: &Activity,
+) -> std::io::Result<()> {
+    let buffer_size = copy_buffer_size(rt);
+    let mut cbuf = vec![0u8; buffer_size];
+    let mut ubuf = vec![0u8; buffer_size];
+    let (mut client_open, mut upstream_open) = (true, true);
+
+    while client_open || upstream_open {
+        let read = async {
+            tokio::select! {
+                n = client.read(&mut cbuf), if client_open => (true, n),
+                n = upstream.read(&mut ubuf), if upstream_open => (false, n),
+            }
+        };
+        let (from_client, n) = with_io_deadline(rt.io_op_timeout, read).await?;
+        let n = n?;
+        if n == 0 {
+            if !rt.half_close_propagation {
+                break;
+            }
+            if from_client {
+                client_open = false;
+                with_io_deadline(rt.io_op_timeout, upstream.shutdown()).await??;
+            } else {
+                upstream_open = false;
+                with_io_deadline(rt.io_op_timeout, client.shutdown()).await??;
+            }
+            continue;
+        }
+        if from_client {
+            with_io_deadline(rt.io_op_timeout, upstream.write_all(&cbuf[..n])).await??;
+        } else {
+            with_io_deadline(rt.io_op_timeout, client.write_all(&ubuf[..n])).await??;
+        }
+        activity.moved(from_client, n);
+    }
+    Ok(())
+}
+
+/// Run `op`, failing with `TimedOut` if it takes longer than `limit` (zero = no limit).
+async fn with_io_deadline<T>(
+    limit: Duration,
+    op: impl std::future::Future<Output = T>,
+) -> std::io::Result<T> {
+    if limit.is_zero() {
+        return Ok(op.await);
+    }
+    time::timeout(limit, op).await.map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("io_op_timeout ({limit:?}) exceeded"),
+        )
+    })
+}
+
+/// Write a PROXY header for `client`; `source` overrides the socket peer as the
+/// reported client address.
+async fn write_proxy_proto(
+    version: config::ProxyProtocol,
+    upstream: &mut (dyn tokio::io::AsyncWrite + Send + Unpin),
+    client: &TcpStream,
+    source: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    match version {
+        config::ProxyProtocol::None => Ok(()),
+        config::ProxyProtocol::V1 => write_proxy_proto_v1(upstream, client, source).await,
+        config::ProxyProtocol::V2 => write_proxy_proto_v2(upstream, client, source).await,
+    }
+}
+
+/// The PROXY header for a connection Prism opens on its own behalf (health probes): v1
+/// `UNKNOWN`, or a v2 `LOCAL` command, so the upstream keeps the socket's own addresses.
+pub(crate) fn proxy_local_header(version: config::ProxyProtocol) -> &'static [u8] {
+    match version {
+        config::ProxyProtocol::None => b"",
+        config::ProxyProtocol::V1 => b"PROXY UNKNOWN\r\n",
+        // Signature, ver=2 | cmd=LOCAL, fam=UNSPEC, zero-length addresses.
+        config::ProxyProtocol::V2 => b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00",
+    }
+}
+
+async fn write_proxy_proto_v1(
+    upstream: &mut (dyn tokio::io::AsyncWrite + Send + Unpin),
+    client: &TcpStream,
+    source: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    let src: SocketAddr = match source {
+        Some(addr) => addr,
+        None => client.peer_addr().context("proxy: peer_addr")?,
+    };
+    let dst: SocketAddr = client.local_addr().context("proxy: local_addr")?;
+
+    upstream
+        .write_all(proxy_v1_line(src, dst).as_bytes())
+        .await
+        .context("proxy: write pp1")?;
+    upstream.flush().await.ok();
+    Ok(())
+}
+
+/// The PROXY v1 text header; mixed address families are reported as `UNKNOWN`.
+fn proxy_v1_line(src: SocketAddr, dst: SocketAddr) -> String {
+    match (src, dst) {
+        (SocketAddr::V4(s), SocketAddr::V4(d)) => format!(
+            "PROXY TCP4 {} {} {} {}\r\n",
+            s.ip(),
+            d.ip(),
+            s.port(),
+            d.port()
+        ),
+        (SocketAddr::V6(s), SocketAddr::V6(d)) => format!(
+            "PROXY TCP6 {} {} {} {}\r\n",
+            s.ip(),
+            d.ip(),
+            s.port(),
+            d.port()
+        ),
+        _ => "PROXY UNKNOWN\r\n".to_string(),
+    }
+}
+
+async fn write_proxy_proto_v2(
+    upstream: &mut (dyn tokio::io::AsyncWrite + Send + Unpin),
+    client: &TcpStream,
+    source: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    use std::net::IpAddr;
+
+    let src: SocketAddr = match source {
+        Some(addr) => addr,
+        None => client.peer_addr().context("proxy: peer_addr")?,
+    };
+    let dst: SocketAddr = client.local_addr().context("proxy: local_addr")?;
+
+    // Signature: "\r\n\r\n\0\r\nQUIT\n"
+    const SIG: [u8; 12] = [13, 10, 13, 10, 0, 13, 10, 81, 85, 73, 84, 10];
+
+    let mut out = Vec::with_capacity(16 + 36);
+    out.extend_from_slice(&SIG);
+
+    // ver=2 (0x2) | cmd=PROXY (0x1)
+    out.push(0x21);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(sip), IpAddr::V4(dip)) => {
+            // fam=INET(0x1) | proto=STREAM(0x1)
+            out.push(0x11);
+            out.extend_from_slice(&(12u16).to_be_bytes());
             out.extend_from_slice(&sip.octets());
             out.extend_from_slice(&dip.octets());
             out.extend_from_slice(&src.port().to_be_bytes());
@@ -836,6 +2622,19 @@ async fn write_proxy_proto_v2(
     Ok(())
 }
 
+/// The label a dial of `upstream` reports: the address with `default_port` filled in,
+/// or `tunnel:<service>`.
+fn upstream_label(upstream: &str, default_port: Option<u16>) -> String {
+    let addr = upstream.trim();
+    if let Some(rest) = addr.strip_prefix("tunnel:") {
+        return format!("tunnel:{}", rest.trim());
+    }
+    match default_port {
+        Some(p) if upstream_needs_port(addr) => format!("{addr}:{p}"),
+        _ => addr.to_string(),
+    }
+}
+
 fn upstream_needs_port(addr: &str) -> bool {
     // Very small heuristic: if there is no ':' after the last ']' (IPv6 brackets), assume missing port.
     let s = addr.trim();
@@ -854,11 +2653,2799 @@ fn upstream_needs_port(addr: &str) -> bool {
 mod tests {
     use super::*;
 
+    fn login_handshake(host: &str) -> Vec<u8> {
+        let mut payload = Vec::new();
+        minecraft::write_varint(763, &mut payload);
+        minecraft::write_string(host, &mut payload);
+        payload.extend_from_slice(&25565u16.to_be_bytes());
+        minecraft::write_varint(minecraft::NEXT_STATE_LOGIN, &mut payload);
+        minecraft::packet(0x00, &payload)
+    }
+
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let (connected, accepted) = tokio::join!(TcpStream::connect(addr), ln.accept());
+        (connected.expect("connect"), accepted.expect("accept").0)
+    }
+
+    fn runtime_with(half_close_propagation: bool, io_op_timeout: Duration) -> TcpRuntimeConfig {
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.half_close_propagation = half_close_propagation;
+        rt.io_op_timeout = io_op_timeout;
+        rt
+    }
+
+    /// Run `proxy_bidirectional` between a client and a backend; returns (client, backend, task).
+    async fn spawn_proxied_pair(
+        rt: TcpRuntimeConfig,
+    ) -> (
+        TcpStream,
+        TcpStream,
+        tokio::task::JoinHandle<anyhow::Result<(u64, u64)>>,
+    ) {
+        let (client, mut conn) = tcp_pair().await;
+        let (up, backend) = tcp_pair().await;
+        let task = tokio::spawn(async move {
+            let up: tunnel::transport::BoxedStream = Box::new(up);
+            let activity = Activity::new();
+            proxy_bidirectional(&mut conn, up, &rt, &activity)
+                .await
+                .map(|()| activity.bytes())
+        });
+        (client, backend, task)
+    }
+
+    #[tokio::test]
+    async fn half_close_from_client_keeps_upstream_direction_open() {
+        let (mut client, mut backend, task) =
+            spawn_proxied_pair(runtime_with(true, Duration::ZERO)).await;
+
+        client.write_all(b"ping").await.expect("write");
+        client.shutdown().await.expect("shutdown");
+        assert_eq!(read_to_end(&mut backend).await, b"ping");
+
+        backend.write_all(b"pong").await.expect("write back");
+        backend.shutdown().await.expect("shutdown");
+        assert_eq!(read_to_end(&mut client).await, b"pong");
+        task.await.expect("proxy task").expect("proxy");
+    }
+
+    #[tokio::test]
+    async fn bulk_transfer_counts_every_byte_with_small_buffers() {
+        const LEN: usize = 4 * 1024 * 1024;
+        let mut rt = runtime_with(true, Duration::ZERO);
+        rt.buffer_size = 4096;
+        let (mut client, mut backend, task) = spawn_proxied_pair(rt).await;
+
+        let up: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+        let down: Vec<u8> = (0..LEN + 7).map(|i| (i % 241) as u8).collect();
+        let (mut cr, mut cw) = client.split();
+        let (mut br, mut bw) = backend.split();
+        let (_, _, from_client, from_backend) = tokio::join!(
+            async {
+                cw.write_all(&up).await.expect("client write");
+                cw.shutdown().await.expect("client shutdown");
+            },
+            async {
+                bw.write_all(&down).await.expect("backend write");
+                bw.shutdown().await.expect("backend shutdown");
+            },
+            async {
+                let mut got = Vec::new();
+                br.read_to_end(&mut got).await.expect("backend read");
+                got
+            },
+            async {
+                let mut got = Vec::new();
+                cr.read_to_end(&mut got).await.expect("client read");
+                got
+            },
+        );
+        assert!(from_client == up, "client -> backend payload corrupted");
+        assert!(from_backend == down, "backend -> client payload corrupted");
+        assert_eq!(
+            task.await.expect("proxy task").expect("proxy"),
+            (LEN as u64, LEN as u64 + 7)
+        );
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_is_reset_by_traffic() {
+        let mut rt = runtime_with(true, Duration::ZERO);
+        rt.idle_timeout = Duration::from_millis(300);
+        let (mut client, mut backend, task) = spawn_proxied_pair(rt).await;
+
+        // A keepalive every 100ms keeps the session up for 4x the idle timeout.
+        for _ in 0..12 {
+            client.write_all(b"k").await.expect("keepalive");
+            let mut b = [0u8; 1];
+            backend
+                .read_exact(&mut b)
+                .await
+                .expect("keepalive forwarded");
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(!task.is_finished(), "active session hit the idle timeout");
+
+        // Once quiet, it is torn down.
+        let err = time::timeout(Duration::from_secs(2), task)
+            .await
+            .expect("idle session should time out")
+            .expect("proxy task")
+            .expect_err("idle timeout");
+        assert!(err.to_string().contains("idle timeout"), "{err}");
+    }
+
     #[test]
-    fn skips_prelude_rewrite_for_tunnel_labels() {
-        assert!(!should_rewrite_prelude("tunnel:cti"));
-        assert!(!should_rewrite_prelude("  TUNNEL:monifactory  "));
-        assert!(!should_rewrite_prelude(""));
-        assert!(should_rewrite_prelude("backend.local:25566"));
+    fn proxy_v1_line_covers_both_families() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert_eq!(
+            proxy_v1_line(addr("203.0.113.7:51234"), addr("10.0.0.1:25565")),
+            "PROXY TCP4 203.0.113.7 10.0.0.1 51234 25565\r\n"
+        );
+        assert_eq!(
+            proxy_v1_line(addr("[2001:db8::7]:51234"), addr("[2001:db8::1]:25565")),
+            "PROXY TCP6 2001:db8::7 2001:db8::1 51234 25565\r\n"
+        );
+        assert_eq!(
+            proxy_v1_line(addr("203.0.113.7:51234"), addr("[2001:db8::1]:25565")),
+            "PROXY UNKNOWN\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn half_close_is_kept_when_io_op_timeout_is_set() {
+        // io_op_timeout switches to the custom copy loop; half-close must still work there.
+        let rt = runtime_with(true, Duration::from_secs(5));
+        let (mut client, mut backend, task) = spawn_proxied_pair(rt).await;
+
+        client.write_all(b"ping").await.expect("write");
+        client.shutdown().await.expect("shutdown");
+        assert_eq!(read_to_end(&mut backend).await, b"ping");
+
+        backend.write_all(b"pong").await.expect("write back");
+        backend.shutdown().await.expect("shutdown");
+        assert_eq!(read_to_end(&mut client).await, b"pong");
+        assert_eq!(task.await.expect("proxy task").expect("proxy"), (4, 4));
+    }
+
+    #[tokio::test]
+    async fn io_op_timeout_aborts_stalled_transfer() {
+        let rt = runtime_with(true, Duration::from_millis(200));
+        let (mut client, mut backend, task) = spawn_proxied_pair(rt).await;
+
+        // The backend sends one byte of a larger payload, then hangs without closing.
+        backend.write_all(b"r").await.expect("write");
+        let mut first = [0u8; 1];
+        client.read_exact(&mut first).await.expect("first byte");
+
+        let res = time::timeout(Duration::from_secs(2), task)
+            .await
+            .expect("session should abort well before the test timeout")
+            .expect("proxy task");
+        let err = res.expect_err("stalled transfer must fail");
+        let io = err.downcast_ref::<std::io::Error>().expect("io error");
+        assert_eq!(io.kind(), std::io::ErrorKind::TimedOut);
+        drop(backend);
+    }
+
+    #[tokio::test]
+    async fn half_close_from_upstream_keeps_client_direction_open() {
+        let (mut client, mut backend, task) =
+            spawn_proxied_pair(runtime_with(true, Duration::ZERO)).await;
+
+        backend.write_all(b"motd").await.expect("write");
+        backend.shutdown().await.expect("shutdown");
+        assert_eq!(read_to_end(&mut client).await, b"motd");
+
+        client.write_all(b"bye").await.expect("write back");
+        client.shutdown().await.expect("shutdown");
+        assert_eq!(read_to_end(&mut backend).await, b"bye");
+        task.await.expect("proxy task").expect("proxy");
+    }
+
+    #[tokio::test]
+    async fn half_close_disabled_tears_down_both_sides() {
+        let (mut client, mut backend, task) =
+            spawn_proxied_pair(runtime_with(false, Duration::ZERO)).await;
+
+        client.write_all(b"ping").await.expect("write");
+        client.shutdown().await.expect("shutdown");
+        assert_eq!(read_to_end(&mut backend).await, b"ping");
+        // The client sees EOF even though the backend never closed its side.
+        assert!(read_to_end(&mut client).await.is_empty());
+        task.await.expect("proxy task").expect("proxy");
+    }
+
+    /// Routes on the Minecraft handshake host (stand-in for the WAT middleware).
+    struct HandshakeChain;
+
+    impl middleware::MiddlewareChain for HandshakeChain {
+        fn name(&self) -> &str {
+            "handshake"
+        }
+
+        fn parse(
+            &self,
+            prelude: &[u8],
+            _client: middleware::ClientMeta,
+        ) -> Result<(String, Option<Vec<u8>>), middleware::MiddlewareError> {
+            match minecraft::parse_handshake(prelude) {
+                Some(hs) => Ok((hs.server_address, None)),
+                None => Err(middleware::MiddlewareError::NeedMoreData),
+            }
+        }
+
+        fn rewrite(
+            &self,
+            _prelude: &[u8],
+            _selected_upstream: &str,
+            _client: middleware::ClientMeta,
+        ) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_minecraft_rejections_are_counted() {
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["127.0.0.1:1".into()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            strict_minecraft: true,
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let handled = tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        // An HTTP request is closed on the first read, without waiting for more bytes.
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(b"GET / HTTP/1.1\r\n").await.expect("write");
+        assert!(read_to_end(&mut c).await.is_empty());
+        handled.await.expect("handler");
+        assert_eq!(sessions.session_metrics().non_minecraft_rejected(), 1);
+        assert!(
+            sessions
+                .render_prometheus()
+                .contains("prism_non_minecraft_rejected_total 1\n")
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn observe_mode_logs_resolution_without_dialing() {
+        use tracing::instrument::WithSubscriber;
+
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let backend_addr = backend.local_addr().expect("addr");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend_addr.to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: true,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let logs = LogBuf::default();
+        let subscriber = {
+            let logs = logs.clone();
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::INFO)
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish()
+        };
+        let handled = tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).with_subscriber(subscriber).await;
+        });
+
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&login_handshake("play.example.com"))
+            .await
+            .expect("write");
+        assert!(read_to_end(&mut c).await.is_empty());
+        handled.await.expect("handler");
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).expect("utf8");
+        assert!(logs.contains("observe: route resolved"), "logs: {logs}");
+        assert!(logs.contains("play.example.com"), "logs: {logs}");
+        assert!(logs.contains(&backend_addr.to_string()), "logs: {logs}");
+        assert!(sessions.snapshot().is_empty());
+        assert!(
+            time::timeout(Duration::from_millis(200), backend.accept())
+                .await
+                .is_err(),
+            "observe mode must not dial upstream"
+        );
+    }
+
+    /// Accept one connection on an ephemeral port and run it through `handle_routing`
+    /// with an empty route table, so every prelude is unmatched.
+    async fn spawn_unrouted_listener(on_no_route: config::NoRoutePolicy) -> std::net::SocketAddr {
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.on_no_route = on_no_route;
+        spawn_unrouted_listener_with(rt, Arc::new(telemetry::SessionRegistry::new())).await
+    }
+
+    async fn spawn_unrouted_listener_with(
+        rt: TcpRuntimeConfig,
+        sessions: Arc<telemetry::SessionRegistry>,
+    ) -> std::net::SocketAddr {
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![])),
+            sessions,
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+        addr
+    }
+
+    async fn read_to_end(conn: &mut TcpStream) -> Vec<u8> {
+        let mut out = Vec::new();
+        time::timeout(Duration::from_secs(5), conn.read_to_end(&mut out))
+            .await
+            .expect("read timeout")
+            .expect("read");
+        out
+    }
+
+    #[test]
+    fn filter_ip_version_picks_family_from_dual_stack_results() {
+        let dual: Vec<std::net::SocketAddr> = vec![
+            "[2001:db8::10]:25565".parse().unwrap(),
+            "192.0.2.10:25565".parse().unwrap(),
+            "[2001:db8::11]:25565".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            filter_ip_version(dual.clone(), config::UpstreamIpVersion::Auto),
+            dual
+        );
+        assert_eq!(
+            filter_ip_version(dual.clone(), config::UpstreamIpVersion::V4),
+            vec![dual[1]]
+        );
+        assert_eq!(
+            filter_ip_version(dual.clone(), config::UpstreamIpVersion::V6),
+            vec![dual[0], dual[2]]
+        );
+    }
+
+    #[tokio::test]
+    async fn upstream_ip_version_filters_dial_targets() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let addr = backend.local_addr().expect("addr").to_string();
+        let timeout = Duration::from_secs(2);
+
+        dial_tcp_stream(
+            &addr,
+            timeout,
+            config::UpstreamIpVersion::V4,
+            TcpSocketOptions::default(),
+        )
+        .await
+        .expect("v4 dial");
+        let err = dial_tcp_stream(
+            &addr,
+            timeout,
+            config::UpstreamIpVersion::V6,
+            TcpSocketOptions::default(),
+        )
+        .await
+        .err()
+        .expect("v6 dial must fail for an IPv4-only upstream");
+        assert!(err.to_string().contains("no IPv6 address"), "{err}");
+    }
+
+    #[test]
+    fn interleave_families_alternates_from_the_first_family() {
+        let addrs: Vec<std::net::SocketAddr> = vec![
+            "[2001:db8::1]:25565".parse().unwrap(),
+            "[2001:db8::2]:25565".parse().unwrap(),
+            "[2001:db8::3]:25565".parse().unwrap(),
+            "192.0.2.1:25565".parse().unwrap(),
+        ];
+        let got: Vec<String> = interleave_families(addrs)
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(
+            got,
+            [
+                "[2001:db8::1]:25565",
+                "192.0.2.1:25565",
+                "[2001:db8::2]:25565",
+                "[2001:db8::3]:25565"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn happy_eyeballs_skips_a_blackholed_address() {
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let good = ln.local_addr().expect("addr");
+        // TEST-NET-1 is never routed, so the first attempt stalls (or fails right away).
+        let dead: std::net::SocketAddr = "192.0.2.1:25565".parse().unwrap();
+
+        let started = std::time::Instant::now();
+        let conn = time::timeout(
+            Duration::from_secs(5),
+            connect_happy_eyeballs(vec![dead, good], Duration::from_millis(50)),
+        )
+        .await
+        .expect("happy eyeballs must not wait for the dead address")
+        .expect("connect");
+        assert_eq!(conn.peer_addr().expect("peer"), good);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn srv_targets_share_one_dial_deadline() {
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.upstream_dial_timeout = Duration::from_millis(200);
+        // TEST-NET-1 targets stall (or fail right away); five of them must not take five
+        // dial timeouts.
+        let targets = (1..=5).map(|i| format!("192.0.2.{i}:25565")).collect();
+
+        let started = time::Instant::now();
+        let deadline = started + rt.upstream_dial_timeout;
+        let res = dial_srv_targets("srv:example.com", targets, deadline, &rt).await;
+        assert!(res.is_err());
+        assert!(started.elapsed() < Duration::from_millis(600));
+    }
+
+    #[tokio::test]
+    async fn per_ip_session_limit_rejects_and_frees_slots() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let backend_addr = backend.local_addr().expect("addr");
+
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.max_sessions_per_ip = 1;
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend_addr.to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_forward(conn, opts.clone()));
+            }
+        });
+        let accept_backend = || async {
+            time::timeout(Duration::from_secs(5), backend.accept())
+                .await
+                .expect("accept timeout")
+                .expect("accept")
+                .0
+        };
+
+        let first = TcpStream::connect(addr).await.expect("connect first");
+        let first_up = accept_backend().await;
+
+        let mut second = TcpStream::connect(addr).await.expect("connect second");
+        assert!(read_to_end(&mut second).await.is_empty());
+
+        // Closing the first session frees its slot.
+        drop(first);
+        drop(first_up);
+        let ip: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+        time::timeout(Duration::from_secs(5), async {
+            while sessions.ip_sessions(ip) != 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("slot released");
+
+        let _third = TcpStream::connect(addr).await.expect("connect third");
+        let _third_up = accept_backend().await;
+        assert_eq!(sessions.ip_sessions(ip), 1);
+    }
+
+    #[tokio::test]
+    async fn killed_session_is_torn_down() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let backend_addr = backend.local_addr().expect("addr");
+
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend_addr.to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_forward(conn, opts).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.expect("connect");
+        let (mut up, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+        client.write_all(b"ping").await.expect("write");
+        let mut buf = [0u8; 4];
+        up.read_exact(&mut buf).await.expect("read");
+        assert_eq!(&buf, b"ping");
+
+        let sid = time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(s) = sessions.snapshot().pop() {
+                    return s.id;
+                }
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session registered");
+        assert!(sessions.kill(&sid));
+
+        // Both sides are closed and the session is gone.
+        assert!(read_to_end(&mut client).await.is_empty());
+        assert!(read_to_end(&mut up).await.is_empty());
+        time::timeout(Duration::from_secs(5), async {
+            while !sessions.snapshot().is_empty() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session removed");
+        assert!(!sessions.kill(&sid));
+    }
+
+    #[tokio::test]
+    async fn upstream_dials_are_timed() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_forward(conn, opts).await;
+        });
+
+        let _client = TcpStream::connect(addr).await.expect("connect");
+        let _up = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+
+        // Recorded once the dial returns, which may trail the backend's accept.
+        time::timeout(Duration::from_secs(5), async {
+            while sessions.latency().upstream_dial.count() == 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("dial recorded");
+        let text = sessions.latency().render_prometheus();
+        assert!(text.contains("# TYPE prism_upstream_dial_seconds histogram"));
+        assert!(text.contains("prism_upstream_dial_seconds_bucket{le=\"+Inf\"} 1"));
+        assert!(text.contains("prism_upstream_dial_seconds_count 1"));
+        assert!(text.contains("prism_handshake_seconds_count 0"));
+    }
+
+    #[tokio::test]
+    async fn finished_sessions_record_duration_and_bytes() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_forward(conn, opts).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.expect("connect");
+        let (mut up, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+        client.write_all(b"ping").await.expect("write");
+        let mut buf = [0u8; 4];
+        up.read_exact(&mut buf).await.expect("read");
+        up.write_all(b"pong!").await.expect("write");
+        client.read_exact(&mut buf).await.expect("read");
+        drop(up);
+        assert_eq!(read_to_end(&mut client).await, b"!");
+        drop(client);
+
+        let summary = time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(s) = sessions.recent_sessions().pop() {
+                    return s;
+                }
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session finished");
+        assert_eq!(summary.ingress_bytes, 4);
+        assert_eq!(summary.egress_bytes, 5);
+        assert!(summary.ended_at_unix_ms >= summary.started_at_unix_ms);
+        assert!(sessions.snapshot().is_empty());
+
+        assert_eq!(sessions.session_metrics().duration.count(), 1);
+        let text = sessions.render_prometheus();
+        assert!(text.contains("prism_session_duration_seconds_count 1"));
+        assert!(text.contains("prism_session_bytes_total{direction=\"ingress\"} 4"));
+        assert!(text.contains("prism_session_bytes_total{direction=\"egress\"} 5"));
+    }
+
+    #[tokio::test]
+    async fn idle_timed_out_sessions_still_report_their_bytes() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.idle_timeout = Duration::from_millis(200);
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_forward(conn, opts).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.expect("connect");
+        let (mut up, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+        client.write_all(b"ping").await.expect("write");
+        let mut buf = [0u8; 4];
+        up.read_exact(&mut buf).await.expect("read");
+        up.write_all(b"pong!").await.expect("write");
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).await.expect("read");
+
+        // Both ends stay open and quiet until the idle timeout closes the session.
+        let summary = time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(s) = sessions.recent_sessions().pop() {
+                    return s;
+                }
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session finished");
+        assert_eq!(summary.close_reason, telemetry::CloseReason::IdleTimeout);
+        assert_eq!(summary.ingress_bytes, 4);
+        assert_eq!(summary.egress_bytes, 5);
+        let text = sessions.render_prometheus();
+        assert!(text.contains("prism_session_bytes_total{direction=\"ingress\"} 4"));
+        assert!(text.contains("prism_session_bytes_total{direction=\"egress\"} 5"));
+    }
+
+    /// Collects everything a `fmt` subscriber writes.
+    #[derive(Clone, Default)]
+    struct CapturedLog(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn access_log_reports_finished_sessions() {
+        use tracing_subscriber::{EnvFilter, layer::SubscriberExt};
+
+        let log = CapturedLog::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(EnvFilter::new("warn,prism::access=info"))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(move || writer.clone()),
+            );
+        // Current-thread test runtime: the spawned handler logs through this too.
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let backend_addr = backend.local_addr().expect("addr");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend_addr.to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_forward(conn, opts).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.expect("connect");
+        let (mut up, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+        client.write_all(b"hello").await.expect("write");
+        let mut buf = [0u8; 5];
+        up.read_exact(&mut buf).await.expect("read");
+        up.write_all(b"hi").await.expect("write");
+        drop(up);
+        assert_eq!(read_to_end(&mut client).await, b"hi");
+        drop(client);
+
+        time::timeout(Duration::from_secs(5), async {
+            while sessions.recent_sessions().is_empty() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session finished");
+
+        let out = String::from_utf8(log.0.lock().unwrap().clone()).expect("utf8");
+        let events: Vec<serde_json::Value> = out
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("json line"))
+            .collect();
+        assert_eq!(events.len(), 1, "only the access event passes: {out}");
+        let ev = &events[0];
+        assert_eq!(ev["target"], "prism::access");
+        assert_eq!(ev["level"], "INFO");
+        let fields = &ev["fields"];
+        assert_eq!(fields["message"], "session closed");
+        assert!(fields["sid"].as_str().is_some_and(|s| !s.is_empty()));
+        assert_eq!(fields["client_ip"], "127.0.0.1");
+        assert_eq!(fields["host"], "");
+        assert_eq!(fields["upstream"], backend_addr.to_string());
+        assert_eq!(fields["bytes_in"], 5);
+        assert_eq!(fields["bytes_out"], 2);
+        assert!(fields["duration_ms"].is_u64());
+        assert_eq!(fields["close_reason"], "closed");
+    }
+
+    #[tokio::test]
+    async fn access_log_reports_bytes_of_killed_sessions() {
+        use tracing_subscriber::{EnvFilter, layer::SubscriberExt};
+
+        let log = CapturedLog::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(EnvFilter::new("warn,prism::access=info"))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(move || writer.clone()),
+            );
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_forward(conn, opts).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.expect("connect");
+        let (mut up, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+        client.write_all(b"hello").await.expect("write");
+        let mut buf = [0u8; 5];
+        up.read_exact(&mut buf).await.expect("read");
+        up.write_all(b"hi").await.expect("write");
+        let mut buf = [0u8; 2];
+        client.read_exact(&mut buf).await.expect("read");
+
+        let sid = sessions.snapshot().pop().expect("live session").id;
+        assert!(sessions.kill(&sid));
+        assert_eq!(read_to_end(&mut client).await, b"");
+
+        time::timeout(Duration::from_secs(5), async {
+            while sessions.recent_sessions().is_empty() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session finished");
+
+        let out = String::from_utf8(log.0.lock().unwrap().clone()).expect("utf8");
+        let events: Vec<serde_json::Value> = out
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("json line"))
+            .collect();
+        assert_eq!(events.len(), 1, "only the access event passes: {out}");
+        let fields = &events[0]["fields"];
+        assert_eq!(fields["sid"], sid.as_str());
+        assert_eq!(fields["close_reason"], "killed");
+        assert_eq!(fields["bytes_in"], 5);
+        assert_eq!(fields["bytes_out"], 2);
+    }
+
+    #[tokio::test]
+    async fn shutdown_drain_lets_in_flight_sessions_finish() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let handler = TcpHandler::forward(TcpForwardHandlerOptions {
+            upstream: backend.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            proxy_protocol: None,
+        });
+        let (stop, stop_rx) = tokio::sync::watch::channel(false);
+        let in_flight = Arc::new(telemetry::ConnectionCounter::default());
+        let accept = tokio::spawn(accept_tcp(ln, handler, stop_rx, Some(in_flight.clone())));
+
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(b"hi").await.expect("write");
+        let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        let mut got = [0u8; 2];
+        b.read_exact(&mut got).await.expect("read");
+
+        // Shutdown: the listener stops accepting, the session keeps going.
+        stop.send(true).expect("stop");
+        accept.await.expect("accept task").expect("accept loop");
+        assert!(TcpStream::connect(addr).await.is_err());
+        assert_eq!(sessions.active_connections(), 1);
+        assert_eq!(in_flight.active(), 1);
+
+        // A drain that ends before the session does gives up.
+        assert!(
+            !in_flight
+                .drain(time::Instant::now() + Duration::from_millis(100))
+                .await
+        );
+
+        // The backend still has 300ms of work; a 5s drain waits for it, and no longer.
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(300)).await;
+            b.write_all(b"bye").await.expect("write");
+        });
+        let client = tokio::spawn(async move {
+            let mut reply = [0u8; 3];
+            c.read_exact(&mut reply).await.expect("read");
+            reply
+        });
+        let started = time::Instant::now();
+        assert!(in_flight.drain(started + Duration::from_secs(5)).await);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(&client.await.expect("client"), b"bye");
+    }
+
+    #[tokio::test]
+    async fn connection_rate_limit_closes_bursts_at_accept() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.rate_limit = config::RateLimitConfig {
+            connections_per_second: 1,
+            burst: 2,
+        };
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let handler = TcpHandler::forward(TcpForwardHandlerOptions {
+            upstream: backend.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        let (_stop, stop_rx) = tokio::sync::watch::channel(false);
+        tokio::spawn(accept_tcp(ln, handler, stop_rx, None));
+
+        let mut conns = Vec::new();
+        for _ in 0..4 {
+            conns.push(TcpStream::connect(addr).await.expect("connect"));
+        }
+        for _ in 0..2 {
+            time::timeout(Duration::from_secs(5), backend.accept())
+                .await
+                .expect("accept timeout")
+                .expect("accept");
+        }
+        for c in &mut conns[2..] {
+            assert!(read_to_end(c).await.is_empty());
+        }
+        let rejections = sessions.rejections();
+        assert_eq!(rejections.len(), 2);
+        assert!(
+            rejections
+                .iter()
+                .all(|r| r.reason == telemetry::RejectReason::RateLimited)
+        );
+        assert_eq!(sessions.session_metrics().ratelimited(), 2);
+        assert!(
+            sessions
+                .render_prometheus()
+                .contains("prism_ratelimited_total 2\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn forward_accepts_proxy_header_from_trusted_front_proxy() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.accept_proxy_protocol = true;
+        rt.trusted_cidrs = vec![net::IpNet::parse("127.0.0.0/8").unwrap()];
+        rt.handshake_timeout = Duration::from_secs(30);
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpForwardHandlerOptions {
+            upstream: backend.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            proxy_protocol: None,
+        });
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_forward(conn, opts.clone()));
+            }
+        });
+
+        let mut pp2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+        pp2.extend_from_slice(&[203, 0, 113, 8, 127, 0, 0, 1, 0xc8, 0x22, 0x63, 0xdd]);
+        let cases: [(&[u8], Option<&str>); 3] = [
+            (
+                b"PROXY TCP4 203.0.113.7 127.0.0.1 51234 25565\r\n",
+                Some("203.0.113.7:51234"),
+            ),
+            (&pp2, Some("203.0.113.8:51234")),
+            // No header: the stream goes through untouched, without waiting for more bytes.
+            (b"", None),
+        ];
+        for (header, want_client) in cases {
+            let mut c = TcpStream::connect(addr).await.expect("connect");
+            let local = c.local_addr().expect("local").to_string();
+            c.write_all(&[header, b"\x10hello"].concat())
+                .await
+                .expect("write");
+            let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+                .await
+                .expect("backend accept timeout")
+                .expect("backend accept");
+            let mut got = [0u8; 6];
+            b.read_exact(&mut got).await.expect("read");
+            assert_eq!(&got, b"\x10hello");
+
+            let want_client = want_client.map_or(local, str::to_string);
+            assert!(
+                sessions.snapshot().iter().any(|s| s.client == want_client),
+                "no session for {want_client}"
+            );
+        }
+
+        // Server-first protocol: the client waits for the upstream's greeting, well within
+        // the handshake timeout.
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        let (mut b, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        b.write_all(b"SSH-2.0-test\r\n").await.expect("greet");
+        let mut got = [0u8; 14];
+        time::timeout(Duration::from_secs(2), c.read_exact(&mut got))
+            .await
+            .expect("greeting timeout")
+            .expect("read greeting");
+        assert_eq!(&got, b"SSH-2.0-test\r\n");
+    }
+
+    #[tokio::test]
+    async fn udp_direct_forwarding_reaches_ipv6_upstream() {
+        let Ok(upstream) = UdpSocket::bind("[::1]:0").await else {
+            // IPv6 loopback unavailable in this environment.
+            return;
+        };
+        let upstream_addr = upstream.local_addr().expect("upstream addr");
+
+        let listener = Arc::new(UdpSocket::bind("127.0.0.1:0").await.expect("bind listener"));
+        let client = UdpSocket::bind("127.0.0.1:0").await.expect("bind client");
+        let client_addr = client.local_addr().expect("client addr");
+
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        tokio::spawn(udp_session_loop(
+            listener.clone(),
+            client_addr,
+            upstream_addr.to_string(),
+            None,
+            None,
+            UdpDatagramLimits::default(),
+            Arc::new(telemetry::SessionRegistry::new()),
+            rx,
+        ));
+        tx.send(b"ping".to_vec()).await.expect("send");
+
+        let mut buf = [0u8; 16];
+        let (n, from) = time::timeout(Duration::from_secs(5), upstream.recv_from(&mut buf))
+            .await
+            .expect("upstream recv timeout")
+            .expect("upstream recv");
+        assert_eq!(&buf[..n], b"ping");
+        assert!(from.is_ipv6(), "bind family should match upstream: {from}");
+        upstream.send_to(b"pong", from).await.expect("reply");
+
+        let (n, from) = time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+            .await
+            .expect("client recv timeout")
+            .expect("client recv");
+        assert_eq!(&buf[..n], b"pong");
+        assert_eq!(from, listener.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn udp_session_cap_drops_new_sources_but_keeps_existing_flows() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.expect("bind upstream");
+        let listener = UdpSocket::bind("127.0.0.1:0").await.expect("bind listener");
+        let listen_addr = listener.local_addr().expect("addr");
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = UdpForwardOptions {
+            upstream: upstream.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            idle_timeout: Duration::ZERO,
+            source_ip: None,
+            max_sessions: 1,
+            limits: UdpDatagramLimits::default(),
+        };
+        let (_stop, stop_rx) = tokio::sync::watch::channel(false);
+        tokio::spawn(serve_udp_socket(listener, opts, stop_rx));
+
+        let recv = async || {
+            let mut buf = [0u8; 16];
+            time::timeout(Duration::from_millis(500), upstream.recv_from(&mut buf))
+                .await
+                .ok()
+                .map(|r| buf[..r.expect("recv").0].to_vec())
+        };
+
+        let a = UdpSocket::bind("127.0.0.1:0").await.expect("bind a");
+        let b = UdpSocket::bind("127.0.0.1:0").await.expect("bind b");
+        a.send_to(b"a1", listen_addr).await.expect("send");
+        assert_eq!(recv().await.as_deref(), Some(&b"a1"[..]));
+
+        b.send_to(b"b1", listen_addr).await.expect("send");
+        assert_eq!(recv().await, None);
+
+        a.send_to(b"a2", listen_addr).await.expect("send");
+        assert_eq!(recv().await.as_deref(), Some(&b"a2"[..]));
+
+        let metrics = sessions.session_metrics();
+        assert_eq!(metrics.udp_sessions_dropped(), 1);
+        assert_eq!(metrics.udp_active_sessions(), 1);
+        let body = sessions.render_prometheus();
+        assert!(
+            body.contains("prism_udp_sessions_dropped_total 1\n"),
+            "{body}"
+        );
+        assert!(body.contains("prism_udp_active_sessions 1\n"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn udp_oversize_datagrams_are_dropped_not_truncated() {
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.expect("bind upstream");
+        let listener = UdpSocket::bind("127.0.0.1:0").await.expect("bind listener");
+        let listen_addr = listener.local_addr().expect("addr");
+        let opts = UdpForwardOptions {
+            upstream: upstream.local_addr().expect("addr").to_string(),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            idle_timeout: Duration::ZERO,
+            source_ip: None,
+            max_sessions: 0,
+            limits: UdpDatagramLimits {
+                buffer_size: 64,
+                max_datagram_bytes: 32,
+            },
+        };
+        let (_stop, stop_rx) = tokio::sync::watch::channel(false);
+        tokio::spawn(serve_udp_socket(listener, opts, stop_rx));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.expect("bind client");
+        for len in [33, 200, 32] {
+            client
+                .send_to(&vec![b'x'; len], listen_addr)
+                .await
+                .expect("send");
+        }
+
+        let mut buf = [0u8; 256];
+        let (n, _) = time::timeout(Duration::from_secs(5), upstream.recv_from(&mut buf))
+            .await
+            .expect("upstream recv timeout")
+            .expect("upstream recv");
+        assert_eq!(n, 32);
+        assert!(
+            time::timeout(Duration::from_millis(300), upstream.recv_from(&mut buf))
+                .await
+                .is_err()
+        );
+        assert_eq!(sessions.session_metrics().udp_oversize(), 2);
+    }
+
+    #[test]
+    fn udp_bind_addr_honors_source_ip_and_family() {
+        let v4: std::net::SocketAddr = "127.0.0.1:19132".parse().unwrap();
+        let v6: std::net::SocketAddr = "[::1]:19132".parse().unwrap();
+        assert_eq!(udp_bind_addr(v4, None).to_string(), "0.0.0.0:0");
+        assert_eq!(udp_bind_addr(v6, None).to_string(), "[::]:0");
+        let src: std::net::IpAddr = "10.0.0.5".parse().unwrap();
+        assert_eq!(udp_bind_addr(v4, Some(src)).to_string(), "10.0.0.5:0");
+    }
+
+    async fn spawn_status_health_listener() -> std::net::SocketAddr {
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let opts = Arc::new(StatusHealthHandlerOptions {
+            status_json: config::DEFAULT_STATUS_HEALTH_JSON.to_string(),
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_status_health(conn, opts).await;
+        });
+        addr
+    }
+
+    /// Handshake, status request and ping packets of a vanilla server list ping.
+    fn status_exchange() -> [Vec<u8>; 3] {
+        let mut hs = Vec::new();
+        minecraft::write_varint(765, &mut hs);
+        minecraft::write_string("prism.example.com", &mut hs);
+        hs.extend_from_slice(&25565u16.to_be_bytes());
+        minecraft::write_varint(minecraft::NEXT_STATE_STATUS, &mut hs);
+        [
+            minecraft::packet(0x00, &hs),
+            minecraft::packet(0x00, &[]),
+            minecraft::packet(0x01, &42i64.to_be_bytes()),
+        ]
+    }
+
+    fn assert_status_and_pong(got: &[u8]) {
+        let (len, id, payload) = minecraft::split_packet(got).expect("status response");
+        assert_eq!(id, 0x00);
+        let (slen, k) = minecraft::read_varint(payload, 0).expect("json len");
+        let v: serde_json::Value =
+            serde_json::from_slice(&payload[k..k + slen as usize]).expect("json");
+        assert_eq!(v["version"]["protocol"], 765);
+        assert_eq!(v["description"]["text"], "Prism is healthy");
+
+        assert_eq!(&got[len..], minecraft::pong_packet(42).as_slice());
+    }
+
+    #[tokio::test]
+    async fn status_health_listener_answers_status_and_ping() {
+        let addr = spawn_status_health_listener().await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&status_exchange().concat())
+            .await
+            .expect("write");
+        assert_status_and_pong(&read_to_end(&mut c).await);
+    }
+
+    #[test]
+    fn status_override_rewrites_json_and_reframes_the_packet() {
+        let ov = config::StatusOverride {
+            motd: Some("Welcome to a much longer message of the day".into()),
+            max_players: Some(1000),
+            version_name: Some("Prism 1.20".into()),
+        };
+        let upstream_json = r#"{"version":{"name":"Paper 1.20.4","protocol":765},"players":{"max":20,"online":3},"description":"A Minecraft Server"}"#;
+        let mut buf = minecraft::status_response_packet(upstream_json);
+        let pong = minecraft::pong_packet(42);
+        buf.extend_from_slice(&pong);
+
+        let out = override_status_response(&buf, &ov).expect("status response");
+        let (frame_len, json) = minecraft::parse_status_response(&out).expect("reframed");
+        let v: serde_json::Value = serde_json::from_str(&json).expect("well-formed json");
+        assert_eq!(
+            v["description"]["text"],
+            "Welcome to a much longer message of the day"
+        );
+        assert_eq!(v["players"]["max"], 1000);
+        assert_eq!(v["players"]["online"], 3);
+        assert_eq!(v["version"]["name"], "Prism 1.20");
+        assert_eq!(v["version"]["protocol"], 765);
+        assert_eq!(&out[frame_len..], pong.as_slice());
+
+        // Non-status frames are left alone.
+        assert!(override_status_response(&pong, &ov).is_none());
+    }
+
+    #[tokio::test]
+    async fn legacy_pings_get_a_legacy_status_and_long_handshakes_still_route() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        // Long enough that the handshake frame length VarInt is FE 01.
+        let long_host = format!("{}.example.com", "a".repeat(234));
+        let route = config::RouteConfig {
+            host: vec![long_host.clone()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+
+        // 1.4/1.5 send FE 01 and wait; 1.6 follows with an MC|PingHost plugin message.
+        let mut ping16 = vec![0xFE, 0x01, 0xFA, 0x00, 0x0B];
+        ping16.extend("MC|PingHost".encode_utf16().flat_map(u16::to_be_bytes));
+        for ping in [vec![0xFE, 0x01], ping16] {
+            let mut c = TcpStream::connect(addr).await.expect("connect");
+            c.write_all(&ping).await.expect("write");
+            let got = time::timeout(Duration::from_secs(2), read_to_end(&mut c))
+                .await
+                .expect("legacy reply timeout");
+            assert_eq!(
+                got,
+                minecraft::legacy_status_packet("Prism", LEGACY_PING_MOTD, 0, 0)
+            );
+        }
+
+        let hs = login_handshake(&long_host);
+        assert_eq!(&hs[..3], &[0xFE, 0x01, 0x00]);
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&hs).await.expect("write");
+        let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        let mut got = vec![0u8; hs.len()];
+        b.read_exact(&mut got).await.expect("read");
+        assert_eq!(got, hs);
+    }
+
+    #[tokio::test]
+    async fn tls_listener_routes_on_sni_and_passes_the_stream_through() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let route = |host: &str, upstream: String| {
+            (
+                config::RouteConfig {
+                    host: vec![host.into()],
+                    upstreams: vec![upstream],
+                    strategy: "sequential".into(),
+                    middlewares: vec!["handshake".into()],
+                    // Minecraft-only checks must not apply to TLS listeners.
+                    strict_minecraft: true,
+                    ..Default::default()
+                },
+                chain.clone(),
+            )
+        };
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![
+                route("other.example.com", "127.0.0.1:1".into()),
+                route(
+                    "*.example.com",
+                    backend.local_addr().expect("addr").to_string(),
+                ),
+            ])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Tls,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        // Split the ClientHello so routing has to wait for the rest of it.
+        let hello = crate::prism::tls::client_hello_with_sni("secure.example.com");
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&hello[..20]).await.expect("write");
+        time::sleep(Duration::from_millis(50)).await;
+        c.write_all(&hello[20..]).await.expect("write");
+
+        let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        let mut got = vec![0u8; hello.len()];
+        b.read_exact(&mut got).await.expect("read");
+        assert_eq!(got, hello);
+
+        b.write_all(b"server hello").await.expect("write");
+        let mut reply = [0u8; 12];
+        c.read_exact(&mut reply).await.expect("read");
+        assert_eq!(&reply, b"server hello");
+    }
+
+    #[tokio::test]
+    async fn auto_listener_routes_minecraft_and_tls_on_one_port() {
+        let mc_backend = TcpListener::bind("127.0.0.1:0").await.expect("bind mc");
+        let tls_backend = TcpListener::bind("127.0.0.1:0").await.expect("bind tls");
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let route = |host: &str, upstream: &TcpListener| {
+            (
+                config::RouteConfig {
+                    host: vec![host.into()],
+                    upstreams: vec![upstream.local_addr().expect("addr").to_string()],
+                    strategy: "sequential".into(),
+                    middlewares: vec!["handshake".into()],
+                    ..Default::default()
+                },
+                chain.clone(),
+            )
+        };
+        let handler = TcpHandler::routing(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![
+                route("play.example.com", &mc_backend),
+                route("secure.example.com", &tls_backend),
+            ])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Auto,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let (_stop, stop_rx) = tokio::sync::watch::channel(false);
+        tokio::spawn(accept_tcp(ln, handler, stop_rx, None));
+
+        // Minecraft goes through the route's middleware chain.
+        let hs = login_handshake("play.example.com");
+        let mut mc = TcpStream::connect(addr).await.expect("connect");
+        mc.write_all(&hs).await.expect("write");
+        let (mut b, _) = time::timeout(Duration::from_secs(2), mc_backend.accept())
+            .await
+            .expect("mc backend accept timeout")
+            .expect("mc backend accept");
+        let mut got = Vec::new();
+        while minecraft::parse_handshake(&got).is_none() {
+            let mut buf = [0u8; 256];
+            let n = b.read(&mut buf).await.expect("read");
+            assert!(n > 0, "mc backend closed early");
+            got.extend_from_slice(&buf[..n]);
+        }
+
+        // TLS is routed on SNI and passed through byte for byte.
+        let hello = crate::prism::tls::client_hello_with_sni("secure.example.com");
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&hello).await.expect("write");
+        let (mut b, _) = time::timeout(Duration::from_secs(2), tls_backend.accept())
+            .await
+            .expect("tls backend accept timeout")
+            .expect("tls backend accept");
+        let mut got = vec![0u8; hello.len()];
+        b.read_exact(&mut got).await.expect("read");
+        assert_eq!(got, hello);
+    }
+
+    #[tokio::test]
+    async fn legacy_forwarding_appends_client_ip_and_uuid_to_handshake_host() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            forwarding: config::Forwarding::Legacy,
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        // Login Start arrives after routing already resolved on the handshake alone.
+        let mut name = Vec::new();
+        minecraft::write_string("Steve", &mut name);
+        let login_start = minecraft::packet(0x00, &name);
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&login_handshake("play.example.com"))
+            .await
+            .expect("write");
+        time::sleep(Duration::from_millis(50)).await;
+        c.write_all(&login_start).await.expect("write");
+
+        let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        let mut got = Vec::new();
+        while minecraft::parse_handshake(&got)
+            .is_none_or(|hs| got.len() < hs.packet_len + login_start.len())
+        {
+            let mut chunk = [0u8; 512];
+            let n = b.read(&mut chunk).await.expect("read");
+            assert!(n > 0, "backend saw EOF");
+            got.extend_from_slice(&chunk[..n]);
+        }
+        let hs = minecraft::parse_handshake(&got).expect("handshake");
+        let uuid: String = minecraft::offline_uuid("Steve")
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(
+            hs.server_address,
+            format!("play.example.com\x00127.0.0.1\0{uuid}")
+        );
+        assert_eq!(hs.protocol_version, 763);
+        assert_eq!(hs.server_port, 25565);
+        assert_eq!(hs.next_state, minecraft::NEXT_STATE_LOGIN);
+        assert_eq!(&got[hs.packet_len..], login_start.as_slice());
+    }
+
+    #[tokio::test]
+    async fn forwarding_login_read_shares_the_handshake_deadline() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            forwarding: config::Forwarding::Legacy,
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.handshake_timeout = Duration::from_secs(1);
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        // The handshake uses most of the budget and Login Start never comes: the wait for
+        // it must end with the handshake deadline, not a fresh timeout after routing.
+        let started = time::Instant::now();
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        time::sleep(Duration::from_millis(700)).await;
+        c.write_all(&login_handshake("play.example.com"))
+            .await
+            .expect("write");
+        assert!(read_to_end(&mut c).await.is_empty());
+        assert!(
+            started.elapsed() < Duration::from_millis(1400),
+            "closed after {:?}",
+            started.elapsed()
+        );
+        assert!(
+            time::timeout(Duration::from_millis(100), backend.accept())
+                .await
+                .is_err(),
+            "no Login Start means no upstream dial"
+        );
+    }
+
+    #[tokio::test]
+    async fn dial_retries_reach_a_backend_that_comes_up_late() {
+        // Bound but not yet listening: the port stays ours, and dials to it are refused
+        // until `listen` is called.
+        let backend_socket = tokio::net::TcpSocket::new_v4().expect("socket");
+        backend_socket
+            .bind("127.0.0.1:0".parse().unwrap())
+            .expect("bind");
+        let backend_addr = backend_socket.local_addr().expect("addr");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend_addr.to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            dial_retries: 4,
+            dial_retry_backoff: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        let hs = login_handshake("play.example.com");
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&hs).await.expect("write");
+
+        time::sleep(Duration::from_millis(150)).await;
+        let backend = backend_socket.listen(16).expect("listen");
+        let (mut b, _) = time::timeout(Duration::from_secs(3), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        let mut got = vec![0u8; hs.len()];
+        b.read_exact(&mut got).await.expect("read handshake");
+        assert_eq!(got, hs);
+    }
+
+    #[tokio::test]
+    async fn route_handshake_timeout_overrides_the_global_one() {
+        let backend = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let route = |host: &str, handshake_timeout| config::RouteConfig {
+            host: vec![host.into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            handshake_timeout,
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.handshake_timeout = Duration::from_millis(150);
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![
+                (
+                    route("modded.example.com", Some(Duration::from_secs(3))),
+                    chain.clone(),
+                ),
+                (route("lobby.example.com", None), chain),
+            ])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            while let Ok((conn, _)) = ln.accept().await {
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+
+        // Both clients take longer than the global timeout to send their handshake.
+        let slow_login = |host: &'static str| async move {
+            let mut c = TcpStream::connect(addr).await.expect("connect");
+            time::sleep(Duration::from_millis(400)).await;
+            let _ = c.write_all(&login_handshake(host)).await;
+            c
+        };
+
+        let _modded = slow_login("modded.example.com").await;
+        let (mut b, _) = time::timeout(Duration::from_secs(3), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        let hs = login_handshake("modded.example.com");
+        let mut got = vec![0u8; hs.len()];
+        b.read_exact(&mut got).await.expect("read handshake");
+        assert_eq!(got, hs);
+
+        let mut lobby = slow_login("lobby.example.com").await;
+        assert!(read_to_end(&mut lobby).await.is_empty());
+        assert!(
+            time::timeout(Duration::from_millis(200), backend.accept())
+                .await
+                .is_err(),
+            "the lobby route must keep the global handshake timeout"
+        );
+    }
+
+    #[tokio::test]
+    async fn route_connection_cap_kicks_the_extra_login_and_keeps_earlier_sessions() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            max_upstream_connections: 2,
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+
+        let hs = login_handshake("play.example.com");
+        let mut open = Vec::new();
+        for _ in 0..2 {
+            let mut c = TcpStream::connect(addr).await.expect("connect");
+            c.write_all(&hs).await.expect("write");
+            let (mut b, _) = time::timeout(Duration::from_secs(5), backend.accept())
+                .await
+                .expect("accept timeout")
+                .expect("accept");
+            let mut got = vec![0u8; hs.len()];
+            b.read_exact(&mut got).await.expect("read handshake");
+            open.push((c, b));
+        }
+
+        let mut extra = TcpStream::connect(addr).await.expect("connect");
+        extra.write_all(&hs).await.expect("write");
+        assert_eq!(
+            read_to_end(&mut extra).await,
+            minecraft::login_disconnect_packet(CONNECTION_LIMIT_KICK_MESSAGE)
+        );
+        assert_eq!(
+            sessions.rejections()[0].reason,
+            telemetry::RejectReason::ConnectionLimit
+        );
+
+        // The first two sessions are still proxying.
+        for (c, b) in &mut open {
+            c.write_all(b"ping").await.expect("write");
+            let mut got = [0u8; 4];
+            b.read_exact(&mut got).await.expect("read");
+            assert_eq!(&got, b"ping");
+        }
+    }
+
+    #[tokio::test]
+    async fn modern_forwarding_answers_the_backend_player_info_query() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            forwarding: config::Forwarding::Modern,
+            forwarding_secret: Some("s3cret".into()),
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        // A 1.20.2+ client claims a UUID in Login Start; only the offline one is signed.
+        let mut hs = Vec::new();
+        minecraft::write_varint(765, &mut hs);
+        minecraft::write_string("play.example.com", &mut hs);
+        hs.extend_from_slice(&25565u16.to_be_bytes());
+        minecraft::write_varint(minecraft::NEXT_STATE_LOGIN, &mut hs);
+        let mut name = Vec::new();
+        minecraft::write_string("Steve", &mut name);
+        name.extend_from_slice(&[0xAA; 16]);
+        let login_start = minecraft::packet(0x00, &name);
+        let mut prelude = minecraft::packet(0x00, &hs);
+        prelude.extend_from_slice(&login_start);
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&prelude).await.expect("write");
+
+        let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        // The handshake reaches the backend untouched.
+        let mut got = vec![0u8; prelude.len()];
+        b.read_exact(&mut got).await.expect("read prelude");
+        assert_eq!(got, prelude);
+
+        // Query player info, followed by a packet that must reach the client as-is.
+        let mut query = Vec::new();
+        minecraft::write_varint(7, &mut query);
+        minecraft::write_string(minecraft::VELOCITY_PLAYER_INFO_CHANNEL, &mut query);
+        query.push(4);
+        let mut out = minecraft::packet(0x04, &query);
+        let login_success = minecraft::packet(0x02, b"ok");
+        out.extend_from_slice(&login_success);
+        b.write_all(&out).await.expect("write query");
+
+        let want = minecraft::login_plugin_response(
+            7,
+            &minecraft::velocity_forwarding_data(
+                b"s3cret",
+                "127.0.0.1",
+                &minecraft::offline_uuid("Steve"),
+                "Steve",
+            ),
+        );
+        let mut resp = vec![0u8; want.len()];
+        b.read_exact(&mut resp).await.expect("read response");
+        assert_eq!(resp, want);
+
+        let mut passed = vec![0u8; login_success.len()];
+        time::timeout(Duration::from_secs(2), c.read_exact(&mut passed))
+            .await
+            .expect("client read timeout")
+            .expect("client read");
+        assert_eq!(passed, login_success);
+    }
+
+    #[tokio::test]
+    async fn offline_status_answers_pings_when_every_upstream_is_down() {
+        // A port nothing listens on.
+        let dead = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind")
+            .local_addr()
+            .expect("addr");
+        let route = config::RouteConfig {
+            host: vec!["prism.example.com".into()],
+            upstreams: vec![dead.to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            offline_status: Some(config::DEFAULT_STATUS_HEALTH_JSON.to_string()),
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&status_exchange().concat())
+            .await
+            .expect("write");
+        assert_status_and_pong(&read_to_end(&mut c).await);
+        assert_eq!(
+            sessions.rejections()[0].reason,
+            telemetry::RejectReason::UpstreamUnavailable
+        );
+
+        // Logins still just get closed.
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&login_handshake("prism.example.com"))
+            .await
+            .expect("write");
+        assert!(read_to_end(&mut c).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn response_middlewares_rewrite_the_status_reply() {
+        // Response-phase middleware: turns every 'P' in the upstream's first reply into 'Q'.
+        let dir = std::env::temp_dir().join(format!(
+            "prism_proxy_response_mw_{}_{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        ));
+        std::fs::create_dir_all(&dir).expect("mkdir");
+        let wat = dir.join("shout.wat");
+        std::fs::write(
+            &wat,
+            r#"(module
+  (memory (export "memory") 2)
+  (global (export "prism_mw_response") i32 (i32.const 1))
+  (func (export "prism_mw_run") (param $n i32) (param $ctx i32) (result i64)
+    (local $i i32)
+    (if (i32.ne (i32.load (i32.add (local.get $ctx) (i32.const 4))) (i32.const 2))
+      (then (return (i64.const 1))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (if (i32.eq (i32.load8_u (local.get $i)) (i32.const 0x50))
+          (then (i32.store8 (local.get $i) (i32.const 0x51))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.store (i32.const 65536) (i32.const 0))
+    (i32.store (i32.const 65540) (i32.const 0))
+    (i32.store (i32.const 65544) (i32.const 0))
+    (i32.store (i32.const 65548) (local.get $n))
+    (i64.or (i64.const 65536) (i64.shl (i64.const 16) (i64.const 32)))
+  )
+)"#,
+        )
+        .expect("write wat");
+        let mc = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../middlewares/minecraft_handshake.wat");
+        let chain = Arc::new(middleware::ChainMiddleware::new(vec![
+            Arc::new(
+                middleware::WasmMiddleware::from_wat_path("mc", &mc, Default::default())
+                    .expect("load mc"),
+            ),
+            Arc::new(
+                middleware::WasmMiddleware::from_wat_path("shout", &wat, Default::default())
+                    .expect("load shout"),
+            ),
+        ])) as middleware::SharedMiddlewareChain;
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let backend = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let backend_addr = backend.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (mut c, _) = backend.accept().await.expect("accept");
+            // Answer the status request, then the ping once it arrives (the handshake may
+            // have been rewritten on the way, so count packets rather than bytes).
+            let mut got = Vec::new();
+            let mut buf = [0u8; 512];
+            let mut answered = false;
+            loop {
+                let mut packets = 0;
+                let mut rest = got.as_slice();
+                while let Some((len, _, _)) = minecraft::split_packet(rest) {
+                    packets += 1;
+                    rest = &rest[len..];
+                }
+                if packets >= 2 && !answered {
+                    answered = true;
+                    let json = config::DEFAULT_STATUS_HEALTH_JSON;
+                    c.write_all(&minecraft::status_response_packet(json))
+                        .await
+                        .expect("write status");
+                }
+                if packets >= 3 {
+                    break;
+                }
+                let n = c.read(&mut buf).await.expect("read");
+                if n == 0 {
+                    return;
+                }
+                got.extend_from_slice(&buf[..n]);
+            }
+            c.write_all(&minecraft::pong_packet(42))
+                .await
+                .expect("write pong");
+        });
+
+        let route = config::RouteConfig {
+            host: vec!["prism.example.com".into()],
+            upstreams: vec![backend_addr.to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["mc".into(), "shout".into()],
+            ..Default::default()
+        };
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&status_exchange().concat())
+            .await
+            .expect("write");
+        let got = read_to_end(&mut c).await;
+        let (len, _, payload) = minecraft::split_packet(&got).expect("status response");
+        let (slen, k) = minecraft::read_varint(payload, 0).expect("json len");
+        let v: serde_json::Value =
+            serde_json::from_slice(&payload[k..k + slen as usize]).expect("json");
+        assert_eq!(v["description"]["text"], "Qrism is healthy");
+        assert_eq!(&got[len..], minecraft::pong_packet(42).as_slice());
+    }
+
+    #[tokio::test]
+    async fn status_health_listener_handles_split_writes() {
+        let packets = status_exchange();
+
+        // One write per packet.
+        let addr = spawn_status_health_listener().await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.set_nodelay(true).expect("nodelay");
+        for p in &packets {
+            c.write_all(p).await.expect("write");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_status_and_pong(&read_to_end(&mut c).await);
+
+        // Writes that straddle packet boundaries (handshake tail + status request head, ...).
+        let all = packets.concat();
+        let cuts = [3, packets[0].len() - 1, packets[0].len() + 1, all.len() - 4];
+        let addr = spawn_status_health_listener().await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.set_nodelay(true).expect("nodelay");
+        let mut prev = 0;
+        for cut in cuts.into_iter().chain([all.len()]) {
+            c.write_all(&all[prev..cut]).await.expect("write");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            prev = cut;
+        }
+        assert_status_and_pong(&read_to_end(&mut c).await);
+    }
+
+    #[tokio::test]
+    async fn status_health_listener_closes_after_handshake_only() {
+        let addr = spawn_status_health_listener().await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&status_exchange()[0]).await.expect("write");
+        c.shutdown().await.expect("shutdown");
+        assert!(read_to_end(&mut c).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_route_close_policy_closes_without_reply() {
+        let addr = spawn_unrouted_listener(config::NoRoutePolicy::Close).await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&login_handshake("unknown.example.com"))
+            .await
+            .expect("write");
+        assert!(read_to_end(&mut c).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_route_kick_policy_sends_login_disconnect() {
+        let addr =
+            spawn_unrouted_listener(config::NoRoutePolicy::Kick("Unknown server".into())).await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&login_handshake("unknown.example.com"))
+            .await
+            .expect("write");
+        let got = read_to_end(&mut c).await;
+        assert_eq!(got, minecraft::login_disconnect_packet("Unknown server"));
+    }
+
+    #[tokio::test]
+    async fn host_secret_mismatch_kicks_without_dialing() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            host_secret: Some("s3cret".into()),
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&login_handshake("guess.play.example.com"))
+            .await
+            .expect("write");
+        let got = read_to_end(&mut c).await;
+        assert_eq!(
+            got,
+            minecraft::login_disconnect_packet(HOST_SECRET_KICK_MESSAGE)
+        );
+        assert!(
+            time::timeout(Duration::from_millis(200), backend.accept())
+                .await
+                .is_err()
+        );
+
+        let rejections = sessions.rejections();
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].reason, telemetry::RejectReason::HostSecret);
+        assert_eq!(rejections[0].host, "play.example.com");
+        assert_eq!(
+            rejections[0].client,
+            c.local_addr().expect("client addr").to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn denied_client_ip_is_closed_without_dialing() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let route = config::RouteConfig {
+            host: vec!["staff.example.com".into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            allow_cidrs: vec![crate::prism::net::IpNet::parse("10.0.0.0/8").expect("cidr")],
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        tokio::spawn(async move {
+            let (conn, _) = ln.accept().await.expect("accept");
+            handle_routing(conn, opts).await;
+        });
+
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&login_handshake("staff.example.com"))
+            .await
+            .expect("write");
+        assert!(read_to_end(&mut c).await.is_empty());
+        assert!(
+            time::timeout(Duration::from_millis(200), backend.accept())
+                .await
+                .is_err()
+        );
+
+        let rejections = sessions.rejections();
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].reason, telemetry::RejectReason::RouteDenied);
+        assert_eq!(rejections[0].host, "staff.example.com");
+        assert_eq!(
+            sessions.session_metrics().route_denied("staff.example.com"),
+            1
+        );
+        assert!(
+            sessions
+                .render_prometheus()
+                .contains("prism_route_denied_total{host=\"staff.example.com\"} 1\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn only_flagged_upstreams_receive_a_proxy_header() {
+        let flagged = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind flagged");
+        let plain = TcpListener::bind("127.0.0.1:0").await.expect("bind plain");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![
+                format!("{}#proxy=v1", flagged.local_addr().expect("addr")),
+                plain.local_addr().expect("addr").to_string(),
+            ],
+            // Each connection starts at the next upstream, so both get one.
+            strategy: "round-robin".into(),
+            middlewares: vec!["handshake".into()],
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &config::empty_managed_runtime_config(),
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+
+        let hs = login_handshake("play.example.com");
+        for backend in [&flagged, &plain] {
+            let mut c = TcpStream::connect(addr).await.expect("connect");
+            c.write_all(&hs).await.expect("write");
+            let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+                .await
+                .expect("backend accept timeout")
+                .expect("backend accept");
+
+            let mut expected = Vec::new();
+            if std::ptr::eq(backend, &flagged) {
+                expected.extend_from_slice(
+                    format!(
+                        "PROXY TCP4 127.0.0.1 127.0.0.1 {} {}\r\n",
+                        c.local_addr().expect("local").port(),
+                        addr.port()
+                    )
+                    .as_bytes(),
+                );
+            }
+            expected.extend_from_slice(&hs);
+            let mut got = vec![0u8; expected.len()];
+            b.read_exact(&mut got).await.expect("read");
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn route_proxy_protocol_overrides_global_setting() {
+        let v1_backend = TcpListener::bind("127.0.0.1:0").await.expect("bind v1");
+        let v2_backend = TcpListener::bind("127.0.0.1:0").await.expect("bind v2");
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+        let route = |host: &str, backend: &TcpListener, pp| config::RouteConfig {
+            host: vec![host.into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            proxy_protocol: pp,
+            ..Default::default()
+        };
+        let router = Arc::new(router::Router::new(vec![
+            (
+                route(
+                    "v1.example.com",
+                    &v1_backend,
+                    Some(config::ProxyProtocol::V1),
+                ),
+                chain.clone(),
+            ),
+            (route("v2.example.com", &v2_backend, None), chain),
+        ]));
+
+        let mut cfg = config::empty_managed_runtime_config();
+        cfg.proxy_protocol = config::ProxyProtocol::V2;
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router,
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &cfg,
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+
+        for (host, backend) in [
+            ("v1.example.com", &v1_backend),
+            ("v2.example.com", &v2_backend),
+        ] {
+            let mut c = TcpStream::connect(addr).await.expect("connect");
+            let hs = login_handshake(host);
+            c.write_all(&hs).await.expect("write");
+
+            let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+                .await
+                .expect("backend accept timeout")
+                .expect("backend accept");
+            let client_port = c.local_addr().expect("local").port();
+
+            if host.starts_with("v1") {
+                let expected = format!(
+                    "PROXY TCP4 127.0.0.1 127.0.0.1 {client_port} {}\r\n",
+                    addr.port()
+                );
+                let mut header = vec![0u8; expected.len()];
+                b.read_exact(&mut header).await.expect("read pp1");
+                assert_eq!(String::from_utf8_lossy(&header), expected);
+            } else {
+                let mut header = [0u8; 28];
+                b.read_exact(&mut header).await.expect("read pp2");
+                assert_eq!(&header[..12], b"\r\n\r\n\0\r\nQUIT\n");
+                assert_eq!(header[12], 0x21);
+                assert_eq!(header[13], 0x11);
+                assert_eq!(u16::from_be_bytes([header[24], header[25]]), client_port);
+            }
+
+            let mut prelude = vec![0u8; hs.len()];
+            b.read_exact(&mut prelude).await.expect("read prelude");
+            assert_eq!(prelude, hs);
+        }
+    }
+
+    #[tokio::test]
+    async fn sniffing_strips_trusted_proxy_header_and_forwards_its_source() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![backend.local_addr().expect("addr").to_string()],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            proxy_protocol: Some(config::ProxyProtocol::V1),
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+
+        let spawn = |trusted: Vec<net::IpNet>| {
+            let route = route.clone();
+            let chain = chain.clone();
+            async move {
+                let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+                rt.sniff_protocols = true;
+                rt.trusted_cidrs = trusted;
+                let sessions = Arc::new(telemetry::SessionRegistry::new());
+                let opts = Arc::new(TcpRoutingHandlerOptions {
+                    router: Arc::new(router::Router::new(vec![(route, chain)])),
+                    sessions: sessions.clone(),
+                    tunnel_manager: None,
+                    runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+                    observe: false,
+                    protocol: RoutingProtocol::Middleware,
+                    buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+                    health_probes: Vec::new(),
+                });
+                let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+                let addr = ln.local_addr().expect("addr");
+                tokio::spawn(async move {
+                    let (conn, _) = ln.accept().await.expect("accept");
+                    handle_routing(conn, opts).await;
+                });
+                (addr, sessions)
+            }
+        };
+
+        // PROXY v2 from 203.0.113.7:51234, then a plain login handshake.
+        let mut pp2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+        pp2.extend_from_slice(&[203, 0, 113, 7, 127, 0, 0, 1, 0xc8, 0x22, 0x63, 0xdd]);
+        let hs = login_handshake("play.example.com");
+
+        let (addr, _) = spawn(vec![net::IpNet::parse("127.0.0.0/8").unwrap()]).await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&[pp2.clone(), hs.clone()].concat())
+            .await
+            .expect("write");
+        let (mut b, _) = time::timeout(Duration::from_secs(2), backend.accept())
+            .await
+            .expect("backend accept timeout")
+            .expect("backend accept");
+        let expected = format!("PROXY TCP4 203.0.113.7 127.0.0.1 51234 {}\r\n", addr.port());
+        let mut got = vec![0u8; expected.len() + hs.len()];
+        b.read_exact(&mut got).await.expect("read");
+        assert_eq!(String::from_utf8_lossy(&got[..expected.len()]), expected);
+        assert_eq!(&got[expected.len()..], hs.as_slice());
+
+        // The same header from an untrusted peer is refused.
+        let (addr, sessions) = spawn(vec![]).await;
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&[pp2, hs].concat()).await.expect("write");
+        assert!(read_to_end(&mut c).await.is_empty());
+        assert_eq!(
+            sessions.rejections()[0].reason,
+            telemetry::RejectReason::HandshakeInvalid
+        );
+    }
+
+    #[tokio::test]
+    async fn health_probes_are_answered_before_routing() {
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.handshake_timeout = Duration::from_secs(30);
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![])),
+            sessions: Arc::new(telemetry::SessionRegistry::new()),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(rt)),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: vec![
+                config::HealthProbe {
+                    pattern: b"PING\n".to_vec(),
+                    reply: b"PONG\n".to_vec(),
+                },
+                config::HealthProbe {
+                    pattern: b"HC".to_vec(),
+                    reply: Vec::new(),
+                },
+            ],
+        });
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+
+        // The probe arrives split; the reply must not wait for the handshake timeout.
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(b"PI").await.expect("write");
+        time::sleep(Duration::from_millis(20)).await;
+        c.write_all(b"NG\n").await.expect("write");
+        let got = time::timeout(Duration::from_secs(2), read_to_end(&mut c))
+            .await
+            .expect("probe reply timeout");
+        assert_eq!(got, b"PONG\n");
+
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(b"HC").await.expect("write");
+        let got = time::timeout(Duration::from_secs(2), read_to_end(&mut c))
+            .await
+            .expect("probe close timeout");
+        assert!(got.is_empty());
+    }
+
+    #[tokio::test]
+    async fn nodelay_and_keepalive_are_applied_to_sockets() {
+        let (a, b) = tcp_pair().await;
+        apply_socket_options(
+            &a,
+            TcpSocketOptions {
+                nodelay: true,
+                keepalive: Duration::from_secs(30),
+                ..Default::default()
+            },
+        );
+        apply_socket_options(&b, TcpSocketOptions::default());
+
+        let a = socket2::SockRef::from(&a);
+        assert!(a.tcp_nodelay().expect("getsockopt"));
+        assert!(a.keepalive().expect("getsockopt"));
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            a.tcp_keepalive_time().expect("getsockopt"),
+            Duration::from_secs(30)
+        );
+        let b = socket2::SockRef::from(&b);
+        assert!(!b.tcp_nodelay().expect("getsockopt"));
+        assert!(!b.keepalive().expect("getsockopt"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn tcp_user_timeout_is_applied_to_sockets() {
+        let (a, b) = tcp_pair().await;
+        apply_tcp_user_timeout(&a, Duration::from_millis(15_000));
+        apply_tcp_user_timeout(&b, Duration::ZERO);
+        assert_eq!(
+            socket2::SockRef::from(&a)
+                .tcp_user_timeout()
+                .expect("getsockopt"),
+            Some(Duration::from_millis(15_000))
+        );
+        assert_eq!(
+            socket2::SockRef::from(&b)
+                .tcp_user_timeout()
+                .expect("getsockopt"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn full_upstream_fails_over_to_next_candidate() {
+        let primary = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind primary");
+        let spare = TcpListener::bind("127.0.0.1:0").await.expect("bind spare");
+        let primary_addr = primary.local_addr().expect("addr").to_string();
+        let route = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec![
+                primary_addr.clone(),
+                spare.local_addr().expect("addr").to_string(),
+            ],
+            strategy: "sequential".into(),
+            middlewares: vec!["handshake".into()],
+            ..Default::default()
+        };
+        let chain = Arc::new(HandshakeChain) as middleware::SharedMiddlewareChain;
+
+        let mut cfg = config::empty_managed_runtime_config();
+        cfg.upstream_max_connections.insert(primary_addr.clone(), 1);
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+        let ln = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = ln.local_addr().expect("addr");
+        let opts = Arc::new(TcpRoutingHandlerOptions {
+            router: Arc::new(router::Router::new(vec![(route, chain)])),
+            sessions: sessions.clone(),
+            tunnel_manager: None,
+            runtime: Arc::new(tokio::sync::RwLock::new(TcpRuntimeConfig::from_config(
+                &cfg,
+            ))),
+            observe: false,
+            protocol: RoutingProtocol::Middleware,
+            buffer_pool: Arc::new(bufpool::BufferPool::new(8)),
+            health_probes: Vec::new(),
+        });
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = ln.accept().await.expect("accept");
+                tokio::spawn(handle_routing(conn, opts.clone()));
+            }
+        });
+        async fn accept(ln: &TcpListener) -> TcpStream {
+            time::timeout(Duration::from_secs(2), ln.accept())
+                .await
+                .expect("backend accept timeout")
+                .expect("backend accept")
+                .0
+        }
+
+        let hs = login_handshake("play.example.com");
+        let mut first = TcpStream::connect(addr).await.expect("connect");
+        first.write_all(&hs).await.expect("write");
+        let mut fb = accept(&primary).await;
+        let mut buf = vec![0u8; hs.len()];
+        fb.read_exact(&mut buf).await.expect("read prelude");
+        assert_eq!(sessions.upstream_connections(&primary_addr), 1);
+
+        // The primary is full, so the second session lands on the spare.
+        let mut second = TcpStream::connect(addr).await.expect("connect");
+        second.write_all(&hs).await.expect("write");
+        let mut sb = accept(&spare).await;
+        sb.read_exact(&mut buf).await.expect("read prelude");
+        assert_eq!(buf, hs);
+
+        // Ending the first session frees the slot.
+        drop(first);
+        drop(fb);
+        time::timeout(Duration::from_secs(2), async {
+            while sessions.upstream_connections(&primary_addr) != 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("slot released");
+        let mut third = TcpStream::connect(addr).await.expect("connect");
+        third.write_all(&hs).await.expect("write");
+        accept(&primary).await;
+    }
+
+    #[tokio::test]
+    async fn no_route_default_upstream_policy_forwards_prelude() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let backend_addr = backend.local_addr().expect("addr");
+        let addr = spawn_unrouted_listener(config::NoRoutePolicy::DefaultUpstream(
+            backend_addr.to_string(),
+        ))
+        .await;
+
+        let hs = login_handshake("unknown.example.com");
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&hs).await.expect("write");
+
+        let (mut up, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+        let mut got = vec![0u8; hs.len()];
+        up.read_exact(&mut got).await.expect("read prelude");
+        assert_eq!(got, hs);
+
+        up.write_all(b"pong").await.expect("write back");
+        let mut reply = [0u8; 4];
+        c.read_exact(&mut reply).await.expect("read reply");
+        assert_eq!(&reply, b"pong");
+    }
+
+    #[tokio::test]
+    async fn no_route_default_upstream_sessions_count_toward_max_connections() {
+        let backend = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind backend");
+        let mut rt = TcpRuntimeConfig::from_config(&config::empty_managed_runtime_config());
+        rt.on_no_route =
+            config::NoRoutePolicy::DefaultUpstream(backend.local_addr().expect("addr").to_string());
+        rt.max_connections = 1;
+        let sessions = Arc::new(telemetry::SessionRegistry::new());
+
+        // A default-upstream session holds a slot while it proxies.
+        let addr = spawn_unrouted_listener_with(rt.clone(), sessions.clone()).await;
+        let hs = login_handshake("unknown.example.com");
+        let mut c = TcpStream::connect(addr).await.expect("connect");
+        c.write_all(&hs).await.expect("write");
+        let (_up, _) = time::timeout(Duration::from_secs(5), backend.accept())
+            .await
+            .expect("accept timeout")
+            .expect("accept");
+        assert_eq!(sessions.active_connections(), 1);
+
+        // With the only slot taken, the next one is closed before dialing.
+        let addr = spawn_unrouted_listener_with(rt, sessions.clone()).await;
+        let mut c2 = TcpStream::connect(addr).await.expect("connect");
+        c2.write_all(&hs).await.expect("write");
+        assert!(read_to_end(&mut c2).await.is_empty());
+        assert!(
+            time::timeout(Duration::from_millis(200), backend.accept())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn skips_prelude_rewrite_for_tunnel_labels() {
+        assert!(!should_rewrite_prelude("tunnel:cti"));
+        assert!(!should_rewrite_prelude("  TUNNEL:monifactory  "));
+        assert!(!should_rewrite_prelude(""));
+        assert!(should_rewrite_prelude("backend.local:25566"));
+    }
+
+    #[test]
+    fn port_less_upstreams_get_the_default_port() {
+        assert_eq!(
+            upstream_label("backend.local", Some(25570)),
+            "backend.local:25570"
+        );
+        assert_eq!(upstream_label("[::1]", Some(19132)), "[::1]:19132");
+        assert_eq!(
+            upstream_label("backend.local:25566", Some(19132)),
+            "backend.local:25566"
+        );
     }
 }
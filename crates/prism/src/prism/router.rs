@@ -1,14 +1,27 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::{
     Arc,
     atomic::{AtomicU64, Ordering},
 };
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
 use arc_swap::ArcSwap;
+use dashmap::DashMap;
 use rand::{RngExt, rng};
 use regex::Regex;
 
 use crate::prism::config;
-use crate::prism::middleware::{MiddlewareError, SharedMiddlewareChain};
+use crate::prism::middleware::{
+    ChainMiddleware, ClientMeta, HostParser, Middleware, MiddlewareError, MiddlewareProvider,
+    SharedMiddlewareChain,
+};
+use crate::prism::minecraft;
+use crate::prism::net::IpNet;
+use crate::prism::net::secret_eq;
+use crate::prism::telemetry;
+use crate::prism::tls;
 
 #[derive(Clone)]
 pub struct Resolution {
@@ -17,25 +30,95 @@ pub struct Resolution {
     #[allow(dead_code)]
     pub matched_host: String,
     pub captures: Vec<String>,
+    /// Named groups from a `host_regex` match, substituted as `$name` in upstreams.
+    pub named_captures: Vec<(String, String)>,
     pub middleware: SharedMiddlewareChain,
     pub prelude_override: Option<Vec<u8>>,
+    /// Head-sampling decision for this session's trace span.
+    pub trace_sampled: bool,
+    /// The host targeted a `host_secret` route without the right leading secret label.
+    pub host_secret_rejected: bool,
+    /// The client IP is not allowed on the route (`allow_cidrs` / `deny_cidrs`).
+    pub client_denied: bool,
+    /// Per-route PROXY protocol override; None follows the listener/global setting.
+    pub proxy_protocol: Option<config::ProxyProtocol>,
+    /// Candidates with a `#proxy=` suffix; their setting beats `proxy_protocol`.
+    pub upstream_proxy_protocols: Vec<(String, config::ProxyProtocol)>,
+    /// Status JSON for pings when no upstream can be dialed.
+    pub offline_status: Option<Arc<str>>,
+    pub forwarding: config::Forwarding,
+    pub forwarding_secret: Option<Arc<str>>,
+    pub status_override: Option<Arc<config::StatusOverride>>,
+    /// Sessions currently proxied through the route, capped at `max_upstream_connections`.
+    pub connections: Arc<telemetry::ConnectionCounter>,
+    pub max_upstream_connections: usize,
+    pub dial_retries: u32,
+    pub dial_retry_backoff: std::time::Duration,
+    /// Per-route overrides of the global timeouts.
+    pub handshake_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub upstream_dial_timeout: Option<Duration>,
 }
 
 pub struct Router {
     compiled: ArcSwap<CompiledRoutes>,
+    /// Passive health of upstreams that recently failed to dial, kept across route updates.
+    health: DashMap<String, UpstreamHealth>,
+}
+
+#[derive(Debug, Default)]
+struct UpstreamHealth {
+    failures: u32,
+    ejected_until: Option<Instant>,
+    /// The last active status probe failed.
+    probe_failed: bool,
+}
+
+impl UpstreamHealth {
+    fn unhealthy(&self, now: Instant) -> bool {
+        self.probe_failed || self.ejected_until.is_some_and(|t| t > now)
+    }
 }
 
+/// Bound on tracked failing upstreams (wildcard templates can produce many names).
+const MAX_TRACKED_UPSTREAMS: usize = 4096;
+
 #[derive(Default)]
 struct CompiledRoutes {
     routes: Vec<CompiledRoute>,
+    /// Longest per-route handshake timeout override.
+    max_handshake_timeout: Option<Duration>,
 }
 
 struct CompiledRoute {
     patterns: Vec<CompiledPattern>,
     upstreams: Vec<String>,
+    weights: Vec<u32>,
+    /// `#proxy=` suffix of each upstream, by index.
+    upstream_proxy_protocols: Vec<Option<config::ProxyProtocol>>,
     strategy: Strategy,
+    /// Catch-all route, tried only after every specific route fails to match.
+    fallback: bool,
+    priority: i32,
     rr: AtomicU64,
     middleware: SharedMiddlewareChain,
+    trace_sample_ratio: Option<f64>,
+    strict_minecraft: bool,
+    host_secret: Option<String>,
+    allow_cidrs: Vec<IpNet>,
+    deny_cidrs: Vec<IpNet>,
+    proxy_protocol: Option<config::ProxyProtocol>,
+    offline_status: Option<Arc<str>>,
+    forwarding: config::Forwarding,
+    forwarding_secret: Option<Arc<str>>,
+    status_override: Option<Arc<config::StatusOverride>>,
+    connections: Arc<telemetry::ConnectionCounter>,
+    max_upstream_connections: usize,
+    dial_retries: u32,
+    dial_retry_backoff: std::time::Duration,
+    handshake_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    upstream_dial_timeout: Option<Duration>,
 }
 
 #[derive(Debug)]
@@ -50,23 +133,101 @@ enum Strategy {
     Sequential,
     Random,
     RoundRobin,
+    Weighted,
+    IpHash,
+}
+
+/// A route built in code, for `Router::from_static`.
+#[derive(Clone)]
+pub struct StaticRoute {
+    /// Host patterns, as in `routes[].host`.
+    pub hosts: Vec<String>,
+    pub upstreams: Vec<String>,
+    /// As in `routes[].strategy`; empty means sequential.
+    pub strategy: String,
+    pub parser: HostParser,
+}
+
+/// Pair every route in `cfg` with its middleware chain from `provider`.
+pub fn build_routes(
+    cfg: &config::Config,
+    provider: &dyn MiddlewareProvider,
+) -> anyhow::Result<Vec<(config::RouteConfig, SharedMiddlewareChain)>> {
+    let mut out = Vec::with_capacity(cfg.routes.len());
+    for (i, r) in cfg.routes.iter().enumerate() {
+        // An empty chain can never extract a host, so the route would silently never match.
+        if r.middlewares.iter().all(|m| m.trim().is_empty()) {
+            anyhow::bail!(
+                "route[{}] has an empty middleware chain (set routes[].middlewares)",
+                i
+            );
+        }
+        let chain = provider
+            .chain(&r.middlewares)
+            .with_context(|| format!("route[{}] build middleware chain", i))?;
+        out.push((r.clone(), chain));
+    }
+    Ok(out)
 }
 
 impl Router {
+    /// Build a router for `cfg.routes`, loading middleware chains from `provider`.
+    pub fn from_config(
+        cfg: &config::Config,
+        provider: &dyn MiddlewareProvider,
+    ) -> anyhow::Result<Self> {
+        Ok(Self::new(build_routes(cfg, provider)?))
+    }
+
+    /// Build a router from host -> upstream maps whose hosts are extracted natively, with
+    /// no wasm middleware involved.
+    pub fn from_static(routes: Vec<StaticRoute>) -> Self {
+        Self::new(
+            routes
+                .into_iter()
+                .map(|r| {
+                    let cfg = config::RouteConfig {
+                        host: r.hosts,
+                        upstreams: r.upstreams,
+                        middlewares: vec![r.parser.name().to_string()],
+                        strategy: r.strategy,
+                        ..Default::default()
+                    };
+                    let chain: SharedMiddlewareChain =
+                        Arc::new(ChainMiddleware::new(vec![Arc::new(r.parser)]));
+                    (cfg, chain)
+                })
+                .collect(),
+        )
+    }
+
     pub fn new(routes: Vec<(config::RouteConfig, SharedMiddlewareChain)>) -> Self {
         let r = Self {
             compiled: ArcSwap::from_pointee(CompiledRoutes::default()),
+            health: DashMap::new(),
         };
         r.update(routes);
         r
     }
 
     pub fn update(&self, routes: Vec<(config::RouteConfig, SharedMiddlewareChain)>) {
+        // Sessions opened before the update keep holding slots of their route's counter,
+        // so a route that survives the update keeps counting them against its cap.
+        let mut counters: HashMap<(Vec<String>, bool), Arc<telemetry::ConnectionCounter>> = self
+            .compiled
+            .load()
+            .routes
+            .iter()
+            .map(|c| (route_identity(c), c.connections.clone()))
+            .collect();
         let mut out = Vec::new();
         let mut skipped = 0usize;
         for (rt, middleware) in routes {
             match compile_route(&rt, middleware) {
-                Ok(c) => {
+                Ok(mut c) => {
+                    if let Some(connections) = counters.remove(&route_identity(&c)) {
+                        c.connections = connections;
+                    }
                     tracing::info!(
                         patterns = ?c.patterns.iter().map(|p| p.pattern.as_str()).collect::<Vec<_>>(),
                         upstreams = ?c.upstreams,
@@ -86,9 +247,204 @@ impl Router {
                 }
             }
         }
+        // Fallbacks go last, then higher priorities first; stable, so equal priorities
+        // keep their configured order.
+        out.sort_by_key(|c| (c.fallback, std::cmp::Reverse(c.priority)));
         tracing::info!(routes = out.len(), skipped, "router: route table updated");
+        let max_handshake_timeout = out.iter().filter_map(|c| c.handshake_timeout).max();
+        self.compiled.store(Arc::new(CompiledRoutes {
+            routes: out,
+            max_handshake_timeout,
+        }));
+    }
+
+    /// How long to wait for a routable handshake before the route is known: `global`, or
+    /// the longest per-route override if that is longer. Zero (no limit) stays zero.
+    pub fn handshake_timeout_ceiling(&self, global: Duration) -> Duration {
+        if global.is_zero() {
+            return global;
+        }
         self.compiled
-            .store(Arc::new(CompiledRoutes { routes: out }));
+            .load()
+            .max_handshake_timeout
+            .map_or(global, |t| t.max(global))
+    }
+
+    /// Whether a `strict_minecraft` route turned `prelude` away, so a connection that matched
+    /// nothing was rejected as non-Minecraft traffic.
+    pub fn rejects_non_minecraft(&self, prelude: &[u8]) -> bool {
+        !minecraft::looks_like_handshake(prelude)
+            && self
+                .compiled
+                .load()
+                .routes
+                .iter()
+                .any(|r| r.strict_minecraft)
+    }
+
+    /// Record the outcome of dialing `upstream` (a candidate from a resolution). After
+    /// `eject_after` consecutive failures (0 = never) the upstream is ejected for `cooldown`:
+    /// it is only tried after every healthy candidate until the cooldown ends or a dial to it
+    /// succeeds. A failure after the cooldown ejects it again straight away.
+    pub fn record_dial(&self, upstream: &str, ok: bool, eject_after: u32, cooldown: Duration) {
+        if ok {
+            // A connect alone doesn't clear a failed status probe; only the next probe does.
+            let mut reinstated = false;
+            self.health.remove_if_mut(upstream, |_, h| {
+                reinstated = h.ejected_until.is_some();
+                h.failures = 0;
+                h.ejected_until = None;
+                !h.probe_failed
+            });
+            if reinstated {
+                tracing::info!(upstream = %upstream, "router: upstream reinstated after a successful dial");
+            }
+            return;
+        }
+        if eject_after == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        if self.health.len() >= MAX_TRACKED_UPSTREAMS && !self.health.contains_key(upstream) {
+            self.health.retain(|_, h| h.unhealthy(now));
+        }
+        let mut h = self.health.entry(upstream.to_string()).or_default();
+        h.failures = h.failures.saturating_add(1);
+        if h.failures >= eject_after {
+            if h.ejected_until.is_none_or(|t| t <= now) {
+                tracing::warn!(
+                    upstream = %upstream,
+                    failures = h.failures,
+                    cooldown_ms = cooldown.as_millis() as u64,
+                    "router: ejecting upstream after consecutive dial failures"
+                );
+            }
+            h.ejected_until = Some(now + cooldown);
+        }
+    }
+
+    /// Move ejected upstreams behind the healthy ones, keeping the strategy's order otherwise.
+    /// They stay in the list as a last resort, so a route whose upstreams are all ejected
+    /// still gets dialed.
+    fn demote_ejected(&self, upstreams: &mut [String]) {
+        if self.health.is_empty() || upstreams.len() <= 1 {
+            return;
+        }
+        let now = Instant::now();
+        upstreams.sort_by_key(|u| {
+            self.health
+                .get(u.as_str())
+                .is_some_and(|h| h.unhealthy(now))
+        });
+    }
+
+    /// Upstreams the active health checker can probe: every configured upstream that is
+    /// neither a wildcard template nor a tunnel service, sorted, with the PROXY protocol
+    /// version a dial to it sends (`#proxy=`, else the route's; None follows the global
+    /// setting). An upstream listed by several routes takes the first route's version.
+    pub fn health_check_targets(&self) -> Vec<(String, Option<config::ProxyProtocol>)> {
+        let cr = self.compiled.load();
+        let mut targets: Vec<(String, Option<config::ProxyProtocol>)> = cr
+            .routes
+            .iter()
+            .flat_map(|r| {
+                r.upstreams
+                    .iter()
+                    .zip(&r.upstream_proxy_protocols)
+                    .map(|(u, pp)| (u.clone(), pp.or(r.proxy_protocol)))
+            })
+            .filter(|(u, _)| !u.contains('$') && !u.starts_with("tunnel:"))
+            .collect();
+        targets.sort_by(|a, b| a.0.cmp(&b.0));
+        targets.dedup_by(|a, b| a.0 == b.0);
+        targets
+    }
+
+    /// Record an active status probe of `upstream`. A failed probe keeps it behind healthy
+    /// candidates until a later probe succeeds; a successful one also lifts any ejection.
+    pub fn record_probe(&self, upstream: &str, healthy: bool) {
+        if healthy {
+            if let Some((_, h)) = self.health.remove(upstream)
+                && (h.probe_failed || h.ejected_until.is_some())
+            {
+                tracing::info!(upstream = %upstream, "router: upstream healthy again after a status probe");
+            }
+            return;
+        }
+        let mut h = self.health.entry(upstream.to_string()).or_default();
+        if !h.probe_failed {
+            tracing::warn!(upstream = %upstream, "router: upstream failed its status probe");
+        }
+        h.probe_failed = true;
+    }
+
+    /// `prism_upstream_unhealthy{upstream}` for every upstream with recent failures: 1 while
+    /// it is ejected or failing its status probes, 0 once it is only counting failures.
+    pub fn render_prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let now = Instant::now();
+        let mut upstreams: Vec<_> = self
+            .health
+            .iter()
+            .map(|e| (e.key().clone(), e.value().unhealthy(now)))
+            .collect();
+        upstreams.sort();
+        let mut out = String::from(
+            "# HELP prism_upstream_unhealthy Whether an upstream is ejected or failing its health probes.\n",
+        );
+        out.push_str("# TYPE prism_upstream_unhealthy gauge\n");
+        for (upstream, unhealthy) in &upstreams {
+            let _ = writeln!(
+                out,
+                "prism_upstream_unhealthy{{upstream=\"{}\"}} {}",
+                telemetry::escape_label(upstream),
+                u8::from(*unhealthy)
+            );
+        }
+        out
+    }
+
+    /// Drop probe results for upstreams that are no longer probed (removed from the config,
+    /// or health checks turned off).
+    pub fn forget_probes_except(&self, targets: &[String]) {
+        self.health.retain(|u, h| {
+            if h.probe_failed && targets.binary_search(u).is_err() {
+                h.probe_failed = false;
+            }
+            h.failures > 0 || h.ejected_until.is_some() || h.probe_failed
+        });
+    }
+
+    /// One human-readable line per active route, in match order. Host secrets are never shown.
+    pub fn describe(&self) -> Vec<String> {
+        let cr = self.compiled.load();
+        cr.routes
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let mut line = format!(
+                    "route[{i}] hosts={:?} upstreams={:?} strategy={:?}",
+                    r.patterns
+                        .iter()
+                        .map(|p| p.pattern.as_str())
+                        .collect::<Vec<_>>(),
+                    r.upstreams,
+                    r.strategy,
+                );
+                if r.strict_minecraft {
+                    line.push_str(" strict_minecraft");
+                }
+                if r.host_secret.is_some() {
+                    line.push_str(" host_secret=<set>");
+                }
+                if let Some(pp) = r.proxy_protocol {
+                    line.push_str(&format!(" proxy_protocol={pp:?}"));
+                }
+                line
+            })
+            .collect()
     }
 
     /// Resolve an incoming connection by repeatedly trying each route's configured parser chain.
@@ -97,7 +453,15 @@ impl Router {
     /// - Ok(Some(resolution)) when a route parses and matches
     /// - Ok(None) when no routes can match this prelude (and no route needs more data)
     /// - Err(NeedMoreData) when at least one route needs more bytes to decide
-    pub fn resolve_prelude(&self, prelude: &[u8]) -> Result<Option<Resolution>, MiddlewareError> {
+    ///
+    /// `client` is handed to the middlewares, and its IP keys the `ip-hash` strategy; without
+    /// one those routes keep their configured order.
+    pub fn resolve_prelude(
+        &self,
+        prelude: &[u8],
+        client: ClientMeta,
+    ) -> Result<Option<Resolution>, MiddlewareError> {
+        let client_ip = client.ip;
         let cr = self.compiled.load();
         if cr.routes.is_empty() {
             tracing::debug!(prelude_len = prelude.len(), "router: no routes configured");
@@ -110,7 +474,18 @@ impl Router {
         let mut pattern_misses = 0usize;
 
         for (idx, rt) in cr.routes.iter().enumerate() {
-            match rt.middleware.parse(prelude) {
+            // Cheap pre-check so scanners and TLS/HTTP clients don't sit in the buffer
+            // waiting for a handshake that will never come.
+            if rt.strict_minecraft && !minecraft::looks_like_handshake(prelude) {
+                tracing::debug!(
+                    route_index = idx,
+                    prelude_len = prelude.len(),
+                    first_byte = ?prelude.first(),
+                    "router: strict_minecraft route rejected non-Minecraft prelude"
+                );
+                continue;
+            }
+            match rt.middleware.parse(prelude, client) {
                 Ok((host, prelude_override)) => {
                     parse_hits += 1;
                     let host = normalize_routing_host(&host);
@@ -123,8 +498,9 @@ impl Router {
                     }
                     last_parsed_host = Some(host.clone());
 
-                    if let Some(mut res) = resolve_route_for_host(rt, &host) {
+                    if let Some(mut res) = resolve_route_for_host(rt, &host, client_ip) {
                         res.prelude_override = prelude_override;
+                        self.demote_ejected(&mut res.upstreams);
                         tracing::info!(
                             route_index = idx,
                             host = %res.host,
@@ -195,8 +571,49 @@ impl Router {
         }
     }
 
+    /// Resolve a `protocol = "tls"` connection on the SNI host name in its ClientHello.
+    ///
+    /// Route middlewares and `strict_minecraft` don't apply: the stream is forwarded
+    /// untouched. Returns like `resolve_prelude`; a malformed ClientHello is `Fatal`.
+    pub fn resolve_sni(
+        &self,
+        prelude: &[u8],
+        client: ClientMeta,
+    ) -> Result<Option<Resolution>, MiddlewareError> {
+        match tls::parse_client_hello(prelude) {
+            Ok(tls::ClientHello::Incomplete) => Err(MiddlewareError::NeedMoreData),
+            Ok(tls::ClientHello::Complete { server_name: None }) => {
+                tracing::debug!(
+                    prelude_len = prelude.len(),
+                    "router: ClientHello without SNI"
+                );
+                Ok(None)
+            }
+            Ok(tls::ClientHello::Complete {
+                server_name: Some(host),
+            }) => {
+                let res = self.resolve_for_client(&host, client.ip);
+                match &res {
+                    Some(res) => tracing::info!(
+                        host = %res.host,
+                        matched_host = %res.matched_host,
+                        upstreams = ?res.upstreams,
+                        "router: matched route by SNI"
+                    ),
+                    None => tracing::debug!(host = %host, "router: no route matched SNI"),
+                }
+                Ok(res)
+            }
+            Err(err) => Err(MiddlewareError::Fatal(err.to_string())),
+        }
+    }
+
     #[allow(dead_code)]
     pub fn resolve(&self, host: &str) -> Option<Resolution> {
+        self.resolve_for_client(host, None)
+    }
+
+    fn resolve_for_client(&self, host: &str, client_ip: Option<IpAddr>) -> Option<Resolution> {
         let cr = self.compiled.load();
         if cr.routes.is_empty() {
             return None;
@@ -208,7 +625,8 @@ impl Router {
         }
 
         for rt in &cr.routes {
-            if let Some(res) = resolve_route_for_host(rt, &host) {
+            if let Some(mut res) = resolve_route_for_host(rt, &host, client_ip) {
+                self.demote_ejected(&mut res.upstreams);
                 return Some(res);
             }
         }
@@ -217,6 +635,15 @@ impl Router {
     }
 }
 
+/// Compile `routes` the way `Router::update` does, but fail on the first invalid route
+/// instead of skipping it.
+pub fn check_routes(routes: &[(config::RouteConfig, SharedMiddlewareChain)]) -> anyhow::Result<()> {
+    for (i, (rt, middleware)) in routes.iter().enumerate() {
+        compile_route(rt, middleware.clone()).with_context(|| format!("route[{i}]"))?;
+    }
+    Ok(())
+}
+
 fn compile_route(
     rt: &config::RouteConfig,
     middleware: SharedMiddlewareChain,
@@ -248,16 +675,33 @@ fn compile_route(
             re: Some(re),
         });
     }
+    for r in &rt.host_regex {
+        let re = Regex::new(&format!("^(?:{r})$"))
+            .with_context(|| format!("router: invalid host_regex {r:?}"))?;
+        patterns.push(CompiledPattern {
+            pattern: r.clone(),
+            exact: false,
+            re: Some(re),
+        });
+    }
     if patterns.is_empty() {
         anyhow::bail!("router: route missing host patterns");
     }
+    let fallback = rt.default || patterns.iter().any(|p| p.pattern == "*");
 
-    let upstreams: Vec<String> = rt
-        .upstreams
-        .iter()
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
+    let mut upstreams = Vec::with_capacity(rt.upstreams.len());
+    let mut weights = Vec::with_capacity(rt.upstreams.len());
+    let mut upstream_proxy_protocols = Vec::with_capacity(rt.upstreams.len());
+    for s in &rt.upstreams {
+        let s = s.trim();
+        if s.is_empty() {
+            continue;
+        }
+        let (upstream, opts) = split_upstream_options(s)?;
+        upstreams.push(upstream);
+        weights.push(opts.weight);
+        upstream_proxy_protocols.push(opts.proxy_protocol);
+    }
     if upstreams.is_empty() {
         anyhow::bail!("router: route missing upstreams");
     }
@@ -265,37 +709,127 @@ fn compile_route(
     Ok(CompiledRoute {
         patterns,
         upstreams,
+        weights,
+        upstream_proxy_protocols,
         strategy: parse_strategy(&rt.strategy),
+        fallback,
+        priority: rt.priority,
         rr: AtomicU64::new(0),
         middleware,
+        trace_sample_ratio: rt.trace_sample_ratio,
+        strict_minecraft: rt.strict_minecraft,
+        host_secret: rt.host_secret.clone(),
+        allow_cidrs: rt.allow_cidrs.clone(),
+        deny_cidrs: rt.deny_cidrs.clone(),
+        proxy_protocol: rt.proxy_protocol,
+        offline_status: rt.offline_status.as_deref().map(Arc::from),
+        forwarding: rt.forwarding,
+        forwarding_secret: rt.forwarding_secret.as_deref().map(Arc::from),
+        status_override: rt.status_override.clone().map(Arc::new),
+        connections: Arc::new(telemetry::ConnectionCounter::default()),
+        max_upstream_connections: rt.max_upstream_connections,
+        dial_retries: rt.dial_retries,
+        dial_retry_backoff: rt.dial_retry_backoff,
+        handshake_timeout: rt.handshake_timeout,
+        idle_timeout: rt.idle_timeout,
+        upstream_dial_timeout: rt.upstream_dial_timeout,
     })
 }
 
-fn resolve_route_for_host(rt: &CompiledRoute, host: &str) -> Option<Resolution> {
+/// What makes two route table entries the same route across an update: its host
+/// patterns and whether it is the fallback.
+fn route_identity(c: &CompiledRoute) -> (Vec<String>, bool) {
+    let patterns = c.patterns.iter().map(|p| p.pattern.clone()).collect();
+    (patterns, c.fallback)
+}
+
+fn resolve_route_for_host(
+    rt: &CompiledRoute,
+    host: &str,
+    client_ip: Option<IpAddr>,
+) -> Option<Resolution> {
     let host = normalize_routing_host(host);
     if host.is_empty() {
         return None;
     }
 
+    let Some(secret) = rt.host_secret.as_deref() else {
+        return match_route_patterns(rt, &host, client_ip);
+    };
+    match host.split_once('.') {
+        Some((label, rest)) if secret_eq(label.as_bytes(), secret.as_bytes()) => {
+            match_route_patterns(rt, rest, client_ip)
+        }
+        split => {
+            // Wrong or missing secret: only claim the connection if it was aimed at this
+            // route, so unrelated hosts can still fall through to later routes.
+            let mut res = split
+                .and_then(|(_, rest)| match_route_patterns(rt, rest, client_ip))
+                .or_else(|| match_route_patterns(rt, &host, client_ip))?;
+            res.host_secret_rejected = true;
+            Some(res)
+        }
+    }
+}
+
+/// Whether `client_ip` may use the route: not in `deny_cidrs`, and in `allow_cidrs` when
+/// that is set. An unknown client IP only passes routes without an allow list.
+fn client_permitted(rt: &CompiledRoute, client_ip: Option<IpAddr>) -> bool {
+    let Some(ip) = client_ip else {
+        return rt.allow_cidrs.is_empty();
+    };
+    if rt.deny_cidrs.iter().any(|n| n.contains(ip)) {
+        return false;
+    }
+    rt.allow_cidrs.is_empty() || rt.allow_cidrs.iter().any(|n| n.contains(ip))
+}
+
+fn match_route_patterns(
+    rt: &CompiledRoute,
+    host: &str,
+    client_ip: Option<IpAddr>,
+) -> Option<Resolution> {
     for p in &rt.patterns {
-        let (matched, groups) = match_host(&host, p);
+        let (matched, groups, named) = match_host(host, p);
         if !matched {
             continue;
         }
 
         let mut candidates = Vec::with_capacity(rt.upstreams.len());
-        for u in &rt.upstreams {
-            candidates.push(substitute_params(u, &groups));
+        let mut upstream_proxy_protocols = Vec::new();
+        for (u, pp) in rt.upstreams.iter().zip(&rt.upstream_proxy_protocols) {
+            let candidate = substitute_params(u, &groups, &named);
+            if let Some(pp) = pp {
+                upstream_proxy_protocols.push((candidate.clone(), *pp));
+            }
+            candidates.push(candidate);
         }
-        let candidates = order_candidates(rt, candidates);
+        let candidates = order_candidates(rt, candidates, client_ip);
 
         return Some(Resolution {
             host: host.to_string(),
             upstreams: candidates,
             matched_host: p.pattern.clone(),
             captures: groups,
+            named_captures: named,
             middleware: rt.middleware.clone(),
             prelude_override: None,
+            trace_sampled: sample_trace(rt.trace_sample_ratio),
+            host_secret_rejected: false,
+            client_denied: !client_permitted(rt, client_ip),
+            proxy_protocol: rt.proxy_protocol,
+            upstream_proxy_protocols,
+            offline_status: rt.offline_status.clone(),
+            forwarding: rt.forwarding,
+            forwarding_secret: rt.forwarding_secret.clone(),
+            status_override: rt.status_override.clone(),
+            connections: rt.connections.clone(),
+            max_upstream_connections: rt.max_upstream_connections,
+            dial_retries: rt.dial_retries,
+            dial_retry_backoff: rt.dial_retry_backoff,
+            handshake_timeout: rt.handshake_timeout,
+            idle_timeout: rt.idle_timeout,
+            upstream_dial_timeout: rt.upstream_dial_timeout,
         });
     }
 
@@ -361,10 +895,61 @@ fn parse_strategy(s: &str) -> Strategy {
         "" | "sequential" => Strategy::Sequential,
         "random" => Strategy::Random,
         "round-robin" | "roundrobin" => Strategy::RoundRobin,
+        "weighted" => Strategy::Weighted,
+        "ip-hash" | "iphash" => Strategy::IpHash,
         _ => Strategy::Sequential,
     }
 }
 
+/// Options appended to an upstream entry as `#key=value` suffixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpstreamOptions {
+    /// `#weight=N`: relative share of first picks under `strategy = "weighted"` (default 1).
+    pub weight: u32,
+    /// `#proxy=none|v1|v2`: PROXY protocol for this upstream only, beating the route and
+    /// global settings.
+    pub proxy_protocol: Option<config::ProxyProtocol>,
+}
+
+impl Default for UpstreamOptions {
+    fn default() -> Self {
+        Self {
+            weight: 1,
+            proxy_protocol: None,
+        }
+    }
+}
+
+/// Splits optional `#weight=N` / `#proxy=...` suffixes off an upstream entry.
+pub fn split_upstream_options(s: &str) -> anyhow::Result<(String, UpstreamOptions)> {
+    let mut parts = s.split('#');
+    let upstream = parts.next().unwrap_or_default().trim();
+    let mut opts = UpstreamOptions::default();
+    for opt in parts {
+        match opt.trim().split_once('=') {
+            Some(("weight", w)) => {
+                opts.weight = w
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|w| *w > 0)
+                    .ok_or_else(|| anyhow::anyhow!("router: invalid upstream weight in {s:?}"))?;
+            }
+            Some(("proxy", v)) => {
+                opts.proxy_protocol = Some(
+                    config::ProxyProtocol::parse(v)
+                        .with_context(|| format!("router: invalid upstream proxy in {s:?}"))?,
+                );
+            }
+            _ => anyhow::bail!("router: unknown upstream option {:?} in {s:?}", opt.trim()),
+        }
+    }
+    if upstream.is_empty() {
+        anyhow::bail!("router: missing upstream address in {s:?}");
+    }
+    Ok((upstream.to_string(), opts))
+}
+
 fn compile_wildcard_pattern(pattern: &str) -> anyhow::Result<Regex> {
     let pattern = pattern.trim().to_ascii_lowercase();
     if pattern.is_empty() {
@@ -403,16 +988,16 @@ fn compile_wildcard_pattern(pattern: &str) -> anyhow::Result<Regex> {
     Ok(Regex::new(&out)?)
 }
 
-fn match_host(host: &str, p: &CompiledPattern) -> (bool, Vec<String>) {
+fn match_host(host: &str, p: &CompiledPattern) -> (bool, Vec<String>, Vec<(String, String)>) {
     if p.exact {
-        return (host == p.pattern, Vec::new());
+        return (host == p.pattern, Vec::new(), Vec::new());
     }
     let Some(re) = &p.re else {
-        return (false, Vec::new());
+        return (false, Vec::new(), Vec::new());
     };
 
     let Some(caps) = re.captures(host) else {
-        return (false, Vec::new());
+        return (false, Vec::new(), Vec::new());
     };
 
     let mut groups = Vec::new();
@@ -421,24 +1006,91 @@ fn match_host(host: &str, p: &CompiledPattern) -> (bool, Vec<String>) {
             groups.push(m.as_str().to_string());
         }
     }
+    let named = re
+        .capture_names()
+        .flatten()
+        .filter_map(|name| Some((name.to_string(), caps.name(name)?.as_str().to_string())))
+        .collect();
 
-    (true, groups)
+    (true, groups, named)
 }
 
-pub(crate) fn substitute_params(template: &str, groups: &[String]) -> String {
-    if template.is_empty() || groups.is_empty() {
+/// Expand `$1`, `$2`... (positional captures) and `$name` / `${name}` (named captures) in
+/// `template`. References without a matching capture are kept as written.
+pub(crate) fn substitute_params(
+    template: &str,
+    groups: &[String],
+    named: &[(String, String)],
+) -> String {
+    if template.is_empty() || (groups.is_empty() && named.is_empty()) {
         return template.to_string();
     }
 
-    // Replace from the end so $10 doesn't interfere with $1.
-    let mut res = template.to_string();
-    for i in (1..=groups.len()).rev() {
-        res = res.replace(&format!("${i}"), &groups[i - 1]);
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(at) = rest.find('$') {
+        res.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+
+        let (braced, body) = match rest.strip_prefix('{') {
+            Some(inner) => match inner.find('}') {
+                Some(end) => (true, &inner[..end]),
+                None => (false, ""),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (false, &rest[..end])
+            }
+        };
+
+        match expand_param(body, braced, groups, named) {
+            Some((value, used)) => {
+                res.push_str(value);
+                // Braces are consumed along with the reference.
+                let consumed = if braced { body.len() + 2 } else { used };
+                rest = &rest[consumed..];
+            }
+            None => res.push('$'),
+        }
     }
+    res.push_str(rest);
     res
 }
 
-fn order_candidates(rt: &CompiledRoute, candidates: Vec<String>) -> Vec<String> {
+/// Value for the reference `body` (the text after `$`, or inside `${...}`) and how many of
+/// its bytes it used. Unbraced positional references take the longest digit prefix that
+/// names a capture, so with fewer than ten groups `$10` is `$1` followed by `0`.
+fn expand_param<'a>(
+    body: &str,
+    braced: bool,
+    groups: &'a [String],
+    named: &'a [(String, String)],
+) -> Option<(&'a str, usize)> {
+    let digits = body.bytes().take_while(u8::is_ascii_digit).count();
+    if digits > 0 {
+        if body.starts_with('0') || (braced && digits != body.len()) {
+            return None;
+        }
+        let shortest = if braced { digits } else { 1 };
+        return (shortest..=digits).rev().find_map(|len| {
+            let n: usize = body[..len].parse().ok()?;
+            let group = groups.get(n.checked_sub(1)?)?;
+            Some((group.as_str(), len))
+        });
+    }
+    named
+        .iter()
+        .find(|(name, _)| name == body)
+        .map(|(_, value)| (value.as_str(), body.len()))
+}
+
+fn order_candidates(
+    rt: &CompiledRoute,
+    candidates: Vec<String>,
+    client_ip: Option<IpAddr>,
+) -> Vec<String> {
     if candidates.len() <= 1 {
         return candidates;
     }
@@ -453,6 +1105,64 @@ fn order_candidates(rt: &CompiledRoute, candidates: Vec<String>) -> Vec<String>
             let start = (rt.rr.fetch_add(1, Ordering::Relaxed) as usize) % candidates.len();
             rotate(candidates, start)
         }
+        Strategy::Weighted => weighted_order(candidates, &rt.weights),
+        Strategy::IpHash => match client_ip {
+            Some(ip) => rendezvous_order(candidates, ip),
+            None => candidates,
+        },
+    }
+}
+
+/// Rendezvous (highest random weight) order for a client IP: the same IP always
+/// prefers the same upstream, and removing an upstream only moves the clients that
+/// preferred it. Weights come from SHA-256, so the mapping also survives restarts,
+/// upgrades and other Prism instances behind the same load balancer.
+fn rendezvous_order(candidates: Vec<String>, ip: IpAddr) -> Vec<String> {
+    use sha2::{Digest, Sha256};
+
+    let ip = match ip.to_canonical() {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+    let mut keyed: Vec<(u64, String)> = candidates
+        .into_iter()
+        .map(|c| {
+            let mut h = Sha256::new();
+            h.update(&ip);
+            h.update(c.as_bytes());
+            let digest = h.finalize();
+            let weight = u64::from_be_bytes(digest[..8].try_into().expect("8 bytes"));
+            (weight, c)
+        })
+        .collect();
+    keyed.sort_by_key(|k| std::cmp::Reverse(k.0));
+    keyed.into_iter().map(|(_, c)| c).collect()
+}
+
+/// Weighted random order without replacement: each upstream is picked first with
+/// probability proportional to its weight, and the rest follow as failover candidates.
+fn weighted_order(candidates: Vec<String>, weights: &[u32]) -> Vec<String> {
+    let mut rng = rng();
+    // Efraimidis-Spirakis: sort by u^(1/w), largest first.
+    let mut keyed: Vec<(f64, String)> = candidates
+        .into_iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let w = f64::from(weights.get(i).copied().unwrap_or(1).max(1));
+            let u: f64 = rng.random_range(f64::MIN_POSITIVE..1.0);
+            (u.powf(1.0 / w), c)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    keyed.into_iter().map(|(_, c)| c).collect()
+}
+
+fn sample_trace(ratio: Option<f64>) -> bool {
+    match ratio {
+        None => true,
+        Some(r) if r >= 1.0 => true,
+        Some(r) if r <= 0.0 => false,
+        Some(r) => rng().random_bool(r),
     }
 }
 
@@ -478,6 +1188,10 @@ mod tests {
     use std::sync::Arc;
 
     fn noop_router(cfg: config::RouteConfig) -> Router {
+        noop_router_with(vec![cfg])
+    }
+
+    fn noop_router_with(cfgs: Vec<config::RouteConfig>) -> Router {
         struct NoopChain;
         impl crate::prism::middleware::MiddlewareChain for NoopChain {
             fn name(&self) -> &str {
@@ -487,18 +1201,60 @@ mod tests {
             fn parse(
                 &self,
                 _prelude: &[u8],
+                _client: ClientMeta,
             ) -> Result<(String, Option<Vec<u8>>), crate::prism::middleware::MiddlewareError>
             {
                 Err(crate::prism::middleware::MiddlewareError::NoMatch)
             }
 
-            fn rewrite(&self, _prelude: &[u8], _selected_upstream: &str) -> Option<Vec<u8>> {
+            fn rewrite(
+                &self,
+                _prelude: &[u8],
+                _selected_upstream: &str,
+                _client: ClientMeta,
+            ) -> Option<Vec<u8>> {
                 None
             }
         }
 
         let chain = Arc::new(NoopChain) as crate::prism::middleware::SharedMiddlewareChain;
-        Router::new(vec![(cfg, chain)])
+        Router::new(cfgs.into_iter().map(|cfg| (cfg, chain.clone())).collect())
+    }
+
+    #[test]
+    fn route_connection_counts_survive_updates() {
+        let route = |host: &str| config::RouteConfig {
+            host: vec![host.into()],
+            upstreams: vec!["a.backend:25565".into()],
+            strategy: "sequential".into(),
+            max_upstream_connections: 1,
+            ..Default::default()
+        };
+        let r = noop_router_with(vec![route("play.example.com"), route("b.example.com")]);
+        let chain = r.compiled.load().routes[0].middleware.clone();
+        let held = r
+            .resolve("play.example.com")
+            .expect("match")
+            .connections
+            .try_acquire(1)
+            .expect("slot");
+
+        // The same route, now with a different upstream list, still sees the session.
+        let mut changed = route("play.example.com");
+        changed.upstreams.push("b.backend:25565".into());
+        r.update(vec![
+            (changed, chain.clone()),
+            (route("c.example.com"), chain),
+        ]);
+        let res = r.resolve("play.example.com").expect("match");
+        assert_eq!(res.connections.active(), 1);
+        assert!(res.connections.try_acquire(1).is_none());
+        drop(held);
+        assert_eq!(res.connections.active(), 0);
+
+        // A route new to the table starts from zero.
+        let res = r.resolve("c.example.com").expect("match");
+        assert_eq!(res.connections.active(), 0);
     }
 
     #[test]
@@ -508,6 +1264,7 @@ mod tests {
             upstreams: vec!["$1.backend:25565".into()],
             strategy: "sequential".into(),
             middlewares: vec!["noop".into()],
+            ..Default::default()
         };
 
         let r = noop_router(cfg);
@@ -516,6 +1273,294 @@ mod tests {
         assert_eq!(res.captures, vec!["play".to_string()]);
     }
 
+    #[test]
+    fn weighted_strategy_picks_upstreams_in_proportion() {
+        let cfg = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["a.backend:25565#weight=3".into(), "b.backend:25565".into()],
+            strategy: "weighted".into(),
+            middlewares: vec!["noop".into()],
+            ..Default::default()
+        };
+
+        let r = noop_router(cfg);
+        let draws = 10_000;
+        let mut first_a = 0;
+        for _ in 0..draws {
+            let res = r.resolve("play.example.com").expect("match");
+            assert_eq!(res.upstreams.len(), 2);
+            if res.upstreams[0] == "a.backend:25565" {
+                first_a += 1;
+            } else {
+                assert_eq!(res.upstreams[0], "b.backend:25565");
+            }
+        }
+        let share = f64::from(first_a) / f64::from(draws);
+        assert!((0.72..0.78).contains(&share), "share = {share}");
+    }
+
+    #[test]
+    fn ip_hash_is_sticky_and_remaps_minimally() {
+        let pool: Vec<String> = (0..5).map(|i| format!("b{i}.backend:25565")).collect();
+        let clients: Vec<IpAddr> = (0..2000u32)
+            .map(|i| IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + i)))
+            .collect();
+
+        let before: Vec<String> = clients
+            .iter()
+            .map(|ip| rendezvous_order(pool.clone(), *ip).remove(0))
+            .collect();
+        for (ip, first) in clients.iter().zip(&before) {
+            assert_eq!(&rendezvous_order(pool.clone(), *ip)[0], first);
+        }
+
+        let removed = &pool[2];
+        let smaller: Vec<String> = pool.iter().filter(|u| *u != removed).cloned().collect();
+        let mut moved = 0;
+        for (ip, first) in clients.iter().zip(&before) {
+            let now = rendezvous_order(smaller.clone(), *ip).remove(0);
+            if first == removed {
+                moved += 1;
+            } else {
+                assert_eq!(&now, first, "client {ip} moved off a surviving upstream");
+            }
+        }
+        // Only the removed upstream's share (~1/5) is reshuffled.
+        assert!((300..500).contains(&moved), "moved = {moved}");
+    }
+
+    #[test]
+    fn ip_hash_order_is_fixed_across_builds() {
+        let pool: Vec<String> = (0..5).map(|i| format!("b{i}.backend:25565")).collect();
+        let order = |ip: &str| -> Vec<String> {
+            rendezvous_order(pool.clone(), ip.parse().unwrap())
+                .into_iter()
+                .map(|u| u[1..2].to_string())
+                .collect()
+        };
+        assert_eq!(order("203.0.113.7"), ["0", "1", "4", "2", "3"]);
+        assert_eq!(order("2001:db8::1"), ["1", "2", "3", "4", "0"]);
+        // An IPv4-mapped address hashes as the IPv4 client it is.
+        assert_eq!(order("::ffff:203.0.113.7"), order("203.0.113.7"));
+    }
+
+    #[test]
+    fn route_cidrs_gate_client_ips() {
+        let cidrs = |v: &[&str]| v.iter().map(|c| IpNet::parse(c).expect("cidr")).collect();
+        let route = |allow: &[&str], deny: &[&str]| config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["a.backend:25565".into()],
+            strategy: "sequential".into(),
+            middlewares: vec!["noop".into()],
+            allow_cidrs: cidrs(allow),
+            deny_cidrs: cidrs(deny),
+            ..Default::default()
+        };
+        let denied = |r: &Router, ip: Option<&str>| {
+            let routes = r.compiled.load();
+            let ip = ip.map(|ip| ip.parse().expect("ip"));
+            resolve_route_for_host(&routes.routes[0], "play.example.com", ip)
+                .expect("match")
+                .client_denied
+        };
+
+        // Allow-only, with a deny carve-out that takes precedence.
+        let staff = noop_router(route(&["10.0.0.0/8", "2001:db8::/32"], &["10.6.6.0/24"]));
+        assert!(!denied(&staff, Some("10.1.2.3")));
+        assert!(!denied(&staff, Some("::ffff:10.1.2.3")));
+        assert!(denied(&staff, Some("192.0.2.1")));
+        assert!(denied(&staff, Some("10.6.6.7")));
+        assert!(!denied(&staff, Some("2001:db8::1")));
+        assert!(denied(&staff, Some("2001:db9::1")));
+        assert!(denied(&staff, None));
+
+        // Deny-only lets everyone else through.
+        let public = noop_router(route(&[], &["2001:db8:bad::/48"]));
+        assert!(denied(&public, Some("2001:db8:bad::7")));
+        assert!(!denied(&public, Some("2001:db8:600d::7")));
+        assert!(!denied(&public, Some("192.0.2.1")));
+        assert!(!denied(&public, None));
+    }
+
+    #[test]
+    fn upstream_suffixes_set_weight_and_proxy_protocol() {
+        let (addr, opts) = split_upstream_options("a.backend:25565#weight=3#proxy=v2").unwrap();
+        assert_eq!(addr, "a.backend:25565");
+        assert_eq!(opts.weight, 3);
+        assert_eq!(opts.proxy_protocol, Some(config::ProxyProtocol::V2));
+        assert_eq!(
+            split_upstream_options("b.backend:25565").unwrap().1,
+            UpstreamOptions::default()
+        );
+        for bad in ["a:1#proxy=v3", "a:1#color=red", "#proxy=v1"] {
+            assert!(split_upstream_options(bad).is_err(), "{bad}");
+        }
+
+        let cfg = config::RouteConfig {
+            host: vec!["*.example.com".into()],
+            upstreams: vec!["$1.backend:25565#proxy=v1".into(), "spare:25565".into()],
+            middlewares: vec!["noop".into()],
+            ..Default::default()
+        };
+        let res = noop_router(cfg).resolve("play.example.com").expect("match");
+        assert_eq!(res.upstreams, vec!["play.backend:25565", "spare:25565"]);
+        assert_eq!(
+            res.upstream_proxy_protocols,
+            vec![("play.backend:25565".to_string(), config::ProxyProtocol::V1)]
+        );
+    }
+
+    #[test]
+    fn invalid_upstream_weight_skips_the_route() {
+        let cfg = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["a.backend:25565#weight=0".into()],
+            strategy: "weighted".into(),
+            middlewares: vec!["noop".into()],
+            ..Default::default()
+        };
+
+        assert!(noop_router(cfg).resolve("play.example.com").is_none());
+    }
+
+    #[test]
+    fn higher_priority_routes_are_tried_first() {
+        let route = |host: &str, upstream: &str, priority| config::RouteConfig {
+            host: vec![host.into()],
+            upstreams: vec![upstream.into()],
+            strategy: "sequential".into(),
+            middlewares: vec!["noop".into()],
+            priority,
+            ..Default::default()
+        };
+        let r = noop_router_with(vec![
+            route("*.example.com", "wildcard.backend:25565", 0),
+            route("*.example.com", "later.backend:25565", 0),
+            route("play.example.com", "play.backend:25565", 10),
+        ]);
+
+        let res = r.resolve("play.example.com").expect("match");
+        assert_eq!(res.upstreams, vec!["play.backend:25565".to_string()]);
+        // Equal priorities keep config order.
+        let res = r.resolve("lobby.example.com").expect("match");
+        assert_eq!(res.upstreams, vec!["wildcard.backend:25565".to_string()]);
+    }
+
+    #[test]
+    fn default_route_only_catches_unmatched_hosts() {
+        let route = |host: &str, upstream: &str, default: bool| config::RouteConfig {
+            host: vec![host.into()],
+            upstreams: vec![upstream.into()],
+            strategy: "sequential".into(),
+            middlewares: vec!["noop".into()],
+            default,
+            ..Default::default()
+        };
+        // Listed first, but still evaluated after the specific routes.
+        let r = noop_router_with(vec![
+            route("lobby.*", "lobby.backend:25565", true),
+            route("*", "fallback.backend:25565", false),
+            route("play.example.com", "play.backend:25565", false),
+        ]);
+
+        let res = r.resolve("play.example.com").expect("specific");
+        assert_eq!(res.upstreams, vec!["play.backend:25565".to_string()]);
+        let res = r.resolve("unknown.example.net").expect("default");
+        assert_eq!(res.upstreams, vec!["fallback.backend:25565".to_string()]);
+        let res = r.resolve("lobby.example.net").expect("default");
+        assert_eq!(res.upstreams, vec!["lobby.backend:25565".to_string()]);
+    }
+
+    #[test]
+    fn host_regex_named_captures_fill_upstream_templates() {
+        let r = noop_router_with(vec![
+            config::RouteConfig {
+                host_regex: vec![r"(?P<region>\w+)\.(\w+)\.play\.example\.com".into()],
+                upstreams: vec![
+                    "$region.backend:25565".into(),
+                    "${region}-$2.backend".into(),
+                ],
+                strategy: "sequential".into(),
+                middlewares: vec!["noop".into()],
+                ..Default::default()
+            },
+            config::RouteConfig {
+                host: vec!["*.example.com".into()],
+                upstreams: vec!["$1.glob.backend:25565".into()],
+                strategy: "sequential".into(),
+                middlewares: vec!["noop".into()],
+                ..Default::default()
+            },
+        ]);
+
+        let res = r.resolve("eu.sky.play.example.com").expect("regex match");
+        assert_eq!(
+            res.upstreams,
+            vec!["eu.backend:25565", "eu-sky.backend"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            res.named_captures,
+            vec![("region".to_string(), "eu".to_string())]
+        );
+
+        // The regex is anchored to the whole host; other hosts fall through to the glob.
+        let res = r.resolve("play.example.com").expect("glob match");
+        assert_eq!(res.upstreams, vec!["play.glob.backend:25565".to_string()]);
+    }
+
+    #[test]
+    fn substitute_params_mixes_positional_and_named_references() {
+        let groups = vec!["a".to_string(), "b".to_string()];
+        let named = vec![("zone".to_string(), "eu".to_string())];
+        assert_eq!(
+            substitute_params("$1-$zone.${zone}x:$2$10", &groups, &named),
+            "a-eu.eux:ba0"
+        );
+        assert_eq!(
+            substitute_params("$3 $zones ${nope} $ ${1", &groups, &named),
+            "$3 $zones ${nope} $ ${1"
+        );
+        let many: Vec<String> = (1..=10).map(|i| format!("g{i}")).collect();
+        assert_eq!(substitute_params("$10|$1", &many, &[]), "g10|g1");
+    }
+
+    #[test]
+    fn failing_upstream_is_ejected_for_the_cooldown() {
+        let cfg = config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["a.backend:25565".into(), "b.backend:25565".into()],
+            strategy: "sequential".into(),
+            middlewares: vec!["noop".into()],
+            ..Default::default()
+        };
+        let r = noop_router(cfg);
+        let order = || r.resolve("play.example.com").expect("match").upstreams;
+        let cooldown = Duration::from_millis(50);
+
+        r.record_dial("a.backend:25565", false, 3, cooldown);
+        r.record_dial("a.backend:25565", false, 3, cooldown);
+        assert_eq!(order()[0], "a.backend:25565");
+        r.record_dial("a.backend:25565", false, 3, cooldown);
+        assert_eq!(order(), vec!["b.backend:25565", "a.backend:25565"]);
+        assert!(
+            r.render_prometheus()
+                .contains("prism_upstream_unhealthy{upstream=\"a.backend:25565\"} 1\n")
+        );
+
+        // After the cooldown it is tried first again; one more failure re-ejects it.
+        std::thread::sleep(cooldown + Duration::from_millis(20));
+        assert_eq!(order()[0], "a.backend:25565");
+        r.record_dial("a.backend:25565", false, 3, cooldown);
+        assert_eq!(order()[0], "b.backend:25565");
+
+        r.record_dial("a.backend:25565", true, 3, cooldown);
+        assert_eq!(order(), vec!["a.backend:25565", "b.backend:25565"]);
+        assert!(!r.render_prometheus().contains("a.backend"));
+    }
+
     #[test]
     fn wildcard_tunnel_service_substitution() {
         let cfg = config::RouteConfig {
@@ -523,6 +1568,7 @@ mod tests {
             upstreams: vec!["tunnel:$1".into()],
             strategy: "sequential".into(),
             middlewares: vec!["noop".into()],
+            ..Default::default()
         };
 
         let r = noop_router(cfg);
@@ -541,6 +1587,7 @@ mod tests {
             upstreams: vec!["tunnel:$1".into()],
             strategy: "sequential".into(),
             middlewares: vec!["noop".into()],
+            ..Default::default()
         };
 
         let r = noop_router(cfg);
@@ -552,6 +1599,117 @@ mod tests {
         assert_eq!(res.upstreams[0], "tunnel:atm10sky");
     }
 
+    #[test]
+    fn trace_sample_ratio_is_respected() {
+        let route = |ratio: Option<f64>| config::RouteConfig {
+            host: vec!["play.example.com".into()],
+            upstreams: vec!["127.0.0.1:25565".into()],
+            strategy: "sequential".into(),
+            middlewares: vec!["noop".into()],
+            trace_sample_ratio: ratio,
+            ..Default::default()
+        };
+        let sampled = |r: &Router, n: usize| {
+            (0..n)
+                .filter(|_| r.resolve("play.example.com").expect("match").trace_sampled)
+                .count()
+        };
+
+        assert_eq!(sampled(&noop_router(route(None)), 100), 100);
+        assert_eq!(sampled(&noop_router(route(Some(0.0))), 100), 0);
+        assert_eq!(sampled(&noop_router(route(Some(1.0))), 100), 100);
+
+        let hits = sampled(&noop_router(route(Some(0.25))), 10_000);
+        assert!((2000..=3000).contains(&hits), "hits={hits}");
+    }
+
+    #[test]
+    fn strict_minecraft_route_rejects_non_minecraft_prelude_immediately() {
+        // A chain that keeps asking for more bytes, like a handshake parser fed garbage.
+        struct PendingChain;
+        impl crate::prism::middleware::MiddlewareChain for PendingChain {
+            fn name(&self) -> &str {
+                "pending"
+            }
+
+            fn parse(
+                &self,
+                _prelude: &[u8],
+                _client: ClientMeta,
+            ) -> Result<(String, Option<Vec<u8>>), crate::prism::middleware::MiddlewareError>
+            {
+                Err(crate::prism::middleware::MiddlewareError::NeedMoreData)
+            }
+
+            fn rewrite(
+                &self,
+                _prelude: &[u8],
+                _selected_upstream: &str,
+                _client: ClientMeta,
+            ) -> Option<Vec<u8>> {
+                None
+            }
+        }
+
+        let router = |strict: bool| {
+            let cfg = config::RouteConfig {
+                host: vec!["play.example.com".into()],
+                upstreams: vec!["127.0.0.1:25565".into()],
+                strategy: "sequential".into(),
+                middlewares: vec!["pending".into()],
+                strict_minecraft: strict,
+                ..Default::default()
+            };
+            let chain = Arc::new(PendingChain) as crate::prism::middleware::SharedMiddlewareChain;
+            Router::new(vec![(cfg, chain)])
+        };
+        let tls_client_hello = [0x16, 0x03, 0x01, 0x00, 0xc8, 0x01];
+
+        assert!(matches!(
+            router(false).resolve_prelude(&tls_client_hello, ClientMeta::default()),
+            Err(MiddlewareError::NeedMoreData)
+        ));
+        assert!(matches!(
+            router(true).resolve_prelude(&tls_client_hello, ClientMeta::default()),
+            Ok(None)
+        ));
+        assert!(router(true).rejects_non_minecraft(&tls_client_hello));
+        assert!(!router(false).rejects_non_minecraft(&tls_client_hello));
+        // Plausible handshake prefixes still reach the middleware.
+        assert!(matches!(
+            router(true).resolve_prelude(&[0x10, 0x00], ClientMeta::default()),
+            Err(MiddlewareError::NeedMoreData)
+        ));
+    }
+
+    #[test]
+    fn host_secret_is_stripped_and_validated() {
+        let r = noop_router(config::RouteConfig {
+            host: vec!["*.example.com".into()],
+            upstreams: vec!["$1-backend:25565".into()],
+            strategy: "sequential".into(),
+            middlewares: vec!["noop".into()],
+            host_secret: Some("s3cret".into()),
+            ..Default::default()
+        });
+
+        let ok = r.resolve("S3cret.Play.Example.com").expect("match");
+        assert!(!ok.host_secret_rejected);
+        assert_eq!(ok.host, "play.example.com");
+        assert_eq!(ok.upstreams, vec!["play-backend:25565".to_string()]);
+
+        for host in [
+            "wrong.play.example.com",
+            "play.example.com",
+            "s3cre.play.example.com",
+        ] {
+            let res = r.resolve(host).expect("claimed by secret route");
+            assert!(res.host_secret_rejected, "{host}");
+        }
+
+        assert!(r.resolve("play.other.net").is_none());
+    }
+
     #[test]
     fn normalize_routing_host_variants() {
         assert_eq!(
@@ -565,4 +1723,64 @@ mod tests {
         assert_eq!(normalize_routing_host("[::1]:25565"), "::1");
         assert_eq!(normalize_routing_host("2001:db8::1"), "2001:db8::1");
     }
+
+    #[test]
+    fn static_routes_resolve_without_wasm() {
+        let r = Router::from_static(vec![
+            StaticRoute {
+                hosts: vec!["play.example.com".into()],
+                upstreams: vec!["10.0.0.1:25565".into()],
+                strategy: String::new(),
+                parser: HostParser::Minecraft,
+            },
+            StaticRoute {
+                hosts: vec!["*.tls.example.com".into()],
+                upstreams: vec!["10.0.0.2:443".into()],
+                strategy: "round-robin".into(),
+                parser: HostParser::Sni,
+            },
+            StaticRoute {
+                hosts: vec!["custom".into()],
+                upstreams: vec!["10.0.0.3:80".into()],
+                strategy: String::new(),
+                parser: HostParser::Custom(Arc::new(|p: &[u8]| {
+                    if p.starts_with(b"HELLO ") {
+                        Ok(String::from_utf8_lossy(&p[6..]).into_owned())
+                    } else {
+                        Err(MiddlewareError::NoMatch)
+                    }
+                })),
+            },
+        ]);
+
+        let mut payload = Vec::new();
+        minecraft::write_varint(763, &mut payload);
+        minecraft::write_string("Play.Example.com\0FML\0", &mut payload);
+        payload.extend_from_slice(&25565u16.to_be_bytes());
+        minecraft::write_varint(minecraft::NEXT_STATE_LOGIN, &mut payload);
+        let hs = minecraft::packet(0x00, &payload);
+        let res = r
+            .resolve_prelude(&hs, ClientMeta::default())
+            .expect("resolve")
+            .expect("match");
+        assert_eq!(res.host, "play.example.com");
+        assert_eq!(res.upstreams, vec!["10.0.0.1:25565".to_string()]);
+        assert!(matches!(
+            r.resolve_prelude(&hs[..hs.len() - 3], ClientMeta::default()),
+            Err(MiddlewareError::NeedMoreData)
+        ));
+
+        let hello = tls::client_hello_with_sni("a.tls.example.com");
+        let res = r
+            .resolve_prelude(&hello, ClientMeta::default())
+            .expect("resolve")
+            .expect("match");
+        assert_eq!(res.upstreams, vec!["10.0.0.2:443".to_string()]);
+
+        let res = r
+            .resolve_prelude(b"HELLO custom", ClientMeta::default())
+            .expect("resolve")
+            .expect("match");
+        assert_eq!(res.upstreams, vec!["10.0.0.3:80".to_string()]);
+    }
 }
@@ -1,6 +1,7 @@
 use std::path::{Component, Path, PathBuf};
 
 use anyhow::Context;
+#[cfg(not(target_os = "linux"))]
 use directories::ProjectDirs;
 
 #[derive(Debug, Clone)]
@@ -47,7 +48,7 @@ fn resolve_workdir(flag_or_env: Option<PathBuf>) -> anyhow::Result<PathBuf> {
     Ok(wd)
 }
 
-fn resolve_middleware_dir(
+pub fn resolve_middleware_dir(
     config_dir: &Path,
     flag_or_env: Option<PathBuf>,
 ) -> anyhow::Result<PathBuf> {
@@ -76,7 +77,7 @@ fn default_workdir() -> anyhow::Result<PathBuf> {
     // Linux: system-wide state dir.
     #[cfg(target_os = "linux")]
     {
-        return Ok(PathBuf::from("/var/lib/prism"));
+        Ok(PathBuf::from("/var/lib/prism"))
     }
 
     // Other OSes: per-user data dir.
@@ -0,0 +1,345 @@
+//! Classify a new connection from its first bytes without consuming them.
+//!
+//! Used by routing listeners with `sniff_protocols` enabled so one port can take both
+//! plain clients and clients behind a PROXY-protocol load balancer.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::Context;
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use crate::prism::{config::HealthProbe, minecraft};
+
+/// Signature that starts every PROXY protocol v2 header.
+const PROXY_V2_SIG: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// A v1 header line is at most 107 bytes including the CRLF.
+const PROXY_V1_MAX_LEN: usize = 107;
+/// Bytes after which an undecided prefix is classified as `Unknown`.
+const SNIFF_LEN: usize = 16;
+/// Delay between peeks while waiting for more bytes; peek returns immediately once
+/// any data is buffered, so polling is the only way to wait for the rest.
+const PEEK_RETRY: Duration = Duration::from_millis(5);
+/// How long a lone `FE` / `FE 01` may sit before it is taken as a legacy ping; those
+/// clients send nothing more until they get an answer.
+const LEGACY_PING_SETTLE: Duration = Duration::from_millis(100);
+
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"POST ",
+    b"PUT ",
+    b"HEAD ",
+    b"DELETE ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    ProxyV1,
+    ProxyV2,
+    Tls,
+    Http,
+    Minecraft,
+    Unknown,
+}
+
+/// Classify a connection from its leading bytes, or None if more bytes are needed.
+pub fn classify(buf: &[u8]) -> Option<Protocol> {
+    if buf.is_empty() {
+        return None;
+    }
+    let is_prefix = |sig: &[u8]| {
+        let n = buf.len().min(sig.len());
+        buf[..n] == sig[..n]
+    };
+
+    if buf.starts_with(PROXY_V2_SIG) {
+        return Some(Protocol::ProxyV2);
+    }
+    if buf.starts_with(b"PROXY ") {
+        return Some(Protocol::ProxyV1);
+    }
+    if buf.len() >= 2 && buf[0] == 0x16 && buf[1] == 0x03 {
+        return Some(Protocol::Tls);
+    }
+    if HTTP_METHODS.iter().any(|m| buf.starts_with(m)) {
+        return Some(Protocol::Http);
+    }
+    // Legacy (pre-1.7) server list ping.
+    if buf[0] == 0xFE {
+        return Some(Protocol::Minecraft);
+    }
+    let undecided = buf.len() < SNIFF_LEN
+        && (is_prefix(PROXY_V2_SIG)
+            || is_prefix(b"PROXY ")
+            || (buf.len() < 2 && buf[0] == 0x16)
+            || HTTP_METHODS.iter().any(|m| is_prefix(m)));
+    if undecided {
+        return None;
+    }
+    if minecraft::looks_like_handshake(buf) {
+        // Complete once the length VarInt and the packet id are in.
+        return match minecraft::read_varint(buf, 0) {
+            Some((_, n)) if buf.len() > n => Some(Protocol::Minecraft),
+            _ if buf.len() >= SNIFF_LEN => Some(Protocol::Unknown),
+            _ => None,
+        };
+    }
+    Some(Protocol::Unknown)
+}
+
+/// Peek at `conn` until its protocol can be classified. Nothing is consumed.
+///
+/// A connection that closes before sending enough bytes is `Unknown`.
+pub async fn peek_protocol(conn: &TcpStream) -> std::io::Result<Protocol> {
+    let mut buf = [0u8; SNIFF_LEN];
+    let mut last = 0;
+    loop {
+        let n = conn.peek(&mut buf).await?;
+        if n == 0 {
+            return Ok(Protocol::Unknown);
+        }
+        if let Some(p) = classify(&buf[..n]) {
+            return Ok(p);
+        }
+        if n == last {
+            tokio::time::sleep(PEEK_RETRY).await;
+        }
+        last = n;
+    }
+}
+
+/// Peek at `conn` to tell whether it opens with a pre-1.7 server list ping. Nothing is
+/// consumed.
+pub async fn peek_legacy_ping(conn: &TcpStream) -> std::io::Result<bool> {
+    let mut buf = [0u8; 3];
+    let mut last = 0;
+    let mut stalled_since: Option<tokio::time::Instant> = None;
+    loop {
+        let n = conn.peek(&mut buf).await?;
+        if n == 0 {
+            return Ok(false);
+        }
+        if let Some(legacy) = minecraft::legacy_ping(&buf[..n]) {
+            return Ok(legacy);
+        }
+        if n == last {
+            let since = *stalled_since.get_or_insert_with(tokio::time::Instant::now);
+            if since.elapsed() >= LEGACY_PING_SETTLE {
+                return Ok(true);
+            }
+            tokio::time::sleep(PEEK_RETRY).await;
+        } else {
+            stalled_since = None;
+        }
+        last = n;
+    }
+}
+
+/// Peek just far enough to tell whether `conn` starts with a PROXY protocol header
+/// (`ProxyV1`/`ProxyV2`) or not (None). Nothing is consumed.
+pub async fn peek_proxy_header(conn: &TcpStream) -> std::io::Result<Option<Protocol>> {
+    let mut buf = [0u8; PROXY_V2_SIG.len()];
+    let mut last = 0;
+    loop {
+        let n = conn.peek(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        let seen = &buf[..n];
+        if seen.starts_with(PROXY_V2_SIG) {
+            return Ok(Some(Protocol::ProxyV2));
+        }
+        if seen.starts_with(b"PROXY ") {
+            return Ok(Some(Protocol::ProxyV1));
+        }
+        let could_be = |sig: &[u8]| sig.starts_with(&seen[..n.min(sig.len())]);
+        if !could_be(PROXY_V2_SIG) && !could_be(b"PROXY ") {
+            return Ok(None);
+        }
+        if n == last {
+            tokio::time::sleep(PEEK_RETRY).await;
+        }
+        last = n;
+    }
+}
+
+/// Peek at `conn` until one of `probes` matches its leading bytes or none still can.
+/// Nothing is consumed.
+pub async fn peek_health_probe<'a>(
+    conn: &TcpStream,
+    probes: &'a [HealthProbe],
+) -> std::io::Result<Option<&'a HealthProbe>> {
+    let max = probes.iter().map(|p| p.pattern.len()).max().unwrap_or(0);
+    let mut buf = vec![0u8; max];
+    let mut last = 0;
+    loop {
+        let n = conn.peek(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        let seen = &buf[..n];
+        if let Some(p) = probes.iter().find(|p| seen.starts_with(&p.pattern)) {
+            return Ok(Some(p));
+        }
+        if !probes.iter().any(|p| p.pattern.starts_with(seen)) {
+            return Ok(None);
+        }
+        if n == last {
+            tokio::time::sleep(PEEK_RETRY).await;
+        }
+        last = n;
+    }
+}
+
+/// Consume a PROXY protocol header from `conn`, returning the client address it carries
+/// (None for `LOCAL`/`UNKNOWN` headers).
+pub async fn read_proxy_header(
+    conn: &mut TcpStream,
+    version: Protocol,
+) -> anyhow::Result<Option<SocketAddr>> {
+    match version {
+        Protocol::ProxyV1 => {
+            let mut buf = [0u8; PROXY_V1_MAX_LEN];
+            let len = loop {
+                let n = conn.peek(&mut buf).await?;
+                if let Some(pos) = buf[..n].windows(2).position(|w| w == b"\r\n") {
+                    break pos + 2;
+                }
+                if n == 0 || n == PROXY_V1_MAX_LEN {
+                    anyhow::bail!("proxy protocol v1 header not terminated");
+                }
+                tokio::time::sleep(PEEK_RETRY).await;
+            };
+            let mut line = vec![0u8; len];
+            conn.read_exact(&mut line).await?;
+            parse_proxy_v1(&line)
+        }
+        Protocol::ProxyV2 => {
+            let mut header = [0u8; 16];
+            conn.read_exact(&mut header).await?;
+            let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+            let mut body = vec![0u8; len];
+            conn.read_exact(&mut body).await?;
+            parse_proxy_v2(&header, &body)
+        }
+        other => anyhow::bail!("not a proxy protocol header: {other:?}"),
+    }
+}
+
+fn parse_proxy_v1(line: &[u8]) -> anyhow::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.strip_suffix("\r\n"))
+        .context("proxy protocol v1 header is not a CRLF-terminated line")?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, sport, _dport] => {
+            let ip: IpAddr = src.parse().context("proxy protocol v1 source address")?;
+            let port: u16 = sport.parse().context("proxy protocol v1 source port")?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => anyhow::bail!("malformed proxy protocol v1 header {line:?}"),
+    }
+}
+
+fn parse_proxy_v2(header: &[u8; 16], body: &[u8]) -> anyhow::Result<Option<SocketAddr>> {
+    if &header[..12] != PROXY_V2_SIG {
+        anyhow::bail!("bad proxy protocol v2 signature");
+    }
+    match header[12] {
+        0x20 => return Ok(None),
+        0x21 => {}
+        other => anyhow::bail!("unsupported proxy protocol v2 version/command 0x{other:02x}"),
+    }
+    match header[13] >> 4 {
+        0x1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        0x2 if body.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        0x1 | 0x2 => anyhow::bail!("truncated proxy protocol v2 address block"),
+        // AF_UNSPEC / AF_UNIX: no usable client address.
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_leading_bytes() {
+        let mut handshake = Vec::new();
+        minecraft::write_varint(763, &mut handshake);
+        minecraft::write_string("play.example.com", &mut handshake);
+        let handshake = minecraft::packet(0x00, &handshake);
+
+        let cases: [(&[u8], Option<Protocol>); 14] = [
+            (
+                b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c",
+                Some(Protocol::ProxyV2),
+            ),
+            (b"\r\n\r\n\0\r", None),
+            (b"PROXY TCP4 203.0.113.7 ", Some(Protocol::ProxyV1)),
+            (b"PROX", None),
+            (
+                b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03",
+                Some(Protocol::Tls),
+            ),
+            (b"\x16", None),
+            (b"GET / HTTP/1.1\r\n", Some(Protocol::Http)),
+            (b"OPTIONS * HTTP/1.1", Some(Protocol::Http)),
+            (b"POS", None),
+            (&handshake, Some(Protocol::Minecraft)),
+            (&handshake[..2], Some(Protocol::Minecraft)),
+            (b"\xfe\x01", Some(Protocol::Minecraft)),
+            (b"SSH-2.0-OpenSSH_9.6\r\n", Some(Protocol::Unknown)),
+            (b"", None),
+        ];
+        for (input, want) in cases {
+            assert_eq!(classify(input), want, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn parses_proxy_headers() {
+        assert_eq!(
+            parse_proxy_v1(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 25565\r\n").unwrap(),
+            Some("203.0.113.7:51234".parse().unwrap())
+        );
+        assert_eq!(
+            parse_proxy_v1(b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 25565\r\n").unwrap(),
+            Some("[2001:db8::7]:51234".parse().unwrap())
+        );
+        assert_eq!(parse_proxy_v1(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert!(parse_proxy_v1(b"PROXY TCP4 nope 10.0.0.1 1 2\r\n").is_err());
+
+        let mut header = [0u8; 16];
+        header[..12].copy_from_slice(PROXY_V2_SIG);
+        header[12] = 0x21;
+        header[13] = 0x11;
+        header[15] = 12;
+        let body = [203, 0, 113, 7, 10, 0, 0, 1, 0xc8, 0x22, 0x63, 0xdd];
+        assert_eq!(
+            parse_proxy_v2(&header, &body).unwrap(),
+            Some("203.0.113.7:51234".parse().unwrap())
+        );
+        header[12] = 0x20;
+        assert_eq!(parse_proxy_v2(&header, &[]).unwrap(), None);
+        header[12] = 0x21;
+        assert!(parse_proxy_v2(&header, &body[..4]).is_err());
+    }
+}
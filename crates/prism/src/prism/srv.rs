@@ -0,0 +1,238 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use rand::{RngExt, rng};
+
+/// Upstream prefix resolved through `_minecraft._tcp.<domain>` SRV records, like the
+/// vanilla client does.
+pub const SRV_PREFIX: &str = "srv:";
+
+/// How long SRV answers (including "no records") are reused before looking up again.
+pub const SRV_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub host: String,
+    pub port: u16,
+}
+
+#[async_trait]
+pub trait SrvLookup: Send + Sync {
+    /// SRV records for `name`; an empty list when the name has none.
+    async fn lookup_srv(&self, name: &str) -> anyhow::Result<Vec<SrvTarget>>;
+}
+
+/// The operating system's DNS configuration (`/etc/resolv.conf` and friends).
+struct SystemLookup {
+    resolver: hickory_resolver::TokioResolver,
+}
+
+impl SystemLookup {
+    fn new() -> Self {
+        let builder = hickory_resolver::TokioResolver::builder_tokio().unwrap_or_else(|err| {
+            tracing::warn!(err = %err, "srv: failed to read system DNS config; using defaults");
+            hickory_resolver::Resolver::builder_with_config(
+                hickory_resolver::config::ResolverConfig::default(),
+                hickory_resolver::name_server::TokioConnectionProvider::default(),
+            )
+        });
+        Self {
+            resolver: builder.build(),
+        }
+    }
+}
+
+#[async_trait]
+impl SrvLookup for SystemLookup {
+    async fn lookup_srv(&self, name: &str) -> anyhow::Result<Vec<SrvTarget>> {
+        match self.resolver.srv_lookup(name).await {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .filter_map(|r| {
+                    // A target of "." means the service is explicitly unavailable.
+                    let host = r.target().to_utf8();
+                    let host = host.trim_end_matches('.');
+                    (!host.is_empty()).then(|| SrvTarget {
+                        priority: r.priority(),
+                        weight: r.weight(),
+                        host: host.to_string(),
+                        port: r.port(),
+                    })
+                })
+                .collect()),
+            Err(err) if err.is_no_records_found() || err.is_nx_domain() => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// SRV resolution with a short-lived answer cache.
+pub struct SrvResolver {
+    lookup: Box<dyn SrvLookup>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Vec<SrvTarget>)>>,
+}
+
+impl SrvResolver {
+    pub fn new(lookup: Box<dyn SrvLookup>, ttl: Duration) -> Self {
+        Self {
+            lookup,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The process-wide resolver backed by the system DNS configuration.
+    pub fn system() -> &'static SrvResolver {
+        static SYSTEM: OnceLock<SrvResolver> = OnceLock::new();
+        SYSTEM.get_or_init(|| SrvResolver::new(Box::new(SystemLookup::new()), SRV_CACHE_TTL))
+    }
+
+    /// `host:port` dial targets for `domain`, in RFC 2782 order: lowest priority first,
+    /// weighted random within a priority. Without SRV records this is `domain:default_port`
+    /// so the usual A/AAAA lookup applies.
+    pub async fn targets(&self, domain: &str, default_port: u16) -> Vec<String> {
+        let domain = domain.trim().trim_end_matches('.');
+        let name = format!("_minecraft._tcp.{domain}");
+        let records = match self.cached(&name) {
+            Some(records) => records,
+            None => match self.lookup.lookup_srv(&name).await {
+                Ok(records) => {
+                    self.store(&name, records.clone());
+                    records
+                }
+                Err(err) => {
+                    tracing::debug!(name = %name, err = %err, "srv: lookup failed; falling back to A/AAAA");
+                    Vec::new()
+                }
+            },
+        };
+
+        if records.is_empty() {
+            return vec![format!("{domain}:{default_port}")];
+        }
+        order_targets(records)
+            .into_iter()
+            .map(|t| format!("{}:{}", t.host, t.port))
+            .collect()
+    }
+
+    fn cached(&self, name: &str) -> Option<Vec<SrvTarget>> {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .get(name)
+            .filter(|(at, _)| at.elapsed() < self.ttl)
+            .map(|(_, records)| records.clone())
+    }
+
+    fn store(&self, name: &str, records: Vec<SrvTarget>) {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|_, (at, _)| at.elapsed() < self.ttl);
+        cache.insert(name.to_string(), (Instant::now(), records));
+    }
+}
+
+fn order_targets(mut records: Vec<SrvTarget>) -> Vec<SrvTarget> {
+    let mut rng = rng();
+    // Weighted random order within a priority (Efraimidis-Spirakis keys); zero-weight
+    // records keep a small chance of going first, as RFC 2782 asks.
+    let mut keyed: Vec<(u16, f64, SrvTarget)> = records
+        .drain(..)
+        .map(|t| {
+            let w = f64::from(t.weight).max(0.01);
+            let u: f64 = rng.random_range(f64::MIN_POSITIVE..1.0);
+            (t.priority, u.powf(1.0 / w), t)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)));
+    keyed.into_iter().map(|(_, _, t)| t).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    struct MockLookup {
+        records: HashMap<String, Vec<SrvTarget>>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl SrvLookup for MockLookup {
+        async fn lookup_srv(&self, name: &str) -> anyhow::Result<Vec<SrvTarget>> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Ok(self.records.get(name).cloned().unwrap_or_default())
+        }
+    }
+
+    fn target(priority: u16, weight: u16, host: &str, port: u16) -> SrvTarget {
+        SrvTarget {
+            priority,
+            weight,
+            host: host.into(),
+            port,
+        }
+    }
+
+    fn mock(records: Vec<(&str, Vec<SrvTarget>)>) -> (SrvResolver, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let lookup = MockLookup {
+            records: records
+                .into_iter()
+                .map(|(name, r)| (name.to_string(), r))
+                .collect(),
+            calls: calls.clone(),
+        };
+        (
+            SrvResolver::new(Box::new(lookup), Duration::from_secs(60)),
+            calls,
+        )
+    }
+
+    #[tokio::test]
+    async fn targets_follow_srv_priority() {
+        let (resolver, calls) = mock(vec![(
+            "_minecraft._tcp.play.example.com",
+            vec![
+                target(20, 5, "backup.example.com", 25570),
+                target(10, 0, "b.example.com", 25566),
+                target(10, 100, "a.example.com", 25565),
+            ],
+        )]);
+
+        let targets = resolver.targets("play.example.com.", 25565).await;
+        assert_eq!(targets.len(), 3);
+        let mut first = targets[..2].to_vec();
+        first.sort();
+        assert_eq!(first, vec!["a.example.com:25565", "b.example.com:25566"]);
+        assert_eq!(targets[2], "backup.example.com:25570");
+
+        resolver.targets("play.example.com", 25565).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 1, "answer is cached");
+    }
+
+    #[tokio::test]
+    async fn missing_srv_records_fall_back_to_the_bare_domain() {
+        let (resolver, calls) = mock(Vec::new());
+
+        let targets = resolver.targets("play.example.com", 25565).await;
+        assert_eq!(targets, vec!["play.example.com:25565".to_string()]);
+
+        resolver.targets("play.example.com", 25565).await;
+        assert_eq!(
+            calls.load(Ordering::Relaxed),
+            1,
+            "negative answer is cached"
+        );
+    }
+}
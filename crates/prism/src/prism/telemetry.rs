@@ -1,14 +1,19 @@
 use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    net::IpAddr,
     sync::{
-        Arc,
-        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
     },
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use dashmap::DashMap;
 use serde::Serialize;
 
+use crate::prism::{config, geoip};
+
 #[derive(Debug, Clone, Serialize)]
 pub struct SessionInfo {
     pub id: String,
@@ -16,26 +21,434 @@ pub struct SessionInfo {
     pub host: String,
     pub upstream: String,
     pub started_at_unix_ms: u64,
+    #[serde(flatten)]
+    pub geo: geoip::GeoInfo,
+}
+
+/// Recent rejections kept for `GET /rejections`; older entries are dropped.
+pub const REJECTION_LOG_CAPACITY: usize = 256;
+
+/// Tracing target of the per-session access log (`logging.access_log`).
+pub const ACCESS_LOG_TARGET: &str = "prism::access";
+
+/// Finished sessions kept for `GET /sessions/recent`; older entries are dropped.
+pub const RECENT_SESSION_CAPACITY: usize = 256;
+
+/// How often `SessionRegistry::drain` rechecks the session count.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A TCP session that has ended, for `GET /sessions/recent`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub client: String,
+    pub host: String,
+    pub upstream: String,
+    pub started_at_unix_ms: u64,
+    pub ended_at_unix_ms: u64,
+    /// Client to upstream, including sessions that were killed, timed out or reset.
+    pub ingress_bytes: u64,
+    /// Upstream to client.
+    pub egress_bytes: u64,
+    pub close_reason: CloseReason,
+}
+
+/// Why a proxied session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// Both sides finished normally.
+    Closed,
+    /// `POST /sessions/{id}/kill`.
+    Killed,
+    /// No bytes moved for `idle_timeout_ms`.
+    IdleTimeout,
+    /// Reset, I/O deadline or another copy error.
+    Error,
+}
+
+impl CloseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Closed => "closed",
+            CloseReason::Killed => "killed",
+            CloseReason::IdleTimeout => "idle_timeout",
+            CloseReason::Error => "error",
+        }
+    }
+}
+
+/// Why a connection was turned away before it was proxied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// `max_sessions_per_ip` reached.
+    IpLimit,
+    /// The client IP opened connections faster than `rate_limit` allows.
+    RateLimited,
+    /// `max_connections` or the route's `max_upstream_connections` reached.
+    ConnectionLimit,
+    /// The client didn't send a routable handshake within `handshake_timeout_ms`.
+    HandshakeTimeout,
+    /// The prelude could not be parsed or read.
+    HandshakeInvalid,
+    /// No route matched (`on_no_route` close or kick).
+    NoRoute,
+    /// A route's `host_secret` did not match.
+    HostSecret,
+    /// The client IP is outside a route's `allow_cidrs` or inside its `deny_cidrs`.
+    RouteDenied,
+    /// Every upstream candidate failed or was at its connection limit.
+    UpstreamUnavailable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    pub at_unix_ms: u64,
+    pub client: String,
+    pub reason: RejectReason,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub host: String,
 }
 
 #[derive(Debug)]
 pub struct SessionRegistry {
     sessions: DashMap<String, SessionInfo>,
+    /// Per-session kill switches for `POST /sessions/{id}/kill`.
+    kill_switches: DashMap<String, Arc<tokio::sync::Notify>>,
+    per_ip: DashMap<IpAddr, usize>,
+    per_upstream: DashMap<String, usize>,
+    conn_buckets: DashMap<IpAddr, TokenBucket>,
+    connections: Arc<ConnectionCounter>,
+    /// The `max_connections` in force (0 = unlimited), for the permits gauge.
+    connection_limit: AtomicUsize,
+    last_bucket_prune: Mutex<Instant>,
+    geo: Option<geoip::GeoDb>,
+    id_seq: AtomicU64,
+    id_format: config::SessionIdFormat,
+    id_client_hash: bool,
+    /// Random key for `client_ip_hash`, so suffixes can't be matched to IPs by hashing
+    /// the whole address space.
+    id_hash_key: [u8; 32],
+    rejections: Mutex<VecDeque<Rejection>>,
+    latency: LatencyMetrics,
+    session_metrics: SessionMetrics,
+    recent: Mutex<VecDeque<SessionSummary>>,
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SessionRegistry {
     pub fn new() -> Self {
         Self {
             sessions: DashMap::new(),
+            kill_switches: DashMap::new(),
+            per_ip: DashMap::new(),
+            per_upstream: DashMap::new(),
+            conn_buckets: DashMap::new(),
+            connections: Arc::new(ConnectionCounter::default()),
+            connection_limit: AtomicUsize::new(0),
+            last_bucket_prune: Mutex::new(Instant::now()),
+            geo: None,
+            id_seq: AtomicU64::new(1),
+            id_format: config::SessionIdFormat::Counter,
+            id_client_hash: false,
+            id_hash_key: {
+                use rand::RngExt;
+                rand::rng().random()
+            },
+            rejections: Mutex::new(VecDeque::with_capacity(REJECTION_LOG_CAPACITY)),
+            latency: LatencyMetrics::default(),
+            session_metrics: SessionMetrics::default(),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_SESSION_CAPACITY)),
+        }
+    }
+
+    /// Generate session ids in `format`, optionally suffixed with a short client-IP hash.
+    pub fn with_id_format(mut self, format: config::SessionIdFormat, client_hash: bool) -> Self {
+        self.id_format = format;
+        self.id_client_hash = client_hash;
+        self
+    }
+
+    /// A new session id in the configured format.
+    pub fn new_session_id(&self, client: Option<IpAddr>) -> String {
+        let mut id = match self.id_format {
+            config::SessionIdFormat::Counter => {
+                format!("s{}", self.id_seq.fetch_add(1, Ordering::Relaxed))
+            }
+            config::SessionIdFormat::Timestamp => format!(
+                "s{}-{}",
+                now_unix_ms(),
+                self.id_seq.fetch_add(1, Ordering::Relaxed)
+            ),
+            config::SessionIdFormat::Uuid => new_uuid_v4(),
+        };
+        if self.id_client_hash
+            && let Some(ip) = client
+        {
+            id.push_str(&format!("-{:08x}", client_ip_hash(&self.id_hash_key, ip)));
+        }
+        id
+    }
+
+    pub fn latency(&self) -> &LatencyMetrics {
+        &self.latency
+    }
+
+    pub fn session_metrics(&self) -> &SessionMetrics {
+        &self.session_metrics
+    }
+
+    /// Everything the registry measures, in the Prometheus text format.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = self.latency.render_prometheus();
+        out.push_str(&self.session_metrics.render_prometheus());
+        out.push_str(
+            "# HELP prism_connection_permits_available Free max_connections slots (+Inf when uncapped).\n",
+        );
+        out.push_str("# TYPE prism_connection_permits_available gauge\n");
+        match self.connection_permits_available() {
+            Some(n) => {
+                let _ = writeln!(out, "prism_connection_permits_available {n}");
+            }
+            None => out.push_str("prism_connection_permits_available +Inf\n"),
+        }
+        out
+    }
+
+    /// Annotate sessions with country/ASN from `geo`.
+    pub fn with_geo(mut self, geo: Option<geoip::GeoDb>) -> Self {
+        self.geo = geo;
+        self
+    }
+
+    /// Country/ASN for a client `ip:port` string; empty when no database is loaded.
+    pub fn geo_lookup(&self, client: &str) -> geoip::GeoInfo {
+        let Some(db) = self.geo.as_ref() else {
+            return geoip::GeoInfo::default();
+        };
+        match client.parse::<std::net::SocketAddr>() {
+            Ok(addr) => db.lookup(addr.ip()),
+            Err(_) => geoip::GeoInfo::default(),
+        }
+    }
+
+    /// Reserve a concurrent-session slot for `ip`, or None if it already holds `limit` slots.
+    ///
+    /// The slot is released when the returned guard is dropped.
+    pub fn try_acquire_ip(self: &Arc<Self>, ip: IpAddr, limit: usize) -> Option<IpSessionGuard> {
+        let ip = ip.to_canonical();
+        let mut n = self.per_ip.entry(ip).or_insert(0);
+        if *n >= limit {
+            return None;
+        }
+        *n += 1;
+        Some(IpSessionGuard {
+            registry: self.clone(),
+            ip,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn ip_sessions(&self, ip: IpAddr) -> usize {
+        self.per_ip.get(&ip.to_canonical()).map(|n| *n).unwrap_or(0)
+    }
+
+    /// Take a token from `ip`'s connection bucket (refilled at `per_second`, holding up to
+    /// `burst`); false means the connection is over the rate limit.
+    pub fn try_admit_connection(&self, ip: IpAddr, per_second: u32, burst: u32) -> bool {
+        self.admit_connection_at(ip.to_canonical(), per_second, burst, Instant::now())
+    }
+
+    fn admit_connection_at(&self, ip: IpAddr, per_second: u32, burst: u32, now: Instant) -> bool {
+        self.prune_connection_buckets(per_second, burst, now);
+        let mut bucket = self.conn_buckets.entry(ip).or_insert(TokenBucket {
+            tokens: burst as f64,
+            updated: now,
+        });
+        bucket.refill(per_second, burst, now);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Drop buckets that have refilled completely; they behave exactly like missing ones.
+    fn prune_connection_buckets(&self, per_second: u32, burst: u32, now: Instant) {
+        {
+            let mut last = self
+                .last_bucket_prune
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if now.saturating_duration_since(*last) < BUCKET_PRUNE_INTERVAL {
+                return;
+            }
+            *last = now;
+        }
+        self.conn_buckets.retain(|_, b| {
+            b.refill(per_second, burst, now);
+            b.tokens < burst as f64
+        });
+    }
+
+    #[cfg(test)]
+    pub fn connection_buckets(&self) -> usize {
+        self.conn_buckets.len()
+    }
+
+    /// Reserve one of the `max_connections` proxied-session slots shared by every listener.
+    pub fn try_acquire_connection(&self, limit: usize) -> Option<ConnectionPermit> {
+        self.connections.try_acquire(limit)
+    }
+
+    /// Proxied TCP sessions currently holding a `max_connections` slot.
+    pub fn active_connections(&self) -> usize {
+        self.connections.active()
+    }
+
+    /// Record the `max_connections` the proxy enforces, at startup and on every reload.
+    pub fn set_connection_limit(&self, limit: usize) {
+        self.connection_limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Free `max_connections` slots; None when the cap is off.
+    pub fn connection_permits_available(&self) -> Option<usize> {
+        let limit = self.connection_limit.load(Ordering::Relaxed);
+        (limit > 0).then(|| limit.saturating_sub(self.active_connections()))
+    }
+
+    /// Reserve a connection slot on `upstream`, or None if it already holds `limit` slots.
+    ///
+    /// The slot is released when the returned guard is dropped.
+    pub fn try_acquire_upstream(
+        self: &Arc<Self>,
+        upstream: &str,
+        limit: usize,
+    ) -> Option<UpstreamSlotGuard> {
+        let mut n = self.per_upstream.entry(upstream.to_string()).or_insert(0);
+        if *n >= limit {
+            return None;
         }
+        *n += 1;
+        Some(UpstreamSlotGuard {
+            registry: self.clone(),
+            upstream: upstream.to_string(),
+        })
     }
 
-    pub fn add(&self, s: SessionInfo) {
+    #[cfg(test)]
+    pub fn upstream_connections(&self, upstream: &str) -> usize {
+        self.per_upstream.get(upstream).map(|n| *n).unwrap_or(0)
+    }
+
+    /// Track a live session. The returned switch fires when the session is killed; the
+    /// session's copy loop should stop once it does.
+    pub fn add(&self, s: SessionInfo) -> Arc<tokio::sync::Notify> {
+        let kill = Arc::new(tokio::sync::Notify::new());
+        self.kill_switches.insert(s.id.clone(), kill.clone());
         self.sessions.insert(s.id.clone(), s);
+        kill
     }
 
     pub fn remove(&self, id: &str) {
         self.sessions.remove(id);
+        self.kill_switches.remove(id);
+    }
+
+    /// Remove a TCP session that carried traffic, recording its duration and the
+    /// (ingress, egress) bytes it moved, whatever the close reason.
+    ///
+    /// Also emits the access log event (target `prism::access`; `logging.access_log`
+    /// decides whether the filter lets it through).
+    pub fn finish(&self, id: &str, (ingress_bytes, egress_bytes): (u64, u64), reason: CloseReason) {
+        self.kill_switches.remove(id);
+        let Some((_, info)) = self.sessions.remove(id) else {
+            return;
+        };
+        let ended_at_unix_ms = now_unix_ms();
+        let duration_ms = ended_at_unix_ms.saturating_sub(info.started_at_unix_ms);
+        let m = &self.session_metrics;
+        m.duration.observe(Duration::from_millis(duration_ms));
+        m.ingress_bytes.fetch_add(ingress_bytes, Ordering::Relaxed);
+        m.egress_bytes.fetch_add(egress_bytes, Ordering::Relaxed);
+
+        let client_ip = info
+            .client
+            .parse::<std::net::SocketAddr>()
+            .map(|a| a.ip().to_string())
+            .unwrap_or_else(|_| info.client.clone());
+        tracing::info!(
+            target: ACCESS_LOG_TARGET,
+            sid = %info.id,
+            client_ip = %client_ip,
+            host = %info.host,
+            upstream = %info.upstream,
+            bytes_in = ingress_bytes,
+            bytes_out = egress_bytes,
+            duration_ms,
+            close_reason = reason.as_str(),
+            "session closed"
+        );
+
+        let mut log = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if log.len() >= RECENT_SESSION_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(SessionSummary {
+            id: info.id,
+            client: info.client,
+            host: info.host,
+            upstream: info.upstream,
+            started_at_unix_ms: info.started_at_unix_ms,
+            ended_at_unix_ms,
+            ingress_bytes,
+            egress_bytes,
+            close_reason: reason,
+        });
+    }
+
+    /// Recently finished sessions, newest first.
+    pub fn recent_sessions(&self) -> Vec<SessionSummary> {
+        let log = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        log.iter().rev().cloned().collect()
+    }
+
+    /// Ask session `id` to close. Returns false if no such session is live.
+    pub fn kill(&self, id: &str) -> bool {
+        match self.kill_switches.get(id) {
+            Some(kill) => {
+                // A stored permit also covers a session that isn't waiting on it yet.
+                kill.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remember a rejected connection; `host` may be empty when it wasn't known yet.
+    pub fn record_rejection(&self, client: &str, reason: RejectReason, host: &str) {
+        let mut log = self.rejections.lock().unwrap_or_else(|e| e.into_inner());
+        if log.len() >= REJECTION_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(Rejection {
+            at_unix_ms: now_unix_ms(),
+            client: client.to_string(),
+            reason,
+            host: host.to_string(),
+        });
+    }
+
+    /// Recent rejections, newest first.
+    pub fn rejections(&self) -> Vec<Rejection> {
+        let log = self.rejections.lock().unwrap_or_else(|e| e.into_inner());
+        log.iter().rev().cloned().collect()
     }
 
     pub fn snapshot(&self) -> Vec<SessionInfo> {
@@ -43,11 +456,462 @@ impl SessionRegistry {
         for s in self.sessions.iter() {
             out.push(s.value().clone());
         }
-        out.sort_by(|a, b| a.started_at_unix_ms.cmp(&b.started_at_unix_ms));
+        out.sort_by_key(|a| a.started_at_unix_ms);
         out
     }
 }
 
+/// Holds one per-IP concurrent-session slot; see `SessionRegistry::try_acquire_ip`.
+#[derive(Debug)]
+pub struct IpSessionGuard {
+    registry: Arc<SessionRegistry>,
+    ip: IpAddr,
+}
+
+impl Drop for IpSessionGuard {
+    fn drop(&mut self) {
+        if let Some(mut n) = self.registry.per_ip.get_mut(&self.ip) {
+            *n = n.saturating_sub(1);
+        }
+        self.registry.per_ip.remove_if(&self.ip, |_, n| *n == 0);
+    }
+}
+
+/// Holds one per-upstream connection slot; see `SessionRegistry::try_acquire_upstream`.
+#[derive(Debug)]
+pub struct UpstreamSlotGuard {
+    registry: Arc<SessionRegistry>,
+    upstream: String,
+}
+
+impl Drop for UpstreamSlotGuard {
+    fn drop(&mut self) {
+        if let Some(mut n) = self.registry.per_upstream.get_mut(&self.upstream) {
+            *n = n.saturating_sub(1);
+        }
+        self.registry
+            .per_upstream
+            .remove_if(&self.upstream, |_, n| *n == 0);
+    }
+}
+
+/// Live count of connections sharing a cap; see `ConnectionCounter::try_acquire`.
+#[derive(Debug, Default)]
+pub struct ConnectionCounter {
+    active: AtomicUsize,
+}
+
+impl ConnectionCounter {
+    /// Take a slot unless `limit` (0 = unlimited) are already taken. The slot is released
+    /// when the returned permit is dropped.
+    pub fn try_acquire(self: &Arc<Self>, limit: usize) -> Option<ConnectionPermit> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (limit == 0 || n < limit).then_some(n + 1)
+            })
+            .ok()?;
+        Some(ConnectionPermit {
+            counter: self.clone(),
+        })
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Wait until every slot is released, or `deadline` passes. Returns whether the
+    /// count reached zero in time.
+    pub async fn drain(&self, deadline: tokio::time::Instant) -> bool {
+        loop {
+            if self.active() == 0 {
+                return true;
+            }
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+}
+
+/// Holds one slot of a `ConnectionCounter`.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    counter: Arc<ConnectionCounter>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.counter.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Upper bounds (seconds) of the latency histogram buckets: 1ms up to 10s, which covers
+/// LAN dials through slow handshakes just short of the usual timeouts.
+const LATENCY_BUCKETS: [f64; 13] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Upper bounds (seconds) of the session duration buckets: a second up to a day.
+const SESSION_DURATION_BUCKETS: [f64; 13] = [
+    1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 10800.0, 21600.0, 43200.0, 86400.0,
+];
+
+/// Cumulative-bucket histogram of durations, rendered in the Prometheus text format.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Non-cumulative counts per bucket in `bounds`; the +Inf bucket is `count`.
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|le| secs <= *le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(d.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    #[cfg(test)]
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render_prometheus(&self, name: &str, help: &str, out: &mut String) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (le, n) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += n.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/// How long proxied connections spend dialing upstreams and reading client handshakes.
+#[derive(Debug)]
+pub struct LatencyMetrics {
+    /// Every `dial_upstream` attempt, failed ones included.
+    pub upstream_dial: Histogram,
+    /// Reading the prelude on routing listeners, until a route matched or none could.
+    pub handshake: Histogram,
+}
+
+impl Default for LatencyMetrics {
+    fn default() -> Self {
+        Self {
+            upstream_dial: Histogram::new(&LATENCY_BUCKETS),
+            handshake: Histogram::new(&LATENCY_BUCKETS),
+        }
+    }
+}
+
+impl LatencyMetrics {
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        self.upstream_dial.render_prometheus(
+            "prism_upstream_dial_seconds",
+            "Time spent dialing upstreams.",
+            &mut out,
+        );
+        self.handshake.render_prometheus(
+            "prism_handshake_seconds",
+            "Time spent reading client handshakes before routing.",
+            &mut out,
+        );
+        out
+    }
+}
+
+/// Totals over finished TCP sessions; see `SessionRegistry::finish`.
+#[derive(Debug)]
+pub struct SessionMetrics {
+    pub duration: Histogram,
+    /// Client to upstream.
+    ingress_bytes: AtomicU64,
+    /// Upstream to client.
+    egress_bytes: AtomicU64,
+    /// UDP datagrams dropped for exceeding `max_udp_datagram_bytes`, either direction.
+    udp_oversize: AtomicU64,
+    /// UDP datagrams from new sources dropped because a listener was at `max_udp_sessions`.
+    udp_sessions_dropped: AtomicU64,
+    /// UDP flows currently tracked across every listener.
+    udp_active_sessions: AtomicU64,
+    /// Connections closed because `strict_minecraft` routes turned their prelude away.
+    non_minecraft_rejected: AtomicU64,
+    /// Connections closed at accept by the per-IP `rate_limit`.
+    ratelimited: AtomicU64,
+    /// Connections refused by a route's `allow_cidrs`/`deny_cidrs`, by the route host
+    /// pattern that matched (config-bounded, unlike the client's host).
+    route_denied: DashMap<String, AtomicU64>,
+}
+
+impl Default for SessionMetrics {
+    fn default() -> Self {
+        Self {
+            duration: Histogram::new(&SESSION_DURATION_BUCKETS),
+            ingress_bytes: AtomicU64::new(0),
+            egress_bytes: AtomicU64::new(0),
+            udp_oversize: AtomicU64::new(0),
+            udp_sessions_dropped: AtomicU64::new(0),
+            udp_active_sessions: AtomicU64::new(0),
+            non_minecraft_rejected: AtomicU64::new(0),
+            ratelimited: AtomicU64::new(0),
+            route_denied: DashMap::new(),
+        }
+    }
+}
+
+impl SessionMetrics {
+    pub fn record_udp_oversize(&self) {
+        self.udp_oversize.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn udp_oversize(&self) -> u64 {
+        self.udp_oversize.load(Ordering::Relaxed)
+    }
+
+    pub fn record_udp_session_dropped(&self) {
+        self.udp_sessions_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn udp_sessions_dropped(&self) -> u64 {
+        self.udp_sessions_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn udp_session_opened(&self) {
+        self.udp_active_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn udp_session_closed(&self) {
+        self.udp_active_sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn udp_active_sessions(&self) -> u64 {
+        self.udp_active_sessions.load(Ordering::Relaxed)
+    }
+
+    pub fn record_non_minecraft_rejected(&self) {
+        self.non_minecraft_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn non_minecraft_rejected(&self) -> u64 {
+        self.non_minecraft_rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn record_ratelimited(&self) {
+        self.ratelimited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ratelimited(&self) -> u64 {
+        self.ratelimited.load(Ordering::Relaxed)
+    }
+
+    pub fn record_route_denied(&self, host: &str) {
+        if let Some(n) = self.route_denied.get(host) {
+            n.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.route_denied
+            .entry(host.to_string())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn route_denied(&self, host: &str) -> u64 {
+        self.route_denied
+            .get(host)
+            .map_or(0, |n| n.load(Ordering::Relaxed))
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        self.duration.render_prometheus(
+            "prism_session_duration_seconds",
+            "How long proxied sessions lasted.",
+            &mut out,
+        );
+        out.push_str("# HELP prism_session_bytes_total Bytes moved by finished sessions.\n");
+        out.push_str("# TYPE prism_session_bytes_total counter\n");
+        for (direction, n) in [
+            ("ingress", &self.ingress_bytes),
+            ("egress", &self.egress_bytes),
+        ] {
+            let _ = writeln!(
+                out,
+                "prism_session_bytes_total{{direction=\"{direction}\"}} {}",
+                n.load(Ordering::Relaxed)
+            );
+        }
+        out.push_str(
+            "# HELP prism_udp_oversize_datagrams_total UDP datagrams dropped for exceeding the size limit.\n",
+        );
+        out.push_str("# TYPE prism_udp_oversize_datagrams_total counter\n");
+        let _ = writeln!(
+            out,
+            "prism_udp_oversize_datagrams_total {}",
+            self.udp_oversize()
+        );
+        out.push_str(
+            "# HELP prism_udp_sessions_dropped_total UDP datagrams from new sources dropped at max_udp_sessions.\n",
+        );
+        out.push_str("# TYPE prism_udp_sessions_dropped_total counter\n");
+        let _ = writeln!(
+            out,
+            "prism_udp_sessions_dropped_total {}",
+            self.udp_sessions_dropped()
+        );
+        out.push_str("# HELP prism_udp_active_sessions UDP flows currently open.\n");
+        out.push_str("# TYPE prism_udp_active_sessions gauge\n");
+        let _ = writeln!(
+            out,
+            "prism_udp_active_sessions {}",
+            self.udp_active_sessions()
+        );
+        out.push_str(
+            "# HELP prism_non_minecraft_rejected_total Connections closed by strict_minecraft routes.\n",
+        );
+        out.push_str("# TYPE prism_non_minecraft_rejected_total counter\n");
+        let _ = writeln!(
+            out,
+            "prism_non_minecraft_rejected_total {}",
+            self.non_minecraft_rejected()
+        );
+        out.push_str(
+            "# HELP prism_ratelimited_total Connections closed by the per-IP connection rate limit.\n",
+        );
+        out.push_str("# TYPE prism_ratelimited_total counter\n");
+        let _ = writeln!(out, "prism_ratelimited_total {}", self.ratelimited());
+        let mut denied: Vec<_> = self
+            .route_denied
+            .iter()
+            .map(|e| (e.key().clone(), e.value().load(Ordering::Relaxed)))
+            .collect();
+        denied.sort();
+        out.push_str(
+            "# HELP prism_route_denied_total Connections refused by a route's client IP allow/deny lists.\n",
+        );
+        out.push_str("# TYPE prism_route_denied_total counter\n");
+        for (host, n) in &denied {
+            let _ = writeln!(
+                out,
+                "prism_route_denied_total{{host=\"{}\"}} {n}",
+                escape_label(host)
+            );
+        }
+        out
+    }
+}
+
+/// Outcome of config reloads, for alerting on reloads that silently fail.
+#[derive(Debug, Default)]
+pub struct ReloadMetrics {
+    success: AtomicU64,
+    failure: AtomicU64,
+    /// Wall-clock time of the last reload attempt, 0 before the first.
+    last_reload_unix_ms: AtomicU64,
+    /// Route/runtime swaps since startup (file reloads and managed applies alike).
+    generation: AtomicU64,
+}
+
+impl ReloadMetrics {
+    pub fn record(&self, ok: bool) {
+        let counter = if ok { &self.success } else { &self.failure };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.last_reload_unix_ms
+            .store(now_unix_ms(), Ordering::Relaxed);
+    }
+
+    pub fn bump_generation(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// (success, failure) reload counts.
+    #[cfg(test)]
+    pub fn counts(&self) -> (u64, u64) {
+        (
+            self.success.load(Ordering::Relaxed),
+            self.failure.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP prism_config_reload_total Config reloads, by result.\n");
+        out.push_str("# TYPE prism_config_reload_total counter\n");
+        for (result, n) in [("success", &self.success), ("failure", &self.failure)] {
+            let _ = writeln!(
+                out,
+                "prism_config_reload_total{{result=\"{result}\"}} {}",
+                n.load(Ordering::Relaxed)
+            );
+        }
+        out.push_str(
+            "# HELP prism_config_last_reload_timestamp_seconds Time of the last reload attempt.\n",
+        );
+        out.push_str("# TYPE prism_config_last_reload_timestamp_seconds gauge\n");
+        let last = self.last_reload_unix_ms.load(Ordering::Relaxed) as f64 / 1e3;
+        let _ = writeln!(out, "prism_config_last_reload_timestamp_seconds {last}");
+        out.push_str("# HELP prism_config_generation Route/runtime config swaps since startup.\n");
+        out.push_str("# TYPE prism_config_generation gauge\n");
+        let _ = writeln!(
+            out,
+            "prism_config_generation {}",
+            self.generation.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+/// How often idle connection rate-limit buckets are swept.
+const BUCKET_PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, per_second: u32, burst: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second as f64).min(burst as f64);
+        self.updated = now;
+    }
+}
+
+/// Escape a Prometheus label value.
+pub fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 pub fn now_unix_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -55,10 +919,35 @@ pub fn now_unix_ms() -> u64 {
         .as_millis() as u64
 }
 
-pub fn new_session_id() -> String {
-    static SEQ: AtomicU64 = AtomicU64::new(1);
-    let n = SEQ.fetch_add(1, Ordering::Relaxed);
-    format!("s{n}")
+fn new_uuid_v4() -> String {
+    use rand::RngExt;
+
+    let mut b: [u8; 16] = rand::rng().random();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|x| format!("{x:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Short fingerprint of a client IP for log correlation: a truncated HMAC-SHA256 under
+/// `key`, stable for as long as the key is.
+fn client_ip_hash(key: &[u8], ip: IpAddr) -> u32 {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect("hmac accepts any key");
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => mac.update(&v4.octets()),
+        IpAddr::V6(v6) => mac.update(&v6.octets()),
+    }
+    let tag = mac.finalize().into_bytes();
+    u32::from_be_bytes([tag[0], tag[1], tag[2], tag[3]])
 }
 
 #[derive(Debug, Clone)]
@@ -67,6 +956,12 @@ pub struct ReloadSignal {
     pub seq: u64,
 }
 
+impl Default for ReloadSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ReloadSignal {
     pub fn new() -> Self {
         Self { seq: 0 }
@@ -77,4 +972,209 @@ impl ReloadSignal {
     }
 }
 
+/// Result of the reload that handled `ReloadSignal` `seq` (or a later one), published by
+/// the reload loop so `POST /reload` can report it.
+#[derive(Debug, Clone, Default)]
+pub struct ReloadOutcome {
+    pub seq: u64,
+    /// Config generation after the reload; see `ReloadMetrics`.
+    pub generation: u64,
+    /// Why the reload failed; None on success.
+    pub error: Option<String>,
+}
+
 pub type SharedSessions = Arc<SessionRegistry>;
+
+/// Startup conditions the admin `/readyz` endpoint waits on: listeners that haven't bound
+/// yet, a tunnel client that isn't connected, and so on.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    next_id: AtomicU64,
+    pending: DashMap<u64, String>,
+}
+
+impl Readiness {
+    /// Register a condition that starts out unready.
+    pub fn check(self: &Arc<Self>, name: impl Into<String>) -> ReadyCheck {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let name = name.into();
+        self.pending.insert(id, name.clone());
+        ReadyCheck {
+            inner: Arc::new(ReadyCheckInner {
+                readiness: self.clone(),
+                id,
+                name,
+            }),
+        }
+    }
+
+    /// Names of the conditions not met yet, sorted; empty once ready.
+    pub fn pending(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.pending.iter().map(|e| e.value().clone()).collect();
+        names.sort();
+        names
+    }
+}
+
+/// One condition of a `Readiness`. Clones share it; it stops counting once the last
+/// clone is dropped.
+#[derive(Debug, Clone)]
+pub struct ReadyCheck {
+    inner: Arc<ReadyCheckInner>,
+}
+
+#[derive(Debug)]
+struct ReadyCheckInner {
+    readiness: Arc<Readiness>,
+    id: u64,
+    name: String,
+}
+
+impl ReadyCheck {
+    pub fn ready(&self) {
+        self.inner.readiness.pending.remove(&self.inner.id);
+    }
+
+    pub fn unready(&self) {
+        self.inner
+            .readiness
+            .pending
+            .insert(self.inner.id, self.inner.name.clone());
+    }
+}
+
+impl Drop for ReadyCheckInner {
+    fn drop(&mut self) {
+        self.readiness.pending.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_ids_are_unique_and_match_format() {
+        let cases = [
+            (config::SessionIdFormat::Counter, false, r"^s\d+$"),
+            (config::SessionIdFormat::Timestamp, false, r"^s\d{13}-\d+$"),
+            (
+                config::SessionIdFormat::Uuid,
+                false,
+                r"^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$",
+            ),
+            (
+                config::SessionIdFormat::Counter,
+                true,
+                r"^s\d+-[0-9a-f]{8}$",
+            ),
+        ];
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        for (format, client_hash, pattern) in cases {
+            let reg = SessionRegistry::new().with_id_format(format, client_hash);
+            let re = regex::Regex::new(pattern).unwrap();
+            let mut seen = std::collections::HashSet::new();
+            for _ in 0..1000 {
+                let id = reg.new_session_id(Some(ip));
+                assert!(re.is_match(&id), "{format:?}: {id}");
+                assert!(seen.insert(id), "{format:?}: duplicate id");
+            }
+        }
+
+        // The client hash is stable per IP and tells clients apart.
+        let reg = SessionRegistry::new().with_id_format(config::SessionIdFormat::Counter, true);
+        let a = reg.new_session_id(Some(ip));
+        let b = reg.new_session_id(Some(ip));
+        let c = reg.new_session_id(Some("198.51.100.1".parse().unwrap()));
+        let suffix = |s: &str| s.rsplit('-').next().unwrap().to_string();
+        assert_eq!(suffix(&a), suffix(&b));
+        assert_ne!(suffix(&a), suffix(&c));
+
+        // Each registry keys the hash afresh.
+        let other = SessionRegistry::new().with_id_format(config::SessionIdFormat::Counter, true);
+        assert_ne!(suffix(&other.new_session_id(Some(ip))), suffix(&a));
+    }
+
+    #[test]
+    fn connection_rate_limit_is_per_ip_and_refills() {
+        let reg = SessionRegistry::new();
+        let a: IpAddr = "203.0.113.7".parse().unwrap();
+        let b: IpAddr = "203.0.113.8".parse().unwrap();
+        let t0 = Instant::now();
+
+        let admitted = (0..10)
+            .filter(|_| reg.admit_connection_at(a, 2, 4, t0))
+            .count();
+        assert_eq!(admitted, 4);
+        assert!(reg.admit_connection_at(b, 2, 4, t0));
+
+        // Half a second at 2/s refills one token.
+        let t1 = t0 + Duration::from_millis(500);
+        assert!(reg.admit_connection_at(a, 2, 4, t1));
+        assert!(!reg.admit_connection_at(a, 2, 4, t1));
+
+        // Once idle long enough to be full again, buckets are pruned.
+        let t2 = t1 + BUCKET_PRUNE_INTERVAL;
+        assert!(reg.admit_connection_at(b, 2, 4, t2));
+        assert_eq!(reg.connection_buckets(), 1);
+    }
+
+    #[test]
+    fn connection_counter_caps_and_releases() {
+        let counter = Arc::new(ConnectionCounter::default());
+        let a = counter.try_acquire(2).expect("first");
+        let _b = counter.try_acquire(2).expect("second");
+        assert!(counter.try_acquire(2).is_none());
+        drop(a);
+        assert_eq!(counter.active(), 1);
+        assert!(counter.try_acquire(2).is_some());
+        // 0 means unlimited.
+        let unlimited: Vec<_> = (0..5).filter_map(|_| counter.try_acquire(0)).collect();
+        assert_eq!(unlimited.len(), 5);
+    }
+
+    #[test]
+    fn connection_permits_gauge_tracks_the_cap() {
+        let reg = SessionRegistry::new();
+        assert!(
+            reg.render_prometheus()
+                .contains("prism_connection_permits_available +Inf\n")
+        );
+
+        reg.set_connection_limit(3);
+        let _a = reg.try_acquire_connection(3).expect("slot");
+        assert_eq!(reg.connection_permits_available(), Some(2));
+        assert!(
+            reg.render_prometheus()
+                .contains("prism_connection_permits_available 2\n")
+        );
+
+        // A reload that lowers the cap below the open sessions reads as none left.
+        reg.set_connection_limit(1);
+        let _b = reg.try_acquire_connection(0).expect("slot");
+        assert_eq!(reg.connection_permits_available(), Some(0));
+    }
+
+    #[test]
+    fn rejection_log_is_bounded_and_newest_first() {
+        let reg = SessionRegistry::new();
+        for i in 0..REJECTION_LOG_CAPACITY + 10 {
+            reg.record_rejection(&format!("203.0.113.7:{i}"), RejectReason::IpLimit, "");
+        }
+        reg.record_rejection("203.0.113.8:1", RejectReason::NoRoute, "play.example.com");
+
+        let got = reg.rejections();
+        assert_eq!(got.len(), REJECTION_LOG_CAPACITY);
+        assert_eq!(got[0].reason, RejectReason::NoRoute);
+        assert_eq!(got[0].host, "play.example.com");
+        assert_eq!(
+            got[1].client,
+            format!("203.0.113.7:{}", REJECTION_LOG_CAPACITY + 9)
+        );
+        assert_eq!(got.last().unwrap().client, "203.0.113.7:11");
+
+        let json = serde_json::to_value(&got[0]).unwrap();
+        assert_eq!(json["reason"], "no_route");
+        assert!(serde_json::to_value(&got[1]).unwrap().get("host").is_none());
+    }
+}
@@ -0,0 +1,412 @@
+use std::sync::Arc;
+
+use rustls::{
+    ClientConfig, ConfigBuilder, ServerConfig, SupportedProtocolVersion, WantsVerifier,
+    crypto::CryptoProvider,
+};
+
+const TLS13_ONLY: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+
+/// Lowest TLS version Prism will negotiate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TlsVersion {
+    /// rustls defaults: TLS 1.2 and 1.3.
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl TlsVersion {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value
+            .trim()
+            .to_ascii_lowercase()
+            .trim_start_matches("tls")
+            .trim()
+        {
+            "" | "1.2" => Ok(Self::Tls12),
+            "1.3" => Ok(Self::Tls13),
+            other => anyhow::bail!(
+                "config: unsupported tls.min_version {:?} (expected 1.2 or 1.3)",
+                other
+            ),
+        }
+    }
+}
+
+/// Version floor and cipher-suite allowlist applied to every rustls config Prism builds.
+///
+/// The default policy is exactly rustls' safe defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsPolicy {
+    pub min_version: TlsVersion,
+    /// rustls cipher suite names (e.g. `TLS13_AES_256_GCM_SHA384`); empty allows all.
+    pub cipher_suites: Vec<String>,
+}
+
+impl TlsPolicy {
+    /// Check suite names against the crypto provider and that something is left to negotiate.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let base = base_provider();
+        for name in &self.cipher_suites {
+            if !base.cipher_suites.iter().any(|s| suite_name(s) == *name) {
+                anyhow::bail!(
+                    "config: tls.cipher_suites: unknown cipher suite {:?} (supported: {})",
+                    name,
+                    base.cipher_suites
+                        .iter()
+                        .map(suite_name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+        if self.provider().cipher_suites.is_empty() {
+            anyhow::bail!("config: tls policy leaves no usable cipher suites for tls.min_version");
+        }
+        Ok(())
+    }
+
+    /// Whether the policy still allows TLS 1.3, which QUIC requires.
+    pub fn allows_tls13(&self) -> bool {
+        self.provider()
+            .cipher_suites
+            .iter()
+            .any(|s| s.version() == &rustls::version::TLS13)
+    }
+
+    pub fn server_builder(&self) -> anyhow::Result<ConfigBuilder<ServerConfig, WantsVerifier>> {
+        Ok(
+            ServerConfig::builder_with_provider(Arc::new(self.provider()))
+                .with_protocol_versions(self.protocol_versions())?,
+        )
+    }
+
+    pub fn client_builder(&self) -> anyhow::Result<ConfigBuilder<ClientConfig, WantsVerifier>> {
+        Ok(
+            ClientConfig::builder_with_provider(Arc::new(self.provider()))
+                .with_protocol_versions(self.protocol_versions())?,
+        )
+    }
+
+    fn protocol_versions(&self) -> &'static [&'static SupportedProtocolVersion] {
+        match self.min_version {
+            TlsVersion::Tls12 => rustls::DEFAULT_VERSIONS,
+            TlsVersion::Tls13 => TLS13_ONLY,
+        }
+    }
+
+    fn provider(&self) -> CryptoProvider {
+        let mut provider = base_provider();
+        provider.cipher_suites.retain(|s| {
+            (self.min_version == TlsVersion::Tls12 || s.version() == &rustls::version::TLS13)
+                && (self.cipher_suites.is_empty()
+                    || self.cipher_suites.iter().any(|n| *n == suite_name(s)))
+        });
+        provider
+    }
+}
+
+/// TLS record content type of handshake messages.
+const RECORD_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const SERVER_NAME_HOST: u8 = 0x00;
+
+/// What a (possibly partial) ClientHello says about the server the client wants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientHello {
+    /// More bytes are needed to see the whole ClientHello.
+    Incomplete,
+    /// The full ClientHello; `server_name` is None when it carries no SNI host name.
+    Complete { server_name: Option<String> },
+}
+
+/// Parse the ClientHello at the start of `buf`, reassembling it across records if the
+/// client split it. Errors when `buf` is not a TLS handshake or the message is malformed.
+pub fn parse_client_hello(buf: &[u8]) -> anyhow::Result<ClientHello> {
+    let mut msg = Vec::new();
+    let mut rest = buf;
+    loop {
+        if rest.len() < 5 {
+            return Ok(ClientHello::Incomplete);
+        }
+        if rest[0] != RECORD_HANDSHAKE || rest[1] != 0x03 {
+            anyhow::bail!("tls: not a handshake record");
+        }
+        let len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        if len == 0 {
+            anyhow::bail!("tls: empty handshake record");
+        }
+        let Some(fragment) = rest.get(5..5 + len) else {
+            return Ok(ClientHello::Incomplete);
+        };
+        msg.extend_from_slice(fragment);
+        rest = &rest[5 + len..];
+
+        if msg[0] != HANDSHAKE_CLIENT_HELLO {
+            anyhow::bail!("tls: first handshake message is not a ClientHello");
+        }
+        if msg.len() < 4 {
+            continue;
+        }
+        let body_len = u32::from_be_bytes([0, msg[1], msg[2], msg[3]]) as usize;
+        if msg.len() >= 4 + body_len {
+            let server_name = client_hello_server_name(&msg[4..4 + body_len])
+                .ok_or_else(|| anyhow::anyhow!("tls: malformed ClientHello"))?;
+            return Ok(ClientHello::Complete { server_name });
+        }
+    }
+}
+
+/// The SNI host name in a ClientHello body; Some(None) when there is none.
+fn client_hello_server_name(body: &[u8]) -> Option<Option<String>> {
+    let mut r = Reader(body);
+    r.take(2 + 32)?; // legacy_version, random
+    let n = r.u8()? as usize;
+    r.take(n)?; // legacy_session_id
+    let n = r.u16()? as usize;
+    r.take(n)?; // cipher_suites
+    let n = r.u8()? as usize;
+    r.take(n)?; // legacy_compression_methods
+    if r.0.is_empty() {
+        return Some(None);
+    }
+    let n = r.u16()? as usize;
+    let mut exts = Reader(r.take(n)?);
+    while !exts.0.is_empty() {
+        let ty = exts.u16()?;
+        let n = exts.u16()? as usize;
+        let data = exts.take(n)?;
+        if ty != EXTENSION_SERVER_NAME {
+            continue;
+        }
+        let mut list = Reader(data);
+        let n = list.u16()? as usize;
+        let mut names = Reader(list.take(n)?);
+        while !names.0.is_empty() {
+            let kind = names.u8()?;
+            let n = names.u16()? as usize;
+            let name = names.take(n)?;
+            if kind == SERVER_NAME_HOST {
+                let name = std::str::from_utf8(name).ok()?;
+                if name.is_empty() || !name.is_ascii() {
+                    return None;
+                }
+                return Some(Some(name.to_string()));
+            }
+        }
+        return Some(None);
+    }
+    Some(None)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// A minimal single-record ClientHello asking for `host`.
+#[cfg(test)]
+pub fn client_hello_with_sni(host: &str) -> Vec<u8> {
+    let name = host.as_bytes();
+    let mut sni = Vec::new();
+    sni.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+    sni.push(SERVER_NAME_HOST);
+    sni.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    sni.extend_from_slice(name);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[0u8; 32]); // random
+    body.push(0); // session id
+    body.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // TLS_AES_128_GCM_SHA256
+    body.extend_from_slice(&[0x01, 0x00]); // null compression
+    body.extend_from_slice(&((sni.len() + 4) as u16).to_be_bytes());
+    body.extend_from_slice(&EXTENSION_SERVER_NAME.to_be_bytes());
+    body.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+    body.extend_from_slice(&sni);
+
+    let mut out = vec![RECORD_HANDSHAKE, 0x03, 0x01];
+    out.extend_from_slice(&((body.len() + 4) as u16).to_be_bytes());
+    out.push(HANDSHAKE_CLIENT_HELLO);
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    out.extend_from_slice(&body);
+    out
+}
+
+fn suite_name(suite: &rustls::SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite())
+}
+
+fn base_provider() -> CryptoProvider {
+    // Prefer the process-level default when main installed one; otherwise use ring.
+    match CryptoProvider::get_default() {
+        Some(provider) => CryptoProvider::clone(provider),
+        None => rustls::crypto::ring::default_provider(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+    fn handshake(server: ServerConfig, client: ClientConfig) -> Result<(), rustls::Error> {
+        let mut server = rustls::ServerConnection::new(Arc::new(server)).unwrap();
+        let mut client =
+            rustls::ClientConnection::new(Arc::new(client), "localhost".try_into().unwrap())
+                .unwrap();
+        let mut buf = Vec::new();
+        while client.is_handshaking() || server.is_handshaking() {
+            buf.clear();
+            client.write_tls(&mut buf).unwrap();
+            server.read_tls(&mut &buf[..]).unwrap();
+            server.process_new_packets()?;
+            buf.clear();
+            server.write_tls(&mut buf).unwrap();
+            client.read_tls(&mut &buf[..]).unwrap();
+            client.process_new_packets()?;
+        }
+        Ok(())
+    }
+
+    fn cert() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        let ck = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+        (
+            ck.cert.der().clone(),
+            PrivatePkcs8KeyDer::from(ck.signing_key.serialize_der()).into(),
+        )
+    }
+
+    #[test]
+    fn tls13_only_policy_rejects_tls12_peer() {
+        let (cert, key) = cert();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.clone()).unwrap();
+
+        let strict = TlsPolicy {
+            min_version: TlsVersion::Tls13,
+            cipher_suites: Vec::new(),
+        };
+        let server = |policy: &TlsPolicy| {
+            policy
+                .server_builder()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert.clone()], key.clone_key())
+                .unwrap()
+        };
+        let client = |versions: &[&'static SupportedProtocolVersion]| {
+            ClientConfig::builder_with_provider(Arc::new(base_provider()))
+                .with_protocol_versions(versions)
+                .unwrap()
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth()
+        };
+
+        assert!(handshake(server(&strict), client(&[&rustls::version::TLS12])).is_err());
+        assert!(handshake(server(&strict), client(rustls::DEFAULT_VERSIONS)).is_ok());
+        assert!(
+            handshake(
+                server(&TlsPolicy::default()),
+                client(&[&rustls::version::TLS12])
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn cipher_suite_policy_is_validated() {
+        let only_aes256 = TlsPolicy {
+            min_version: TlsVersion::Tls12,
+            cipher_suites: vec!["TLS13_AES_256_GCM_SHA384".into()],
+        };
+        only_aes256.validate().unwrap();
+        assert!(only_aes256.allows_tls13());
+        assert_eq!(only_aes256.provider().cipher_suites.len(), 1);
+
+        let tls12_only = TlsPolicy {
+            min_version: TlsVersion::Tls12,
+            cipher_suites: vec!["TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384".into()],
+        };
+        tls12_only.validate().unwrap();
+        assert!(!tls12_only.allows_tls13());
+
+        let unknown = TlsPolicy {
+            min_version: TlsVersion::Tls12,
+            cipher_suites: vec!["TLS_RSA_WITH_RC4_128_MD5".into()],
+        };
+        assert!(unknown.validate().is_err());
+
+        let empty = TlsPolicy {
+            min_version: TlsVersion::Tls13,
+            cipher_suites: tls12_only.cipher_suites.clone(),
+        };
+        assert!(empty.validate().is_err());
+
+        assert_eq!(TlsVersion::parse("TLS1.3").unwrap(), TlsVersion::Tls13);
+        assert!(TlsVersion::parse("1.1").is_err());
+    }
+
+    #[test]
+    fn client_hello_sni_is_extracted() {
+        let hello = client_hello_with_sni("secure.example.com");
+        let want = ClientHello::Complete {
+            server_name: Some("secure.example.com".into()),
+        };
+        assert_eq!(parse_client_hello(&hello).unwrap(), want);
+        for n in [0, 3, 5, 40, hello.len() - 1] {
+            assert_eq!(
+                parse_client_hello(&hello[..n]).unwrap(),
+                ClientHello::Incomplete
+            );
+        }
+
+        // The same message split over two records.
+        let msg = &hello[5..];
+        let mut split = Vec::new();
+        for part in [&msg[..10], &msg[10..]] {
+            split.extend_from_slice(&[0x16, 0x03, 0x03]);
+            split.extend_from_slice(&(part.len() as u16).to_be_bytes());
+            split.extend_from_slice(part);
+        }
+        assert_eq!(parse_client_hello(&split).unwrap(), want);
+
+        // A real rustls ClientHello, with all its other extensions.
+        let roots = rustls::RootCertStore::empty();
+        let config = TlsPolicy::default()
+            .client_builder()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut client =
+            rustls::ClientConnection::new(Arc::new(config), "play.example.net".try_into().unwrap())
+                .unwrap();
+        let mut real = Vec::new();
+        client.write_tls(&mut real).unwrap();
+        assert_eq!(
+            parse_client_hello(&real).unwrap(),
+            ClientHello::Complete {
+                server_name: Some("play.example.net".into())
+            }
+        );
+
+        assert!(parse_client_hello(b"GET / HTTP/1.1\r\n\r\n").is_err());
+    }
+}
@@ -412,6 +412,95 @@ mod tests {
         }
     }
 
+    /// A client session whose service echoes everything back.
+    struct EchoSession;
+
+    #[async_trait::async_trait]
+    impl crate::prism::tunnel::transport::TransportSession for EchoSession {
+        async fn open_stream(
+            &self,
+        ) -> anyhow::Result<crate::prism::tunnel::transport::BoxedStream> {
+            let (ours, mut theirs) = tokio::io::duplex(4096);
+            tokio::spawn(async move {
+                protocol::read_proxy_stream_header(&mut theirs).await?;
+                let (mut r, mut w) = tokio::io::split(theirs);
+                tokio::io::copy(&mut r, &mut w).await?;
+                anyhow::Ok(())
+            });
+            Ok(Box::new(ours))
+        }
+
+        async fn accept_stream(
+            &self,
+        ) -> anyhow::Result<crate::prism::tunnel::transport::BoxedStream> {
+            anyhow::bail!("not implemented")
+        }
+
+        async fn close(&self) {}
+
+        fn remote_addr(&self) -> Option<SocketAddr> {
+            None
+        }
+
+        fn local_addr(&self) -> Option<SocketAddr> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn tunneled_tcp_transfer_is_counted() {
+        let mgr = Arc::new(Manager::new());
+        mgr.register_client(
+            "c-1".into(),
+            Arc::new(EchoSession),
+            vec![protocol::RegisteredService {
+                name: "svc".into(),
+                proto: "tcp".into(),
+                local_addr: "127.0.0.1:25565".into(),
+                route_only: false,
+                remote_addr: String::new(),
+                masquerade_host: String::new(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: String::new(),
+            }],
+        )
+        .await
+        .unwrap();
+
+        let ln = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut user = TcpStream::connect(ln.local_addr().unwrap()).await.unwrap();
+        let (mut c, _) = ln.accept().await.unwrap();
+        let conn = tokio::spawn({
+            let mgr = mgr.clone();
+            async move { handle_tcp_conn(mgr, "c-1", "svc", &mut c).await }
+        });
+
+        user.write_all(b"hello tunnel").await.unwrap();
+        let mut buf = [0u8; 12];
+        user.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello tunnel");
+        let metrics = mgr.metrics();
+        assert_eq!(metrics.active_streams(), 1);
+
+        user.shutdown().await.unwrap();
+        assert_eq!(user.read(&mut buf).await.unwrap(), 0);
+        conn.await.unwrap().unwrap();
+
+        assert_eq!(metrics.streams_total("svc"), 1);
+        assert_eq!(metrics.bytes_total("svc", "c-1"), (12, 12));
+        assert_eq!(metrics.active_streams(), 0);
+        let text = metrics.render_prometheus();
+        assert!(text.contains(
+            "prism_tunnel_bytes_total{service=\"svc\",client_id=\"c-1\",direction=\"to_service\"} 12"
+        ));
+        assert!(text.contains("prism_tunnel_streams_total{service=\"svc\"} 1"));
+        assert!(text.contains("prism_tunnel_active_streams 0"));
+
+        mgr.unregister_client("c-1").await;
+        assert_eq!(metrics.bytes_total("svc", "c-1"), (0, 0));
+    }
+
     #[tokio::test]
     async fn reconcile_skips_route_only_even_with_remote_addr() {
         let mgr = Arc::new(Manager::new());
@@ -426,6 +515,9 @@ mod tests {
                 route_only: true,
                 remote_addr: "127.0.0.1:0".into(),
                 masquerade_host: "".into(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: String::new(),
             }],
         )
         .await
@@ -451,6 +543,9 @@ mod tests {
                 route_only: false,
                 remote_addr: "127.0.0.1:0".into(),
                 masquerade_host: "svc.internal".into(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: String::new(),
             }],
         )
         .await
@@ -1,16 +1,26 @@
 use std::{sync::Arc, time::Duration};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinSet,
+};
 
-use crate::prism::tunnel::{
-    protocol::{self, ProxyStreamKind, RegisterRequest, RegisteredService},
-    transport::{TransportDialOptions, transport_by_name},
+use crate::prism::{
+    telemetry,
+    tunnel::{
+        protocol::{self, ProxyStreamKind, RegisterRequest, RegisteredService},
+        throttle::{self, Throttle},
+        transport::{TransportDialOptions, transport_by_name},
+    },
 };
 
 #[derive(Debug, Clone)]
 pub struct QuicClientOptions {
     pub server_name: String,
     pub insecure_skip_verify: bool,
+    pub client_cert_file: String,
+    pub client_key_file: String,
+    pub tls: crate::prism::tls::TlsPolicy,
 }
 
 #[derive(Debug, Clone)]
@@ -20,12 +30,22 @@ pub struct ClientOptions {
     pub auth_token: String,
     pub services: Vec<RegisteredService>,
     pub dial_timeout: Duration,
+    /// How often to prove liveness to the server (0 = never).
+    pub heartbeat_interval: Duration,
+    /// On shutdown, how long to let open streams finish after asking the server to stop
+    /// routing new ones here (0 = close at once).
+    pub drain_timeout: Duration,
     pub quic: QuicClientOptions,
+    pub accept_queue_depth: usize,
+    /// Ready while registered with the server.
+    pub ready: Option<telemetry::ReadyCheck>,
 }
 
 pub struct Client {
     opts: ClientOptions,
     local_map: Arc<std::collections::HashMap<String, RegisteredService>>,
+    /// Rate limits shared by all connections of `rate_limit_scope = "service"` services.
+    shared_throttles: Arc<std::collections::HashMap<String, Throttle>>,
 }
 
 impl Client {
@@ -48,9 +68,20 @@ impl Client {
         }
         opts.services = svcs;
 
+        let shared_throttles = opts
+            .services
+            .iter()
+            .filter(|s| {
+                s.rate_limit_bytes_per_sec > 0
+                    && s.rate_limit_scope == protocol::RateLimitScope::Service
+            })
+            .map(|s| (s.name.clone(), Throttle::new(s.rate_limit_bytes_per_sec)))
+            .collect();
+
         Ok(Self {
             opts,
             local_map: Arc::new(map),
+            shared_throttles: Arc::new(shared_throttles),
         })
     }
 
@@ -68,9 +99,24 @@ impl Client {
                 return Ok(());
             }
 
-            match self.run_once(shutdown.clone()).await {
+            let res = self.run_once(shutdown.clone()).await;
+            if let Some(ready) = &self.opts.ready {
+                ready.unready();
+            }
+            match res {
                 Ok(()) => return Ok(()),
                 Err(err) => {
+                    // Retrying won't help until one side is upgraded, so park this tunnel
+                    // until shutdown; the rest of the process keeps serving.
+                    if let Some(protocol::ProtocolError::BadVersion(ver)) = err.downcast_ref() {
+                        tracing::error!(
+                            server=%self.opts.server_addr,
+                            version=ver,
+                            "tunnel: server speaks a tunnel protocol version this build does not support; not retrying until prism is upgraded on one side"
+                        );
+                        let _ = shutdown.wait_for(|stop| *stop).await;
+                        return Ok(());
+                    }
                     tracing::warn!(
                         transport=%self.opts.transport,
                         server=%self.opts.server_addr,
@@ -104,8 +150,12 @@ impl Client {
                     quic: crate::prism::tunnel::transport::QuicDialOptions {
                         server_name: self.opts.quic.server_name.clone(),
                         insecure_skip_verify: self.opts.quic.insecure_skip_verify,
+                        client_cert_file: self.opts.quic.client_cert_file.clone(),
+                        client_key_file: self.opts.quic.client_key_file.clone(),
                         next_protos: vec![],
+                        tls: self.opts.quic.tls.clone(),
                     },
+                    accept_queue_depth: self.opts.accept_queue_depth,
                 },
             )
             .await
@@ -118,9 +168,14 @@ impl Client {
         let req = RegisterRequest {
             token: self.opts.auth_token.clone(),
             services: self.opts.services.clone(),
+            heartbeat_interval_ms: self.opts.heartbeat_interval.as_millis() as u64,
         };
         protocol::write_register_request(&mut reg, &req).await?;
         reg.shutdown().await?;
+        // The server only answers on the register stream to reject our version.
+        let reply = protocol::read_register_reply(&mut reg);
+        tokio::pin!(reply);
+        let mut reply_done = false;
 
         tracing::info!(
             transport=%tr.name(),
@@ -128,33 +183,126 @@ impl Client {
             services=self.opts.services.len(),
             "tunnel: connected"
         );
+        if let Some(ready) = &self.opts.ready {
+            ready.ready();
+        }
+
+        let heartbeat_interval = self.opts.heartbeat_interval;
+        let heartbeats = send_heartbeats(sess.as_ref(), heartbeat_interval);
+        tokio::pin!(heartbeats);
 
         // Accept proxy streams.
+        let mut streams = JoinSet::new();
         let mut shutdown = shutdown;
         loop {
             tokio::select! {
                 _ = shutdown.changed() => {
                     if *shutdown.borrow() {
+                        break;
+                    }
+                }
+                err = &mut heartbeats, if !heartbeat_interval.is_zero() => {
+                    sess.close().await;
+                    return Err(err);
+                }
+                r = &mut reply, if !reply_done => {
+                    reply_done = true;
+                    if let Ok(Some(ver)) = r {
                         sess.close().await;
-                        return Ok(());
+                        return Err(protocol::ProtocolError::BadVersion(ver).into());
                     }
                 }
                 st = sess.accept_stream() => {
-                    let st = st?;
-                    let map = self.local_map.clone();
-                    tokio::spawn(async move {
-                        if let Err(err) = handle_stream(map, st).await {
-                            tracing::debug!(err=%err, "tunnel: stream ended");
+                    self.spawn_stream(&mut streams, st?);
+                }
+                Some(_) = streams.join_next(), if !streams.is_empty() => {}
+            }
+        }
+
+        // Shutting down: ask the server to route new streams elsewhere, then let the open
+        // ones finish. Heartbeats keep going so the server doesn't drop us meanwhile.
+        let drain_timeout = self.opts.drain_timeout;
+        if !drain_timeout.is_zero() && !streams.is_empty() {
+            let deadline = tokio::time::Instant::now() + drain_timeout;
+            let notice = tokio::time::timeout_at(deadline, async {
+                let mut st = sess.open_stream().await?;
+                protocol::write_drain(&mut st).await?;
+                protocol::read_control_stream(&mut st).await?;
+                anyhow::Ok(())
+            })
+            .await;
+            if !matches!(notice, Ok(Ok(()))) {
+                tracing::debug!("tunnel: server did not acknowledge drain");
+            }
+            tracing::info!(streams = streams.len(), "tunnel: draining");
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => {
+                        tracing::warn!(
+                            streams = streams.len(),
+                            "tunnel: drain timed out; closing open streams"
+                        );
+                        break;
+                    }
+                    _ = &mut heartbeats, if !heartbeat_interval.is_zero() => break,
+                    // Streams the server opened before it saw the notice.
+                    st = sess.accept_stream() => match st {
+                        Ok(st) => self.spawn_stream(&mut streams, st),
+                        Err(_) => break,
+                    },
+                    done = streams.join_next() => {
+                        if done.is_none() {
+                            break;
                         }
-                    });
+                    }
                 }
             }
         }
+        sess.close().await;
+        Ok(())
+    }
+
+    fn spawn_stream(
+        &self,
+        streams: &mut JoinSet<()>,
+        st: crate::prism::tunnel::transport::BoxedStream,
+    ) {
+        let map = self.local_map.clone();
+        let throttles = self.shared_throttles.clone();
+        streams.spawn(async move {
+            if let Err(err) = handle_stream(map, throttles, st).await {
+                tracing::debug!(err=%err, "tunnel: stream ended");
+            }
+        });
+    }
+}
+
+/// Send a heartbeat every `interval` until one goes unanswered for that long, which means
+/// the server (or the path to it) is gone.
+async fn send_heartbeats(
+    sess: &dyn crate::prism::tunnel::transport::TransportSession,
+    interval: Duration,
+) -> anyhow::Error {
+    loop {
+        tokio::time::sleep(interval).await;
+        let beat = tokio::time::timeout(interval, async {
+            let mut st = sess.open_stream().await?;
+            protocol::write_heartbeat(&mut st).await?;
+            protocol::read_heartbeat(&mut st).await?;
+            anyhow::Ok(())
+        })
+        .await;
+        match beat {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => return err.context("tunnel: heartbeat failed"),
+            Err(_) => return anyhow::anyhow!("tunnel: heartbeat timed out"),
+        }
     }
 }
 
 async fn handle_stream(
     local_map: Arc<std::collections::HashMap<String, RegisteredService>>,
+    shared_throttles: Arc<std::collections::HashMap<String, Throttle>>,
     mut st: crate::prism::tunnel::transport::BoxedStream,
 ) -> anyhow::Result<()> {
     let (kind, svc) = protocol::read_proxy_stream_header(&mut st).await?;
@@ -167,12 +315,28 @@ async fn handle_stream(
     if local.is_empty() {
         return Ok(());
     }
+    let throttle = match meta.rate_limit_bytes_per_sec {
+        0 => None,
+        rate => Some(
+            shared_throttles
+                .get(&svc)
+                .cloned()
+                .unwrap_or_else(|| Throttle::new(rate)),
+        ),
+    };
 
     match kind {
         ProxyStreamKind::Tcp => {
             let mut up = tokio::net::TcpStream::connect(&local).await?;
             let mut st = st;
-            let _ = tokio::io::copy_bidirectional(&mut st, &mut up).await;
+            match &throttle {
+                Some(t) => {
+                    let _ = throttle::copy_bidirectional(st, up, t).await;
+                }
+                None => {
+                    let _ = tokio::io::copy_bidirectional(&mut st, &mut up).await;
+                }
+            }
         }
         ProxyStreamKind::Udp => {
             // Proxy framed datagrams over the tunnel stream <-> local UDP socket.
@@ -185,6 +349,7 @@ async fn handle_stream(
 
             // We cannot reuse AsyncRead/Write-based copying for UDP because datagram framing must be preserved.
             let sock_to_local = sock.clone();
+            let to_local = throttle.as_ref().map(|t| t.to_local.clone());
             let t1 = tokio::spawn(async move {
                 let mut buf = vec![0u8; 64 * 1024];
                 loop {
@@ -197,12 +362,16 @@ async fn handle_stream(
                         buf.resize(n, 0);
                     }
                     rd.read_exact(&mut buf[..n]).await?;
+                    if let Some(bucket) = &to_local {
+                        bucket.take(n).await;
+                    }
                     let _ = sock_to_local.send(&buf[..n]).await?;
                 }
                 Ok::<(), anyhow::Error>(())
             });
 
             let sock_from_local = sock;
+            let from_local = throttle.map(|t| t.from_local);
             let t2 = tokio::spawn(async move {
                 let mut buf = vec![0u8; 64 * 1024];
                 loop {
@@ -211,6 +380,9 @@ async fn handle_stream(
                     if n32 > protocol::MAX_DATAGRAM_BYTES {
                         continue;
                     }
+                    if let Some(bucket) = &from_local {
+                        bucket.take(n).await;
+                    }
                     wr.write_u32(n32).await?;
                     wr.write_all(&buf[..n]).await?;
                     wr.flush().await?;
@@ -225,3 +397,75 @@ async fn handle_stream(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prism::tunnel::transport::TransportListenOptions;
+
+    #[tokio::test]
+    async fn version_mismatch_from_server_stops_retrying() {
+        let tr = transport_by_name("tcp").expect("tcp transport");
+        let ln = tr
+            .listen("127.0.0.1:0", TransportListenOptions::default())
+            .await
+            .expect("listen");
+        let addr = ln.local_addr().expect("addr").to_string();
+
+        // A server from a newer build: it rejects our version 1 request the way
+        // `write_version_mismatch` would, naming its own version 2.
+        let dials = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        tokio::spawn({
+            let dials = dials.clone();
+            async move {
+                loop {
+                    let sess = ln.accept().await.expect("accept");
+                    dials.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    tokio::spawn(async move {
+                        let mut reg = sess.accept_stream().await.expect("register stream");
+                        let mut header = [0u8; 5];
+                        reg.read_exact(&mut header).await.expect("read header");
+                        assert_eq!(&header, b"PRRG\x01");
+                        reg.write_all(b"PRRG\x02").await.expect("reply");
+                        reg.shutdown().await.expect("shutdown");
+                        let _ = sess.accept_stream().await;
+                    });
+                }
+            }
+        });
+
+        let client = Client::new(ClientOptions {
+            server_addr: addr,
+            transport: "tcp".into(),
+            auth_token: String::new(),
+            services: vec![],
+            dial_timeout: Duration::from_secs(2),
+            heartbeat_interval: Duration::ZERO,
+            drain_timeout: Duration::ZERO,
+            quic: QuicClientOptions {
+                server_name: String::new(),
+                insecure_skip_verify: false,
+                client_cert_file: String::new(),
+                client_key_file: String::new(),
+                tls: Default::default(),
+            },
+            accept_queue_depth: 0,
+            ready: None,
+        })
+        .expect("client");
+        let (tx, rx) = tokio::sync::watch::channel(false);
+        let run = tokio::spawn(async move { client.run(rx).await });
+
+        // Past the first reconnect backoff (1s) without a second dial.
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(dials.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(!run.is_finished(), "a mismatch must not end the process");
+
+        tx.send(true).expect("shutdown");
+        tokio::time::timeout(Duration::from_secs(2), run)
+            .await
+            .expect("client ignored shutdown")
+            .expect("join")
+            .expect("run");
+    }
+}
@@ -36,10 +36,7 @@ where
             // Drain to keep stream aligned.
             let mut drain = vec![0u8; n];
             self.inner.read_exact(&mut drain).await?;
-            return Err(ProtocolError::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "short buffer",
-            )));
+            return Err(ProtocolError::ShortBuffer(n));
         }
         self.inner.read_exact(&mut out[..n]).await?;
         Ok(n)
@@ -58,3 +55,25 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn oversize_datagram_is_skipped_and_the_stream_stays_aligned() {
+        let (a, b) = tokio::io::duplex(1024);
+        let mut tx = DatagramConn::new(a);
+        let mut rx = DatagramConn::new(b);
+        tx.write_datagram(&[b'x'; 16]).await.expect("write");
+        tx.write_datagram(b"ok").await.expect("write");
+
+        let mut buf = [0u8; 8];
+        assert!(matches!(
+            rx.read_datagram(&mut buf).await,
+            Err(ProtocolError::ShortBuffer(16))
+        ));
+        let n = rx.read_datagram(&mut buf).await.expect("next datagram");
+        assert_eq!(&buf[..n], b"ok");
+    }
+}
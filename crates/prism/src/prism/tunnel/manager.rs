@@ -1,15 +1,17 @@
 use std::{
     collections::HashMap,
+    net::IpAddr,
     sync::{
         Arc,
         atomic::{AtomicU64, Ordering},
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use tokio::sync::RwLock;
 
 use crate::prism::tunnel::{
+    metrics::TunnelMetrics,
     protocol::{self, ProxyStreamKind, RegisteredService},
     transport::{BoxedStream, TransportSession},
 };
@@ -26,24 +28,46 @@ pub struct ServiceSnapshot {
     pub client_id: String,
     pub remote: String,
     pub primary: bool,
+    /// The client is shutting down and gets no new streams.
+    pub draining: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClientSnapshot {
+    pub client_id: String,
+    pub remote: String,
+    pub connected_ms: u64,
+    pub draining: bool,
+    pub services: Vec<RegisteredService>,
+    /// Services this client currently owns routing for.
+    pub primary_for: Vec<String>,
 }
 
 struct ClientConn {
     sess: Arc<dyn TransportSession>,
     services: HashMap<String, RegisteredService>,
     remote: String,
+    remote_ip: Option<IpAddr>,
     started: Instant,
+    draining: bool,
 }
 
 struct State {
     clients: HashMap<String, ClientConn>,
     primary: HashMap<String, String>,
+    /// When a primary last dropped, keyed by its remote IP (for `primary_grace`).
+    primary_drops: HashMap<IpAddr, Instant>,
 }
 
 pub struct Manager {
     id_seq: AtomicU64,
     state: RwLock<State>,
     changed: tokio::sync::watch::Sender<u64>,
+    primary_grace: Duration,
+    dial_service_wait: Duration,
+    metrics: Arc<TunnelMetrics>,
+    /// Next pick per `dial_policy = "balance"` service.
+    balance_seq: dashmap::DashMap<String, usize>,
 }
 
 impl std::fmt::Debug for Manager {
@@ -52,6 +76,12 @@ impl std::fmt::Debug for Manager {
     }
 }
 
+impl Default for Manager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Manager {
     pub fn new() -> Self {
         let (tx, _rx) = tokio::sync::watch::channel(0u64);
@@ -60,11 +90,36 @@ impl Manager {
             state: RwLock::new(State {
                 clients: HashMap::new(),
                 primary: HashMap::new(),
+                primary_drops: HashMap::new(),
             }),
             changed: tx,
+            primary_grace: Duration::ZERO,
+            dial_service_wait: Duration::ZERO,
+            metrics: Arc::new(TunnelMetrics::default()),
+            balance_seq: dashmap::DashMap::new(),
         }
     }
 
+    /// After a primary drops, don't promote another session from the same remote IP for
+    /// `grace` while a client from elsewhere can take over, so a flapping link doesn't
+    /// keep winning routing back.
+    pub fn with_primary_grace(mut self, grace: Duration) -> Self {
+        self.primary_grace = grace;
+        self
+    }
+
+    /// Let dials for a service that is momentarily unregistered (e.g. while its client
+    /// reconnects) wait up to `wait` for it to come back instead of failing at once.
+    pub fn with_dial_service_wait(mut self, wait: Duration) -> Self {
+        self.dial_service_wait = wait;
+        self
+    }
+
+    /// Traffic counters for every stream dialed through this manager.
+    pub fn metrics(&self) -> &Arc<TunnelMetrics> {
+        &self.metrics
+    }
+
     pub fn subscribe(&self) -> tokio::sync::watch::Receiver<u64> {
         self.changed.subscribe()
     }
@@ -93,10 +148,13 @@ impl Manager {
             sess,
             services: HashMap::new(),
             remote: String::new(),
+            remote_ip: None,
             started: Instant::now(),
+            draining: false,
         };
         if let Some(ra) = cc.sess.remote_addr() {
             cc.remote = ra.to_string();
+            cc.remote_ip = Some(ra.ip().to_canonical());
         }
         for s in services {
             if let Some(ns) = s.normalize() {
@@ -109,12 +167,7 @@ impl Manager {
         // Replace any existing client with the same id.
         if let Some(old) = st.clients.remove(&id) {
             old.sess.close().await;
-            for name in old.services.keys() {
-                if st.primary.get(name).is_some_and(|v| v == &id) {
-                    st.primary.remove(name);
-                    promote_primary_locked(&mut st, name);
-                }
-            }
+            self.drop_primaries_locked(&mut st, &id, &old);
         }
 
         // First writer wins for routing ownership.
@@ -140,14 +193,36 @@ impl Manager {
             return;
         };
 
-        for name in old.services.keys() {
-            if st.primary.get(name).is_some_and(|v| v == id) {
-                st.primary.remove(name);
-                promote_primary_locked(&mut st, name);
+        self.drop_primaries_locked(&mut st, id, &old);
+        drop(st);
+        old.sess.close().await;
+        self.metrics.forget_client(id);
+        self.bump_changed();
+    }
+
+    /// Stop routing new streams to client `id` ahead of its shutdown: it loses primary for
+    /// its services (another provider takes over if there is one) and dials, pinned or not,
+    /// skip it. Streams already open are left alone.
+    pub async fn drain_client(&self, id: &str) {
+        let id = id.trim();
+        let mut st = self.state.write().await;
+        let Some(cc) = st.clients.get_mut(id) else {
+            return;
+        };
+        if cc.draining {
+            return;
+        }
+        cc.draining = true;
+        let names: Vec<String> = cc.services.keys().cloned().collect();
+
+        let now = Instant::now();
+        for name in names {
+            if st.primary.get(&name).is_some_and(|v| v == id) {
+                st.primary.remove(&name);
+                promote_primary_locked(&mut st, &name, self.primary_grace, now);
             }
         }
         drop(st);
-        old.sess.close().await;
         self.bump_changed();
     }
 
@@ -161,12 +236,40 @@ impl Manager {
                     client_id: cid.clone(),
                     remote: cc.remote.clone(),
                     primary: st.primary.get(name).is_some_and(|v| v == cid),
+                    draining: cc.draining,
                 });
             }
         }
         out
     }
 
+    pub async fn snapshot_clients(&self) -> Vec<ClientSnapshot> {
+        let st = self.state.read().await;
+        let mut out: Vec<ClientSnapshot> = st
+            .clients
+            .iter()
+            .map(|(cid, cc)| {
+                let mut services: Vec<RegisteredService> = cc.services.values().cloned().collect();
+                services.sort_by(|a, b| a.name.cmp(&b.name));
+                let primary_for = services
+                    .iter()
+                    .filter(|s| st.primary.get(&s.name).is_some_and(|v| v == cid))
+                    .map(|s| s.name.clone())
+                    .collect();
+                ClientSnapshot {
+                    client_id: cid.clone(),
+                    remote: cc.remote.clone(),
+                    connected_ms: cc.started.elapsed().as_millis() as u64,
+                    draining: cc.draining,
+                    services,
+                    primary_for,
+                }
+            })
+            .collect();
+        out.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        out
+    }
+
     #[allow(dead_code)]
     pub async fn has_service(&self, service: &str) -> bool {
         let st = self.state.read().await;
@@ -218,6 +321,23 @@ impl Manager {
         self.dial_service_udp_inner(Some(client_id), service).await
     }
 
+    /// Wait up to `dial_service_wait` for `service` to have a primary. Returns at once when
+    /// it already has one; the caller's lookup decides what happens after a timeout.
+    async fn wait_for_service(&self, service: &str) {
+        if self.dial_service_wait.is_zero() {
+            return;
+        }
+        // Subscribe before checking so a registration in between isn't missed.
+        let mut changed = self.subscribe();
+        let deadline = tokio::time::Instant::now() + self.dial_service_wait;
+        while !self.has_service(service).await {
+            match tokio::time::timeout_at(deadline, changed.changed()).await {
+                Ok(Ok(())) => {}
+                _ => return,
+            }
+        }
+    }
+
     async fn dial_service_tcp_inner(
         &self,
         client_id: Option<&str>,
@@ -227,17 +347,14 @@ impl Manager {
         if service.is_empty() {
             return Err(ManagerError::ServiceNotFound);
         }
+        // A pinned client id can't come back under the same id, so only wait for primaries.
+        if client_id.is_none() {
+            self.wait_for_service(service).await;
+        }
 
-        let (sess, svc): (Arc<dyn TransportSession>, RegisteredService) = {
+        let (cid, sess, svc): (String, Arc<dyn TransportSession>, RegisteredService) = {
             let st = self.state.read().await;
-            let cid = if let Some(pinned) = client_id {
-                pinned.trim().to_string()
-            } else {
-                st.primary
-                    .get(service)
-                    .cloned()
-                    .ok_or(ManagerError::ServiceNotFound)?
-            };
+            let cid = self.pick_client_locked(&st, client_id, service)?;
 
             let cc = st.clients.get(&cid).ok_or(ManagerError::ServiceNotFound)?;
             let svc = cc
@@ -245,7 +362,7 @@ impl Manager {
                 .get(service)
                 .cloned()
                 .ok_or(ManagerError::ServiceNotFound)?;
-            (cc.sess.clone(), svc)
+            (cid, cc.sess.clone(), svc)
         };
 
         let mut st = sess
@@ -255,7 +372,7 @@ impl Manager {
         protocol::write_proxy_stream_header(&mut st, ProxyStreamKind::Tcp, service)
             .await
             .map_err(|_| ManagerError::ServiceNotFound)?;
-        Ok((st, svc))
+        Ok((self.metrics.track(service, &cid, st), svc))
     }
 
     async fn dial_service_udp_inner(
@@ -267,23 +384,19 @@ impl Manager {
         if service.is_empty() {
             return Err(ManagerError::ServiceNotFound);
         }
+        if client_id.is_none() {
+            self.wait_for_service(service).await;
+        }
 
-        let sess: Arc<dyn TransportSession> = {
+        let (cid, sess): (String, Arc<dyn TransportSession>) = {
             let st = self.state.read().await;
-            let cid = if let Some(pinned) = client_id {
-                pinned.trim().to_string()
-            } else {
-                st.primary
-                    .get(service)
-                    .cloned()
-                    .ok_or(ManagerError::ServiceNotFound)?
-            };
+            let cid = self.pick_client_locked(&st, client_id, service)?;
 
             let cc = st.clients.get(&cid).ok_or(ManagerError::ServiceNotFound)?;
             if !cc.services.contains_key(service) {
                 return Err(ManagerError::ServiceNotFound);
             }
-            cc.sess.clone()
+            (cid, cc.sess.clone())
         };
 
         let mut st = sess
@@ -293,7 +406,92 @@ impl Manager {
         protocol::write_proxy_stream_header(&mut st, ProxyStreamKind::Udp, service)
             .await
             .map_err(|_| ManagerError::ServiceNotFound)?;
-        Ok(st)
+        Ok(self.metrics.track(service, &cid, st))
+    }
+
+    /// The client a dial for `service` goes to: the pinned one if given, else the primary,
+    /// or when the primary registered `dial_policy = "balance"`, each client that also
+    /// registered the service with `"balance"` in turn, oldest first.
+    fn pick_client_locked(
+        &self,
+        st: &State,
+        pinned: Option<&str>,
+        service: &str,
+    ) -> Result<String, ManagerError> {
+        if let Some(pinned) = pinned {
+            let pinned = pinned.trim();
+            if st.clients.get(pinned).is_none_or(|cc| cc.draining) {
+                return Err(ManagerError::ServiceNotFound);
+            }
+            return Ok(pinned.to_string());
+        }
+        let primary = st
+            .primary
+            .get(service)
+            .ok_or(ManagerError::ServiceNotFound)?;
+        let balance = st
+            .clients
+            .get(primary)
+            .and_then(|cc| cc.services.get(service))
+            .is_some_and(|svc| svc.dial_policy == "balance");
+        if !balance {
+            return Ok(primary.clone());
+        }
+
+        // A client that registered the service under the default policy only serves it
+        // as primary.
+        let mut providers: Vec<(Instant, &String)> = st
+            .clients
+            .iter()
+            .filter(|(_, cc)| {
+                !cc.draining
+                    && cc
+                        .services
+                        .get(service)
+                        .is_some_and(|svc| svc.dial_policy == "balance")
+            })
+            .map(|(cid, cc)| (cc.started, cid))
+            .collect();
+        providers.sort();
+        let n = {
+            let mut next = self.balance_seq.entry(service.to_string()).or_insert(0);
+            let n = *next;
+            *next = n.wrapping_add(1);
+            n
+        };
+        Ok(providers[n % providers.len()].1.clone())
+    }
+
+    /// Re-elect primaries for services that `old` (id `id`) was primary for.
+    fn drop_primaries_locked(&self, st: &mut State, id: &str, old: &ClientConn) {
+        let mut dropped = false;
+        for name in old.services.keys() {
+            if st.primary.get(name).is_some_and(|v| v == id) {
+                st.primary.remove(name);
+                dropped = true;
+            }
+        }
+        if !dropped {
+            return;
+        }
+
+        let now = Instant::now();
+        if !self.primary_grace.is_zero() {
+            st.primary_drops
+                .retain(|_, at| now.duration_since(*at) < self.primary_grace);
+            if let Some(ip) = old.remote_ip {
+                st.primary_drops.insert(ip, now);
+            }
+        }
+        for name in old.services.keys() {
+            if !st.primary.contains_key(name) {
+                promote_primary_locked(st, name, self.primary_grace, now);
+            }
+            // Nobody provides the service any more; don't keep its dial cursor around.
+            if !st.primary.contains_key(name) {
+                self.balance_seq.remove(name);
+            }
+        }
     }
 
     fn bump_changed(&self) {
@@ -302,18 +500,227 @@ impl Manager {
     }
 }
 
-fn promote_primary_locked(st: &mut State, service_name: &str) {
-    // Choose the oldest active client that provides this service.
-    let mut chosen: Option<(String, Instant)> = None;
+fn promote_primary_locked(st: &mut State, service_name: &str, grace: Duration, now: Instant) {
+    // Choose the oldest active client that provides this service, skipping hosts whose
+    // primary dropped within the grace window unless nobody else can serve it.
+    let held_down = |cc: &ClientConn| {
+        cc.remote_ip
+            .and_then(|ip| st.primary_drops.get(&ip))
+            .is_some_and(|at| now.duration_since(*at) < grace)
+    };
+    let mut chosen: Option<(&String, bool, Instant)> = None;
     for (cid, cc) in &st.clients {
-        if !cc.services.contains_key(service_name) {
+        if cc.draining || !cc.services.contains_key(service_name) {
             continue;
         }
-        if chosen.is_none() || cc.started < chosen.as_ref().unwrap().1 {
-            chosen = Some((cid.clone(), cc.started));
+        let held = held_down(cc);
+        let better = match chosen {
+            None => true,
+            Some((_, chosen_held, started)) => (held, cc.started) < (chosen_held, started),
+        };
+        if better {
+            chosen = Some((cid, held, cc.started));
         }
     }
-    if let Some((cid, _)) = chosen {
+    if let Some((cid, _, _)) = chosen {
+        let cid = cid.clone();
         st.primary.insert(service_name.to_string(), cid);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    struct FakeSession {
+        remote: SocketAddr,
+    }
+
+    #[async_trait::async_trait]
+    impl TransportSession for FakeSession {
+        async fn open_stream(&self) -> anyhow::Result<BoxedStream> {
+            // Swallows the proxy header; enough for dials to succeed.
+            Ok(Box::new(tokio::io::join(
+                tokio::io::empty(),
+                tokio::io::sink(),
+            )))
+        }
+
+        async fn accept_stream(&self) -> anyhow::Result<BoxedStream> {
+            anyhow::bail!("not implemented")
+        }
+
+        async fn close(&self) {}
+
+        fn remote_addr(&self) -> Option<SocketAddr> {
+            Some(self.remote)
+        }
+
+        fn local_addr(&self) -> Option<SocketAddr> {
+            None
+        }
+    }
+
+    async fn register(mgr: &Manager, id: &str, remote: &str) {
+        register_with_policy(mgr, id, remote, "").await;
+    }
+
+    async fn register_with_policy(mgr: &Manager, id: &str, remote: &str, dial_policy: &str) {
+        let sess = Arc::new(FakeSession {
+            remote: remote.parse().unwrap(),
+        });
+        mgr.register_client(
+            id.into(),
+            sess,
+            vec![RegisteredService {
+                name: "svc".into(),
+                proto: "tcp".into(),
+                local_addr: "127.0.0.1:25565".into(),
+                route_only: true,
+                remote_addr: String::new(),
+                masquerade_host: String::new(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: dial_policy.into(),
+            }],
+        )
+        .await
+        .unwrap();
+        // Distinct start times so "oldest" is well defined.
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+
+    async fn primary(mgr: &Manager) -> Option<String> {
+        mgr.snapshot_services()
+            .await
+            .into_iter()
+            .find(|s| s.primary)
+            .map(|s| s.client_id)
+    }
+
+    /// A flapping primary reconnects (c-2) before its old session (c-1) is reaped, then a
+    /// standby from another host (c-3) joins.
+    async fn flap(mgr: &Manager) {
+        register(mgr, "c-1", "10.0.0.1:40001").await;
+        register(mgr, "c-2", "10.0.0.1:40002").await;
+        register(mgr, "c-3", "10.0.0.2:40001").await;
+        assert_eq!(primary(mgr).await.as_deref(), Some("c-1"));
+        mgr.unregister_client("c-1").await;
+    }
+
+    #[tokio::test]
+    async fn primary_grace_keeps_flapping_host_out_of_promotion() {
+        let mgr = Manager::new().with_primary_grace(Duration::from_secs(60));
+        flap(&mgr).await;
+        assert_eq!(primary(&mgr).await.as_deref(), Some("c-3"));
+
+        // The standby stays primary while the flapping host keeps reconnecting.
+        mgr.unregister_client("c-2").await;
+        register(&mgr, "c-4", "10.0.0.1:40003").await;
+        assert_eq!(primary(&mgr).await.as_deref(), Some("c-3"));
+
+        // The flapping host still serves when nobody else can.
+        mgr.unregister_client("c-3").await;
+        assert_eq!(primary(&mgr).await.as_deref(), Some("c-4"));
+    }
+
+    #[tokio::test]
+    async fn dial_waits_for_a_reconnecting_client() {
+        let mgr = Arc::new(Manager::new().with_dial_service_wait(Duration::from_secs(5)));
+        register(&mgr, "c-1", "10.0.0.1:40001").await;
+        mgr.unregister_client("c-1").await;
+
+        // Dialed in the gap; the client comes back under a new id shortly after.
+        let dial = tokio::spawn({
+            let mgr = mgr.clone();
+            async move { mgr.dial_service_tcp("svc").await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!dial.is_finished());
+        register(&mgr, "c-2", "10.0.0.1:40002").await;
+        tokio::time::timeout(Duration::from_secs(2), dial)
+            .await
+            .expect("dial kept waiting")
+            .expect("join")
+            .expect("dial after reconnect");
+
+        // Without a wait, or past it, a missing service still fails.
+        assert!(matches!(
+            Manager::new().dial_service_tcp("svc").await,
+            Err(ManagerError::ServiceNotFound)
+        ));
+        let mgr = Manager::new().with_dial_service_wait(Duration::from_millis(50));
+        assert!(matches!(
+            mgr.dial_service_udp("svc").await,
+            Err(ManagerError::ServiceNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn without_primary_grace_oldest_client_is_promoted() {
+        let mgr = Manager::new();
+        flap(&mgr).await;
+        assert_eq!(primary(&mgr).await.as_deref(), Some("c-2"));
+    }
+
+    /// Which client a dial landed on, read from the stream's traffic counters.
+    async fn dialed_client(mgr: &Manager, ids: &[&str]) -> String {
+        let before: Vec<u64> = ids
+            .iter()
+            .map(|id| mgr.metrics().bytes_total("svc", id).0)
+            .collect();
+        let mut st = mgr.dial_service_tcp("svc").await.expect("dial");
+        tokio::io::AsyncWriteExt::write_all(&mut st, b"x")
+            .await
+            .expect("write");
+        ids.iter()
+            .zip(before)
+            .find(|(id, b)| mgr.metrics().bytes_total("svc", id).0 > *b)
+            .map(|(id, _)| id.to_string())
+            .expect("dial reached a client")
+    }
+
+    #[tokio::test]
+    async fn balance_policy_alternates_dials_between_clients() {
+        let mgr = Manager::new();
+        register_with_policy(&mgr, "c-1", "10.0.0.1:40001", "balance").await;
+        register_with_policy(&mgr, "c-2", "10.0.0.2:40001", "balance").await;
+
+        let mut picks = Vec::new();
+        for _ in 0..4 {
+            picks.push(dialed_client(&mgr, &["c-1", "c-2"]).await);
+        }
+        assert_eq!(picks, ["c-1", "c-2", "c-1", "c-2"]);
+        // Routing ownership is unchanged.
+        assert_eq!(primary(&mgr).await.as_deref(), Some("c-1"));
+
+        // The default policy keeps every dial on the primary.
+        let mgr = Manager::new();
+        register(&mgr, "c-1", "10.0.0.1:40001").await;
+        register(&mgr, "c-2", "10.0.0.2:40001").await;
+        for _ in 0..3 {
+            assert_eq!(dialed_client(&mgr, &["c-1", "c-2"]).await, "c-1");
+        }
+    }
+
+    #[tokio::test]
+    async fn balance_policy_skips_clients_that_did_not_opt_in() {
+        let mgr = Manager::new();
+        register_with_policy(&mgr, "c-1", "10.0.0.1:40001", "balance").await;
+        register(&mgr, "c-2", "10.0.0.2:40001").await;
+        register_with_policy(&mgr, "c-3", "10.0.0.3:40001", "balance").await;
+
+        let mut picks = Vec::new();
+        for _ in 0..4 {
+            picks.push(dialed_client(&mgr, &["c-1", "c-2", "c-3"]).await);
+        }
+        assert_eq!(picks, ["c-1", "c-3", "c-1", "c-3"]);
+
+        // The cursor goes with the last provider.
+        for id in ["c-1", "c-2", "c-3"] {
+            mgr.unregister_client(id).await;
+        }
+        assert!(mgr.balance_seq.is_empty());
+    }
+}
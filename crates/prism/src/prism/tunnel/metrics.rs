@@ -0,0 +1,227 @@
+//! Per-service traffic counters for streams the tunnel server dials, rendered in the
+//! Prometheus text format by the admin `/metrics` endpoint.
+
+use std::{
+    fmt::Write as _,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use dashmap::DashMap;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::prism::{telemetry::escape_label, tunnel::transport::BoxedStream};
+
+#[derive(Default)]
+struct ByteCounters {
+    /// Bytes written into the tunnel, towards the client's local service.
+    to_service: AtomicU64,
+    /// Bytes read back from the tunnel.
+    from_service: AtomicU64,
+}
+
+#[derive(Default)]
+pub struct TunnelMetrics {
+    /// Keyed by (service, client_id).
+    bytes: DashMap<(String, String), Arc<ByteCounters>>,
+    streams: DashMap<String, AtomicU64>,
+    active: AtomicU64,
+    /// Registrations rejected for speaking another tunnel protocol version.
+    version_mismatches: AtomicU64,
+    /// Streams waiting in the server's per-session accept queues.
+    accept_queued: Arc<AtomicUsize>,
+}
+
+impl TunnelMetrics {
+    /// Count a newly dialed stream and wrap it so its traffic is counted too.
+    pub fn track(self: &Arc<Self>, service: &str, client_id: &str, st: BoxedStream) -> BoxedStream {
+        self.streams
+            .entry(service.to_string())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+        let counters = self
+            .bytes
+            .entry((service.to_string(), client_id.to_string()))
+            .or_default()
+            .clone();
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Box::new(MeteredStream {
+            inner: st,
+            counters,
+            metrics: self.clone(),
+        })
+    }
+
+    /// Drop the byte series of a client that went away, so reconnect churn (every session
+    /// gets a new id) doesn't grow the label set forever.
+    pub fn forget_client(&self, client_id: &str) {
+        self.bytes.retain(|(_, cid), _| cid != client_id);
+    }
+
+    pub fn record_version_mismatch(&self) {
+        self.version_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn version_mismatches(&self) -> u64 {
+        self.version_mismatches.load(Ordering::Relaxed)
+    }
+
+    /// Gauge handed to the tunnel listener, which keeps it at the accept queue backlog.
+    pub fn accept_queue_gauge(&self) -> Arc<AtomicUsize> {
+        self.accept_queued.clone()
+    }
+
+    pub fn accept_queue_depth(&self) -> usize {
+        self.accept_queued.load(Ordering::Relaxed)
+    }
+
+    pub fn active_streams(&self) -> u64 {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    #[allow(dead_code)]
+    pub fn streams_total(&self, service: &str) -> u64 {
+        self.streams
+            .get(service)
+            .map_or(0, |v| v.load(Ordering::Relaxed))
+    }
+
+    /// (to_service, from_service) byte totals for one service/client pair.
+    #[allow(dead_code)]
+    pub fn bytes_total(&self, service: &str, client_id: &str) -> (u64, u64) {
+        self.bytes
+            .get(&(service.to_string(), client_id.to_string()))
+            .map_or((0, 0), |c| {
+                (
+                    c.to_service.load(Ordering::Relaxed),
+                    c.from_service.load(Ordering::Relaxed),
+                )
+            })
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let mut bytes: Vec<_> = self
+            .bytes
+            .iter()
+            .map(|e| {
+                let (svc, cid) = e.key().clone();
+                let c = e.value();
+                (
+                    svc,
+                    cid,
+                    c.to_service.load(Ordering::Relaxed),
+                    c.from_service.load(Ordering::Relaxed),
+                )
+            })
+            .collect();
+        bytes.sort();
+        out.push_str("# HELP prism_tunnel_bytes_total Bytes carried by tunnel streams.\n");
+        out.push_str("# TYPE prism_tunnel_bytes_total counter\n");
+        for (svc, cid, to, from) in &bytes {
+            let (svc, cid) = (escape_label(svc), escape_label(cid));
+            for (direction, n) in [("to_service", to), ("from_service", from)] {
+                let _ = writeln!(
+                    out,
+                    "prism_tunnel_bytes_total{{service=\"{svc}\",client_id=\"{cid}\",direction=\"{direction}\"}} {n}"
+                );
+            }
+        }
+
+        let mut streams: Vec<_> = self
+            .streams
+            .iter()
+            .map(|e| (e.key().clone(), e.value().load(Ordering::Relaxed)))
+            .collect();
+        streams.sort();
+        out.push_str("# HELP prism_tunnel_streams_total Tunnel streams opened, by service.\n");
+        out.push_str("# TYPE prism_tunnel_streams_total counter\n");
+        for (svc, n) in &streams {
+            let _ = writeln!(
+                out,
+                "prism_tunnel_streams_total{{service=\"{}\"}} {n}",
+                escape_label(svc)
+            );
+        }
+
+        out.push_str("# HELP prism_tunnel_active_streams Tunnel streams currently open.\n");
+        out.push_str("# TYPE prism_tunnel_active_streams gauge\n");
+        let _ = writeln!(out, "prism_tunnel_active_streams {}", self.active_streams());
+
+        out.push_str("# HELP prism_tunnel_accept_queue_depth Accepted tunnel streams waiting to be handled.\n");
+        out.push_str("# TYPE prism_tunnel_accept_queue_depth gauge\n");
+        let _ = writeln!(
+            out,
+            "prism_tunnel_accept_queue_depth {}",
+            self.accept_queue_depth()
+        );
+
+        out.push_str("# HELP prism_tunnel_version_mismatch_total Tunnel clients rejected for a protocol version mismatch.\n");
+        out.push_str("# TYPE prism_tunnel_version_mismatch_total counter\n");
+        let _ = writeln!(
+            out,
+            "prism_tunnel_version_mismatch_total {}",
+            self.version_mismatches()
+        );
+        out
+    }
+}
+
+struct MeteredStream {
+    inner: BoxedStream,
+    counters: Arc<ByteCounters>,
+    metrics: Arc<TunnelMetrics>,
+}
+
+impl Drop for MeteredStream {
+    fn drop(&mut self) {
+        self.metrics.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for MeteredStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - before;
+        if n > 0 {
+            self.counters
+                .from_service
+                .fetch_add(n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+impl AsyncWrite for MeteredStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, data);
+        if let Poll::Ready(Ok(n)) = res {
+            self.counters
+                .to_service
+                .fetch_add(n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -7,6 +7,8 @@ pub mod autolisten;
 pub mod client;
 pub mod datagram;
 pub mod manager;
+pub mod metrics;
 pub mod protocol;
 pub mod server;
+pub mod throttle;
 pub mod transport;
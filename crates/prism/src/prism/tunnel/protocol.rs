@@ -7,6 +7,8 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 const MAGIC_REGISTER: &[u8; 4] = b"PRRG"; // Prism Reverse Register
 const MAGIC_PROXY_TCP: &[u8; 4] = b"PRPX"; // Prism Reverse Proxy (TCP stream)
 const MAGIC_PROXY_UDP: &[u8; 4] = b"PRPU"; // Prism Reverse Proxy (UDP datagram stream)
+const MAGIC_HEARTBEAT: &[u8; 4] = b"PRHB"; // Prism Reverse Heartbeat
+const MAGIC_DRAIN: &[u8; 4] = b"PRDR"; // Prism Reverse Drain
 const PROTOCOL_V1: u8 = 1;
 
 pub const MAX_REGISTER_JSON_BYTES: u32 = 1 << 20; // 1 MiB
@@ -16,10 +18,15 @@ pub const MAX_DATAGRAM_BYTES: u32 = 1 << 20; // 1 MiB
 pub enum ProtocolError {
     #[error("bad magic")]
     BadMagic,
-    #[error("unsupported version")]
-    BadVersion,
+    /// The peer speaks a different tunnel protocol version (the one carried here).
+    #[error("unsupported protocol version {0} (this build speaks {PROTOCOL_V1})")]
+    BadVersion(u8),
     #[error("payload too large: {0}")]
     PayloadTooLarge(u32),
+    /// A datagram larger than the read buffer; it was consumed, so the stream is still
+    /// aligned and the next datagram can be read.
+    #[error("datagram of {0} bytes does not fit the read buffer")]
+    ShortBuffer(usize),
     #[error("empty service")]
     EmptyService,
     #[error("io: {0}")]
@@ -34,6 +41,11 @@ pub struct RegisterRequest {
     pub token: String,
     #[serde(default)]
     pub services: Vec<RegisteredService>,
+    /// How often the client sends heartbeats (0 = never). The server drops a client that
+    /// misses several in a row; with 0 (also what older clients send by leaving this out)
+    /// the server only notices a client when its connection closes.
+    #[serde(default)]
+    pub heartbeat_interval_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +63,28 @@ pub struct RegisteredService {
     /// (tunnel:<service>). This supports $1, $2... substitutions from route wildcard captures.
     #[serde(default)]
     pub masquerade_host: String,
+    /// Cap on each direction of this service's traffic, enforced by the tunnel client
+    /// (0 = unlimited).
+    #[serde(default)]
+    pub rate_limit_bytes_per_sec: u64,
+    #[serde(default)]
+    pub rate_limit_scope: RateLimitScope,
+    /// `primary` (default) sends every dial to the service's primary client; `balance`
+    /// round-robins dials across all clients that provide it.
+    #[serde(default)]
+    pub dial_policy: String,
+}
+
+/// What a service's `rate_limit_bytes_per_sec` caps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitScope {
+    /// One cap shared across all of the service's connections.
+    Service,
+    /// Each connection separately. Empty or unknown values from other builds mean this too.
+    #[default]
+    #[serde(other)]
+    Connection,
 }
 
 impl RegisteredService {
@@ -66,6 +100,7 @@ impl RegisteredService {
         self.local_addr = self.local_addr.trim().to_string();
         self.remote_addr = self.remote_addr.trim().to_string();
         self.masquerade_host = self.masquerade_host.trim().to_ascii_lowercase();
+        self.dial_policy = self.dial_policy.trim().to_ascii_lowercase();
         if self.route_only {
             self.remote_addr.clear();
         }
@@ -98,7 +133,7 @@ pub async fn read_register_request<R: AsyncRead + Unpin>(
 
     let ver = r.read_u8().await?;
     if ver != PROTOCOL_V1 {
-        return Err(ProtocolError::BadVersion);
+        return Err(ProtocolError::BadVersion(ver));
     }
 
     let n = r.read_u32().await?;
@@ -120,6 +155,87 @@ pub async fn read_register_request<R: AsyncRead + Unpin>(
     Ok(req)
 }
 
+/// Sent back on the register stream when the client's version is rejected, so the client
+/// can tell a mismatch from an ordinary disconnect. Carries the server's version.
+pub async fn write_version_mismatch<W: AsyncWrite + Unpin>(w: &mut W) -> Result<(), ProtocolError> {
+    w.write_all(MAGIC_REGISTER).await?;
+    w.write_u8(PROTOCOL_V1).await?;
+    Ok(())
+}
+
+/// Read the server's reply on the register stream: the server's version if it rejected
+/// ours, or None once the stream ends without a reply.
+pub async fn read_register_reply<R: AsyncRead + Unpin>(
+    r: &mut R,
+) -> Result<Option<u8>, ProtocolError> {
+    let mut magic = [0u8; 4];
+    match r.read_exact(&mut magic).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    if &magic != MAGIC_REGISTER {
+        return Err(ProtocolError::BadMagic);
+    }
+    Ok(Some(r.read_u8().await?))
+}
+
+/// A heartbeat is a client-opened stream carrying just this header; the server echoes it
+/// back and closes the stream.
+pub async fn write_heartbeat<W: AsyncWrite + Unpin>(w: &mut W) -> Result<(), ProtocolError> {
+    w.write_all(MAGIC_HEARTBEAT).await?;
+    w.write_u8(PROTOCOL_V1).await?;
+    Ok(())
+}
+
+pub async fn read_heartbeat<R: AsyncRead + Unpin>(r: &mut R) -> Result<(), ProtocolError> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic).await?;
+    if &magic != MAGIC_HEARTBEAT {
+        return Err(ProtocolError::BadMagic);
+    }
+    let ver = r.read_u8().await?;
+    if ver != PROTOCOL_V1 {
+        return Err(ProtocolError::BadVersion(ver));
+    }
+    Ok(())
+}
+
+/// A drain notice is a client-opened stream carrying just this header: the client is
+/// shutting down and wants no new streams. The server echoes it back once it has stopped
+/// routing to the client.
+pub async fn write_drain<W: AsyncWrite + Unpin>(w: &mut W) -> Result<(), ProtocolError> {
+    w.write_all(MAGIC_DRAIN).await?;
+    w.write_u8(PROTOCOL_V1).await?;
+    Ok(())
+}
+
+/// Control streams a registered client may open to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlStream {
+    Heartbeat,
+    Drain,
+}
+
+pub async fn read_control_stream<R: AsyncRead + Unpin>(
+    r: &mut R,
+) -> Result<ControlStream, ProtocolError> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic).await?;
+    let kind = if &magic == MAGIC_HEARTBEAT {
+        ControlStream::Heartbeat
+    } else if &magic == MAGIC_DRAIN {
+        ControlStream::Drain
+    } else {
+        return Err(ProtocolError::BadMagic);
+    };
+    let ver = r.read_u8().await?;
+    if ver != PROTOCOL_V1 {
+        return Err(ProtocolError::BadVersion(ver));
+    }
+    Ok(kind)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProxyStreamKind {
     Tcp,
@@ -161,7 +277,7 @@ pub async fn read_proxy_stream_header<R: AsyncRead + Unpin>(
 
     let ver = r.read_u8().await?;
     if ver != PROTOCOL_V1 {
-        return Err(ProtocolError::BadVersion);
+        return Err(ProtocolError::BadVersion(ver));
     }
 
     let s = read_mc_string(r).await?;
@@ -245,6 +361,9 @@ mod tests {
                     route_only: false,
                     remote_addr: " 127.0.0.1:0 ".into(),
                     masquerade_host: "  $1.edge.internal  ".into(),
+                    rate_limit_bytes_per_sec: 0,
+                    rate_limit_scope: Default::default(),
+                    dial_policy: String::new(),
                 },
                 RegisteredService {
                     name: "   ".into(),
@@ -253,6 +372,9 @@ mod tests {
                     route_only: false,
                     remote_addr: "".into(),
                     masquerade_host: "".into(),
+                    rate_limit_bytes_per_sec: 0,
+                    rate_limit_scope: Default::default(),
+                    dial_policy: String::new(),
                 },
                 RegisteredService {
                     name: "svc2".into(),
@@ -261,8 +383,12 @@ mod tests {
                     route_only: true,
                     remote_addr: "127.0.0.1:9999".into(),
                     masquerade_host: "svc2.internal".into(),
+                    rate_limit_bytes_per_sec: 1024,
+                    rate_limit_scope: RateLimitScope::Service,
+                    dial_policy: String::new(),
                 },
             ],
+            heartbeat_interval_ms: 0,
         };
 
         let w = tokio::spawn(async move { write_register_request(&mut a, &req).await });
@@ -285,6 +411,21 @@ mod tests {
         // route_only clears remote_addr
         assert_eq!(got.services[1].remote_addr, "");
         assert_eq!(got.services[1].masquerade_host, "svc2.internal");
+        assert_eq!(got.services[1].rate_limit_bytes_per_sec, 1024);
+        assert_eq!(got.services[1].rate_limit_scope, RateLimitScope::Service);
+    }
+
+    #[test]
+    fn rate_limit_scope_reads_what_other_builds_send() {
+        let scope = |json: &str| serde_json::from_str::<RateLimitScope>(json).expect(json);
+        assert_eq!(scope(r#""service""#), RateLimitScope::Service);
+        assert_eq!(scope(r#""connection""#), RateLimitScope::Connection);
+        assert_eq!(scope(r#""""#), RateLimitScope::Connection);
+        assert_eq!(scope(r#""burst""#), RateLimitScope::Connection);
+        assert_eq!(
+            serde_json::to_string(&RateLimitScope::Service).unwrap(),
+            r#""service""#
+        );
     }
 
     #[tokio::test]
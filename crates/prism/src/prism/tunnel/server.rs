@@ -1,26 +1,41 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use tokio::io::AsyncWriteExt;
 
-use crate::prism::tunnel::{
-    manager::Manager,
-    protocol,
-    transport::{TransportListenOptions, transport_by_name},
+use crate::prism::{
+    telemetry,
+    tunnel::{
+        manager::Manager,
+        protocol,
+        transport::{TransportListenOptions, transport_by_name},
+    },
 };
 
+/// Heartbeat intervals a client may miss in a row before the server drops it.
+const HEARTBEAT_MISSES: u32 = 3;
+/// Longest interval a client may announce; larger values are clamped.
+const MAX_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(600);
+
 #[derive(Debug, Clone)]
 pub struct QuicServerOptions {
     pub cert_file: String,
     pub key_file: String,
+    pub client_ca_file: String,
+    pub tls: crate::prism::tls::TlsPolicy,
 }
 
 #[derive(Debug, Clone)]
 pub struct ServerOptions {
     pub listen_addr: String,
     pub transport: String,
-    pub auth_token: String,
+    /// Tokens clients may register with, each mapped to a label that prefixes the client's
+    /// id in logs and the manager (empty = "c"). An empty map disables auth.
+    pub auth_tokens: HashMap<String, String>,
     pub quic: QuicServerOptions,
+    pub accept_queue_depth: usize,
     pub manager: Arc<Manager>,
+    /// Marked ready once the endpoint is listening.
+    pub ready: Option<telemetry::ReadyCheck>,
 }
 
 pub struct Server {
@@ -50,8 +65,12 @@ impl Server {
                     quic: crate::prism::tunnel::transport::QuicListenOptions {
                         cert_file: self.opts.quic.cert_file.clone(),
                         key_file: self.opts.quic.key_file.clone(),
+                        client_ca_file: self.opts.quic.client_ca_file.clone(),
                         next_protos: vec![],
+                        tls: self.opts.quic.tls.clone(),
                     },
+                    accept_queue_depth: self.opts.accept_queue_depth,
+                    queued_streams: self.opts.manager.metrics().accept_queue_gauge(),
                 },
             )
             .await?;
@@ -61,7 +80,11 @@ impl Server {
             transport = %tr.name(),
             "tunnel: listening"
         );
+        if let Some(ready) = &self.opts.ready {
+            ready.ready();
+        }
 
+        let auth_tokens = Arc::new(self.opts.auth_tokens.clone());
         let mut shutdown = ctx.clone();
         loop {
             tokio::select! {
@@ -73,9 +96,9 @@ impl Server {
                 sess = ln.accept() => {
                     let sess = sess?;
                     let mgr = self.opts.manager.clone();
-                    let token = self.opts.auth_token.clone();
+                    let tokens = auth_tokens.clone();
                     tokio::spawn(async move {
-                        if let Err(err) = handle_session(mgr, sess, token).await {
+                        if let Err(err) = handle_session(mgr, sess, tokens).await {
                             tracing::warn!(err=%err, "tunnel: session ended with error");
                         }
                     });
@@ -91,9 +114,8 @@ impl Server {
 async fn handle_session(
     mgr: Arc<Manager>,
     sess: Arc<dyn crate::prism::tunnel::transport::TransportSession>,
-    auth_token: String,
+    auth_tokens: Arc<HashMap<String, String>>,
 ) -> anyhow::Result<()> {
-    let cid = mgr.next_client_id("c");
     let remote = sess
         .remote_addr()
         .map(|a| a.to_string())
@@ -101,25 +123,446 @@ async fn handle_session(
 
     // First stream must be register.
     let mut reg = sess.accept_stream().await?;
-    let req = protocol::read_register_request(&mut reg).await?;
+    let req = match protocol::read_register_request(&mut reg).await {
+        Ok(req) => req,
+        Err(protocol::ProtocolError::BadVersion(ver)) => {
+            tracing::warn!(client=%remote, version=ver, "tunnel: client protocol version mismatch");
+            mgr.metrics().record_version_mismatch();
+            let _ = protocol::write_version_mismatch(&mut reg).await;
+            let _ = reg.shutdown().await;
+            // Give the client a moment to read the reply and hang up itself.
+            let _ =
+                tokio::time::timeout(std::time::Duration::from_secs(1), sess.accept_stream()).await;
+            sess.close().await;
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
 
-    if !auth_token.trim().is_empty() && req.token != auth_token {
-        tracing::warn!(client=%remote, "tunnel: bad token");
-        sess.close().await;
-        return Ok(());
-    }
+    let label = if auth_tokens.is_empty() {
+        ""
+    } else {
+        match auth_tokens.get(&req.token) {
+            Some(label) => label.as_str(),
+            None => {
+                tracing::warn!(client=%remote, "tunnel: bad token");
+                sess.close().await;
+                return Ok(());
+            }
+        }
+    };
+    let cid = mgr.next_client_id(label);
+
+    let liveness = heartbeat_interval(req.heartbeat_interval_ms).map(|i| i * HEARTBEAT_MISSES);
 
     mgr.register_client(cid.clone(), sess.clone(), req.services)
         .await?;
     tracing::info!(cid=%cid, client=%remote, "tunnel: client connected");
 
-    // Hold an accept loop to detect disconnects and close unexpected streams.
-    while let Ok(mut st) = sess.accept_stream().await {
-        // Unexpected stream opened by client; close quietly.
-        let _ = tokio::time::timeout(std::time::Duration::from_secs(1), st.shutdown()).await;
+    // Hold an accept loop to detect disconnects, answer heartbeats and close unexpected
+    // streams. A client that goes quiet (e.g. a half-open connection) is dropped once its
+    // heartbeats stop arriving; clients that send none are held until they disconnect.
+    let mut deadline = liveness.map(|l| tokio::time::Instant::now() + l);
+    loop {
+        let st = tokio::select! {
+            st = sess.accept_stream() => st,
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                if deadline.is_some() => {
+                tracing::warn!(cid=%cid, client=%remote, "tunnel: client stopped sending heartbeats; dropping");
+                let _ = tokio::time::timeout(Duration::from_secs(1), sess.close()).await;
+                break;
+            }
+        };
+        let Ok(mut st) = st else {
+            break;
+        };
+        let control = tokio::time::timeout(Duration::from_secs(1), async {
+            let kind = protocol::read_control_stream(&mut st).await?;
+            match kind {
+                protocol::ControlStream::Heartbeat => protocol::write_heartbeat(&mut st).await?,
+                protocol::ControlStream::Drain => {
+                    mgr.drain_client(&cid).await;
+                    tracing::info!(cid=%cid, client=%remote, "tunnel: client draining");
+                    protocol::write_drain(&mut st).await?;
+                }
+            }
+            Ok::<_, protocol::ProtocolError>(kind)
+        })
+        .await;
+        // Any answered control stream proves the client is alive.
+        if matches!(control, Ok(Ok(_))) {
+            deadline = liveness.map(|l| tokio::time::Instant::now() + l);
+        }
+        // Control streams are done and anything else is unexpected; close quietly.
+        let _ = tokio::time::timeout(Duration::from_secs(1), st.shutdown()).await;
     }
 
     mgr.unregister_client(&cid).await;
     tracing::info!(cid=%cid, client=%remote, "tunnel: client disconnected");
     Ok(())
 }
+
+/// The heartbeat interval a client announced (in ms), bounded to something the server can
+/// schedule: huge values are clamped, and 0 (heartbeats disabled) means no interval.
+fn heartbeat_interval(announced_ms: u64) -> Option<Duration> {
+    match announced_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms).min(MAX_HEARTBEAT_INTERVAL)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::prism::tunnel::transport::TransportDialOptions;
+
+    #[tokio::test]
+    async fn client_with_wrong_version_gets_a_mismatch_reply() {
+        let tr = transport_by_name("tcp").expect("tcp transport");
+        let ln = tr
+            .listen("127.0.0.1:0", TransportListenOptions::default())
+            .await
+            .expect("listen");
+        let addr = ln.local_addr().expect("addr").to_string();
+
+        let mgr = Arc::new(Manager::new());
+        let server = tokio::spawn({
+            let mgr = mgr.clone();
+            async move {
+                let sess = ln.accept().await.expect("accept");
+                handle_session(mgr, sess, Arc::default()).await
+            }
+        });
+
+        let sess = tr
+            .dial(&addr, TransportDialOptions::default())
+            .await
+            .expect("dial");
+        let mut reg = sess.open_stream().await.expect("open");
+        reg.write_all(b"PRRG\x02").await.expect("write");
+        reg.write_u32(2).await.expect("write");
+        reg.write_all(b"{}").await.expect("write");
+        reg.flush().await.expect("flush");
+
+        let reply = tokio::time::timeout(
+            Duration::from_secs(2),
+            protocol::read_register_reply(&mut reg),
+        )
+        .await
+        .expect("reply timeout")
+        .expect("reply");
+        assert_eq!(reply, Some(1));
+        sess.close().await;
+
+        // Handled as a rejection, not a session error, and nothing was registered.
+        server.await.expect("join").expect("session");
+        assert!(mgr.snapshot_services().await.is_empty());
+        assert_eq!(mgr.metrics().version_mismatches(), 1);
+        assert!(
+            mgr.metrics()
+                .render_prometheus()
+                .contains("prism_tunnel_version_mismatch_total 1\n")
+        );
+    }
+
+    #[test]
+    fn announced_heartbeat_intervals_are_bounded() {
+        assert_eq!(heartbeat_interval(0), None);
+        assert_eq!(heartbeat_interval(250), Some(Duration::from_millis(250)));
+        // A hostile value must not overflow the liveness deadline.
+        let clamped = heartbeat_interval(u64::MAX).expect("interval");
+        assert_eq!(clamped, MAX_HEARTBEAT_INTERVAL);
+        let _ = tokio::time::Instant::now() + clamped * HEARTBEAT_MISSES;
+    }
+
+    #[tokio::test]
+    async fn client_that_stops_sending_heartbeats_is_unregistered() {
+        let tr = transport_by_name("tcp").expect("tcp transport");
+        let ln = tr
+            .listen("127.0.0.1:0", TransportListenOptions::default())
+            .await
+            .expect("listen");
+        let addr = ln.local_addr().expect("addr").to_string();
+
+        let mgr = Arc::new(Manager::new());
+        let server = tokio::spawn({
+            let mgr = mgr.clone();
+            async move {
+                let sess = ln.accept().await.expect("accept");
+                handle_session(mgr, sess, Arc::default()).await
+            }
+        });
+
+        // A client that registers, answers for a moment, then goes silent without closing.
+        let sess = tr
+            .dial(&addr, TransportDialOptions::default())
+            .await
+            .expect("dial");
+        let mut reg = sess.open_stream().await.expect("open");
+        let req = protocol::RegisterRequest {
+            token: String::new(),
+            services: vec![protocol::RegisteredService {
+                name: "home".into(),
+                proto: "tcp".into(),
+                local_addr: "127.0.0.1:25565".into(),
+                route_only: true,
+                remote_addr: String::new(),
+                masquerade_host: String::new(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: String::new(),
+            }],
+            heartbeat_interval_ms: 100,
+        };
+        protocol::write_register_request(&mut reg, &req)
+            .await
+            .expect("register");
+        reg.shutdown().await.expect("shutdown");
+
+        let mut hb = sess.open_stream().await.expect("open heartbeat");
+        protocol::write_heartbeat(&mut hb).await.expect("heartbeat");
+        tokio::time::timeout(Duration::from_secs(2), protocol::read_heartbeat(&mut hb))
+            .await
+            .expect("heartbeat reply timeout")
+            .expect("heartbeat reply");
+        assert!(mgr.has_service("home").await);
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("silent client was never dropped")
+            .expect("join")
+            .expect("session");
+        assert!(!mgr.has_service("home").await);
+        drop(sess);
+    }
+
+    /// Register `service` with `token` over a fresh session to `addr`.
+    async fn register_with_token(
+        addr: &str,
+        token: &str,
+        service: &str,
+    ) -> Arc<dyn crate::prism::tunnel::transport::TransportSession> {
+        let tr = transport_by_name("tcp").expect("tcp transport");
+        let sess = tr
+            .dial(addr, TransportDialOptions::default())
+            .await
+            .expect("dial");
+        let mut reg = sess.open_stream().await.expect("open");
+        let req = protocol::RegisterRequest {
+            token: token.into(),
+            services: vec![protocol::RegisteredService {
+                name: service.into(),
+                proto: "tcp".into(),
+                local_addr: "127.0.0.1:25565".into(),
+                route_only: true,
+                remote_addr: String::new(),
+                masquerade_host: String::new(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: String::new(),
+            }],
+            heartbeat_interval_ms: 0,
+        };
+        protocol::write_register_request(&mut reg, &req)
+            .await
+            .expect("register");
+        reg.shutdown().await.expect("shutdown");
+        sess
+    }
+
+    /// Serve every session accepted on a fresh tcp listener; returns its address.
+    async fn serve(mgr: Arc<Manager>, tokens: HashMap<String, String>) -> String {
+        let tr = transport_by_name("tcp").expect("tcp transport");
+        let ln = tr
+            .listen("127.0.0.1:0", TransportListenOptions::default())
+            .await
+            .expect("listen");
+        let addr = ln.local_addr().expect("addr").to_string();
+        let tokens = Arc::new(tokens);
+        tokio::spawn(async move {
+            while let Ok(sess) = ln.accept().await {
+                tokio::spawn(handle_session(mgr.clone(), sess, tokens.clone()));
+            }
+        });
+        addr
+    }
+
+    async fn owner_of(mgr: &Manager, service: &str) -> Option<String> {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while tokio::time::Instant::now() < deadline {
+            if let Some(s) = mgr
+                .snapshot_services()
+                .await
+                .into_iter()
+                .find(|s| s.service.name == service)
+            {
+                return Some(s.client_id);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn any_listed_token_registers_and_others_are_rejected() {
+        let mgr = Arc::new(Manager::new());
+        let tokens = HashMap::from([
+            ("current".to_string(), "home".to_string()),
+            ("next".to_string(), String::new()),
+        ]);
+        let addr = serve(mgr.clone(), tokens).await;
+
+        let _a = register_with_token(&addr, "current", "a").await;
+        let _b = register_with_token(&addr, "next", "b").await;
+        let owner_a = owner_of(&mgr, "a").await.expect("a registered");
+        let owner_b = owner_of(&mgr, "b").await.expect("b registered");
+        assert!(owner_a.starts_with("home-"), "{owner_a}");
+        assert!(owner_b.starts_with("c-"), "{owner_b}");
+
+        // A token dropped from the list (or never on it) is turned away.
+        let revoked = register_with_token(&addr, "previous", "x").await;
+        let closed = tokio::time::timeout(Duration::from_secs(2), revoked.accept_stream())
+            .await
+            .expect("rejected session was never closed");
+        assert!(closed.is_err());
+        assert!(!mgr.has_service("x").await);
+    }
+
+    #[tokio::test]
+    async fn empty_token_list_disables_auth() {
+        let mgr = Arc::new(Manager::new());
+        let addr = serve(mgr.clone(), HashMap::new()).await;
+
+        let _s = register_with_token(&addr, "anything", "svc").await;
+        let owner = owner_of(&mgr, "svc")
+            .await
+            .expect("registered without auth");
+        assert!(owner.starts_with("c-"), "{owner}");
+    }
+
+    #[tokio::test]
+    async fn draining_client_finishes_open_streams_while_new_dials_fail_over() {
+        use crate::prism::tunnel::client::{Client, ClientOptions, QuicClientOptions};
+        use tokio::io::AsyncReadExt;
+
+        // Both clients front the same echo backend.
+        let backend = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend_addr = backend.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut c, _)) = backend.accept().await {
+                tokio::spawn(async move {
+                    let (mut r, mut w) = c.split();
+                    let _ = tokio::io::copy(&mut r, &mut w).await;
+                });
+            }
+        });
+
+        let mgr = Arc::new(Manager::new());
+        let addr = serve(mgr.clone(), HashMap::new()).await;
+        let start_client = |shutdown| {
+            let client = Client::new(ClientOptions {
+                server_addr: addr.clone(),
+                transport: "tcp".into(),
+                auth_token: String::new(),
+                services: vec![protocol::RegisteredService {
+                    name: "svc".into(),
+                    proto: "tcp".into(),
+                    local_addr: backend_addr.clone(),
+                    route_only: true,
+                    remote_addr: String::new(),
+                    masquerade_host: String::new(),
+                    rate_limit_bytes_per_sec: 0,
+                    rate_limit_scope: Default::default(),
+                    dial_policy: String::new(),
+                }],
+                dial_timeout: Duration::from_secs(2),
+                heartbeat_interval: Duration::ZERO,
+                drain_timeout: Duration::from_secs(10),
+                quic: QuicClientOptions {
+                    server_name: String::new(),
+                    insecure_skip_verify: false,
+                    client_cert_file: String::new(),
+                    client_key_file: String::new(),
+                    tls: Default::default(),
+                },
+                accept_queue_depth: 0,
+                ready: None,
+            })
+            .expect("client");
+            tokio::spawn(async move { client.run(shutdown).await })
+        };
+
+        let (stop_a, rx_a) = tokio::sync::watch::channel(false);
+        let client_a = start_client(rx_a);
+        let cid_a = owner_of(&mgr, "svc")
+            .await
+            .expect("first client registered");
+        let (_stop_b, rx_b) = tokio::sync::watch::channel(false);
+        let _client_b = start_client(rx_b);
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while mgr.snapshot_services().await.len() < 2 {
+            assert!(tokio::time::Instant::now() < deadline, "second client");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let cid_b = mgr
+            .snapshot_services()
+            .await
+            .into_iter()
+            .map(|s| s.client_id)
+            .find(|cid| *cid != cid_a)
+            .unwrap();
+
+        let echo = |mut st: crate::prism::tunnel::transport::BoxedStream, msg: &'static [u8]| async move {
+            st.write_all(msg).await.expect("write");
+            let mut buf = vec![0u8; msg.len()];
+            tokio::time::timeout(Duration::from_secs(2), st.read_exact(&mut buf))
+                .await
+                .expect("echo timeout")
+                .expect("echo");
+            assert_eq!(buf, msg);
+            st
+        };
+        let open = echo(mgr.dial_service_tcp("svc").await.expect("dial"), b"one").await;
+        assert_eq!(mgr.metrics().bytes_total("svc", &cid_a).0, 3);
+
+        stop_a.send(true).unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        loop {
+            let snap = mgr.snapshot_services().await;
+            let drained = snap.iter().any(|s| s.client_id == cid_a && s.draining);
+            let failed_over = snap.iter().any(|s| s.client_id == cid_b && s.primary);
+            if drained && failed_over {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "drain never reached the server"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // New dials go to the other client, pinned ones to the draining client fail...
+        let _new = echo(mgr.dial_service_tcp("svc").await.expect("dial"), b"two").await;
+        assert_eq!(mgr.metrics().bytes_total("svc", &cid_b).0, 3);
+        assert!(
+            mgr.dial_service_tcp_from_client(&cid_a, "svc")
+                .await
+                .is_err()
+        );
+
+        // ...while the stream that was already open keeps working until it is done.
+        let mut open = echo(open, b"three").await;
+        assert!(!client_a.is_finished());
+        open.shutdown().await.expect("shutdown");
+        let mut rest = Vec::new();
+        let _ = open.read_to_end(&mut rest).await;
+        drop(open);
+        tokio::time::timeout(Duration::from_secs(5), client_a)
+            .await
+            .expect("client waited out the whole drain timeout")
+            .expect("join")
+            .expect("client run");
+    }
+}
@@ -0,0 +1,158 @@
+//! Byte-rate limiting for tunnel service traffic (`rate_limit_bytes_per_sec`).
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::Instant,
+};
+
+/// Smallest burst a bucket allows, so tiny limits still move whole reads.
+const MIN_BURST_BYTES: f64 = 16.0 * 1024.0;
+
+/// Token bucket on bytes.
+///
+/// A take never fails: one that overdraws the bucket sleeps until the debt is paid off, so
+/// callers move at most `rate` bytes per second on average (plus a quarter second of burst).
+pub struct ByteBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl ByteBucket {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let burst = (rate / 4.0).max(MIN_BURST_BYTES);
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    pub async fn take(&self, n: usize) {
+        let wait = {
+            let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let (tokens, last) = &mut *st;
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate)
+                .min(self.burst)
+                - n as f64;
+            *last = now;
+            if *tokens < 0.0 {
+                Duration::from_secs_f64(-*tokens / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Limits for both directions of a service's traffic. Clones share the same buckets, which
+/// is how `rate_limit_scope = "service"` caps all of a service's connections together.
+#[derive(Clone)]
+pub struct Throttle {
+    /// Bytes from the tunnel towards the local service.
+    pub to_local: Arc<ByteBucket>,
+    /// Bytes from the local service back through the tunnel.
+    pub from_local: Arc<ByteBucket>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            to_local: Arc::new(ByteBucket::new(bytes_per_sec)),
+            from_local: Arc::new(ByteBucket::new(bytes_per_sec)),
+        }
+    }
+}
+
+/// Like `tokio::io::copy_bidirectional`, with each direction paced by `throttle`.
+/// Returns (tunnel->local, local->tunnel) byte counts.
+pub async fn copy_bidirectional<T, L>(
+    tunnel: T,
+    local: L,
+    throttle: &Throttle,
+) -> std::io::Result<(u64, u64)>
+where
+    T: AsyncRead + AsyncWrite,
+    L: AsyncRead + AsyncWrite,
+{
+    let (mut tr, mut tw) = tokio::io::split(tunnel);
+    let (mut lr, mut lw) = tokio::io::split(local);
+    tokio::try_join!(
+        copy_half(&mut tr, &mut lw, &throttle.to_local),
+        copy_half(&mut lr, &mut tw, &throttle.from_local),
+    )
+}
+
+async fn copy_half<R, W>(r: &mut R, w: &mut W, bucket: &ByteBucket) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut total = 0u64;
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            w.shutdown().await?;
+            return Ok(total);
+        }
+        bucket.take(n).await;
+        w.write_all(&buf[..n]).await?;
+        total += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn throughput_stays_near_the_cap() {
+        const RATE: u64 = 512 * 1024;
+        const RUN: Duration = Duration::from_secs(2);
+
+        let (mut sender, tunnel) = tokio::io::duplex(64 * 1024);
+        let (local, mut receiver) = tokio::io::duplex(64 * 1024);
+        let throttle = Throttle::new(RATE);
+        tokio::spawn(async move {
+            let _ = copy_bidirectional(tunnel, local, &throttle).await;
+        });
+        // Push as fast as the throttle lets us.
+        tokio::spawn(async move {
+            let chunk = vec![0u8; 32 * 1024];
+            while sender.write_all(&chunk).await.is_ok() {}
+        });
+
+        let start = Instant::now();
+        let mut got = 0u64;
+        let mut buf = vec![0u8; 64 * 1024];
+        while start.elapsed() < RUN {
+            match tokio::time::timeout(Duration::from_millis(100), receiver.read(&mut buf)).await {
+                Ok(Ok(n)) => got += n as u64,
+                Ok(Err(err)) => panic!("read: {err}"),
+                Err(_) => {}
+            }
+        }
+
+        let allowed = RATE as f64 * start.elapsed().as_secs_f64() + RATE as f64 / 4.0;
+        let got = got as f64;
+        assert!(
+            got <= allowed * 1.1,
+            "{got} bytes exceeds the cap ({allowed})"
+        );
+        assert!(
+            got >= allowed * 0.7,
+            "{got} bytes is far below the cap ({allowed})"
+        );
+    }
+}
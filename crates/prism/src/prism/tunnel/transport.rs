@@ -1,6 +1,13 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
 use async_trait::async_trait;
+use tokio::sync::mpsc;
 
 /// A bidirectional async byte stream.
 ///
@@ -15,24 +22,129 @@ pub type BoxedStream = Box<dyn AsyncStream + Unpin + Send>;
 pub struct QuicListenOptions {
     pub cert_file: String,
     pub key_file: String,
+    /// CA bundle (PEM) that client certificates must chain to; empty disables mTLS.
+    pub client_ca_file: String,
     pub next_protos: Vec<Vec<u8>>,
+    pub tls: crate::prism::tls::TlsPolicy,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct QuicDialOptions {
     pub server_name: String,
     pub insecure_skip_verify: bool,
+    /// Certificate chain and key (PEM) presented to servers that require mTLS.
+    pub client_cert_file: String,
+    pub client_key_file: String,
     pub next_protos: Vec<Vec<u8>>,
+    pub tls: crate::prism::tls::TlsPolicy,
 }
 
+/// Streams buffered between a session's transport driver and `accept_stream`.
+pub const DEFAULT_ACCEPT_QUEUE_DEPTH: usize = 64;
+
 #[derive(Debug, Clone, Default)]
 pub struct TransportListenOptions {
     pub quic: QuicListenOptions,
+    /// Accepted-stream queue depth per session (0 = `DEFAULT_ACCEPT_QUEUE_DEPTH`).
+    pub accept_queue_depth: usize,
+    /// Streams currently waiting in the accept queues of this listener's sessions.
+    pub queued_streams: Arc<AtomicUsize>,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct TransportDialOptions {
     pub quic: QuicDialOptions,
+    /// Accepted-stream queue depth for the dialed session (0 = `DEFAULT_ACCEPT_QUEUE_DEPTH`).
+    pub accept_queue_depth: usize,
+}
+
+/// Capacity of a session's accepted-stream queue and the gauge its backlog is counted in.
+///
+/// The driver task awaits `send` on a full queue instead of dropping the stream, which
+/// stops it from pulling new streams off the connection: yamux stops reading the socket
+/// and QUIC withholds stream credit, so a fast peer is slowed down rather than losing
+/// streams.
+#[derive(Debug, Clone)]
+pub(crate) struct AcceptQueue {
+    depth: usize,
+    queued: Arc<AtomicUsize>,
+}
+
+impl AcceptQueue {
+    /// `configured = 0` means `DEFAULT_ACCEPT_QUEUE_DEPTH`.
+    pub(crate) fn new(configured: usize, queued: Arc<AtomicUsize>) -> Self {
+        let depth = if configured == 0 {
+            DEFAULT_ACCEPT_QUEUE_DEPTH
+        } else {
+            configured
+        };
+        Self { depth, queued }
+    }
+
+    pub(crate) fn listen(opts: &TransportListenOptions) -> Self {
+        Self::new(opts.accept_queue_depth, opts.queued_streams.clone())
+    }
+
+    pub(crate) fn dial(opts: &TransportDialOptions) -> Self {
+        Self::new(opts.accept_queue_depth, Arc::default())
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub(crate) fn channel<T>(&self) -> (AcceptQueueSender<T>, AcceptQueueReceiver<T>) {
+        let (tx, rx) = mpsc::channel(self.depth);
+        (
+            AcceptQueueSender {
+                tx,
+                queued: self.queued.clone(),
+            },
+            AcceptQueueReceiver {
+                rx,
+                queued: self.queued.clone(),
+            },
+        )
+    }
+}
+
+pub(crate) struct AcceptQueueSender<T> {
+    tx: mpsc::Sender<T>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl<T> AcceptQueueSender<T> {
+    /// Waits for room in the queue; fails once the receiving session is gone.
+    pub(crate) async fn send(&self, item: T) -> Result<(), ()> {
+        let permit = self.tx.reserve().await.map_err(|_| ())?;
+        // Counted before it becomes visible so `recv` can never take the gauge below zero.
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        permit.send(item);
+        Ok(())
+    }
+}
+
+pub(crate) struct AcceptQueueReceiver<T> {
+    rx: mpsc::Receiver<T>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl<T> AcceptQueueReceiver<T> {
+    pub(crate) async fn recv(&mut self) -> Option<T> {
+        let item = self.rx.recv().await?;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        Some(item)
+    }
+}
+
+impl<T> Drop for AcceptQueueReceiver<T> {
+    fn drop(&mut self) {
+        // Streams still queued when the session goes away leave the gauge with it.
+        self.rx.close();
+        while self.rx.try_recv().is_ok() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
 }
 
 #[async_trait]
@@ -74,8 +186,8 @@ pub fn parse_transport(name: &str) -> anyhow::Result<String> {
         n = "tcp".into();
     }
     match n.as_str() {
-        "tcp" | "udp" | "quic" => Ok(n),
-        _ => anyhow::bail!("tunnel: unknown transport {name:?} (expected tcp|udp|quic)"),
+        "tcp" | "udp" | "quic" | "ws" | "wss" => Ok(n),
+        _ => anyhow::bail!("tunnel: unknown transport {name:?} (expected tcp|udp|quic|ws|wss)"),
     }
 }
 
@@ -89,6 +201,7 @@ pub fn default_alpn(next: &[Vec<u8>]) -> Vec<Vec<u8>> {
 pub mod quic;
 pub mod tcp;
 pub mod udp;
+pub mod ws;
 
 pub fn transport_by_name(name: &str) -> anyhow::Result<Arc<dyn Transport>> {
     let n = parse_transport(name)?;
@@ -96,6 +209,8 @@ pub fn transport_by_name(name: &str) -> anyhow::Result<Arc<dyn Transport>> {
         "tcp" => Ok(Arc::new(tcp::TcpTransport::new())),
         "quic" => Ok(Arc::new(quic::QuicTransport::new())),
         "udp" => Ok(Arc::new(udp::UdpTransport::new())),
+        "ws" => Ok(Arc::new(ws::WsTransport::new(false))),
+        "wss" => Ok(Arc::new(ws::WsTransport::new(true))),
         _ => unreachable!(),
     }
 }
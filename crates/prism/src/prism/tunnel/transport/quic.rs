@@ -3,16 +3,22 @@ use std::{net::SocketAddr, sync::Arc, time::Duration};
 use async_trait::async_trait;
 use pin_project_lite::pin_project;
 use quinn::{ClientConfig, Connection, Endpoint, ServerConfig, TransportConfig};
-use tokio::sync::mpsc;
 
 use crate::prism::net;
 use crate::prism::tunnel::transport::{
-    BoxedStream, QuicDialOptions, QuicListenOptions, Transport, TransportDialOptions,
-    TransportListenOptions, TransportListener, TransportSession, default_alpn,
+    AcceptQueue, AcceptQueueReceiver, BoxedStream, QuicDialOptions, QuicListenOptions, Transport,
+    TransportDialOptions, TransportListenOptions, TransportListener, TransportSession,
+    default_alpn,
 };
 
 pub struct QuicTransport;
 
+impl Default for QuicTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl QuicTransport {
     pub fn new() -> Self {
         Self
@@ -32,10 +38,13 @@ impl Transport for QuicTransport {
     ) -> anyhow::Result<Box<dyn TransportListener>> {
         let bind_addr = net::normalize_bind_addr(addr);
         let addr: SocketAddr = bind_addr.parse()?;
+        let accept_queue = AcceptQueue::listen(&opts);
         let QuicListenOptions {
             cert_file,
             key_file,
+            client_ca_file,
             next_protos,
+            tls,
         } = opts.quic;
 
         let next_protos = default_alpn(&next_protos);
@@ -45,14 +54,19 @@ impl Transport for QuicTransport {
         transport_cfg.max_idle_timeout(Some(Duration::from_secs(60).try_into()?));
         transport_cfg.keep_alive_interval(Some(Duration::from_secs(20)));
 
-        let server_crypto = quic_tls::server_crypto_config(cert_chain, key, next_protos)?;
+        let client_roots = quic_tls::load_client_roots(client_ca_file)?;
+        let server_crypto =
+            quic_tls::server_crypto_config(&tls, cert_chain, key, client_roots, next_protos)?;
         let mut server_cfg = ServerConfig::with_crypto(Arc::new(
             quinn::crypto::rustls::QuicServerConfig::try_from(server_crypto)?,
         ));
         server_cfg.transport_config(Arc::new(transport_cfg));
 
         let endpoint = Endpoint::server(server_cfg, addr)?;
-        Ok(Box::new(QuicTransportListener { endpoint }))
+        Ok(Box::new(QuicTransportListener {
+            endpoint,
+            accept_queue,
+        }))
     }
 
     async fn dial(
@@ -60,18 +74,24 @@ impl Transport for QuicTransport {
         addr: &str,
         opts: TransportDialOptions,
     ) -> anyhow::Result<Arc<dyn TransportSession>> {
+        let accept_queue = AcceptQueue::dial(&opts);
         let QuicDialOptions {
             server_name,
             insecure_skip_verify,
+            client_cert_file,
+            client_key_file,
             next_protos,
+            tls,
         } = opts.quic;
         let next_protos = default_alpn(&next_protos);
+        let client_auth = quic_tls::load_client_auth(client_cert_file, client_key_file)?;
 
         let mut transport_cfg = TransportConfig::default();
         transport_cfg.max_idle_timeout(Some(Duration::from_secs(60).try_into()?));
         transport_cfg.keep_alive_interval(Some(Duration::from_secs(20)));
 
-        let client_crypto = quic_tls::client_crypto_config(insecure_skip_verify, next_protos)?;
+        let client_crypto =
+            quic_tls::client_crypto_config(&tls, insecure_skip_verify, client_auth, next_protos)?;
         let mut client_cfg = ClientConfig::new(Arc::new(
             quinn::crypto::rustls::QuicClientConfig::try_from(client_crypto)?,
         ));
@@ -90,7 +110,7 @@ impl Transport for QuicTransport {
         let remote = resolve_socket_addr(addr).await?;
         let connecting = endpoint.connect(remote, &name)?;
         let conn = connecting.await?;
-        Ok(Arc::new(QuicSession::new(conn)))
+        Ok(Arc::new(QuicSession::new(conn, accept_queue)))
     }
 }
 
@@ -105,17 +125,24 @@ async fn resolve_socket_addr(addr: &str) -> anyhow::Result<SocketAddr> {
 
 pub struct QuicTransportListener {
     endpoint: Endpoint,
+    accept_queue: AcceptQueue,
 }
 
 #[async_trait]
 impl TransportListener for QuicTransportListener {
     async fn accept(&self) -> anyhow::Result<Arc<dyn TransportSession>> {
-        let incoming = self.endpoint.accept();
-        let connecting = incoming
-            .await
-            .ok_or_else(|| anyhow::anyhow!("tunnel: quic endpoint closed"))?;
-        let conn = connecting.await?;
-        Ok(Arc::new(QuicSession::new(conn)))
+        loop {
+            let incoming = self.endpoint.accept();
+            let connecting = incoming
+                .await
+                .ok_or_else(|| anyhow::anyhow!("tunnel: quic endpoint closed"))?;
+            // A peer that fails the handshake (e.g. no valid client certificate under mTLS)
+            // must not take the listener down with it.
+            match connecting.await {
+                Ok(conn) => return Ok(Arc::new(QuicSession::new(conn, self.accept_queue.clone()))),
+                Err(err) => tracing::debug!(err=%err, "tunnel: quic handshake failed"),
+            }
+        }
     }
 
     fn local_addr(&self) -> Option<SocketAddr> {
@@ -130,13 +157,13 @@ impl TransportListener for QuicTransportListener {
 
 struct QuicSession {
     conn: Connection,
-    incoming: tokio::sync::Mutex<mpsc::Receiver<(quinn::SendStream, quinn::RecvStream)>>,
+    incoming: tokio::sync::Mutex<AcceptQueueReceiver<(quinn::SendStream, quinn::RecvStream)>>,
     task: tokio::task::JoinHandle<()>,
 }
 
 impl QuicSession {
-    fn new(conn: Connection) -> Self {
-        let (tx, rx) = mpsc::channel(64);
+    fn new(conn: Connection, queue: AcceptQueue) -> Self {
+        let (tx, rx) = queue.channel();
         let c = conn.clone();
         let task = tokio::spawn(async move {
             while let Ok(st) = c.accept_bi().await {
@@ -242,10 +269,12 @@ impl tokio::io::AsyncWrite for QuicBiStream {
     }
 }
 
-mod quic_tls {
+pub(super) mod quic_tls {
     use std::{fs, path::Path, sync::Arc};
 
     use rcgen::generate_simple_self_signed;
+
+    use crate::prism::tls::TlsPolicy;
     use rustls::{
         client::danger::{ServerCertVerified, ServerCertVerifier},
         pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
@@ -277,6 +306,43 @@ mod quic_tls {
         Ok((vec![cert_der], key_der))
     }
 
+    /// CA roots for verifying client certificates, or None when mTLS is off.
+    pub fn load_client_roots(
+        client_ca_file: String,
+    ) -> anyhow::Result<Option<Arc<rustls::RootCertStore>>> {
+        let client_ca_file = client_ca_file.trim();
+        if client_ca_file.is_empty() {
+            return Ok(None);
+        }
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in load_certs(Path::new(client_ca_file))? {
+            roots.add(cert)?;
+        }
+        if roots.is_empty() {
+            anyhow::bail!("tunnel: no certificates found in {client_ca_file}");
+        }
+        Ok(Some(Arc::new(roots)))
+    }
+
+    /// The client's certificate chain and key, or None when it has none to present.
+    pub fn load_client_auth(
+        client_cert_file: String,
+        client_key_file: String,
+    ) -> anyhow::Result<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>> {
+        let client_cert_file = client_cert_file.trim();
+        let client_key_file = client_key_file.trim();
+        match (client_cert_file.is_empty(), client_key_file.is_empty()) {
+            (true, true) => Ok(None),
+            (false, false) => Ok(Some((
+                load_certs(Path::new(client_cert_file))?,
+                load_key(Path::new(client_key_file))?,
+            ))),
+            _ => anyhow::bail!(
+                "tunnel: quic requires both client_cert_file and client_key_file (or neither)"
+            ),
+        }
+    }
+
     fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
         let data = fs::read(path)?;
         let mut rd = std::io::Cursor::new(&data);
@@ -304,37 +370,46 @@ mod quic_tls {
     }
 
     pub fn server_crypto_config(
+        tls: &TlsPolicy,
         certs: Vec<CertificateDer<'static>>,
         key: PrivateKeyDer<'static>,
+        client_roots: Option<Arc<rustls::RootCertStore>>,
         next_protos: Vec<Vec<u8>>,
     ) -> anyhow::Result<rustls::ServerConfig> {
-        let mut cfg = rustls::ServerConfig::builder_with_provider(crypto_provider())
-            .with_safe_default_protocol_versions()?
-            .with_no_client_auth()
-            .with_single_cert(certs, key)?;
+        let builder = tls.server_builder()?;
+        let builder = match client_roots {
+            Some(roots) => builder.with_client_cert_verifier(
+                rustls::server::WebPkiClientVerifier::builder_with_provider(
+                    roots,
+                    crypto_provider(),
+                )
+                .build()?,
+            ),
+            None => builder.with_no_client_auth(),
+        };
+        let mut cfg = builder.with_single_cert(certs, key)?;
         cfg.alpn_protocols = next_protos;
         Ok(cfg)
     }
 
     pub fn client_crypto_config(
+        tls: &TlsPolicy,
         insecure_skip_verify: bool,
+        client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
         next_protos: Vec<Vec<u8>>,
     ) -> anyhow::Result<rustls::ClientConfig> {
-        if insecure_skip_verify {
-            let mut cfg = rustls::ClientConfig::builder_with_provider(crypto_provider())
-                .with_safe_default_protocol_versions()?
+        let builder = if insecure_skip_verify {
+            tls.client_builder()?
                 .dangerous()
                 .with_custom_certificate_verifier(SkipServerVerification::new())
-                .with_no_client_auth();
-            cfg.alpn_protocols = next_protos;
-            return Ok(cfg);
-        }
-
-        let root = rustls::RootCertStore::empty();
-        let mut cfg = rustls::ClientConfig::builder_with_provider(crypto_provider())
-            .with_safe_default_protocol_versions()?
-            .with_root_certificates(root)
-            .with_no_client_auth();
+        } else {
+            let root = rustls::RootCertStore::empty();
+            tls.client_builder()?.with_root_certificates(root)
+        };
+        let mut cfg = match client_auth {
+            Some((certs, key)) => builder.with_client_auth_cert(certs, key)?,
+            None => builder.with_no_client_auth(),
+        };
         cfg.alpn_protocols = next_protos;
         Ok(cfg)
     }
@@ -343,10 +418,10 @@ mod quic_tls {
     ///
     /// NOTE: vulnerable to MITM. Intended for local dev / testing only.
     #[derive(Debug)]
-    struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
+    pub struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
 
     impl SkipServerVerification {
-        fn new() -> Arc<Self> {
+        pub fn new() -> Arc<Self> {
             Arc::new(Self(crypto_provider()))
         }
     }
@@ -396,3 +471,136 @@ mod quic_tls {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir =
+            std::env::temp_dir().join(format!("prism_quic_{name}_{}_{nanos}", std::process::id()));
+        fs::create_dir_all(&dir).expect("mkdir");
+        dir
+    }
+
+    /// Write `cert` and its key as PEM files, returning their paths.
+    fn write_pair(dir: &std::path::Path, name: &str, cert: &str, key: &str) -> (String, String) {
+        let cert_path = dir.join(format!("{name}.pem"));
+        let key_path = dir.join(format!("{name}.key"));
+        fs::write(&cert_path, cert).expect("write cert");
+        fs::write(&key_path, key).expect("write key");
+        (
+            cert_path.display().to_string(),
+            key_path.display().to_string(),
+        )
+    }
+
+    fn dial_opts(client_cert_file: String, client_key_file: String) -> TransportDialOptions {
+        TransportDialOptions {
+            quic: QuicDialOptions {
+                insecure_skip_verify: true,
+                client_cert_file,
+                client_key_file,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Whether the dialed session is usable: a stream round-trips through the server.
+    async fn works(tr: &QuicTransport, addr: &str, opts: TransportDialOptions) -> bool {
+        let attempt = async {
+            let sess = tr.dial(addr, opts).await?;
+            let mut st = sess.open_stream().await?;
+            st.write_all(b"ping").await?;
+            st.flush().await?;
+            let mut b = [0u8; 4];
+            st.read_exact(&mut b).await?;
+            anyhow::Ok(&b == b"pong")
+        };
+        matches!(
+            tokio::time::timeout(Duration::from_secs(5), attempt).await,
+            Ok(Ok(true))
+        )
+    }
+
+    #[tokio::test]
+    async fn mtls_rejects_clients_without_a_trusted_certificate() {
+        let dir = temp_dir("mtls");
+
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).expect("params");
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::CertifiedIssuer::self_signed(
+            ca_params,
+            rcgen::KeyPair::generate().expect("ca key"),
+        )
+        .expect("ca");
+        let ca_file = dir.join("ca.pem");
+        fs::write(&ca_file, ca.pem()).expect("write ca");
+
+        let client_key = rcgen::KeyPair::generate().expect("client key");
+        let client_cert = rcgen::CertificateParams::new(vec!["tunnel-client".to_string()])
+            .expect("params")
+            .signed_by(&client_key, &ca)
+            .expect("sign client");
+        let (good_cert, good_key) = write_pair(
+            &dir,
+            "client",
+            &client_cert.pem(),
+            &client_key.serialize_pem(),
+        );
+
+        let rogue =
+            rcgen::generate_simple_self_signed(["tunnel-client".to_string()]).expect("rogue cert");
+        let (rogue_cert, rogue_key) = write_pair(
+            &dir,
+            "rogue",
+            &rogue.cert.pem(),
+            &rogue.signing_key.serialize_pem(),
+        );
+
+        let tr = QuicTransport::new();
+        let ln = tr
+            .listen(
+                "127.0.0.1:0",
+                TransportListenOptions {
+                    quic: QuicListenOptions {
+                        client_ca_file: ca_file.display().to_string(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("listen");
+        let addr = ln.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            while let Ok(sess) = ln.accept().await {
+                tokio::spawn(async move {
+                    while let Ok(mut st) = sess.accept_stream().await {
+                        let mut b = [0u8; 4];
+                        if st.read_exact(&mut b).await.is_ok() {
+                            let _ = st.write_all(b"pong").await;
+                            let _ = st.flush().await;
+                        }
+                    }
+                });
+            }
+        });
+
+        assert!(!works(&tr, &addr, dial_opts(String::new(), String::new())).await);
+        assert!(!works(&tr, &addr, dial_opts(rogue_cert, rogue_key)).await);
+        // Rejections don't take the listener down.
+        assert!(works(&tr, &addr, dial_opts(good_cert, good_key)).await);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
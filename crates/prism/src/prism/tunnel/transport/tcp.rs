@@ -2,16 +2,26 @@ use std::{net::SocketAddr, sync::Arc};
 
 use async_trait::async_trait;
 use futures_util::StreamExt;
-use tokio::{net::TcpListener, net::TcpStream, sync::mpsc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    net::TcpStream,
+};
 
 use crate::prism::net;
 use crate::prism::tunnel::transport::{
-    BoxedStream, Transport, TransportDialOptions, TransportListenOptions, TransportListener,
-    TransportSession,
+    AcceptQueue, AcceptQueueReceiver, BoxedStream, Transport, TransportDialOptions,
+    TransportListenOptions, TransportListener, TransportSession,
 };
 
 pub struct TcpTransport;
 
+impl Default for TcpTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TcpTransport {
     pub fn new() -> Self {
         Self
@@ -27,32 +37,48 @@ impl Transport for TcpTransport {
     async fn listen(
         &self,
         addr: &str,
-        _opts: TransportListenOptions,
+        opts: TransportListenOptions,
     ) -> anyhow::Result<Box<dyn TransportListener>> {
         let bind_addr = net::normalize_bind_addr(addr);
         let ln = TcpListener::bind(bind_addr.as_ref()).await?;
-        Ok(Box::new(TcpTransportListener { ln }))
+        Ok(Box::new(TcpTransportListener {
+            ln,
+            accept_queue: AcceptQueue::listen(&opts),
+        }))
     }
 
     async fn dial(
         &self,
         addr: &str,
-        _opts: TransportDialOptions,
+        opts: TransportDialOptions,
     ) -> anyhow::Result<Arc<dyn TransportSession>> {
         let c = TcpStream::connect(addr).await?;
-        Ok(Arc::new(YamuxSession::client(c)))
+        let (remote, local) = (c.peer_addr().ok(), c.local_addr().ok());
+        Ok(Arc::new(YamuxSession::client(
+            c,
+            remote,
+            local,
+            AcceptQueue::dial(&opts),
+        )))
     }
 }
 
 pub struct TcpTransportListener {
     ln: TcpListener,
+    accept_queue: AcceptQueue,
 }
 
 #[async_trait]
 impl TransportListener for TcpTransportListener {
     async fn accept(&self) -> anyhow::Result<Arc<dyn TransportSession>> {
         let (c, _) = self.ln.accept().await?;
-        Ok(Arc::new(YamuxSession::server(c)))
+        let (remote, local) = (c.peer_addr().ok(), c.local_addr().ok());
+        Ok(Arc::new(YamuxSession::server(
+            c,
+            remote,
+            local,
+            self.accept_queue.clone(),
+        )))
     }
 
     fn local_addr(&self) -> Option<SocketAddr> {
@@ -65,38 +91,54 @@ impl TransportListener for TcpTransportListener {
     }
 }
 
-struct YamuxSession {
+/// A yamux session over any byte stream (a TCP connection, a WebSocket, ...).
+pub(super) struct YamuxSession {
     control: tokio::sync::Mutex<tokio_yamux::Control>,
-    incoming: tokio::sync::Mutex<mpsc::Receiver<tokio_yamux::StreamHandle>>,
+    incoming: tokio::sync::Mutex<AcceptQueueReceiver<tokio_yamux::StreamHandle>>,
     remote: Option<SocketAddr>,
-    #[allow(dead_code)]
     local: Option<SocketAddr>,
     task: tokio::task::JoinHandle<()>,
 }
 
 impl YamuxSession {
-    fn server(c: TcpStream) -> Self {
-        let remote = c.peer_addr().ok();
-        let local = c.local_addr().ok();
-        let session = tokio_yamux::Session::new_server(c, tokio_yamux::Config::default());
-        Self::from_session(session, remote, local)
+    pub(super) fn server<T>(
+        io: T,
+        remote: Option<SocketAddr>,
+        local: Option<SocketAddr>,
+        queue: AcceptQueue,
+    ) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let session = tokio_yamux::Session::new_server(io, tokio_yamux::Config::default());
+        Self::from_session(session, remote, local, queue)
     }
 
-    fn client(c: TcpStream) -> Self {
-        let remote = c.peer_addr().ok();
-        let local = c.local_addr().ok();
-        let session = tokio_yamux::Session::new_client(c, tokio_yamux::Config::default());
-        Self::from_session(session, remote, local)
+    pub(super) fn client<T>(
+        io: T,
+        remote: Option<SocketAddr>,
+        local: Option<SocketAddr>,
+        queue: AcceptQueue,
+    ) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let session = tokio_yamux::Session::new_client(io, tokio_yamux::Config::default());
+        Self::from_session(session, remote, local, queue)
     }
 
-    fn from_session(
-        mut session: tokio_yamux::Session<TcpStream>,
+    fn from_session<T>(
+        mut session: tokio_yamux::Session<T>,
         remote: Option<SocketAddr>,
         local: Option<SocketAddr>,
-    ) -> Self {
+        queue: AcceptQueue,
+    ) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         let control = session.control();
 
-        let (tx, rx) = mpsc::channel::<tokio_yamux::StreamHandle>(64);
+        let (tx, rx) = queue.channel::<tokio_yamux::StreamHandle>();
         let task = tokio::spawn(async move {
             while let Some(next) = session.next().await {
                 match next {
@@ -151,3 +193,82 @@ impl TransportSession for YamuxSession {
         self.local
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn full_accept_queue_applies_backpressure_without_losing_streams() {
+        const STREAMS: u8 = 100;
+
+        let tr = TcpTransport::new();
+        let queued = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let queued_now = || queued.load(std::sync::atomic::Ordering::Relaxed);
+        let ln = tr
+            .listen(
+                "127.0.0.1:0",
+                TransportListenOptions {
+                    accept_queue_depth: 2,
+                    queued_streams: queued.clone(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("listen");
+        let addr = ln.local_addr().expect("local addr").to_string();
+
+        let client = tr
+            .dial(&addr, TransportDialOptions::default())
+            .await
+            .expect("dial");
+        let server = ln.accept().await.expect("accept");
+
+        // Open far more streams than the server queue holds before it drains any.
+        let opener = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            for i in 0..STREAMS {
+                let mut st = client.open_stream().await.expect("open");
+                st.write_all(&[i]).await.expect("write");
+                st.flush().await.expect("flush");
+                streams.push(st);
+            }
+            (client, streams)
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(queued_now(), 2, "the full queue shows on the gauge");
+
+        let mut seen = Vec::new();
+        for _ in 0..STREAMS {
+            let mut st =
+                tokio::time::timeout(std::time::Duration::from_secs(5), server.accept_stream())
+                    .await
+                    .expect("stream not lost")
+                    .expect("accept stream");
+            let mut b = [0u8; 1];
+            st.read_exact(&mut b).await.expect("read");
+            seen.push(b[0]);
+        }
+        let (client, _keep) = opener.await.expect("opener");
+
+        seen.sort_unstable();
+        assert_eq!(seen, (0..STREAMS).collect::<Vec<_>>());
+        assert_eq!(queued_now(), 0);
+
+        // Streams still queued when a session goes away leave the gauge with it.
+        for _ in 0..2 {
+            let mut st = client.open_stream().await.expect("open");
+            st.write_all(b"x").await.expect("write");
+            st.flush().await.expect("flush");
+        }
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        while queued_now() < 2 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(queued_now(), 2);
+        server.close().await;
+        drop(server);
+        assert_eq!(queued_now(), 0);
+    }
+}
@@ -2,13 +2,13 @@ use std::{net::SocketAddr, sync::Arc};
 
 use async_trait::async_trait;
 use futures_util::StreamExt;
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::Mutex;
 use tokio_kcp::{KcpConfig, KcpListener, KcpStream};
 
 use crate::prism::net;
 use crate::prism::tunnel::transport::{
-    BoxedStream, Transport, TransportDialOptions, TransportListenOptions, TransportListener,
-    TransportSession,
+    AcceptQueue, AcceptQueueReceiver, BoxedStream, Transport, TransportDialOptions,
+    TransportListenOptions, TransportListener, TransportSession,
 };
 
 /// UDP transport implemented as KCP (reliable UDP) + yamux multiplexing.
@@ -18,6 +18,12 @@ pub struct UdpTransport {
     kcp: KcpConfig,
 }
 
+impl Default for UdpTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UdpTransport {
     pub fn new() -> Self {
         Self {
@@ -35,7 +41,7 @@ impl Transport for UdpTransport {
     async fn listen(
         &self,
         addr: &str,
-        _opts: TransportListenOptions,
+        opts: TransportListenOptions,
     ) -> anyhow::Result<Box<dyn TransportListener>> {
         let bind_addr = net::normalize_bind_addr(addr);
         let bind_addr: SocketAddr = bind_addr.parse()?;
@@ -44,23 +50,29 @@ impl Transport for UdpTransport {
         Ok(Box::new(UdpTransportListener {
             ln: Mutex::new(ln),
             local,
+            accept_queue: AcceptQueue::listen(&opts),
         }))
     }
 
     async fn dial(
         &self,
         addr: &str,
-        _opts: TransportDialOptions,
+        opts: TransportDialOptions,
     ) -> anyhow::Result<Arc<dyn TransportSession>> {
         let remote = resolve_socket_addr(addr).await?;
         let c = KcpStream::connect(&self.kcp, remote).await?;
-        Ok(Arc::new(YamuxSession::client(c, Some(remote))))
+        Ok(Arc::new(YamuxSession::client(
+            c,
+            Some(remote),
+            AcceptQueue::dial(&opts),
+        )))
     }
 }
 
 pub struct UdpTransportListener {
     ln: Mutex<KcpListener>,
     local: Option<SocketAddr>,
+    accept_queue: AcceptQueue,
 }
 
 #[async_trait]
@@ -69,7 +81,12 @@ impl TransportListener for UdpTransportListener {
         let mut ln = self.ln.lock().await;
         let (c, peer) = ln.accept().await?;
         let local = self.local;
-        Ok(Arc::new(YamuxSession::server(c, Some(peer), local)))
+        Ok(Arc::new(YamuxSession::server(
+            c,
+            Some(peer),
+            local,
+            self.accept_queue.clone(),
+        )))
     }
 
     fn local_addr(&self) -> Option<SocketAddr> {
@@ -83,7 +100,7 @@ impl TransportListener for UdpTransportListener {
 
 struct YamuxSession {
     control: Mutex<tokio_yamux::Control>,
-    incoming: Mutex<mpsc::Receiver<tokio_yamux::StreamHandle>>,
+    incoming: Mutex<AcceptQueueReceiver<tokio_yamux::StreamHandle>>,
     remote: Option<SocketAddr>,
     #[allow(dead_code)]
     local: Option<SocketAddr>,
@@ -91,24 +108,30 @@ struct YamuxSession {
 }
 
 impl YamuxSession {
-    fn server(c: KcpStream, remote: Option<SocketAddr>, local: Option<SocketAddr>) -> Self {
+    fn server(
+        c: KcpStream,
+        remote: Option<SocketAddr>,
+        local: Option<SocketAddr>,
+        queue: AcceptQueue,
+    ) -> Self {
         let session = tokio_yamux::Session::new_server(c, tokio_yamux::Config::default());
-        Self::from_session(session, remote, local)
+        Self::from_session(session, remote, local, queue)
     }
 
-    fn client(c: KcpStream, remote: Option<SocketAddr>) -> Self {
+    fn client(c: KcpStream, remote: Option<SocketAddr>, queue: AcceptQueue) -> Self {
         let session = tokio_yamux::Session::new_client(c, tokio_yamux::Config::default());
-        Self::from_session(session, remote, None)
+        Self::from_session(session, remote, None, queue)
     }
 
     fn from_session(
         mut session: tokio_yamux::Session<KcpStream>,
         remote: Option<SocketAddr>,
         local: Option<SocketAddr>,
+        queue: AcceptQueue,
     ) -> Self {
         let control = session.control();
 
-        let (tx, rx) = mpsc::channel::<tokio_yamux::StreamHandle>(64);
+        let (tx, rx) = queue.channel::<tokio_yamux::StreamHandle>();
         let task = tokio::spawn(async move {
             while let Some(next) = session.next().await {
                 match next {
@@ -0,0 +1,399 @@
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures_util::{Sink, Stream};
+use rustls::pki_types::ServerName;
+use rustls_platform_verifier::BuilderVerifierExt;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::{Mutex, mpsc},
+};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{Message, http::Uri},
+};
+
+use crate::prism::net;
+use crate::prism::tunnel::transport::{
+    AcceptQueue, BoxedStream, QuicDialOptions, QuicListenOptions, Transport, TransportDialOptions,
+    TransportListenOptions, TransportListener, TransportSession, quic::quic_tls, tcp::YamuxSession,
+};
+
+/// How long an accepted connection may take to finish its TLS and WebSocket handshakes.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// WebSocket transport: yamux over the binary messages of a WebSocket, optionally inside
+/// TLS (`wss`), so tunnels can cross HTTP proxies and CDNs that only pass web traffic.
+///
+/// TLS settings (certificate, client CA, server name, skip-verify) come from the same
+/// `quic` options the QUIC transport uses.
+pub struct WsTransport {
+    tls: bool,
+}
+
+impl WsTransport {
+    pub fn new(tls: bool) -> Self {
+        Self { tls }
+    }
+}
+
+#[async_trait]
+impl Transport for WsTransport {
+    fn name(&self) -> &'static str {
+        if self.tls { "wss" } else { "ws" }
+    }
+
+    async fn listen(
+        &self,
+        addr: &str,
+        opts: TransportListenOptions,
+    ) -> anyhow::Result<Box<dyn TransportListener>> {
+        let bind_addr = net::normalize_bind_addr(addr);
+        let ln = TcpListener::bind(bind_addr.as_ref()).await?;
+        let local = ln.local_addr().ok();
+        let accept_queue = AcceptQueue::listen(&opts);
+        let acceptor = if self.tls {
+            Some(server_tls(opts.quic)?)
+        } else {
+            None
+        };
+
+        // Handshakes run concurrently so one slow peer can't hold up the others.
+        let (tx, rx) = mpsc::channel::<(WsByteStream, SocketAddr)>(accept_queue.depth());
+        let task = tokio::spawn(async move {
+            while let Ok((c, peer)) = ln.accept().await {
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+                        let c: BoxedStream = match acceptor {
+                            Some(acceptor) => Box::new(acceptor.accept(c).await?),
+                            None => Box::new(c),
+                        };
+                        anyhow::Ok(tokio_tungstenite::accept_async(c).await?)
+                    })
+                    .await;
+                    match handshake {
+                        Ok(Ok(ws)) => {
+                            let _ = tx.send((WsByteStream::new(ws), peer)).await;
+                        }
+                        Ok(Err(err)) => tracing::debug!(
+                            peer=%peer, err=%err, "tunnel: websocket handshake failed"
+                        ),
+                        Err(_) => tracing::debug!(
+                            peer=%peer, "tunnel: websocket handshake timed out"
+                        ),
+                    }
+                });
+            }
+        });
+
+        Ok(Box::new(WsTransportListener {
+            incoming: Mutex::new(rx),
+            local,
+            accept_queue,
+            task,
+        }))
+    }
+
+    async fn dial(
+        &self,
+        addr: &str,
+        opts: TransportDialOptions,
+    ) -> anyhow::Result<Arc<dyn TransportSession>> {
+        let accept_queue = AcceptQueue::dial(&opts);
+        let url = ws_url(addr, self.tls)?;
+        let host = url.host().unwrap_or_default().to_string();
+        let port = url.port_u16().unwrap_or(if self.tls { 443 } else { 80 });
+        let c = TcpStream::connect((host.trim_matches(['[', ']']), port)).await?;
+        let remote = c.peer_addr().ok();
+        let local = c.local_addr().ok();
+
+        let c: BoxedStream = if self.tls {
+            let server_name = if opts.quic.server_name.trim().is_empty() {
+                host.trim_matches(['[', ']']).to_string()
+            } else {
+                opts.quic.server_name.trim().to_string()
+            };
+            let connector = client_tls(opts.quic)?;
+            Box::new(
+                connector
+                    .connect(ServerName::try_from(server_name)?, c)
+                    .await?,
+            )
+        } else {
+            Box::new(c)
+        };
+
+        let (ws, _) = tokio_tungstenite::client_async(url.to_string(), c).await?;
+        Ok(Arc::new(YamuxSession::client(
+            WsByteStream::new(ws),
+            remote,
+            local,
+            accept_queue,
+        )))
+    }
+}
+
+/// Accept either a bare `host:port` (path `/`) or a full `ws://` / `wss://` URL, which
+/// lets the client pick the path and Host a CDN or reverse proxy routes on.
+fn ws_url(addr: &str, tls: bool) -> anyhow::Result<Uri> {
+    let addr = addr.trim();
+    let scheme = if tls { "wss" } else { "ws" };
+    let url = if addr.contains("://") {
+        addr.to_string()
+    } else {
+        format!("{scheme}://{addr}/")
+    };
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| anyhow::anyhow!("tunnel: invalid websocket address {addr:?}: {e}"))?;
+    if uri.scheme_str() != Some(scheme) {
+        anyhow::bail!("tunnel: {scheme} transport needs a {scheme}:// address (got {addr:?})");
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        anyhow::bail!("tunnel: websocket address {addr:?} has no host");
+    }
+    Ok(uri)
+}
+
+fn server_tls(opts: QuicListenOptions) -> anyhow::Result<tokio_rustls::TlsAcceptor> {
+    let (certs, key) = quic_tls::load_or_generate_cert(opts.cert_file, opts.key_file)?;
+    let client_roots = quic_tls::load_client_roots(opts.client_ca_file)?;
+    let cfg = quic_tls::server_crypto_config(
+        &opts.tls,
+        certs,
+        key,
+        client_roots,
+        vec![b"http/1.1".to_vec()],
+    )?;
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(cfg)))
+}
+
+fn client_tls(opts: QuicDialOptions) -> anyhow::Result<tokio_rustls::TlsConnector> {
+    let client_auth = quic_tls::load_client_auth(opts.client_cert_file, opts.client_key_file)?;
+    let builder = opts.tls.client_builder()?;
+    // Unlike QUIC endpoints, wss servers usually sit behind a CDN or reverse proxy with a
+    // publicly trusted certificate, so verify against the platform's roots.
+    let builder = if opts.insecure_skip_verify {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(quic_tls::SkipServerVerification::new())
+    } else {
+        builder.with_platform_verifier()?
+    };
+    let mut cfg = match client_auth {
+        Some((certs, key)) => builder.with_client_auth_cert(certs, key)?,
+        None => builder.with_no_client_auth(),
+    };
+    cfg.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(tokio_rustls::TlsConnector::from(Arc::new(cfg)))
+}
+
+pub struct WsTransportListener {
+    incoming: Mutex<mpsc::Receiver<(WsByteStream, SocketAddr)>>,
+    local: Option<SocketAddr>,
+    accept_queue: AcceptQueue,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[async_trait]
+impl TransportListener for WsTransportListener {
+    async fn accept(&self) -> anyhow::Result<Arc<dyn TransportSession>> {
+        let mut rx = self.incoming.lock().await;
+        let (ws, peer) = rx
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("tunnel: websocket listener closed"))?;
+        Ok(Arc::new(YamuxSession::server(
+            ws,
+            Some(peer),
+            self.local,
+            self.accept_queue.clone(),
+        )))
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.local
+    }
+
+    async fn close(&self) -> anyhow::Result<()> {
+        self.task.abort();
+        Ok(())
+    }
+}
+
+/// Byte stream over a WebSocket: writes become binary messages and reads concatenate the
+/// payloads of incoming binary messages. Control frames are handled by tungstenite.
+struct WsByteStream {
+    ws: WebSocketStream<BoxedStream>,
+    pending: Bytes,
+}
+
+impl WsByteStream {
+    fn new(ws: WebSocketStream<BoxedStream>) -> Self {
+        Self {
+            ws,
+            pending: Bytes::new(),
+        }
+    }
+}
+
+impl AsyncRead for WsByteStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            if !self.pending.is_empty() {
+                let n = self.pending.len().min(buf.remaining());
+                buf.put_slice(&self.pending[..n]);
+                self.pending.advance(n);
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(Pin::new(&mut self.ws).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => self.pending = data,
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Err(std::io::Error::other(err))),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for WsByteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(Pin::new(&mut self.ws).poll_ready(cx)).map_err(std::io::Error::other)?;
+        Pin::new(&mut self.ws)
+            .start_send(Message::Binary(Bytes::copy_from_slice(buf)))
+            .map_err(std::io::Error::other)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.ws)
+            .poll_flush(cx)
+            .map_err(std::io::Error::other)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.ws)
+            .poll_close(cx)
+            .map_err(std::io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prism::tunnel::protocol::{self, RegisterRequest, RegisteredService};
+
+    async fn register_roundtrip(tls: bool) {
+        let tr = WsTransport::new(tls);
+        let ln = tr
+            .listen("127.0.0.1:0", TransportListenOptions::default())
+            .await
+            .expect("listen");
+        let addr = ln.local_addr().expect("addr").to_string();
+
+        let server = tokio::spawn(async move {
+            let sess = ln.accept().await.expect("accept");
+            let mut st = sess.accept_stream().await.expect("accept stream");
+            let req = protocol::read_register_request(&mut st)
+                .await
+                .expect("read register");
+            // Echo it back so the client sees what crossed the WebSocket.
+            protocol::write_register_request(&mut st, &req)
+                .await
+                .expect("echo register");
+            tokio::io::AsyncWriteExt::flush(&mut st)
+                .await
+                .expect("flush");
+            let _keep = (ln, sess);
+            std::future::pending::<()>().await;
+        });
+
+        let sess = tr
+            .dial(
+                &addr,
+                TransportDialOptions {
+                    quic: QuicDialOptions {
+                        insecure_skip_verify: true,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("dial");
+        let mut st = sess.open_stream().await.expect("open stream");
+        let req = RegisterRequest {
+            token: "secret".into(),
+            services: vec![RegisteredService {
+                name: "home".into(),
+                proto: "tcp".into(),
+                local_addr: "127.0.0.1:25565".into(),
+                route_only: true,
+                remote_addr: String::new(),
+                masquerade_host: String::new(),
+                rate_limit_bytes_per_sec: 0,
+                rate_limit_scope: Default::default(),
+                dial_policy: String::new(),
+            }],
+            heartbeat_interval_ms: 0,
+        };
+        protocol::write_register_request(&mut st, &req)
+            .await
+            .expect("write register");
+        tokio::io::AsyncWriteExt::flush(&mut st)
+            .await
+            .expect("flush");
+
+        let got = tokio::time::timeout(
+            Duration::from_secs(5),
+            protocol::read_register_request(&mut st),
+        )
+        .await
+        .expect("echo timeout")
+        .expect("read echo");
+        assert_eq!(got.token, "secret");
+        assert_eq!(got.services.len(), 1);
+        assert_eq!(got.services[0].name, "home");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn ws_roundtrips_a_register_request() {
+        register_roundtrip(false).await;
+    }
+
+    #[tokio::test]
+    async fn wss_roundtrips_a_register_request() {
+        register_roundtrip(true).await;
+    }
+
+    #[test]
+    fn ws_url_accepts_bare_addresses_and_urls() {
+        assert_eq!(
+            ws_url("tunnel.example.com:443", true).unwrap().to_string(),
+            "wss://tunnel.example.com:443/"
+        );
+        let u = ws_url("wss://cdn.example.com/prism", true).unwrap();
+        assert_eq!(u.host(), Some("cdn.example.com"));
+        assert_eq!(u.path(), "/prism");
+        assert!(ws_url("ws://cdn.example.com/", true).is_err());
+    }
+}
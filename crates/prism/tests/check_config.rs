@@ -0,0 +1,92 @@
+use std::{path::PathBuf, process::Command};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("prism_it_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create temp dir");
+    dir
+}
+
+fn check_config(config: &PathBuf) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_prism"))
+        .arg("--check-config")
+        .arg("--config")
+        .arg(config)
+        .env_remove("PRISM_CONFIG")
+        .env_remove("PRISM_MIDDLEWARE_DIR")
+        .output()
+        .expect("run prism")
+}
+
+#[test]
+fn check_config_accepts_a_valid_config_without_writing_files() {
+    let dir = temp_dir("check_ok");
+    let cfg = dir.join("prism.toml");
+    std::fs::write(
+        &cfg,
+        r#"
+[[listeners]]
+listen_addr = "127.0.0.1:0"
+protocol = "tcp"
+
+[[routes]]
+host = "*.example.com"
+upstreams = ["127.0.0.1:25566#weight=2", "127.0.0.1:25567"]
+strategy = "weighted"
+middlewares = ["minecraft_handshake"]
+"#,
+    )
+    .expect("write config");
+
+    let out = check_config(&cfg);
+    assert!(
+        out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(
+        !dir.join("middlewares").exists(),
+        "default middlewares must not be materialized"
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn check_config_rejects_a_broken_route() {
+    let dir = temp_dir("check_broken");
+    let cfg = dir.join("prism.toml");
+    std::fs::write(
+        &cfg,
+        r#"
+[[listeners]]
+listen_addr = "127.0.0.1:0"
+protocol = "tcp"
+
+[[routes]]
+host = "play.example.com"
+upstreams = ["127.0.0.1:25566"]
+middlewares = ["does_not_exist"]
+"#,
+    )
+    .expect("write config");
+
+    let out = check_config(&cfg);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("route[0]"), "stderr: {stderr}");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn check_config_does_not_create_a_missing_config() {
+    let dir = temp_dir("check_missing");
+    let cfg = dir.join("prism.toml");
+
+    let out = check_config(&cfg);
+    assert!(!out.status.success());
+    assert!(!cfg.exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,120 @@
+use std::{sync::Arc, time::Duration};
+
+use prism::{config, embed, middleware, proxy, router};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// Handshake packet (id 0x00) for protocol 767 with next state login. Every length
+/// here fits a single-byte varint.
+fn login_handshake(host: &str, port: u16) -> Vec<u8> {
+    let mut body = vec![0x00, 0xff, 0x05, host.len() as u8];
+    body.extend_from_slice(host.as_bytes());
+    body.extend_from_slice(&port.to_be_bytes());
+    body.push(2);
+    let mut packet = vec![body.len() as u8];
+    packet.extend_from_slice(&body);
+    packet
+}
+
+#[tokio::test]
+async fn embedded_proxy_routes_a_connection_and_shuts_down() {
+    let backend = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind backend");
+
+    let mut cfg = config::Config::default();
+    cfg.routes.push(config::RouteConfig {
+        host: vec!["play.example.com".into()],
+        upstreams: vec![backend.local_addr().expect("addr").to_string()],
+        middlewares: vec!["minecraft_handshake".into()],
+        strategy: "sequential".into(),
+        ..Default::default()
+    });
+    let router = router::Router::from_config(&cfg, &middleware::BuiltinMiddlewareProvider::new())
+        .expect("build router");
+    let mut runtime = proxy::TcpRuntimeConfig::from_config(&cfg);
+    runtime.shutdown_drain_timeout = Duration::from_secs(5);
+    let handle = embed::Proxy::new(Arc::new(router), runtime)
+        .serve("127.0.0.1:0")
+        .await
+        .expect("serve");
+    let addr = handle.local_addr();
+
+    let hello = login_handshake("play.example.com", 25565);
+    let mut c = TcpStream::connect(addr).await.expect("connect");
+    c.write_all(&hello).await.expect("write");
+    let (mut b, _) = time::timeout(Duration::from_secs(5), backend.accept())
+        .await
+        .expect("backend accept timeout")
+        .expect("backend accept");
+    // The handshake arrives with its host rewritten to the upstream.
+    let len = b.read_u8().await.expect("read length");
+    let mut body = vec![0u8; len as usize];
+    b.read_exact(&mut body).await.expect("read handshake");
+    assert_eq!(body[0], 0x00, "handshake packet id");
+    assert_eq!(body.last(), Some(&2), "next state stays login");
+    b.write_all(b"pong").await.expect("write");
+    let mut reply = [0u8; 4];
+    c.read_exact(&mut reply).await.expect("read");
+    assert_eq!(&reply, b"pong");
+    drop((b, c));
+
+    assert!(handle.shutdown().await.expect("shutdown"));
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_waits_only_for_its_own_sessions() {
+    let backend = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind backend");
+
+    let mut cfg = config::Config::default();
+    cfg.routes.push(config::RouteConfig {
+        host: vec!["play.example.com".into()],
+        upstreams: vec![backend.local_addr().expect("addr").to_string()],
+        middlewares: vec!["minecraft_handshake".into()],
+        ..Default::default()
+    });
+    let router = Arc::new(
+        router::Router::from_config(&cfg, &middleware::BuiltinMiddlewareProvider::new())
+            .expect("build router"),
+    );
+    let mut runtime = proxy::TcpRuntimeConfig::from_config(&cfg);
+    runtime.shutdown_drain_timeout = Duration::from_secs(30);
+    let sessions = Arc::new(embed::SessionRegistry::new());
+    let busy = embed::Proxy::new(router.clone(), runtime.clone())
+        .with_sessions(sessions.clone())
+        .serve("127.0.0.1:0")
+        .await
+        .expect("serve busy");
+    let idle = embed::Proxy::new(router, runtime)
+        .with_sessions(sessions.clone())
+        .serve("127.0.0.1:0")
+        .await
+        .expect("serve idle");
+
+    let mut c = TcpStream::connect(busy.local_addr())
+        .await
+        .expect("connect");
+    c.write_all(&login_handshake("play.example.com", 25565))
+        .await
+        .expect("write");
+    let (_b, _) = time::timeout(Duration::from_secs(5), backend.accept())
+        .await
+        .expect("backend accept timeout")
+        .expect("backend accept");
+    assert_eq!(sessions.active_connections(), 1);
+
+    // The busy proxy's session is live in the shared registry, yet the idle one stops
+    // right away.
+    let drained = time::timeout(Duration::from_secs(5), idle.shutdown())
+        .await
+        .expect("idle proxy shut down promptly")
+        .expect("shutdown");
+    assert!(drained);
+    drop(busy);
+}
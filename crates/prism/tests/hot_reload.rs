@@ -0,0 +1,143 @@
+use std::{
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("prism_it_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create temp dir");
+    dir
+}
+
+fn free_tcp_addr() -> String {
+    let ln = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    ln.local_addr().expect("addr").to_string()
+}
+
+fn write_config(path: &Path, listeners: &[&str]) {
+    write_config_with(path, "", listeners);
+}
+
+fn write_config_with(path: &Path, preamble: &str, listeners: &[&str]) {
+    let mut toml = format!(
+        r#"{preamble}admin_addr = ""
+
+[logging]
+output = "discard"
+
+[reload]
+enabled = true
+poll_interval_ms = 200
+listener_mode = "hot"
+"#,
+    );
+    toml.push_str(&listeners_toml(listeners));
+    std::fs::write(path, toml).expect("write config");
+}
+
+fn listeners_toml(listeners: &[&str]) -> String {
+    listeners
+        .iter()
+        .map(|addr| format!("\n[[listeners]]\nlisten_addr = \"{addr}\"\nprotocol = \"tcp\"\n"))
+        .collect()
+}
+
+/// Poll until `addr` accepting connections equals `want`, or give up after 10s.
+fn wait_accepting(addr: &str, want: bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if TcpStream::connect(addr).is_ok() == want {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn_prism(dir: &Path, cfg: &Path) -> KillOnDrop {
+    KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_prism"))
+            .arg("--config")
+            .arg(cfg)
+            .arg("--workdir")
+            .arg(dir.join("work"))
+            .arg("--middleware-dir")
+            .arg(dir.join("middlewares"))
+            .env_remove("PRISM_CONFIG")
+            .env_remove("PRISM_WORKDIR")
+            .env_remove("PRISM_MIDDLEWARE_DIR")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn prism"),
+    )
+}
+
+#[test]
+fn removing_a_startup_listener_keeps_the_process_serving() {
+    let dir = temp_dir("hot_reload_remove");
+    let cfg = dir.join("prism.toml");
+    let (kept, removed) = (free_tcp_addr(), free_tcp_addr());
+    write_config(&cfg, &[&kept, &removed]);
+
+    let mut prism = spawn_prism(&dir, &cfg);
+    assert!(wait_accepting(&kept, true), "{kept} never came up");
+    assert!(wait_accepting(&removed, true), "{removed} never came up");
+
+    write_config(&cfg, &[&kept]);
+    assert!(wait_accepting(&removed, false), "{removed} was not removed");
+
+    // Give a wrongly exiting process time to go away before checking.
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(
+        prism.0.try_wait().expect("try_wait").is_none(),
+        "prism exited after a listener was removed"
+    );
+    assert!(wait_accepting(&kept, true), "{kept} stopped serving");
+
+    drop(prism);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn editing_an_included_file_triggers_a_reload() {
+    let dir = temp_dir("hot_reload_include");
+    let cfg = dir.join("prism.toml");
+    let inc_dir = dir.join("listeners.d");
+    std::fs::create_dir_all(&inc_dir).expect("mkdir");
+    let (main, included) = (free_tcp_addr(), free_tcp_addr());
+    write_config_with(&cfg, "include = [\"listeners.d/*.toml\"]\n", &[&main]);
+
+    let prism = spawn_prism(&dir, &cfg);
+    assert!(wait_accepting(&main, true), "{main} never came up");
+
+    // Only the included file changes; the main config keeps its mtime and size.
+    std::fs::write(inc_dir.join("extra.toml"), listeners_toml(&[&included]))
+        .expect("write include");
+    assert!(
+        wait_accepting(&included, true),
+        "{included} from the included file never came up"
+    );
+
+    std::fs::remove_file(inc_dir.join("extra.toml")).expect("remove include");
+    assert!(
+        wait_accepting(&included, false),
+        "{included} stayed up after its file was removed"
+    );
+    assert!(wait_accepting(&main, true), "{main} stopped serving");
+
+    drop(prism);
+    let _ = std::fs::remove_dir_all(&dir);
+}